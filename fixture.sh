@@ -0,0 +1,61 @@
+#!/bin/sh
+# Rebuilds fixture.db, schema.db, utf16le.db and utf16be.db, the databases the tests read, from the statements below.
+# Needs the sqlite3 shell on the PATH: sh fixture.sh
+set -e
+cd "$(dirname "$0")"
+rm -f fixture.db
+sqlite3 fixture.db <<'SQL'
+-- small pages so 2000 short rows need a three level table b-tree
+PRAGMA page_size = 512;
+
+CREATE TABLE items(id INTEGER PRIMARY KEY, name TEXT COLLATE NOCASE, grp INTEGER, body TEXT, data BLOB);
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+INSERT INTO items(id, name, grp) SELECT i, printf(iif(i % 2, 'ITEM%04d', 'item%04d'), i), i % 10 FROM n;
+
+-- rows 7 and 1500 spill 3600 characters of body and a 2500 byte blob onto overflow chains
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1200)
+UPDATE items SET body = (SELECT group_concat(printf('%03d', i % 1000), '') FROM n) WHERE id IN (7, 1500);
+UPDATE items SET data = iif(id = 7, zeroblob(2500), CAST(substr(body, 1, 2500) AS BLOB)) WHERE body IS NOT NULL;
+
+CREATE INDEX items_grp_name ON items(grp DESC, name);
+CREATE INDEX items_name ON items(name);
+
+-- groups 10 and 11 have no items, tag 'lost' no item
+CREATE TABLE groups(id INTEGER PRIMARY KEY, label TEXT);
+INSERT INTO groups VALUES (0, 'zero'), (1, 'one'), (2, 'two'), (3, 'three'), (10, 'ten'), (11, NULL);
+CREATE TABLE tags(item INTEGER, tag TEXT);
+INSERT INTO tags VALUES (1, 'red'), (1, 'big'), (2, 'red'), (4, 'blue'), (2001, 'lost');
+
+-- b is computed on every read, c is stored in the record
+CREATE TABLE q(a INT, b TEXT GENERATED ALWAYS AS (upper(a)) VIRTUAL, c AS (a*2) STORED);
+INSERT INTO q(a) VALUES (1), (2), ('x');
+
+-- stored in k order in the primary key b-tree, k first
+CREATE TABLE wr(v INTEGER, k TEXT PRIMARY KEY, w TEXT) WITHOUT ROWID;
+INSERT INTO wr VALUES (2, 'a', 'ay'), (1, 'b', 'bee'), (3, 'c', NULL);
+SQL
+
+# schema.db only holds schema objects: 100 tables, each with a UNIQUE autoindex, an index,
+# and for every other table a view and a trigger, so sqlite_schema spans an interior page
+rm -f schema.db
+sqlite3 :memory: <<'SQL' | sqlite3 schema.db
+SELECT 'PRAGMA page_size = 512;';
+WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i < 99)
+SELECT printf('CREATE TABLE t%03d(id INTEGER PRIMARY KEY, v TEXT UNIQUE);', i)
+    || printf('CREATE INDEX i%03d ON t%03d(v, id);', i, i)
+    || iif(i % 2, '', printf('CREATE VIEW v%03d AS SELECT v FROM t%03d;', i, i)
+        || printf('CREATE TRIGGER r%03d AFTER INSERT ON t%03d BEGIN SELECT 1; END;', i, i))
+FROM n;
+SQL
+
+# utf16le.db and utf16be.db hold the same words, whose BINARY order differs between UTF-8,
+# UTF-16LE and UTF-16BE: 'Ā' is U+0100, '𝄞' needs a surrogate pair and '�' is U+FFFD
+for encoding in le be; do
+    rm -f utf16$encoding.db
+    sqlite3 utf16$encoding.db <<SQL
+PRAGMA encoding = 'UTF-16$encoding';
+CREATE TABLE words(id INTEGER PRIMARY KEY, w TEXT, n);
+INSERT INTO words VALUES (1, 'été', 12), (2, 'b', 1.5), (3, 'Āb', NULL), (4, '𝄞 clef', 0), (5, '�', X'00ff'), (6, 'abc', -3);
+CREATE INDEX words_w ON words(w);
+SQL
+done
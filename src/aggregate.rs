@@ -0,0 +1,557 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Write};
+
+use crate::collation::{Collation, KeyFolder};
+use crate::datetime::Clock;
+use crate::encoding::TextEncoding;
+use crate::eval::{self, EvalContext, Scope, SubqueryRunner};
+use crate::json::JsonGroup;
+use crate::spill::{read_values, values_size, write_values, SpillFile};
+use crate::sql::ParseError;
+use crate::sql::ast::{Expr, FunctionArgs, Span};
+use crate::value::Value;
+
+
+/// Files the overflowing groups are hashed into once the memory budget is used up.
+const SPILL_PARTITIONS: usize = 8;
+
+/// Rough per-group bookkeeping on top of its key and row.
+const GROUP_OVERHEAD: usize = 64;
+
+
+/// Whether a call is an aggregate; `min` and `max` with several arguments are scalar functions.
+pub fn is_aggregate(name: &str, args: &FunctionArgs) -> bool {
+    match name {
+        "count" | "sum" | "total" | "avg" | "group_concat" | "string_agg" | "json_group_array" | "json_group_object" => true,
+        "min" | "max" => matches!(args, FunctionArgs::List(args) if args.len() == 1),
+        _ => false,
+    }
+}
+
+/// Collects the aggregate calls of an expression, checking their arguments.
+/// Calls with an OVER clause are window functions, not aggregates; the calls inside them are still collected.
+pub fn collect_aggregates<'a>(expr: &'a Expr, out: &mut Vec<&'a Expr>) -> Result<(), ParseError> {
+    if let Expr::Function { name, args, distinct, over: None, span } = expr
+        && is_aggregate(name, args)
+    {
+        check_arguments(name, args, *distinct, *span)?;
+
+        let mut nested = Vec::new();
+        for child in expr.children() {
+            collect_aggregates(child, &mut nested)?;
+        }
+        if let Some(Expr::Function { name, span, .. }) = nested.first() {
+            return Err(ParseError::new(format!("misuse of aggregate function {}()", name), *span));
+        }
+
+        if !out.iter().any(|call| std::ptr::eq(*call, expr)) {
+            out.push(expr);
+        }
+        return Ok(());
+    }
+
+    for child in expr.children() {
+        collect_aggregates(child, out)?;
+    }
+    Ok(())
+}
+
+
+/// Checks the number of arguments of an aggregate call, and that DISTINCT has exactly one.
+pub fn check_arguments(name: &str, args: &FunctionArgs, distinct: bool, span: Span) -> Result<(), ParseError> {
+    let arg_count = match args {
+        FunctionArgs::Star => None,
+        FunctionArgs::List(args) => Some(args.len()),
+    };
+    let valid = match name {
+        "count" => arg_count.is_none_or(|n| n <= 1),
+        "group_concat" => matches!(arg_count, Some(1 | 2)),
+        "string_agg" | "json_group_object" => arg_count == Some(2),
+        _ => arg_count == Some(1),
+    };
+    if !valid {
+        return Err(ParseError::new(format!("wrong number of arguments to function {}()", name), span));
+    }
+    if distinct && arg_count != Some(1) {
+        return Err(ParseError::new("DISTINCT aggregates must have exactly one argument", span));
+    }
+    Ok(())
+}
+
+
+/// Values compared for grouping and DISTINCT: 1 and 1.0 are the same key.
+#[derive(Debug, Clone)]
+pub struct GroupKey(pub Vec<Value>);
+
+impl PartialEq for GroupKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len() && self.0.iter().zip(&other.0).all(|(a, b)| a.compare(b).is_eq())
+    }
+}
+
+impl Eq for GroupKey {}
+
+impl Hash for GroupKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for value in &self.0 {
+            match value {
+                Value::Null => 0u8.hash(state),
+                Value::Integer(n) => (1u8, *n).hash(state),
+                // integral reals hash like the equal integer
+                Value::Real(r) if (*r as i64) as f64 == *r && *r >= -9223372036854775808.0 && *r < 9223372036854775808.0 => {
+                    (1u8, *r as i64).hash(state)
+                }
+                Value::Real(r) => (2u8, r.to_bits()).hash(state),
+                Value::Text(s) => (3u8, s.as_bytes()).hash(state),
+                Value::Blob(b) => (4u8, b.as_slice()).hash(state),
+            }
+        }
+    }
+}
+
+
+/// Running state of one aggregate call within one group, or within one window frame.
+#[derive(Debug, Clone)]
+pub enum Accumulator {
+    Count(i64),
+    Sum {
+        /// `None` once the integer sum overflowed
+        integer: Option<i64>,
+        real: f64,
+        /// a non-integer was added, the result is REAL
+        approximate: bool,
+        count: i64,
+    },
+    Total(f64),
+    Avg { sum: f64, count: i64 },
+    /// the least value so far, by the collation of the argument
+    Min(Value, Collation),
+    Max(Value, Collation),
+    GroupConcat(Option<String>),
+    JsonGroup(JsonGroup),
+}
+
+impl Accumulator {
+    /// The state before any row of an aggregate call whose arguments are read in `scope`.
+    pub fn new(call: &Expr, scope: &Scope) -> Result<Self, Box<dyn Error>> {
+        let collation = || match first_arg(call) {
+            Some(arg) => eval::sort_collation(arg, scope),
+            None => Ok(Collation::Binary),
+        };
+        Ok(match call_name(call) {
+            "count" => Accumulator::Count(0),
+            "sum" => Accumulator::Sum { integer: Some(0), real: 0.0, approximate: false, count: 0 },
+            "total" => Accumulator::Total(0.0),
+            "avg" => Accumulator::Avg { sum: 0.0, count: 0 },
+            "min" => Accumulator::Min(Value::Null, collation()?),
+            "max" => Accumulator::Max(Value::Null, collation()?),
+            "json_group_array" | "json_group_object" => Accumulator::JsonGroup(JsonGroup::new(call, scope)),
+            _ => Accumulator::GroupConcat(None),
+        })
+    }
+
+    /// Adds one row's arguments (none for `count(*)`), with text stored in `encoding`; returns
+    /// the bytes the state grew by and whether a min/max changed.
+    pub fn step(&mut self, args: &[Value], encoding: TextEncoding) -> (usize, bool) {
+        // NULLs are part of a JSON array
+        if let Accumulator::JsonGroup(group) = self {
+            return (group.step(args, encoding), false);
+        }
+        let Some(value) = args.first() else {
+            // count(*) counts rows
+            if let Accumulator::Count(n) = self {
+                *n += 1;
+            }
+            return (0, false);
+        };
+        if value.is_null() {
+            return (0, false);
+        }
+
+        match self {
+            Accumulator::Count(n) => *n += 1,
+            Accumulator::Sum { integer, real, approximate, count } => {
+                *count += 1;
+                match exact_integer(value) {
+                    Some(n) if !*approximate => {
+                        *integer = integer.and_then(|sum| sum.checked_add(n));
+                        *real += n as f64;
+                    }
+                    _ => {
+                        *approximate = true;
+                        *real += value.to_f64(encoding);
+                    }
+                }
+            }
+            Accumulator::Total(sum) => *sum += value.to_f64(encoding),
+            Accumulator::Avg { sum, count } => {
+                *sum += value.to_f64(encoding);
+                *count += 1;
+            }
+            Accumulator::Min(current, collation) => {
+                if current.is_null() || collation.compare(value, current, encoding).is_lt() {
+                    *current = value.clone();
+                    return (0, true);
+                }
+            }
+            Accumulator::Max(current, collation) => {
+                if current.is_null() || collation.compare(value, current, encoding).is_gt() {
+                    *current = value.clone();
+                    return (0, true);
+                }
+            }
+            Accumulator::JsonGroup(_) => {}
+            Accumulator::GroupConcat(text) => {
+                let value = value.to_text(encoding);
+                let grown = value.len();
+                match text {
+                    Some(text) => {
+                        // the separator comes from the row of the value it precedes
+                        let separator = match args.get(1) {
+                            Some(separator) => separator.to_text(encoding),
+                            None => ",".to_string(),
+                        };
+                        text.push_str(&separator);
+                        text.push_str(&value);
+                        return (grown + separator.len(), false);
+                    }
+                    None => *text = Some(value),
+                }
+                return (grown, false);
+            }
+        }
+        (0, false)
+    }
+
+    pub fn finish(&self) -> Result<Value, Box<dyn Error>> {
+        Ok(match self {
+            Accumulator::Count(n) => Value::Integer(*n),
+            Accumulator::Sum { count: 0, .. } => Value::Null,
+            Accumulator::Sum { real, approximate: true, .. } => Value::Real(*real),
+            Accumulator::Sum { integer: Some(n), .. } => Value::Integer(*n),
+            Accumulator::Sum { integer: None, .. } => return Err("integer overflow".into()),
+            Accumulator::Total(sum) => Value::Real(*sum),
+            Accumulator::Avg { count: 0, .. } => Value::Null,
+            Accumulator::Avg { sum, count } => Value::Real(sum / *count as f64),
+            Accumulator::Min(value, _) | Accumulator::Max(value, _) => value.clone(),
+            Accumulator::GroupConcat(text) => text.clone().map_or(Value::Null, Value::Text),
+            Accumulator::JsonGroup(group) => return group.finish(),
+        })
+    }
+}
+
+/// Integer value of an INTEGER, or of text that spells one; sum() stays exact for those.
+fn exact_integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(n) => Some(*n),
+        Value::Text(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+
+struct Group {
+    accumulators: Vec<Accumulator>,
+    /// values already seen by each DISTINCT call
+    seen: Vec<Option<HashSet<GroupKey>>>,
+    /// row that bare columns are read from
+    row: Vec<Value>,
+}
+
+/// A finished group: its key, the row for bare columns, and the value of every aggregate call.
+pub struct GroupResult<'a> {
+    pub key: Vec<Value>,
+    pub row: Vec<Value>,
+    pub aggregates: Vec<(&'a Expr, Value)>,
+}
+
+struct Partition {
+    file: SpillFile,
+    writer: BufWriter<File>,
+}
+
+/// Hash aggregation. Groups live in a hash table until it outgrows the memory budget;
+/// after that rows of new groups are hashed into partition files that are aggregated
+/// one at a time once the input is exhausted.
+pub struct Aggregator<'a> {
+    scope: &'a Scope,
+    subqueries: Option<&'a dyn SubqueryRunner>,
+    encoding: TextEncoding,
+    clock: Clock,
+    group_by: &'a [Expr],
+    calls: &'a [&'a Expr],
+    /// bare columns come from the row where this min()/max() call last changed,
+    /// otherwise from the first row of the group
+    extreme: Option<usize>,
+    memory_budget: usize,
+    /// partitioning depth, salts the hash so a partition splits differently when it spills again
+    level: u64,
+    groups: HashMap<GroupKey, Group>,
+    /// folds the group keys for the collations of the GROUP BY terms
+    keys: KeyFolder,
+    /// folds the arguments of each DISTINCT call for their collation
+    arguments: Vec<KeyFolder>,
+    used: usize,
+    partitions: Vec<Partition>,
+}
+
+impl<'a> Aggregator<'a> {
+    pub fn new(
+        scope: &'a Scope,
+        group_by: &'a [Expr],
+        calls: &'a [&'a Expr],
+        memory_budget: usize,
+        encoding: TextEncoding,
+        clock: Clock,
+    ) -> Result<Self, Box<dyn Error>> {
+        // SQLite follows the last min() or max() of the query
+        let extreme = calls
+            .iter()
+            .rposition(|call| matches!(call, Expr::Function { name, .. } if name == "min" || name == "max"));
+        let collations = group_by.iter().map(|expr| eval::sort_collation(expr, scope)).collect::<Result<_, _>>()?;
+        let mut arguments = Vec::with_capacity(calls.len());
+        for call in calls {
+            let collation = match first_arg(call) {
+                Some(arg) if is_distinct(call) => eval::sort_collation(arg, scope)?,
+                _ => Collation::Binary,
+            };
+            arguments.push(KeyFolder::new(vec![collation]));
+        }
+        Ok(Aggregator {
+            scope,
+            subqueries: None,
+            encoding,
+            clock,
+            group_by,
+            calls,
+            extreme,
+            memory_budget,
+            level: 0,
+            groups: HashMap::new(),
+            keys: KeyFolder::new(collations),
+            arguments,
+            used: 0,
+            partitions: Vec::new(),
+        })
+    }
+
+    pub fn with_subqueries(self, subqueries: Option<&'a dyn SubqueryRunner>) -> Self {
+        Aggregator { subqueries, ..self }
+    }
+
+    pub fn push(&mut self, row: &[Value]) -> Result<(), Box<dyn Error>> {
+        let ctx = EvalContext::new(self.scope, row, self.encoding, self.clock).with_subqueries(self.subqueries);
+        let key = GroupKey(self.keys.fold(self.group_by.iter().map(|expr| ctx.eval(expr)).collect::<Result<_, _>>()?));
+
+        if !self.groups.contains_key(&key) {
+            if !self.partitions.is_empty() {
+                let partition = self.partition_of(&key);
+                return write_values(&mut self.partitions[partition].writer, row);
+            }
+
+            let group = Group {
+                accumulators: self.calls.iter().map(|call| Accumulator::new(call, self.scope)).collect::<Result<_, _>>()?,
+                seen: self.calls.iter().map(|call| is_distinct(call).then(HashSet::new)).collect(),
+                row: row.to_vec(),
+            };
+            self.used += values_size(&key.0) + values_size(row) + GROUP_OVERHEAD * (self.calls.len() + 1);
+            self.groups.insert(key.clone(), group);
+        }
+
+        let group = self.groups.get_mut(&key).ok_or("Missing group")?;
+        for (i, call) in self.calls.iter().enumerate() {
+            let args = call_args(call, &ctx)?;
+            if let (Some(seen), Some(value)) = (&mut group.seen[i], args.first()) {
+                if value.is_null() || !seen.insert(GroupKey(self.arguments[i].fold(vec![value.clone()]))) {
+                    continue;
+                }
+                self.used += values_size(&args[..1]);
+            }
+            let (grown, changed) = group.accumulators[i].step(&args, self.encoding);
+            self.used += grown;
+            if changed && self.extreme == Some(i) {
+                group.row.clone_from_slice(row);
+            }
+        }
+
+        if self.used > self.memory_budget && self.partitions.is_empty() {
+            for _ in 0..SPILL_PARTITIONS {
+                let file = SpillFile::create()?;
+                let writer = BufWriter::new(file.file().try_clone()?);
+                self.partitions.push(Partition { file, writer });
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands every group to `emit`. Without GROUP BY there is exactly one group, even for no rows.
+    pub fn finish(self, emit: &mut dyn FnMut(GroupResult<'a>) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+        let Aggregator { scope, subqueries, encoding, clock, group_by, calls, memory_budget, level, mut groups, partitions, .. } = self;
+
+        if groups.is_empty() && group_by.is_empty() && level == 0 {
+            let row = vec![Value::Null; scope.columns.len()];
+            let aggregates = calls
+                .iter()
+                .map(|call| Ok((*call, Accumulator::new(call, scope)?.finish()?)))
+                .collect::<Result<_, Box<dyn Error>>>()?;
+            return emit(GroupResult { key: vec![], row, aggregates });
+        }
+
+        for (key, group) in groups.drain() {
+            let mut aggregates = Vec::with_capacity(calls.len());
+            for (call, accumulator) in calls.iter().zip(&group.accumulators) {
+                aggregates.push((*call, accumulator.finish()?));
+            }
+            emit(GroupResult { key: key.0, row: group.row, aggregates })?;
+        }
+        drop(groups);
+
+        for mut partition in partitions {
+            partition.writer.flush()?;
+            drop(partition.writer);
+            partition.file.rewind()?;
+
+            let mut aggregator = Aggregator::new(scope, group_by, calls, memory_budget, encoding, clock)?.with_subqueries(subqueries);
+            aggregator.level = level + 1;
+            let mut reader = BufReader::new(partition.file.file());
+            while let Some(row) = read_values(&mut reader)? {
+                aggregator.push(&row)?;
+            }
+            aggregator.finish(emit)?;
+        }
+        Ok(())
+    }
+
+    fn partition_of(&self, key: &GroupKey) -> usize {
+        let mut hasher = DefaultHasher::new();
+        self.level.hash(&mut hasher);
+        key.hash(&mut hasher);
+        (hasher.finish() % SPILL_PARTITIONS as u64) as usize
+    }
+}
+
+
+/// Name of the accumulator an aggregate call runs on.
+pub fn call_name(call: &Expr) -> &str {
+    match call {
+        Expr::Function { name, .. } if name == "string_agg" => "group_concat",
+        Expr::Function { name, .. } => name,
+        _ => "",
+    }
+}
+
+fn is_distinct(call: &Expr) -> bool {
+    matches!(call, Expr::Function { distinct: true, .. })
+}
+
+fn first_arg(call: &Expr) -> Option<&Expr> {
+    match call {
+        Expr::Function { args: FunctionArgs::List(args), .. } => args.first(),
+        _ => None,
+    }
+}
+
+fn call_args(call: &Expr, ctx: &EvalContext) -> Result<Vec<Value>, Box<dyn Error>> {
+    match call {
+        Expr::Function { args: FunctionArgs::List(args), .. } => args.iter().map(|arg| ctx.eval(arg)).collect(),
+        _ => Ok(vec![]),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Ordering;
+    use crate::sql::parse;
+    use crate::sql::ast::{ResultColumn, Statement};
+
+    /// Runs `SELECT <columns> FROM t GROUP BY <group_by>` over rows of (a, b) and returns
+    /// each group's aggregate values, ordered by group key.
+    fn aggregate(columns: &str, group_by: &str, rows: &[[Value; 2]], memory_budget: usize) -> Vec<Vec<Value>> {
+        let group_clause = if group_by.is_empty() { String::new() } else { format!(" GROUP BY {}", group_by) };
+        let Statement::Select(select) = parse(&format!("SELECT {} FROM t{}", columns, group_clause)).unwrap() else {
+            panic!()
+        };
+        let mut calls = Vec::new();
+        for column in &select.columns {
+            let ResultColumn::Expr { expr, .. } = column else { panic!() };
+            collect_aggregates(expr, &mut calls).unwrap();
+        }
+
+        let scope = Scope::for_table("t", &["a".to_string(), "b".to_string()]);
+        let mut aggregator = Aggregator::new(&scope, &select.group_by, &calls, memory_budget, TextEncoding::Utf8, Clock::System).unwrap();
+        for row in rows {
+            aggregator.push(row).unwrap();
+        }
+        let mut groups = Vec::new();
+        aggregator
+            .finish(&mut |group| {
+                groups.push((group.key, group.aggregates.into_iter().map(|(_, v)| v).collect::<Vec<_>>()));
+                Ok(())
+            })
+            .unwrap();
+        groups.sort_by(|(a, _), (b, _)| a.iter().zip(b).map(|(x, y)| x.compare(y)).find(|o| o.is_ne()).unwrap_or(Ordering::Equal));
+        groups.into_iter().map(|(_, values)| values).collect()
+    }
+
+    #[test]
+    fn test_aggregate_functions() {
+        let rows = [
+            [Value::Integer(1), Value::Integer(2)],
+            [Value::Integer(1), Value::Real(3.5)],
+            [Value::Null, Value::Text("5".into())],
+            [Value::Integer(2), Value::Null],
+        ];
+        let result = aggregate(
+            "count(*), count(b), count(DISTINCT a), sum(a), total(b), avg(b), min(b), max(b), group_concat(a, '-')",
+            "",
+            &rows,
+            usize::MAX,
+        );
+        assert_eq!(
+            result,
+            [[
+                Value::Integer(4),
+                Value::Integer(3),
+                Value::Integer(2),
+                Value::Integer(4),
+                Value::Real(10.5),
+                Value::Real(3.5),
+                Value::Integer(2),
+                Value::Text("5".into()),
+                Value::Text("1-1-2".into()),
+            ]]
+        );
+
+        // no rows and no GROUP BY still gives one group
+        let empty = aggregate("count(*), sum(a), total(a), group_concat(a)", "", &[], usize::MAX);
+        assert_eq!(empty, [[Value::Integer(0), Value::Null, Value::Real(0.0), Value::Null]]);
+    }
+
+    #[test]
+    fn test_aggregate_groups_spill() {
+        let rows: Vec<[Value; 2]> = (0..3000).map(|i| [Value::Integer(i % 500), Value::Integer(i)]).collect();
+        // a budget of a few groups pushes most of them through the partition files
+        let result = aggregate("count(*), sum(b)", "a", &rows, 4000);
+        assert_eq!(result.len(), 500);
+        for (a, values) in result.iter().enumerate() {
+            let expected: i64 = (0..6).map(|k| a as i64 + 500 * k).sum();
+            assert_eq!(values, &[Value::Integer(6), Value::Integer(expected)]);
+        }
+    }
+
+    #[test]
+    fn test_integral_reals_group_with_integers() {
+        // every whole real below 2^63 is in the group of the equal integer, however large
+        let rows: Vec<[Value; 2]> = [Value::Integer(9_200_000_000_000_000_000), Value::Real(9.2e18), Value::Real(-0.0), Value::Integer(0)]
+            .into_iter()
+            .map(|a| [a, Value::Null])
+            .collect();
+        assert_eq!(aggregate("count(*)", "a", &rows, usize::MAX), [[Value::Integer(2)], [Value::Integer(2)]]);
+    }
+}
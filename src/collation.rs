@@ -0,0 +1,202 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+
+use crate::encoding::TextEncoding;
+use crate::value::Value;
+
+
+/// Compares two TEXT values for a registered collating sequence.
+pub type CompareFn = dyn Fn(&str, &str) -> Ordering + Send + Sync;
+
+/// Collations added with [`register`], looked up by name, case-insensitively.
+static REGISTRY: RwLock<Vec<(String, Arc<CompareFn>)>> = RwLock::new(Vec::new());
+
+/// Makes a collating sequence available to COLLATE clauses and column definitions under `name`.
+/// Registrations are never replaced: a name already taken, built in or registered, is an error.
+pub fn register(name: &str, compare: impl Fn(&str, &str) -> Ordering + Send + Sync + 'static) -> Result<(), Box<dyn Error>> {
+    if Collation::builtin(name).is_some() {
+        return Err(format!("cannot replace built-in collation {}", name).into());
+    }
+    let mut registry = REGISTRY.write().map_err(|_| "collation registry poisoned")?;
+    if registry.iter().any(|(registered, _)| registered.eq_ignore_ascii_case(name)) {
+        return Err(format!("collation {} is already registered", name).into());
+    }
+    registry.push((name.to_string(), Arc::new(compare)));
+    Ok(())
+}
+
+/// Case-insensitive for all of Unicode rather than ASCII alone, unlike NOCASE.
+pub fn unicode_nocase(a: &str, b: &str) -> Ordering {
+    a.chars().flat_map(char::to_lowercase).cmp(b.chars().flat_map(char::to_lowercase))
+}
+
+
+/// Collating sequences. They only change how two TEXT values compare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collation {
+    /// bytewise
+    #[default]
+    Binary,
+    /// ASCII letters compare case-insensitively
+    NoCase,
+    /// trailing spaces are ignored
+    RTrim,
+    /// a registered collation, by its place in the registry
+    Custom(usize),
+}
+
+impl Collation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::builtin(name).or_else(|| {
+            let registry = REGISTRY.read().ok()?;
+            registry.iter().position(|(registered, _)| registered.eq_ignore_ascii_case(name)).map(Collation::Custom)
+        })
+    }
+
+    /// Like `from_name`, with SQLite's error for an unknown name.
+    pub fn named(name: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_name(name).ok_or_else(|| format!("no such collation sequence: {}", name).into())
+    }
+
+    fn builtin(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "BINARY" => Some(Collation::Binary),
+            "NOCASE" => Some(Collation::NoCase),
+            "RTRIM" => Some(Collation::RTrim),
+            _ => None,
+        }
+    }
+
+    /// Compares two values, text as it is stored in `encoding`, which BINARY depends on.
+    pub fn compare(&self, a: &Value, b: &Value, encoding: TextEncoding) -> Ordering {
+        match (a, b) {
+            (Value::Text(a), Value::Text(b)) => self.compare_text(a, b, encoding),
+            _ => a.compare(b),
+        }
+    }
+
+    fn compare_text(&self, a: &str, b: &str, encoding: TextEncoding) -> Ordering {
+        match self {
+            Collation::Binary => encoding.compare(a, b),
+            Collation::NoCase => {
+                let a = a.bytes().map(|c| c.to_ascii_lowercase());
+                let b = b.bytes().map(|c| c.to_ascii_lowercase());
+                a.cmp(b)
+            }
+            Collation::RTrim => a.trim_end_matches(' ').cmp(b.trim_end_matches(' ')),
+            Collation::Custom(index) => {
+                let compare = REGISTRY.read().ok().and_then(|registry| registry.get(*index).map(|(_, f)| f.clone()));
+                match compare {
+                    Some(compare) => compare(a, b),
+                    None => a.cmp(b),
+                }
+            }
+        }
+    }
+}
+
+
+/// Turns values into hash keys that are equal exactly when the values compare equal under
+/// their collations. Registered collations only come with a comparison, so their texts map
+/// to the first equal text seen.
+#[derive(Debug, Default)]
+pub struct KeyFolder {
+    collations: Vec<Collation>,
+    seen: Vec<BTreeSet<Collated>>,
+}
+
+impl KeyFolder {
+    /// A folder for keys whose values are compared with `collations`, in order.
+    pub fn new(collations: Vec<Collation>) -> Self {
+        let seen = collations.iter().map(|_| BTreeSet::new()).collect();
+        KeyFolder { collations, seen }
+    }
+
+    pub fn fold(&mut self, mut values: Vec<Value>) -> Vec<Value> {
+        for ((value, collation), seen) in values.iter_mut().zip(&self.collations).zip(&mut self.seen) {
+            let Value::Text(text) = value else { continue };
+            match collation {
+                Collation::Binary => {}
+                Collation::NoCase => text.make_ascii_lowercase(),
+                Collation::RTrim => text.truncate(text.trim_end_matches(' ').len()),
+                Collation::Custom(_) => {
+                    let collated = Collated(*collation, std::mem::take(text));
+                    *text = match seen.get(&collated) {
+                        Some(first) => first.1.clone(),
+                        None => {
+                            let first = collated.1.clone();
+                            seen.insert(collated);
+                            first
+                        }
+                    };
+                }
+            }
+        }
+        values
+    }
+}
+
+/// A text ordered by its registered collation, which sees the text and not how it is stored.
+#[derive(Debug)]
+struct Collated(Collation, String);
+
+impl Ord for Collated {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.compare_text(&self.1, &other.1, TextEncoding::Utf8)
+    }
+}
+
+impl PartialOrd for Collated {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Collated {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Collated {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UTF8: TextEncoding = TextEncoding::Utf8;
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_collations() {
+        assert_eq!(Collation::Binary.compare(&text("a"), &text("B"), UTF8), Ordering::Greater);
+        assert_eq!(Collation::Binary.compare(&text("a"), &text("ā"), TextEncoding::Utf16Le), Ordering::Greater);
+        assert_eq!(Collation::NoCase.compare(&text("a"), &text("B"), UTF8), Ordering::Less);
+        assert_eq!(Collation::NoCase.compare(&text("ABC"), &text("abc"), UTF8), Ordering::Equal);
+        assert_eq!(Collation::RTrim.compare(&text("abc  "), &text("abc"), UTF8), Ordering::Equal);
+        assert_eq!(Collation::RTrim.compare(&text(" abc"), &text("abc"), UTF8), Ordering::Less);
+        // numbers are unaffected
+        assert_eq!(Collation::NoCase.compare(&Value::Integer(2), &text("1"), UTF8), Ordering::Less);
+    }
+
+    #[test]
+    fn test_registered_collation() {
+        register("test_length", |a, b| a.len().cmp(&b.len())).unwrap();
+        assert!(register("nocase", |a, b| a.cmp(b)).is_err());
+        assert!(register("TEST_LENGTH", |a, b| a.cmp(b)).is_err());
+        let length = Collation::from_name("TEST_LENGTH").unwrap();
+        assert_eq!(length.compare(&text("bb"), &text("aaa"), UTF8), Ordering::Less);
+        assert_eq!(length.compare(&text("ab"), &text("cd"), UTF8), Ordering::Equal);
+        assert_eq!(unicode_nocase("ÉTÉ", "été"), Ordering::Equal);
+
+        let mut folder = KeyFolder::new(vec![length, Collation::NoCase, Collation::Binary]);
+        assert_eq!(folder.fold(vec![text("ab"), text("X"), text("Y")]), [text("ab"), text("x"), text("Y")]);
+        assert_eq!(folder.fold(vec![text("cd"), text("x"), text("y")]), [text("ab"), text("x"), text("y")]);
+    }
+}
@@ -0,0 +1,1234 @@
+use std::error::Error;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::encoding::TextEncoding;
+use crate::functions::printf;
+use crate::value::Value;
+
+
+/// 1970-01-01 00:00:00 as Julian day milliseconds.
+const UNIX_EPOCH_JD: i64 = 210_866_760_000_000;
+
+/// 9999-12-31 23:59:59.999, the last moment the date functions handle.
+const MAX_JD: i64 = 464_269_060_799_999;
+
+const DAY_MS: i64 = 86_400_000;
+
+/// Units of the '+N unit' modifiers: name, largest magnitude accepted and length in seconds.
+const UNITS: [(&str, f64, f64); 6] = [
+    ("second", 4.6427e14, 1.0),
+    ("minute", 7.7379e12, 60.0),
+    ("hour", 1.2897e11, 3600.0),
+    ("day", 5373485.0, 86400.0),
+    ("month", 176546.0, 30.0 * 86400.0),
+    ("year", 14713.0, 365.0 * 86400.0),
+];
+
+
+/// Where 'now' comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Clock {
+    #[default]
+    System,
+    /// a fixed instant in Julian day milliseconds, for reproducible results
+    Fixed(i64),
+}
+
+impl Clock {
+    /// A fixed clock from a unix timestamp or a date and time such as '2024-01-15 10:30:00' (UTC).
+    pub fn parse(text: &str) -> Option<Clock> {
+        if let Some(seconds) = parse_number(text.as_bytes()) {
+            return Some(Clock::Fixed(UNIX_EPOCH_JD + (seconds * 1000.0).round() as i64));
+        }
+        let mut date = DateTime::default();
+        if !date.parse_date(text.as_bytes()) && !date.parse_time(text.as_bytes()) {
+            return None;
+        }
+        date.compute_jd();
+        (!date.error && valid_jd(date.jd)).then_some(Clock::Fixed(date.jd))
+    }
+
+    /// The clock for a new statement: the system clock is read once, so that like SQLite,
+    /// 'now' stays the same for the whole statement.
+    pub fn start_statement(self) -> Clock {
+        Clock::Fixed(self.now())
+    }
+
+    /// The current time in Julian day milliseconds.
+    fn now(self) -> i64 {
+        match self {
+            Clock::System => {
+                let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                UNIX_EPOCH_JD + elapsed.as_millis() as i64
+            }
+            Clock::Fixed(jd) => jd,
+        }
+    }
+}
+
+/// date(), time(), datetime(), julianday(), unixepoch(), strftime() and timediff().
+/// Anything that is not a valid date, time or modifier gives NULL. Blobs are read as `encoding`
+/// text and 'now' is read from `clock`.
+pub fn call(name: &str, args: &[Value], encoding: TextEncoding, clock: Clock) -> Result<Value, Box<dyn Error>> {
+    let text = |s: Option<String>| s.map_or(Value::Null, Value::Text);
+    match name {
+        "strftime" => match args.split_first() {
+            Some((format, rest)) if !format.is_null() => match DateTime::from_args(rest, encoding, clock) {
+                Some(mut date) => Ok(text(date.strftime(&format.to_text(encoding))?)),
+                None => Ok(Value::Null),
+            },
+            _ => Ok(Value::Null),
+        },
+        "timediff" => Ok(text(timediff(&args[0], &args[1], encoding, clock))),
+        _ => {
+            let Some(mut date) = DateTime::from_args(args, encoding, clock) else { return Ok(Value::Null) };
+            Ok(match name {
+                "date" => Value::Text(date.date_text()),
+                "time" => Value::Text(date.time_text()),
+                "datetime" => Value::Text(format!("{} {}", date.date_text(), date.time_text())),
+                "julianday" => Value::Real(date.jd as f64 / DAY_MS as f64),
+                "unixepoch" if date.subsec => Value::Real((date.jd - UNIX_EPOCH_JD) as f64 / 1000.0),
+                "unixepoch" => Value::Integer(date.jd / 1000 - UNIX_EPOCH_JD / 1000),
+                _ => unreachable!("no date function {}", name),
+            })
+        }
+    }
+}
+
+
+/// A date and time being parsed and modified. Like SQLite it is kept as a Julian day number,
+/// as year, month and day, and as hours, minutes and seconds, each computed from the others when needed.
+#[derive(Debug, Clone, Default)]
+struct DateTime {
+    /// milliseconds since noon at Greenwich on November 24, 4714 B.C.
+    jd: i64,
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: f64,
+    /// timezone offset in minutes
+    tz: i64,
+    valid_jd: bool,
+    valid_ymd: bool,
+    valid_hms: bool,
+    /// a bare number, either a Julian day or, after 'unixepoch', a unix time
+    raw: Option<f64>,
+    error: bool,
+    /// show milliseconds
+    subsec: bool,
+    utc: bool,
+    local: bool,
+    /// days past the end of the month after adding months or years, undone by 'floor'
+    overflow: i64,
+}
+
+fn valid_jd(jd: i64) -> bool {
+    (0..=MAX_JD).contains(&jd)
+}
+
+impl DateTime {
+    /// The time value and modifiers of a call; no arguments means now.
+    fn from_args(args: &[Value], encoding: TextEncoding, clock: Clock) -> Option<Self> {
+        let mut date = DateTime::default();
+        let Some((first, modifiers)) = args.split_first() else {
+            date.set_now(clock);
+            return Some(date);
+        };
+        match first {
+            Value::Null => return None,
+            Value::Integer(_) | Value::Real(_) => date.set_raw(first.to_f64(encoding)),
+            value => {
+                if !date.parse(&value.to_text(encoding), clock) {
+                    return None;
+                }
+            }
+        }
+        for (i, modifier) in modifiers.iter().enumerate() {
+            if modifier.is_null() || !date.modify(&modifier.to_text(encoding), i + 1) {
+                return None;
+            }
+        }
+        date.compute_jd();
+        if date.error || !valid_jd(date.jd) {
+            return None;
+        }
+        // recompute a day past the end of the month: 2023-02-31 is 2023-03-03
+        if args.len() == 1 && date.valid_ymd && date.day > 28 {
+            date.valid_ymd = false;
+        }
+        Some(date)
+    }
+
+    fn set_now(&mut self, clock: Clock) {
+        *self = DateTime { jd: clock.now(), valid_jd: true, utc: true, ..DateTime::default() };
+    }
+
+    fn set_raw(&mut self, r: f64) {
+        self.raw = Some(r);
+        if (0.0..5373484.5).contains(&r) {
+            self.jd = (r * DAY_MS as f64 + 0.5) as i64;
+            self.valid_jd = true;
+        }
+    }
+
+    fn set_error(&mut self) {
+        *self = DateTime { error: true, ..DateTime::default() };
+    }
+
+    fn clear_ymd_hms_tz(&mut self) {
+        self.valid_ymd = false;
+        self.valid_hms = false;
+        self.tz = 0;
+    }
+
+    /// YYYY-MM-DD [HH:MM[:SS[.SSS]]], HH:MM[:SS[.SSS]], 'now' or a Julian day number.
+    fn parse(&mut self, text: &str, clock: Clock) -> bool {
+        let bytes = text.as_bytes();
+        if self.parse_date(bytes) || self.parse_time(bytes) {
+            return true;
+        }
+        if text.eq_ignore_ascii_case("now") {
+            self.set_now(clock);
+            return true;
+        }
+        if let Some(r) = parse_number(bytes) {
+            self.set_raw(r);
+            return true;
+        }
+        if text.eq_ignore_ascii_case("subsec") || text.eq_ignore_ascii_case("subsecond") {
+            self.set_now(clock);
+            self.subsec = true;
+            return true;
+        }
+        false
+    }
+
+    /// [-]YYYY-MM-DD, optionally followed by spaces or 'T' and a time.
+    fn parse_date(&mut self, s: &[u8]) -> bool {
+        let (negative, s) = match s.strip_prefix(b"-") {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (Some(year), Some(month), Some(day)) = (field(s, 0, 4, 0, 9999), field(s, 5, 2, 1, 12), field(s, 8, 2, 1, 31))
+        else {
+            return false;
+        };
+        if s[4] != b'-' || s[7] != b'-' {
+            return false;
+        }
+        let mut rest = &s[10..];
+        while let [c, tail @ ..] = rest
+            && (c.is_ascii_whitespace() || *c == b'T')
+        {
+            rest = tail;
+        }
+        if !self.parse_time(rest) {
+            if !rest.is_empty() {
+                return false;
+            }
+            self.valid_hms = false;
+        }
+        self.valid_jd = false;
+        self.valid_ymd = true;
+        self.year = if negative { -year } else { year };
+        self.month = month;
+        self.day = day;
+        self.compute_overflow();
+        if self.tz != 0 {
+            self.compute_jd();
+        }
+        true
+    }
+
+    /// HH:MM[:SS[.SSS]] followed by an optional timezone.
+    fn parse_time(&mut self, s: &[u8]) -> bool {
+        let (Some(hour), Some(minute)) = (field(s, 0, 2, 0, 24), field(s, 3, 2, 0, 59)) else {
+            return false;
+        };
+        if s[2] != b':' {
+            return false;
+        }
+        let mut rest = &s[5..];
+        let mut second = 0.0;
+        if let Some(after) = rest.strip_prefix(b":") {
+            let Some(whole) = field(after, 0, 2, 0, 59) else { return false };
+            second = whole as f64;
+            rest = &after[2..];
+            if let [b'.', digit, ..] = rest
+                && digit.is_ascii_digit()
+            {
+                let digits = rest[1..].iter().take_while(|c| c.is_ascii_digit()).count();
+                let mut fraction = 0.0;
+                let mut scale = 1.0;
+                for &d in &rest[1..1 + digits] {
+                    fraction = fraction * 10.0 + (d - b'0') as f64;
+                    scale *= 10.0;
+                }
+                // truncated, so that rounding never reaches the next second
+                second += (fraction / scale).min(0.999);
+                rest = &rest[1 + digits..];
+            }
+        }
+        self.valid_jd = false;
+        self.raw = None;
+        self.valid_hms = true;
+        self.hour = hour;
+        self.minute = minute;
+        self.second = second;
+        self.parse_timezone(rest)
+    }
+
+    /// [spaces] then nothing, 'Z' or ±HH:MM.
+    fn parse_timezone(&mut self, s: &[u8]) -> bool {
+        let s = s.trim_ascii_start();
+        self.tz = 0;
+        let rest = match s.first() {
+            None => return true,
+            Some(b'Z' | b'z') => {
+                self.local = false;
+                self.utc = true;
+                &s[1..]
+            }
+            Some(&sign @ (b'+' | b'-')) => {
+                let s = &s[1..];
+                let (Some(hours), Some(minutes)) = (field(s, 0, 2, 0, 14), field(s, 3, 2, 0, 59)) else {
+                    return false;
+                };
+                if s[2] != b':' {
+                    return false;
+                }
+                self.tz = (hours * 60 + minutes) * if sign == b'-' { -1 } else { 1 };
+                &s[5..]
+            }
+            Some(_) => return false,
+        };
+        rest.trim_ascii_start().is_empty()
+    }
+
+    fn compute_jd(&mut self) {
+        if self.valid_jd {
+            return;
+        }
+        let (mut year, mut month, day) =
+            if self.valid_ymd { (self.year, self.month, self.day) } else { (2000, 1, 1) };
+        if !(-4713..=9999).contains(&year) || self.raw.is_some() {
+            self.set_error();
+            return;
+        }
+        if month <= 2 {
+            year -= 1;
+            month += 12;
+        }
+        let a = year / 100;
+        let b = 2 - a + a / 4;
+        let x1 = 36525 * (year + 4716) / 100;
+        let x2 = 306001 * (month + 1) / 10000;
+        self.jd = (((x1 + x2 + day + b) as f64 - 1524.5) * DAY_MS as f64) as i64;
+        self.valid_jd = true;
+        if self.valid_hms {
+            self.jd += self.hour * 3_600_000 + self.minute * 60_000 + (self.second * 1000.0 + 0.5) as i64;
+            if self.tz != 0 {
+                self.jd -= self.tz * 60_000;
+                self.valid_ymd = false;
+                self.valid_hms = false;
+                self.tz = 0;
+                self.utc = true;
+                self.local = false;
+            }
+        }
+    }
+
+    fn compute_ymd(&mut self) {
+        if self.valid_ymd {
+            return;
+        }
+        if !self.valid_jd {
+            (self.year, self.month, self.day) = (2000, 1, 1);
+        } else if !valid_jd(self.jd) {
+            self.set_error();
+            return;
+        } else {
+            let z = (self.jd + DAY_MS / 2) / DAY_MS;
+            let a = ((z as f64 - 1867216.25) / 36524.25) as i64;
+            let a = z + 1 + a - a / 4;
+            let b = a + 1524;
+            let c = ((b as f64 - 122.1) / 365.25) as i64;
+            let d = (36525 * (c & 32767)) / 100;
+            let e = ((b - d) as f64 / 30.6001) as i64;
+            let x1 = (30.6001 * e as f64) as i64;
+            self.day = b - d - x1;
+            self.month = if e < 14 { e - 1 } else { e - 13 };
+            self.year = if self.month > 2 { c - 4716 } else { c - 4715 };
+        }
+        self.valid_ymd = true;
+    }
+
+    fn compute_hms(&mut self) {
+        if self.valid_hms {
+            return;
+        }
+        self.compute_jd();
+        let day_ms = (self.jd + DAY_MS / 2) % DAY_MS;
+        self.second = (day_ms % 60_000) as f64 / 1000.0;
+        let day_minutes = day_ms / 60_000;
+        self.minute = day_minutes % 60;
+        self.hour = day_minutes / 60;
+        self.raw = None;
+        self.valid_hms = true;
+    }
+
+    fn compute_ymd_hms(&mut self) {
+        self.compute_ymd();
+        self.compute_hms();
+    }
+
+    /// How far the day is past the end of its month.
+    fn compute_overflow(&mut self) {
+        let days_in_month = match self.month {
+            2 if self.year % 4 != 0 || (self.year % 100 == 0 && self.year % 400 != 0) => 28,
+            2 => 29,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+        self.overflow = (self.day - days_in_month).max(0);
+    }
+
+    /// Applies the modifier at argument `position`; false when it is not valid.
+    fn modify(&mut self, modifier: &str, position: usize) -> bool {
+        let lower = modifier.to_ascii_lowercase();
+        match lower.as_str() {
+            // these only apply to the time value itself
+            "auto" | "julianday" | "unixepoch" if position > 1 => false,
+            "auto" => {
+                match self.raw {
+                    Some(r) if !self.valid_jd => {
+                        // a unix time between -4713-11-24 12:00:00 and 9999-12-31 23:59:59
+                        if !(-210_866_760_000.0..=253_402_300_799.0).contains(&r) {
+                            return true;
+                        }
+                        self.clear_ymd_hms_tz();
+                        self.jd = (r * 1000.0 + UNIX_EPOCH_JD as f64 + 0.5) as i64;
+                        self.valid_jd = true;
+                        self.raw = None;
+                    }
+                    _ => self.raw = None,
+                }
+                true
+            }
+            "julianday" => {
+                if self.valid_jd && self.raw.is_some() {
+                    self.raw = None;
+                    return true;
+                }
+                false
+            }
+            "unixepoch" => {
+                let Some(r) = self.raw else { return false };
+                let jd = r * 1000.0 + UNIX_EPOCH_JD as f64;
+                if !(0.0..(MAX_JD + 1) as f64).contains(&jd) {
+                    return false;
+                }
+                self.clear_ymd_hms_tz();
+                self.jd = (jd + 0.5) as i64;
+                self.valid_jd = true;
+                self.raw = None;
+                true
+            }
+            "localtime" => {
+                if !self.local {
+                    self.convert_to_local();
+                }
+                self.utc = false;
+                self.local = true;
+                true
+            }
+            "utc" => {
+                if !self.utc {
+                    self.convert_to_utc();
+                }
+                true
+            }
+            "ceiling" => {
+                self.compute_jd();
+                self.clear_ymd_hms_tz();
+                self.overflow = 0;
+                true
+            }
+            "floor" => {
+                self.compute_jd();
+                self.jd -= self.overflow * DAY_MS;
+                self.clear_ymd_hms_tz();
+                true
+            }
+            "subsec" | "subsecond" => {
+                self.subsec = true;
+                true
+            }
+            _ if lower.starts_with("weekday ") => self.weekday(&lower[8..]),
+            _ if lower.starts_with("start of ") => self.start_of(&lower[9..]),
+            _ if lower.starts_with(|c: char| c == '+' || c == '-' || c.is_ascii_digit()) => self.shift(modifier.as_bytes()),
+            _ => false,
+        }
+    }
+
+    /// 'weekday N' moves forward to the next day that is weekday N, 0 being Sunday.
+    fn weekday(&mut self, n: &str) -> bool {
+        let Some(n) = parse_number(n.as_bytes()).filter(|n| (0.0..7.0).contains(n) && n.fract() == 0.0) else {
+            return false;
+        };
+        let n = n as i64;
+        self.compute_ymd_hms();
+        self.tz = 0;
+        self.valid_jd = false;
+        self.compute_jd();
+        let mut weekday = ((self.jd + 129_600_000) / DAY_MS) % 7;
+        if weekday > n {
+            weekday -= 7;
+        }
+        self.jd += (n - weekday) * DAY_MS;
+        self.clear_ymd_hms_tz();
+        true
+    }
+
+    /// 'start of day', 'start of month' and 'start of year'.
+    fn start_of(&mut self, unit: &str) -> bool {
+        if !self.valid_jd && !self.valid_ymd && !self.valid_hms {
+            return false;
+        }
+        self.compute_ymd();
+        self.valid_hms = true;
+        (self.hour, self.minute, self.second) = (0, 0, 0.0);
+        self.raw = None;
+        self.tz = 0;
+        self.valid_jd = false;
+        match unit {
+            "day" => true,
+            "month" => {
+                self.day = 1;
+                true
+            }
+            "year" => {
+                self.month = 1;
+                self.day = 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// '±N unit[s]', '±HH:MM[:SS[.SSS]]' and '±YYYY-MM-DD[ HH:MM[:SS[.SSS]]]'.
+    fn shift(&mut self, z: &[u8]) -> bool {
+        let sign = z[0];
+        // the number ends at a colon, a space or the dash after a year
+        let mut n = 1;
+        while n < z.len() {
+            match z[n] {
+                b':' => break,
+                c if c.is_ascii_whitespace() => break,
+                b'-' if (n == 5 || n == 6) && field(&z[1..], 0, n - 1, 0, 9999).is_some() => break,
+                _ => {}
+            }
+            n += 1;
+        }
+        let Some(mut r) = parse_number(&z[..n]) else { return false };
+
+        let mut time = (z.get(n) == Some(&b':')).then_some(z);
+        if z.get(n) == Some(&b'-') {
+            if sign != b'+' && sign != b'-' {
+                return false;
+            }
+            let date = &z[1..];
+            let width = n - 1;
+            let (Some(years), Some(months), Some(mut days)) =
+                (field(date, 0, width, 0, 9999), field(date, width + 1, 2, 0, 12), field(date, width + 4, 2, 0, 31))
+            else {
+                return false;
+            };
+            if date[width + 3] != b'-' || months >= 12 || days >= 31 {
+                return false;
+            }
+            self.compute_ymd_hms();
+            self.valid_jd = false;
+            if sign == b'-' {
+                self.year -= years;
+                self.month -= months;
+                days = -days;
+            } else {
+                self.year += years;
+                self.month += months;
+            }
+            self.normalize_month();
+            self.compute_overflow();
+            self.compute_jd();
+            self.valid_hms = false;
+            self.valid_ymd = false;
+            self.jd += days * DAY_MS;
+
+            let rest = &date[width + 6..];
+            if rest.is_empty() {
+                return true;
+            }
+            if !rest[0].is_ascii_whitespace() || field(rest, 1, 2, 0, 24).is_none() || field(rest, 4, 2, 0, 59).is_none() {
+                return false;
+            }
+            time = Some(&rest[1..]);
+        }
+
+        if let Some(time) = time {
+            let time = if time[0].is_ascii_digit() { time } else { &time[1..] };
+            let mut offset = DateTime::default();
+            if !offset.parse_time(time) {
+                return false;
+            }
+            offset.compute_jd();
+            offset.jd -= DAY_MS / 2;
+            offset.jd -= offset.jd / DAY_MS * DAY_MS;
+            if sign == b'-' {
+                offset.jd = -offset.jd;
+            }
+            self.compute_jd();
+            self.clear_ymd_hms_tz();
+            self.jd += offset.jd;
+            return true;
+        }
+
+        // N units
+        let unit = z[n..].trim_ascii_start();
+        if !(3..=10).contains(&unit.len()) {
+            return false;
+        }
+        let unit = String::from_utf8_lossy(unit).to_ascii_lowercase();
+        let unit = unit.strip_suffix('s').unwrap_or(&unit);
+        self.compute_jd();
+        let rounder = if r < 0.0 { -0.5 } else { 0.5 };
+        self.overflow = 0;
+        let Some(&(name, _, seconds)) = UNITS.iter().find(|(name, limit, _)| *name == unit && r.abs() < *limit) else {
+            self.clear_ymd_hms_tz();
+            return false;
+        };
+        if name == "month" || name == "year" {
+            self.compute_ymd_hms();
+            if name == "month" {
+                self.month += r as i64;
+                self.normalize_month();
+            } else {
+                self.year += r as i64;
+            }
+            self.compute_overflow();
+            self.valid_jd = false;
+            r -= r.trunc();
+        }
+        self.compute_jd();
+        self.jd += (r * 1000.0 * seconds + rounder) as i64;
+        self.clear_ymd_hms_tz();
+        true
+    }
+
+    /// Brings the month back into 1..=12, carrying into the year.
+    fn normalize_month(&mut self) {
+        let carry = if self.month > 0 { (self.month - 1) / 12 } else { (self.month - 12) / 12 };
+        self.year += carry;
+        self.month -= carry * 12;
+    }
+
+    /// Treats the time as UTC and converts it to local time.
+    fn convert_to_local(&mut self) {
+        self.compute_jd();
+        // outside 1970..2038 use a year with the same leap years and weekdays, as SQLite does
+        let (unix, year_shift) = if !(UNIX_EPOCH_JD..=213_014_145_600_000).contains(&self.jd) {
+            let mut shifted = self.clone();
+            shifted.compute_ymd_hms();
+            let shift = (2000 + shifted.year % 4) - shifted.year;
+            shifted.year += shift;
+            shifted.valid_jd = false;
+            shifted.compute_jd();
+            (shifted.jd / 1000 - UNIX_EPOCH_JD / 1000, shift)
+        } else {
+            (self.jd / 1000 - UNIX_EPOCH_JD / 1000, 0)
+        };
+        let local_seconds = unix + local_zone().offset(unix);
+        let mut local =
+            DateTime { jd: local_seconds * 1000 + UNIX_EPOCH_JD, valid_jd: true, ..DateTime::default() };
+        local.compute_ymd_hms();
+
+        self.year = local.year - year_shift;
+        self.month = local.month;
+        self.day = local.day;
+        self.hour = local.hour;
+        self.minute = local.minute;
+        self.second = local.second + (self.jd % 1000) as f64 * 0.001;
+        self.valid_ymd = true;
+        self.valid_hms = true;
+        self.valid_jd = false;
+        self.raw = None;
+        self.tz = 0;
+        self.error = false;
+    }
+
+    /// Treats the time as local time and converts it to UTC, by guessing until the
+    /// local time of the guess matches.
+    fn convert_to_utc(&mut self) {
+        self.compute_jd();
+        let original = self.jd;
+        let mut guess = original;
+        let mut error = 0;
+        for _ in 0..4 {
+            guess -= error;
+            let mut local = DateTime { jd: guess, valid_jd: true, ..DateTime::default() };
+            local.convert_to_local();
+            local.compute_jd();
+            error = local.jd - original;
+            if error == 0 {
+                break;
+            }
+        }
+        *self = DateTime { jd: guess, valid_jd: true, utc: true, ..DateTime::default() };
+    }
+
+    fn date_text(&mut self) -> String {
+        self.compute_ymd();
+        let sign = if self.year < 0 { "-" } else { "" };
+        format!("{}{:04}-{:02}-{:02}", sign, self.year.abs(), self.month, self.day)
+    }
+
+    fn time_text(&mut self) -> String {
+        self.compute_hms();
+        if self.subsec {
+            let ms = (self.second * 1000.0 + 0.5) as i64;
+            format!("{:02}:{:02}:{:02}.{:03}", self.hour, self.minute, ms / 1000, ms % 1000)
+        } else {
+            format!("{:02}:{:02}:{:02}", self.hour, self.minute, self.second as i64)
+        }
+    }
+
+    fn days_after_jan_01(&self) -> i64 {
+        let mut jan_01 = DateTime { valid_jd: false, month: 1, day: 1, ..self.clone() };
+        jan_01.compute_jd();
+        (self.jd - jan_01.jd + DAY_MS / 2) / DAY_MS
+    }
+
+    fn days_after_monday(&self) -> i64 {
+        ((self.jd + DAY_MS / 2) / DAY_MS) % 7
+    }
+
+    fn days_after_sunday(&self) -> i64 {
+        ((self.jd + 3 * DAY_MS / 2) / DAY_MS) % 7
+    }
+
+    /// The Thursday of the same ISO week, whose year is the ISO year.
+    fn iso_thursday(&self) -> DateTime {
+        let mut thursday = self.clone();
+        thursday.jd += (3 - self.days_after_monday()) * DAY_MS;
+        thursday.valid_ymd = false;
+        thursday.compute_ymd();
+        thursday
+    }
+
+    /// Formats the time with strftime() conversions; an unknown one gives `None`.
+    fn strftime(&mut self, format: &str) -> Result<Option<String>, Box<dyn Error>> {
+        self.compute_jd();
+        self.compute_ymd_hms();
+        let mut out = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            let piece = match chars.next() {
+                Some('d') => format!("{:02}", self.day),
+                Some('e') => format!("{:2}", self.day),
+                Some('f') => format!("{:06.3}", self.second.min(59.999)),
+                Some('F') => format!("{:04}-{:02}-{:02}", self.year, self.month, self.day),
+                Some('g') => format!("{:02}", self.iso_thursday().year % 100),
+                Some('G') => format!("{:04}", self.iso_thursday().year),
+                Some('H') => format!("{:02}", self.hour),
+                Some('k') => format!("{:2}", self.hour),
+                Some(c @ ('I' | 'l')) => {
+                    let hour = match self.hour {
+                        0 => 12,
+                        h if h > 12 => h - 12,
+                        h => h,
+                    };
+                    if c == 'I' { format!("{:02}", hour) } else { format!("{:2}", hour) }
+                }
+                Some('j') => format!("{:03}", self.days_after_jan_01() + 1),
+                Some('J') => printf("%.16g", &[Value::Real(self.jd as f64 / DAY_MS as f64)], TextEncoding::Utf8)?,
+                Some('m') => format!("{:02}", self.month),
+                Some('M') => format!("{:02}", self.minute),
+                Some('p') => if self.hour >= 12 { "PM" } else { "AM" }.to_string(),
+                Some('P') => if self.hour >= 12 { "pm" } else { "am" }.to_string(),
+                Some('R') => format!("{:02}:{:02}", self.hour, self.minute),
+                Some('s') if self.subsec => format!("{:.3}", (self.jd - UNIX_EPOCH_JD) as f64 / 1000.0),
+                Some('s') => (self.jd / 1000 - UNIX_EPOCH_JD / 1000).to_string(),
+                Some('S') => format!("{:02}", self.second as i64),
+                Some('T') => format!("{:02}:{:02}:{:02}", self.hour, self.minute, self.second as i64),
+                Some('u') => match self.days_after_sunday() {
+                    0 => "7".to_string(),
+                    day => day.to_string(),
+                },
+                Some('w') => self.days_after_sunday().to_string(),
+                Some('U') => format!("{:02}", (self.days_after_jan_01() - self.days_after_sunday() + 7) / 7),
+                Some('V') => format!("{:02}", self.iso_thursday().days_after_jan_01() / 7 + 1),
+                Some('W') => format!("{:02}", (self.days_after_jan_01() - self.days_after_monday() + 7) / 7),
+                Some('Y') => format!("{:04}", self.year),
+                Some('%') => "%".to_string(),
+                _ => return Ok(None),
+            };
+            out.push_str(&piece);
+        }
+        Ok(Some(out))
+    }
+}
+
+/// timediff(A, B): how much later A is than B, as '±YYYY-MM-DD HH:MM:SS.SSS'.
+fn timediff(a: &Value, b: &Value, encoding: TextEncoding, clock: Clock) -> Option<String> {
+    let mut d1 = DateTime::from_args(std::slice::from_ref(a), encoding, clock)?;
+    let mut d2 = DateTime::from_args(std::slice::from_ref(b), encoding, clock)?;
+    d1.compute_ymd_hms();
+    d2.compute_ymd_hms();
+
+    let later = d1.jd >= d2.jd;
+    let (mut years, mut months) =
+        if later { (d1.year - d2.year, d1.month - d2.month) } else { (d2.year - d1.year, d2.month - d1.month) };
+    if years != 0 {
+        d2.year = d1.year;
+        d2.valid_jd = false;
+        d2.compute_jd();
+    }
+    if months < 0 {
+        years -= 1;
+        months += 12;
+    }
+    if months != 0 {
+        d2.month = d1.month;
+        d2.valid_jd = false;
+        d2.compute_jd();
+    }
+    // step d2 a month at a time until it is no longer past d1
+    while if later { d1.jd < d2.jd } else { d1.jd > d2.jd } {
+        months -= 1;
+        if months < 0 {
+            months = 11;
+            years -= 1;
+        }
+        d2.month += if later { -1 } else { 1 };
+        if d2.month < 1 {
+            d2.month = 12;
+            d2.year -= 1;
+        } else if d2.month > 12 {
+            d2.month = 1;
+            d2.year += 1;
+        }
+        d2.valid_jd = false;
+        d2.compute_jd();
+    }
+    // the remainder, as a time after 0000-01-01 00:00:00
+    let remainder = if later { d1.jd - d2.jd } else { d2.jd - d1.jd };
+    let mut rest = DateTime { jd: remainder + 148_699_540_800_000, valid_jd: true, ..DateTime::default() };
+    rest.compute_ymd_hms();
+    Some(format!(
+        "{}{:04}-{:02}-{:02} {:02}:{:02}:{:06.3}",
+        if later { '+' } else { '-' },
+        years,
+        months,
+        rest.day - 1,
+        rest.hour,
+        rest.minute,
+        rest.second
+    ))
+}
+
+/// A fixed-width number of `width` digits at `at`, within `min..=max`.
+fn field(s: &[u8], at: usize, width: usize, min: i64, max: i64) -> Option<i64> {
+    let digits = s.get(at..at + width)?;
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let n = digits.iter().fold(0, |n, d| n * 10 + (d - b'0') as i64);
+    (min..=max).contains(&n).then_some(n)
+}
+
+/// The text as a number, when all of it (but surrounding spaces) is one.
+fn parse_number(s: &[u8]) -> Option<f64> {
+    let s = std::str::from_utf8(s).ok()?.trim_ascii();
+    let unsigned = s.strip_prefix(['+', '-']).unwrap_or(s);
+    let mantissa = unsigned.split(['e', 'E']).next().unwrap_or("");
+    let valid = mantissa.bytes().any(|c| c.is_ascii_digit())
+        && mantissa.bytes().all(|c| c.is_ascii_digit() || c == b'.')
+        && unsigned.bytes().all(|c| c.is_ascii_digit() || matches!(c, b'.' | b'e' | b'E' | b'+' | b'-'));
+    if !valid {
+        return None;
+    }
+    s.parse().ok()
+}
+
+
+/// UTC offsets of the local timezone over time, from the TZif file named by `TZ` or
+/// /etc/localtime. Without one local time is UTC.
+#[derive(Debug, Default)]
+struct Zone {
+    /// (unix time the offset starts at, seconds east of UTC), in time order
+    transitions: Vec<(i64, i64)>,
+    /// the offset before the first transition
+    initial: i64,
+    /// the footer's rule for times after the last transition
+    rule: Option<PosixRule>,
+}
+
+fn local_zone() -> &'static Zone {
+    static ZONE: OnceLock<Zone> = OnceLock::new();
+    ZONE.get_or_init(|| {
+        let path = match std::env::var("TZ") {
+            Ok(tz) => {
+                let tz = tz.strip_prefix(':').unwrap_or(&tz);
+                match tz {
+                    "" => return Zone::default(),
+                    _ if tz.starts_with('/') => tz.to_string(),
+                    _ => format!("/usr/share/zoneinfo/{}", tz),
+                }
+            }
+            Err(_) => "/etc/localtime".to_string(),
+        };
+        std::fs::read(path).ok().and_then(|data| Zone::parse(&data)).unwrap_or_default()
+    })
+}
+
+impl Zone {
+    fn offset(&self, unix: i64) -> i64 {
+        let i = self.transitions.partition_point(|&(start, _)| start <= unix);
+        if i == self.transitions.len() && let Some(rule) = &self.rule {
+            return rule.offset(unix);
+        }
+        match i {
+            0 => self.initial,
+            i => self.transitions[i - 1].1,
+        }
+    }
+
+    /// Reads the transitions of a TZif file, from its 64-bit part when there is one,
+    /// and the POSIX TZ string that follows that part.
+    fn parse(data: &[u8]) -> Option<Zone> {
+        let u32_at = |at: usize| Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as usize);
+        // magic, version, 15 reserved bytes, then the counts of the sections
+        let header = |at: usize| -> Option<[usize; 6]> {
+            if data.get(at..at + 4)? != b"TZif" {
+                return None;
+            }
+            let mut counts = [0; 6];
+            for (i, count) in counts.iter_mut().enumerate() {
+                *count = u32_at(at + 20 + 4 * i)?;
+            }
+            Some(counts)
+        };
+
+        let [utc_count, std_count, leap_count, time_count, type_count, char_count] = header(0)?;
+        let (start, counts, time_size) = if *data.get(4)? >= b'2' {
+            let v1_size = time_count * 5 + type_count * 6 + char_count + leap_count * 8 + std_count + utc_count;
+            (44 + v1_size + 44, header(44 + v1_size)?, 8)
+        } else {
+            (44, [utc_count, std_count, leap_count, time_count, type_count, char_count], 4)
+        };
+        let [utc_count, std_count, leap_count, time_count, type_count, char_count] = counts;
+        let indices = start + time_count * time_size;
+        let types = indices + time_count;
+        let offset = |index: usize| Some(u32_at(types + index * 6)? as i32 as i64);
+
+        let mut transitions = Vec::with_capacity(time_count);
+        for i in 0..time_count {
+            let bytes = data.get(start + i * time_size..start + (i + 1) * time_size)?;
+            let time = match time_size {
+                8 => i64::from_be_bytes(bytes.try_into().ok()?),
+                _ => i32::from_be_bytes(bytes.try_into().ok()?) as i64,
+            };
+            transitions.push((time, offset(*data.get(indices + i)? as usize)?));
+        }
+
+        // the footer is "\n<TZ string>\n"; an empty or unreadable one leaves the last offset in force
+        let footer = types + type_count * 6 + char_count + leap_count * (time_size + 4) + std_count + utc_count;
+        let rule = match data.get(footer..) {
+            Some([b'\n', rest @ ..]) if time_size == 8 => rest
+                .iter()
+                .position(|&b| b == b'\n')
+                .and_then(|end| std::str::from_utf8(&rest[..end]).ok())
+                .and_then(PosixRule::parse),
+            _ => None,
+        };
+        Some(Zone { transitions, initial: offset(0)?, rule })
+    }
+}
+
+
+/// A POSIX TZ string such as "EST5EDT,M3.2.0,M11.1.0".
+#[derive(Debug, PartialEq)]
+struct PosixRule {
+    /// seconds east of UTC
+    standard: i64,
+    /// the daylight saving offset, and the local times it starts and ends at
+    daylight: Option<(i64, RuleTime, RuleTime)>,
+}
+
+/// A day of the year and a number of seconds after its midnight.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RuleTime {
+    day: RuleDay,
+    seconds: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleDay {
+    /// Jn: 1 to 365, February 29 is never counted
+    Julian(i64),
+    /// n: 0 to 365, counting February 29
+    Ordinal(i64),
+    /// Mm.w.d: weekday d (0 is Sunday) of week w (5 is the last) of month m
+    Weekday { month: i64, week: i64, weekday: i64 },
+}
+
+impl PosixRule {
+    fn parse(text: &str) -> Option<PosixRule> {
+        let mut s = text.as_bytes();
+        parse_zone_name(&mut s)?;
+        // POSIX offsets count hours west of UTC
+        let standard = -parse_duration(&mut s)?;
+        if s.is_empty() {
+            return Some(PosixRule { standard, daylight: None });
+        }
+        parse_zone_name(&mut s)?;
+        let daylight = match s.first() {
+            Some(b',') | None => standard + 3600,
+            _ => -parse_duration(&mut s)?,
+        };
+        // without rules the US ones apply, as in glibc
+        let (start, end) = if s.is_empty() {
+            let sunday = |month, week| RuleTime { day: RuleDay::Weekday { month, week, weekday: 0 }, seconds: 7200 };
+            (sunday(3, 2), sunday(11, 1))
+        } else {
+            s = s.strip_prefix(b",")?;
+            let start = parse_rule_time(&mut s)?;
+            s = s.strip_prefix(b",")?;
+            (start, parse_rule_time(&mut s)?)
+        };
+        s.is_empty().then_some(PosixRule { standard, daylight: Some((daylight, start, end)) })
+    }
+
+    fn offset(&self, unix: i64) -> i64 {
+        let Some((daylight, start, end)) = self.daylight else {
+            return self.standard;
+        };
+        let year = year_of_day((unix + self.standard).div_euclid(86400));
+        // start is given in standard time and end in daylight saving time
+        let start = start.unix(year) - self.standard;
+        let end = end.unix(year) - daylight;
+        let in_daylight = if start < end {
+            (start..end).contains(&unix)
+        } else {
+            // southern hemisphere: daylight saving spans the new year
+            !(end..start).contains(&unix)
+        };
+        if in_daylight { daylight } else { self.standard }
+    }
+}
+
+impl RuleTime {
+    /// The local time in the given year, as seconds since 1970-01-01 00:00.
+    fn unix(self, year: i64) -> i64 {
+        let jan_01 = day_of_civil(year, 1, 1);
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let day = match self.day {
+            RuleDay::Julian(n) => jan_01 + n - 1 + (leap && n >= 60) as i64,
+            RuleDay::Ordinal(n) => jan_01 + n,
+            RuleDay::Weekday { month, week, weekday } => {
+                let first = day_of_civil(year, month, 1);
+                let next_month = if month == 12 { day_of_civil(year + 1, 1, 1) } else { day_of_civil(year, month + 1, 1) };
+                // 1970-01-01 was a Thursday
+                let mut day = first + (weekday - (first + 4)).rem_euclid(7) + 7 * (week - 1);
+                while day >= next_month {
+                    day -= 7;
+                }
+                day
+            }
+        };
+        day * 86400 + self.seconds
+    }
+}
+
+/// A name such as "EST" or "<+0330>".
+fn parse_zone_name(s: &mut &[u8]) -> Option<()> {
+    let len = if s.first() == Some(&b'<') {
+        s.iter().position(|&b| b == b'>')? + 1
+    } else {
+        s.iter().position(|b| !b.is_ascii_alphabetic()).unwrap_or(s.len())
+    };
+    (len >= 3).then(|| *s = &s[len..])
+}
+
+/// [+|-]hh[:mm[:ss]] as seconds.
+fn parse_duration(s: &mut &[u8]) -> Option<i64> {
+    let sign = match s.first() {
+        Some(b'-') => -1,
+        _ => 1,
+    };
+    if matches!(s.first(), Some(b'+' | b'-')) {
+        *s = &s[1..];
+    }
+    let mut seconds = 0;
+    for (i, scale) in [3600, 60, 1].into_iter().enumerate() {
+        if i > 0 {
+            match s.strip_prefix(b":") {
+                Some(rest) => *s = rest,
+                None => break,
+            }
+        }
+        let digits = s.iter().position(|b| !b.is_ascii_digit()).unwrap_or(s.len());
+        seconds += std::str::from_utf8(&s[..digits]).ok()?.parse::<i64>().ok()? * scale;
+        *s = &s[digits..];
+    }
+    Some(sign * seconds)
+}
+
+/// Jn, n or Mm.w.d, then an optional /time that defaults to 02:00.
+fn parse_rule_time(s: &mut &[u8]) -> Option<RuleTime> {
+    let number = |s: &mut &[u8]| {
+        let digits = s.iter().position(|b| !b.is_ascii_digit()).unwrap_or(s.len());
+        let n = std::str::from_utf8(&s[..digits]).ok()?.parse::<i64>().ok();
+        *s = &s[digits..];
+        n
+    };
+    let day = match s.first()? {
+        b'J' => {
+            *s = &s[1..];
+            RuleDay::Julian(number(s).filter(|n| (1..=365).contains(n))?)
+        }
+        b'M' => {
+            *s = &s[1..];
+            let month = number(s).filter(|n| (1..=12).contains(n))?;
+            *s = s.strip_prefix(b".")?;
+            let week = number(s).filter(|n| (1..=5).contains(n))?;
+            *s = s.strip_prefix(b".")?;
+            let weekday = number(s).filter(|n| (0..=6).contains(n))?;
+            RuleDay::Weekday { month, week, weekday }
+        }
+        _ => RuleDay::Ordinal(number(s).filter(|n| (0..=365).contains(n))?),
+    };
+    let seconds = match s.strip_prefix(b"/") {
+        Some(rest) => {
+            *s = rest;
+            parse_duration(s)?
+        }
+        None => 7200,
+    };
+    Some(RuleTime { day, seconds })
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn day_of_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    era * 146_097 + year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year - 719_468
+}
+
+/// The year of a day counted from 1970-01-01.
+fn year_of_day(day: i64) -> i64 {
+    let mut year = 1970 + day.div_euclid(366);
+    while day_of_civil(year + 1, 1, 1) <= day {
+        year += 1;
+    }
+    year
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(name: &str, args: &[&str]) -> Value {
+        let args: Vec<Value> = args.iter().map(|a| Value::Text(a.to_string())).collect();
+        call(name, &args, TextEncoding::Utf8, Clock::System).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(text("datetime", &["2024-02-30 25:00"]), Value::Null);
+        assert_eq!(text("date", &["2023-02-31"]), Value::Text("2023-03-03".into()));
+        assert_eq!(text("datetime", &["2024-01-15T10:30:05.25+02:00"]), Value::Text("2024-01-15 08:30:05".into()));
+        assert_eq!(text("time", &["12:34:56.789", "subsec"]), Value::Text("12:34:56.789".into()));
+        let unix = [Value::Integer(1700000000), Value::Text("unixepoch".into())];
+        assert_eq!(call("datetime", &unix, TextEncoding::Utf8, Clock::System).unwrap(), Value::Text("2023-11-14 22:13:20".into()));
+        assert_eq!(text("julianday", &["2000-01-01 12:00"]), Value::Real(2451545.0));
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let cases = [
+            (["2024-01-31", "+1 month"], "2024-03-02"),
+            (["2024-03-15", "start of month"], "2024-03-01"),
+            (["2024-03-15", "weekday 0"], "2024-03-17"),
+            (["2024-03-15", "-0001-02-00"], "2023-01-15"),
+            (["2024-03-15", "-36 hours"], "2024-03-13"),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(text("date", &args), Value::Text(expected.into()), "{:?}", args);
+        }
+        assert_eq!(text("date", &["2024-01-31", "+1 month", "floor"]), Value::Text("2024-02-29".into()));
+    }
+
+    #[test]
+    fn test_fixed_clock() {
+        let at = |clock: &str, name: &str, args: &[&str]| {
+            let args: Vec<Value> = args.iter().map(|a| Value::Text(a.to_string())).collect();
+            call(name, &args, TextEncoding::Utf8, Clock::parse(clock).unwrap()).unwrap()
+        };
+        assert_eq!(at("2024-06-01 08:00:00", "datetime", &[]), Value::Text("2024-06-01 08:00:00".into()));
+        assert_eq!(at("2024-06-01 08:00:00", "date", &["now", "start of year"]), Value::Text("2024-01-01".into()));
+        assert_eq!(at("0", "unixepoch", &["now"]), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_strftime() {
+        let date = ["%Y-%m-%d %H:%M:%S %j %w %u %W %V %G %s %%", "2021-01-03 04:05:06"];
+        assert_eq!(text("strftime", &date), Value::Text("2021-01-03 04:05:06 003 0 7 00 53 2020 1609646706 %".into()));
+        assert_eq!(text("strftime", &["%Q", "2021-01-03"]), Value::Null);
+    }
+
+    /// `zic -b slim` output for the US rules since 2007: one transition, to EDT in
+    /// March 2007, and a footer for everything after it.
+    const SLIM_NEW_YORK: &[u8] = b"\
+        TZif2\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\0\0\0\x01\
+        \0\0\0\0\0\0\0\
+        TZif2\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\0\0\0\x01\0\0\0\x04\
+        \0\0\0\0\x45\xf3\xa8\xf0\0\xff\xff\xc7\xc0\x01\0EDT\0\
+        \nEST5EDT,M3.2.0,M11.1.0\n";
+
+    #[test]
+    fn test_zone_footer() {
+        let zone = Zone::parse(SLIM_NEW_YORK).unwrap();
+        assert_eq!(zone.transitions, [(1173596400, -4 * 3600)]);
+        let at = |text: &str| {
+            let mut time = DateTime::default();
+            assert!(time.parse(text, Clock::System));
+            time.compute_jd();
+            zone.offset(time.jd / 1000 - UNIX_EPOCH_JD / 1000) / 3600
+        };
+        assert_eq!(at("2024-01-01 12:00:00"), -5);
+        assert_eq!(at("2024-07-01 12:00:00"), -4);
+        assert_eq!(at("2024-03-10 06:59:59"), -5);
+        assert_eq!(at("2024-03-10 07:00:00"), -4);
+        assert_eq!(at("2024-11-03 05:59:59"), -4);
+        assert_eq!(at("2024-11-03 06:00:00"), -5);
+        // before the only transition the file's first type, EDT, applies
+        assert_eq!(at("2000-01-01 00:00:00"), -4);
+
+        // southern hemisphere, with daylight saving across the new year
+        let sydney = PosixRule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.offset(1704067200) / 3600, 11); // 2024-01-01
+        assert_eq!(sydney.offset(1719792000) / 3600, 10); // 2024-07-01
+        assert_eq!(PosixRule::parse("<+0530>-5:30").unwrap().offset(0), 19800);
+        assert_eq!(PosixRule::parse("EST5EDT,M3.2.0"), None);
+    }
+}
@@ -0,0 +1,92 @@
+use std::cmp::Ordering;
+use std::error::Error;
+
+
+/// How the database file stores TEXT, set once for the whole file by header offset 56.
+/// Values are decoded to Rust strings when read; the encoding still decides how BINARY
+/// orders text and what bytes text turns into as a blob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl TextEncoding {
+    /// The header value: 1, 2 or 3. A database that has never held a table may still say 0.
+    pub fn from_header(value: u32) -> Result<Self, Box<dyn Error>> {
+        match value {
+            0 | 1 => Ok(TextEncoding::Utf8),
+            2 => Ok(TextEncoding::Utf16Le),
+            3 => Ok(TextEncoding::Utf16Be),
+            other => Err(format!("Database disk image is malformed: unknown text encoding {}", other).into()),
+        }
+    }
+
+    /// The value written at header offset 56 and the name SQLite's `.dbinfo` shows for it.
+    pub fn header_value(self) -> (u32, &'static str) {
+        match self {
+            TextEncoding::Utf8 => (1, "utf8"),
+            TextEncoding::Utf16Le => (2, "utf16le"),
+            TextEncoding::Utf16Be => (3, "utf16be"),
+        }
+    }
+
+    /// Text as stored; invalid sequences are replaced rather than failing the read.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        let units = |unit: fn([u8; 2]) -> u16| bytes.chunks_exact(2).map(move |pair| unit([pair[0], pair[1]]));
+        match self {
+            TextEncoding::Utf8 => match std::str::from_utf8(bytes) {
+                Ok(s) => s.to_string(),
+                Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+            },
+            TextEncoding::Utf16Le => char::decode_utf16(units(u16::from_le_bytes)).map(|c| c.unwrap_or('\u{FFFD}')).collect(),
+            TextEncoding::Utf16Be => char::decode_utf16(units(u16::from_be_bytes)).map(|c| c.unwrap_or('\u{FFFD}')).collect(),
+        }
+    }
+
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            TextEncoding::Utf8 => text.as_bytes().to_vec(),
+            TextEncoding::Utf16Le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            TextEncoding::Utf16Be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+        }
+    }
+
+    /// Orders text as comparing the stored bytes would, which is how BINARY compares and how
+    /// the text of an index is sorted. In UTF-16LE the low byte of each unit comes first.
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            TextEncoding::Utf8 => a.as_bytes().cmp(b.as_bytes()),
+            TextEncoding::Utf16Le => a.encode_utf16().map(u16::swap_bytes).cmp(b.encode_utf16().map(u16::swap_bytes)),
+            TextEncoding::Utf16Be => a.encode_utf16().cmp(b.encode_utf16()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_encodings() {
+        for encoding in [TextEncoding::Utf8, TextEncoding::Utf16Le, TextEncoding::Utf16Be] {
+            for text in ["", "apple", "日本語", "😀 emoji"] {
+                assert_eq!(encoding.decode(&encoding.encode(text)), text);
+            }
+        }
+        assert_eq!(TextEncoding::Utf16Le.encode("a"), [0x61, 0]);
+        assert_eq!(TextEncoding::Utf16Be.decode(&[0, 0x61, 0xD8]), "a");
+        assert!(TextEncoding::from_header(4).is_err());
+
+        // 'ā' is U+0101: after 'a' in UTF-8 and UTF-16BE, before it in UTF-16LE
+        assert_eq!(TextEncoding::Utf8.compare("a", "ā"), Ordering::Less);
+        assert_eq!(TextEncoding::Utf16Be.compare("a", "ā"), Ordering::Less);
+        assert_eq!(TextEncoding::Utf16Le.compare("a", "ā"), Ordering::Greater);
+        // a surrogate pair sorts below U+FF5A in UTF-16 but above it in UTF-8
+        assert_eq!(TextEncoding::Utf8.compare("😀", "ｚ"), Ordering::Greater);
+        assert_eq!(TextEncoding::Utf16Be.compare("😀", "ｚ"), Ordering::Less);
+    }
+}
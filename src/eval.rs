@@ -0,0 +1,874 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::ops::Range;
+
+use crate::aggregate::is_aggregate;
+use crate::collation::Collation;
+use crate::datetime::Clock;
+use crate::encoding::TextEncoding;
+use crate::functions;
+use crate::json;
+use crate::sql::ParseError;
+use crate::sql::ast::{BinaryOp, ColumnRef, Expr, LikeOp, SelectStatement, UnaryOp};
+use crate::value::{real_to_i64, Affinity, Value};
+use crate::window::{self, is_window_function};
+
+
+/// A column visible to expressions: the table (or its alias) it comes from and its name.
+#[derive(Debug, Clone)]
+pub struct ScopeColumn {
+    pub table: String,
+    pub name: String,
+    pub kind: ColumnKind,
+    /// holds JSON, as the value column of json_each() does
+    pub json: bool,
+    /// `None` for a column computed from an expression without affinity
+    pub affinity: Option<Affinity>,
+    /// declared with COLLATE, or that of the expression a subquery column is computed from
+    pub collation: Collation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    Regular,
+    /// the table's rowid: not part of `*`, reached as rowid/oid/_rowid_ unless a column has that name
+    Rowid,
+    /// right-hand column of a USING or NATURAL join: only reached through its table name
+    Merged,
+    /// reached by name but not part of `*`, like the json and root columns of json_each()
+    Hidden,
+}
+
+/// Columns of the rows an expression is evaluated against, in row order.
+/// In a subquery the columns of the enclosing queries come first, so a correlated subquery's
+/// rows start with the values of the enclosing row.
+#[derive(Debug, Clone, Default)]
+pub struct Scope {
+    pub columns: Vec<ScopeColumn>,
+    /// where the columns of each nested query start, innermost last
+    pub levels: Vec<usize>,
+}
+
+/// Names of the rowid pseudo-column.
+const ROWID_NAMES: [&str; 3] = ["rowid", "oid", "_rowid_"];
+
+impl Scope {
+    /// The table's columns followed by its hidden rowid. The columns have BLOB affinity, as if declared without a type.
+    pub fn for_table(table: &str, column_names: &[String]) -> Self {
+        let column = |name: &str, kind, affinity| ScopeColumn {
+            table: table.to_string(),
+            name: name.to_string(),
+            kind,
+            json: false,
+            affinity: Some(affinity),
+            collation: Collation::Binary,
+        };
+        let mut columns: Vec<ScopeColumn> =
+            column_names.iter().map(|name| column(name, ColumnKind::Regular, Affinity::Blob)).collect();
+        columns.push(column("rowid", ColumnKind::Rowid, Affinity::Integer));
+        Scope { columns, levels: Vec::new() }
+    }
+
+    /// Scope of a subquery: the columns seen so far, to which the subquery adds its own.
+    pub fn nested(&self) -> Self {
+        let mut levels = self.levels.clone();
+        levels.push(self.columns.len());
+        Scope { columns: self.columns.clone(), levels }
+    }
+
+    /// Position of the query's own first column.
+    pub fn own_start(&self) -> usize {
+        self.levels.last().copied().unwrap_or(0)
+    }
+
+    /// Index of the referenced column; names are case-insensitive.
+    /// `rowid`, `oid` and `_rowid_` reach the hidden rowid unless a real column has that name.
+    /// The query's own columns are searched first, then those of each enclosing query.
+    /// `Ok(None)` means nothing matched, which the caller may still accept for "quoted" strings.
+    pub fn resolve(&self, column: &ColumnRef) -> Result<Option<usize>, ParseError> {
+        if column.position.is_some() {
+            return Ok(column.position);
+        }
+        let mut end = self.columns.len();
+        for start in self.levels.iter().rev().copied().chain([0]) {
+            if let Some(found) = self.resolve_within(start..end, column)? {
+                return Ok(Some(found));
+            }
+            end = start;
+        }
+        Ok(None)
+    }
+
+    fn resolve_within(&self, range: Range<usize>, column: &ColumnRef) -> Result<Option<usize>, ParseError> {
+        let mut found = self.find(range.clone(), column, |c| {
+            matches!(c.kind, ColumnKind::Regular | ColumnKind::Hidden) && c.name.eq_ignore_ascii_case(&column.name)
+        })?;
+        // the right-hand copies of a USING column are only reached by their table name, and only
+        // when no other column of that name is: of the same table name on both sides it is the left,
+        // and of several copies the left-most, which holds the same value
+        if found.is_none() && column.table.is_some() {
+            let table = column.table.as_deref().unwrap_or_default();
+            found = range.clone().find(|&i| {
+                let c = &self.columns[i];
+                c.kind == ColumnKind::Merged && c.name.eq_ignore_ascii_case(&column.name) && c.table.eq_ignore_ascii_case(table)
+            });
+        }
+        if found.is_some() || !ROWID_NAMES.iter().any(|n| n.eq_ignore_ascii_case(&column.name)) {
+            return Ok(found);
+        }
+        self.find(range, column, |c| c.kind == ColumnKind::Rowid)
+    }
+
+    /// Positions `*` (no table) or `table.*` expands to.
+    pub fn star_columns(&self, table: Option<&str>) -> Vec<usize> {
+        (self.own_start()..self.columns.len())
+            .filter(|&i| {
+                let c = &self.columns[i];
+                match table {
+                    None => c.kind == ColumnKind::Regular,
+                    Some(table) => matches!(c.kind, ColumnKind::Regular | ColumnKind::Merged) && c.table.eq_ignore_ascii_case(table),
+                }
+            })
+            .collect()
+    }
+
+    fn find(
+        &self,
+        range: Range<usize>,
+        column: &ColumnRef,
+        matches: impl Fn(&ScopeColumn) -> bool,
+    ) -> Result<Option<usize>, ParseError> {
+        let mut found = None;
+        for (i, c) in self.columns.iter().enumerate().take(range.end).skip(range.start) {
+            let table_matches = column.table.as_ref().is_none_or(|t| t.eq_ignore_ascii_case(&c.table));
+            if table_matches && matches(c) {
+                if found.is_some() {
+                    return Err(ParseError::new(format!("ambiguous column name: {}", column.name), column.span));
+                }
+                found = Some(i);
+            }
+        }
+        Ok(found)
+    }
+}
+
+
+/// Runs the subqueries met in expressions; only the engine can read their tables.
+/// `ctx` is the enclosing row, which correlated subqueries refer to.
+pub trait SubqueryRunner {
+    /// first column of the first row, NULL without rows
+    fn scalar(&self, select: &SelectStatement, ctx: &EvalContext) -> Result<Value, Box<dyn Error>>;
+    fn exists(&self, select: &SelectStatement, ctx: &EvalContext) -> Result<bool, Box<dyn Error>>;
+    /// `value IN (select)`, where `None` is NULL; `left` describes the expression on the left
+    fn contains(
+        &self,
+        select: &SelectStatement,
+        value: &Value,
+        left: ExprType,
+        ctx: &EvalContext,
+    ) -> Result<Option<bool>, Box<dyn Error>>;
+    /// affinity and collation of the first result column
+    fn column_type(&self, select: &SelectStatement, ctx: &EvalContext) -> Result<ExprType, Box<dyn Error>>;
+}
+
+
+/// One row together with the scope describing its columns.
+pub struct EvalContext<'a> {
+    pub scope: &'a Scope,
+    pub row: &'a [Value],
+    /// results of the aggregate and window calls for the current row, matched by node identity
+    pub aggregates: &'a [(&'a Expr, Value)],
+    pub subqueries: Option<&'a dyn SubqueryRunner>,
+    /// how the database stores text, which decides how BINARY orders it and what hex() shows
+    pub encoding: TextEncoding,
+    /// what 'now' is to the date and time functions
+    pub clock: Clock,
+}
+
+impl<'a> EvalContext<'a> {
+    pub fn new(scope: &'a Scope, row: &'a [Value], encoding: TextEncoding, clock: Clock) -> Self {
+        EvalContext { scope, row, aggregates: &[], subqueries: None, encoding, clock }
+    }
+
+    pub fn with_subqueries(self, subqueries: Option<&'a dyn SubqueryRunner>) -> Self {
+        EvalContext { subqueries, ..self }
+    }
+
+    fn subqueries(&self) -> Result<&'a dyn SubqueryRunner, Box<dyn Error>> {
+        self.subqueries.ok_or_else(|| "subqueries are not supported here".into())
+    }
+
+
+    pub fn eval(&self, expr: &Expr) -> Result<Value, Box<dyn Error>> {
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+
+            Expr::Column(column) => match self.scope.resolve(column)? {
+                Some(i) => Ok(self.row.get(i).cloned().unwrap_or(Value::Null)),
+                // SQLite falls back to a string for an unknown "double quoted" name
+                None if column.double_quoted && column.table.is_none() => Ok(Value::Text(column.name.clone())),
+                None => Err(no_such_column(column).into()),
+            },
+
+            Expr::Unary { op, expr } => {
+                let value = self.eval(expr)?;
+                Ok(unary(*op, value, self.encoding))
+            }
+
+            // the collation only matters where values are compared
+            Expr::Collate { expr, .. } => self.eval(expr),
+
+            Expr::Binary { op: BinaryOp::And, left, right } => {
+                // FALSE AND anything is FALSE, even NULL
+                let l = self.eval(left)?.is_true(self.encoding);
+                if l == Some(false) {
+                    return Ok(Value::Integer(0));
+                }
+                let r = self.eval(right)?.is_true(self.encoding);
+                Ok(match (l, r) {
+                    (_, Some(false)) => Value::Integer(0),
+                    (Some(true), Some(true)) => Value::Integer(1),
+                    _ => Value::Null,
+                })
+            }
+
+            Expr::Binary { op: BinaryOp::Or, left, right } => {
+                let l = self.eval(left)?.is_true(self.encoding);
+                if l == Some(true) {
+                    return Ok(Value::Integer(1));
+                }
+                let r = self.eval(right)?.is_true(self.encoding);
+                Ok(match (l, r) {
+                    (_, Some(true)) => Value::Integer(1),
+                    (Some(false), Some(false)) => Value::Integer(0),
+                    _ => Value::Null,
+                })
+            }
+
+            Expr::Binary { op: op @ (BinaryOp::Extract | BinaryOp::ExtractValue), left, right } => {
+                let document = self.eval(left)?;
+                let selector = self.eval(right)?;
+                json::extract(&document, &selector, *op == BinaryOp::Extract, self.encoding)
+            }
+
+            Expr::Binary { op, left, right } if op.is_comparison() => {
+                let (l, r, collation) = self.operands(left, right)?;
+                Ok(comparison(*op, &l, &r, collation, self.encoding))
+            }
+
+            Expr::Binary { op, left, right } => {
+                let l = self.eval(left)?;
+                let r = self.eval(right)?;
+                Ok(binary(*op, &l, &r, self.encoding))
+            }
+
+            Expr::Between { expr, low, high, negated } => {
+                // x is evaluated once, then compared with each bound under that pair's affinity and collation
+                let (value, left) = (self.eval(expr)?, self.expr_type(expr)?);
+                let against = |bound: &Expr| -> Result<Option<Ordering>, Box<dyn Error>> {
+                    let right = self.expr_type(bound)?;
+                    let conversion = comparison_affinity(left.affinity, right.affinity);
+                    let collation = comparison_collation(left.collation, right.collation);
+                    Ok(compare(&conversion.apply(value.clone()), &conversion.apply(self.eval(bound)?), collation, self.encoding))
+                };
+                let ge = against(low)?.map(|o| o != Ordering::Less);
+                let le = against(high)?.map(|o| o != Ordering::Greater);
+                let result = match (ge, le) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                };
+                Ok(negate_if(result, *negated))
+            }
+
+            Expr::InList { expr, list, negated } => {
+                if list.is_empty() {
+                    return Ok(Value::from_bool(*negated));
+                }
+                let value = self.eval(expr)?;
+                if value.is_null() {
+                    return Ok(Value::Null);
+                }
+                let left = self.expr_type(expr)?;
+                let mut saw_null = false;
+                for item in list {
+                    let right = self.expr_type(item)?;
+                    let conversion = comparison_affinity(left.affinity, right.affinity);
+                    let collation = comparison_collation(left.collation, right.collation);
+                    let item = conversion.apply(self.eval(item)?);
+                    match compare(&conversion.apply(value.clone()), &item, collation, self.encoding) {
+                        Some(Ordering::Equal) => return Ok(Value::from_bool(!*negated)),
+                        None => saw_null = true,
+                        _ => {}
+                    }
+                }
+                Ok(if saw_null { Value::Null } else { Value::from_bool(*negated) })
+            }
+
+            Expr::Like { op, expr, pattern, escape, negated } => {
+                let value = self.eval(expr)?;
+                let pattern = self.eval(pattern)?;
+                let escape = match escape {
+                    Some(e) => Some(self.eval(e)?),
+                    None => None,
+                };
+                if value.is_null() || pattern.is_null() || escape.as_ref().is_some_and(|e| e.is_null()) {
+                    return Ok(Value::Null);
+                }
+
+                let escape = match escape {
+                    Some(e) => {
+                        let text = e.to_text(self.encoding);
+                        let mut chars = text.chars();
+                        match (chars.next(), chars.next()) {
+                            (Some(c), None) => Some(c),
+                            _ => return Err("ESCAPE expression must be a single character".into()),
+                        }
+                    }
+                    None => None,
+                };
+
+                let matched = match op {
+                    LikeOp::Like => like_match(&pattern.to_text(self.encoding), &value.to_text(self.encoding), escape),
+                    LikeOp::Glob => glob_match(&pattern.to_text(self.encoding), &value.to_text(self.encoding)),
+                };
+                Ok(Value::from_bool(matched != *negated))
+            }
+
+            Expr::Subquery(select) => self.subqueries()?.scalar(select, self),
+
+            Expr::Exists(select) => Ok(Value::from_bool(self.subqueries()?.exists(select, self)?)),
+
+            Expr::InSelect { expr, select, negated } => {
+                let value = self.eval(expr)?;
+                let left = self.expr_type(expr)?;
+                Ok(negate_if(self.subqueries()?.contains(select, &value, left, self)?, *negated))
+            }
+
+            Expr::Function { name, args, over, span, .. } => {
+                if let Some((_, value)) = self.aggregates.iter().find(|(call, _)| std::ptr::eq(*call, expr)) {
+                    return Ok(value.clone());
+                }
+                if over.is_some() || is_window_function(name) {
+                    return Err(window::misuse(name, *span).into());
+                }
+                if is_aggregate(name, args) {
+                    return Err(ParseError::new(format!("misuse of aggregate: {}()", name), *span).into());
+                }
+                functions::call(self, name, args, *span)
+            }
+        }
+    }
+
+    /// Both sides of a comparison, converted to the affinity they are compared with,
+    /// and the collation that compares them.
+    fn operands(&self, left: &Expr, right: &Expr) -> Result<(Value, Value, Collation), Box<dyn Error>> {
+        let (l, r) = (self.expr_type(left)?, self.expr_type(right)?);
+        let affinity = comparison_affinity(l.affinity, r.affinity);
+        let collation = comparison_collation(l.collation, r.collation);
+        Ok((affinity.apply(self.eval(left)?), affinity.apply(self.eval(right)?), collation))
+    }
+
+    /// Like `expr_type`, also looking into scalar subqueries. Of the subquery's column only
+    /// an explicit COLLATE carries over, as in SQLite.
+    fn expr_type(&self, expr: &Expr) -> Result<ExprType, Box<dyn Error>> {
+        let affinity = match expr {
+            Expr::Collate { expr: inner, .. } if matches!(**inner, Expr::Subquery(_)) => self.expr_type(inner)?.affinity,
+            Expr::Subquery(select) => {
+                let column = self.subqueries()?.column_type(select, self)?;
+                let collation = column.collation.filter(|c| c.explicit);
+                return Ok(ExprType { affinity: column.affinity, collation });
+            }
+            _ => affinity(expr, self.scope),
+        };
+        Ok(ExprType { affinity, collation: collation(expr, self.scope)? })
+    }
+
+    /// WHERE-style truth test: NULL counts as false.
+    pub fn is_true(&self, expr: &Expr) -> Result<bool, Box<dyn Error>> {
+        Ok(self.eval(expr)?.is_true(self.encoding) == Some(true))
+    }
+}
+
+
+/// Affinity of an expression: a column has that of its declared type, a COLLATE keeps it,
+/// anything else has none. Subqueries are left to `EvalContext::affinity`.
+pub fn affinity(expr: &Expr, scope: &Scope) -> Option<Affinity> {
+    match expr {
+        Expr::Column(column) => scope.resolve(column).ok()?.and_then(|i| scope.columns[i].affinity),
+        Expr::Collate { expr, .. } => affinity(expr, scope),
+        _ => None,
+    }
+}
+
+/// Affinity and collating sequence of an expression, which decide how it compares.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExprType {
+    pub affinity: Option<Affinity>,
+    pub collation: Option<ExprCollation>,
+}
+
+impl ExprType {
+    /// The collation the value compares with on its own, as in ORDER BY, GROUP BY or DISTINCT.
+    pub fn sort_collation(&self) -> Collation {
+        self.collation.map_or(Collation::Binary, |c| c.collation)
+    }
+}
+
+/// A collating sequence, and whether it was asked for with COLLATE rather than declared on a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExprCollation {
+    pub collation: Collation,
+    pub explicit: bool,
+}
+
+pub fn expr_type(expr: &Expr, scope: &Scope) -> Result<ExprType, Box<dyn Error>> {
+    Ok(ExprType { affinity: affinity(expr, scope), collation: collation(expr, scope)? })
+}
+
+/// Collation of an expression: the first COLLATE met going down its operands from the left,
+/// else that of a column standing alone (a unary + does not hide it), else none.
+pub fn collation(expr: &Expr, scope: &Scope) -> Result<Option<ExprCollation>, Box<dyn Error>> {
+    if let Some(name) = explicit_collation(expr) {
+        return Ok(Some(ExprCollation { collation: Collation::named(name)?, explicit: true }));
+    }
+    let mut expr = expr;
+    loop {
+        match expr {
+            Expr::Unary { op: UnaryOp::Plus, expr: inner } => expr = inner,
+            Expr::Column(column) => {
+                let collation = scope.resolve(column).ok().flatten().map(|i| scope.columns[i].collation);
+                return Ok(collation.map(|collation| ExprCollation { collation, explicit: false }));
+            }
+            _ => return Ok(None),
+        }
+    }
+}
+
+/// Collation an expression is compared with on its own, as in ORDER BY, GROUP BY or DISTINCT.
+pub fn sort_collation(expr: &Expr, scope: &Scope) -> Result<Collation, Box<dyn Error>> {
+    Ok(expr_type(expr, scope)?.sort_collation())
+}
+
+fn explicit_collation(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Collate { collation, .. } => Some(collation),
+        _ => expr.children().into_iter().find_map(explicit_collation),
+    }
+}
+
+/// The collation two operands are compared with: an explicit COLLATE on the left, then on
+/// the right, then a column's on the left, then on the right, else BINARY.
+pub fn comparison_collation(left: Option<ExprCollation>, right: Option<ExprCollation>) -> Collation {
+    [left, right]
+        .into_iter()
+        .flatten()
+        .min_by_key(|c| !c.explicit)
+        .map(|c| c.collation)
+        .unwrap_or_default()
+}
+
+/// What two operands are converted to before they are compared. A number column compared with
+/// anything that has affinity makes it numeric; otherwise an operand without affinity takes
+/// that of the other side. BLOB means no conversion.
+pub fn comparison_affinity(left: Option<Affinity>, right: Option<Affinity>) -> Affinity {
+    match (left, right) {
+        (Some(l), Some(r)) if l.is_numeric() || r.is_numeric() => Affinity::Numeric,
+        (Some(_), Some(_)) | (None, None) => Affinity::Blob,
+        (Some(a), None) | (None, Some(a)) => a,
+    }
+}
+
+fn no_such_column(column: &ColumnRef) -> ParseError {
+    let name = match &column.table {
+        Some(table) => format!("{}.{}", table, column.name),
+        None => column.name.clone(),
+    };
+    ParseError::new(format!("no such column: {}", name), column.span)
+}
+
+fn negate_if(result: Option<bool>, negated: bool) -> Value {
+    match result {
+        Some(b) => Value::from_bool(b != negated),
+        None => Value::Null,
+    }
+}
+
+/// Comparison with SQL NULL semantics: any NULL operand gives `None`.
+fn compare(l: &Value, r: &Value, collation: Collation, encoding: TextEncoding) -> Option<Ordering> {
+    if l.is_null() || r.is_null() {
+        None
+    } else {
+        Some(collation.compare(l, r, encoding))
+    }
+}
+
+
+fn unary(op: UnaryOp, value: Value, encoding: TextEncoding) -> Value {
+    match op {
+        UnaryOp::Plus => value,
+        UnaryOp::Not => match value.is_true(encoding) {
+            Some(b) => Value::from_bool(!b),
+            None => Value::Null,
+        },
+        UnaryOp::Negate => match value.to_numeric(encoding) {
+            Value::Integer(n) => n.checked_neg().map(Value::Integer).unwrap_or(Value::Real(-(n as f64))),
+            Value::Real(r) => Value::Real(-r),
+            _ => Value::Null,
+        },
+        UnaryOp::BitNot => match value {
+            Value::Null => Value::Null,
+            v => Value::Integer(!v.to_i64(encoding)),
+        },
+    }
+}
+
+
+/// An operator other than a comparison, reading blobs taken as text or numbers as `encoding` text.
+pub fn binary(op: BinaryOp, l: &Value, r: &Value, encoding: TextEncoding) -> Value {
+    match op {
+        BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Is | BinaryOp::IsNot | BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => {
+            unreachable!("comparisons need a collation, they are evaluated in eval")
+        }
+        _ if l.is_null() || r.is_null() => Value::Null,
+        BinaryOp::Concat => Value::Text(l.to_text(encoding) + &r.to_text(encoding)),
+        BinaryOp::BitAnd => Value::Integer(l.to_i64(encoding) & r.to_i64(encoding)),
+        BinaryOp::BitOr => Value::Integer(l.to_i64(encoding) | r.to_i64(encoding)),
+        BinaryOp::ShiftLeft => Value::Integer(shift_left(l.to_i64(encoding), r.to_i64(encoding))),
+        BinaryOp::ShiftRight => Value::Integer(shift_left(l.to_i64(encoding), r.to_i64(encoding).saturating_neg())),
+        BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo => {
+            arithmetic(op, l, r, encoding)
+        }
+        BinaryOp::And | BinaryOp::Or => unreachable!("logical operators are short-circuited in eval"),
+        BinaryOp::Extract | BinaryOp::ExtractValue => unreachable!("JSON operators can fail, they are evaluated in eval"),
+    }
+}
+
+/// A comparison operator applied with `collation` to text stored in `encoding`.
+pub fn comparison(op: BinaryOp, l: &Value, r: &Value, collation: Collation, encoding: TextEncoding) -> Value {
+    let ordering = match (op, compare(l, r, collation, encoding)) {
+        // `IS`: like `=`, but NULL IS NULL is true
+        (BinaryOp::Is | BinaryOp::IsNot, ordering) => {
+            let same = ordering.map_or(l.is_null() && r.is_null(), Ordering::is_eq);
+            return Value::from_bool(same == (op == BinaryOp::Is));
+        }
+        (_, None) => return Value::Null,
+        (_, Some(ordering)) => ordering,
+    };
+    Value::from_bool(match op {
+        BinaryOp::Eq => ordering.is_eq(),
+        BinaryOp::NotEq => ordering.is_ne(),
+        BinaryOp::Lt => ordering.is_lt(),
+        BinaryOp::LtEq => ordering.is_le(),
+        BinaryOp::Gt => ordering.is_gt(),
+        BinaryOp::GtEq => ordering.is_ge(),
+        _ => unreachable!("not a comparison: {:?}", op),
+    })
+}
+
+/// Negative amounts shift the other way; shifting everything out gives 0 (or -1 for negatives).
+fn shift_left(n: i64, amount: i64) -> i64 {
+    if amount >= 64 {
+        0
+    } else if amount >= 0 {
+        n << amount
+    } else if amount > -64 {
+        n >> -amount
+    } else if n < 0 {
+        -1
+    } else {
+        0
+    }
+}
+
+/// Integer arithmetic falls back to REAL on overflow; division by zero is NULL.
+fn arithmetic(op: BinaryOp, l: &Value, r: &Value, encoding: TextEncoding) -> Value {
+    let (l, r) = (l.to_numeric(encoding), r.to_numeric(encoding));
+    if let (Value::Integer(a), Value::Integer(b)) = (&l, &r) {
+        let (a, b) = (*a, *b);
+        let checked = match op {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Subtract => a.checked_sub(b),
+            BinaryOp::Multiply => a.checked_mul(b),
+            BinaryOp::Divide if b == 0 => return Value::Null,
+            BinaryOp::Divide => a.checked_div(b),
+            BinaryOp::Modulo if b == 0 => return Value::Null,
+            BinaryOp::Modulo => Some(a.wrapping_rem(b)),
+            _ => None,
+        };
+        if let Some(n) = checked {
+            return Value::Integer(n);
+        }
+    }
+
+    let a = l.to_f64(encoding);
+    let b = r.to_f64(encoding);
+    match op {
+        BinaryOp::Add => Value::Real(a + b),
+        BinaryOp::Subtract => Value::Real(a - b),
+        BinaryOp::Multiply => Value::Real(a * b),
+        BinaryOp::Divide if b == 0.0 => Value::Null,
+        BinaryOp::Divide => Value::Real(a / b),
+        // REAL % works on the integer parts but stays REAL
+        _ => {
+            let (a, b) = (real_to_i64(a), real_to_i64(b));
+            if b == 0 {
+                Value::Null
+            } else {
+                Value::Real(a.wrapping_rem(b) as f64)
+            }
+        }
+    }
+}
+
+
+enum PatternToken {
+    /// `%` or `*`
+    AnySequence,
+    /// `_` or `?`
+    AnyChar,
+    Char(char),
+    /// GLOB `[...]`, a list of inclusive ranges
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+fn like_match(pattern: &str, text: &str, escape: Option<char>) -> bool {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if Some(c) == escape {
+            match chars.next() {
+                Some(next) => tokens.push(PatternToken::Char(next)),
+                None => return false,
+            }
+        } else if c == '%' {
+            tokens.push(PatternToken::AnySequence);
+        } else if c == '_' {
+            tokens.push(PatternToken::AnyChar);
+        } else {
+            tokens.push(PatternToken::Char(c));
+        }
+    }
+    // LIKE only folds ASCII letters
+    wildcard_match(&tokens, text, |p, t| p.eq_ignore_ascii_case(&t))
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => tokens.push(PatternToken::AnySequence),
+            '?' => tokens.push(PatternToken::AnyChar),
+            '[' => {
+                let negated = chars.next_if_eq(&'^').is_some();
+                let mut ranges = Vec::new();
+                // a ']' right after '[' or '[^' is a literal
+                if let Some(']') = chars.peek() {
+                    chars.next();
+                    ranges.push((']', ']'));
+                }
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    if c == ']' {
+                        closed = true;
+                        break;
+                    }
+                    if chars.peek() == Some(&'-') {
+                        chars.next();
+                        match chars.peek() {
+                            Some(&end) if end != ']' => {
+                                chars.next();
+                                ranges.push((c, end));
+                            }
+                            _ => {
+                                ranges.push((c, c));
+                                ranges.push(('-', '-'));
+                            }
+                        }
+                    } else {
+                        ranges.push((c, c));
+                    }
+                }
+                if !closed {
+                    return false;
+                }
+                tokens.push(PatternToken::Class { negated, ranges });
+            }
+            c => tokens.push(PatternToken::Char(c)),
+        }
+    }
+    wildcard_match(&tokens, text, |p, t| p == t)
+}
+
+/// Iterative wildcard matching, backtracking to the last `AnySequence` on mismatch.
+fn wildcard_match(tokens: &[PatternToken], text: &str, char_eq: impl Fn(char, char) -> bool) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match tokens.get(p) {
+            Some(PatternToken::AnySequence) => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some(PatternToken::AnyChar) => true,
+            Some(PatternToken::Char(c)) => char_eq(*c, text[t]),
+            Some(PatternToken::Class { negated, ranges }) => {
+                let inside = ranges.iter().any(|(lo, hi)| *lo <= text[t] && text[t] <= *hi);
+                inside != *negated
+            }
+            None => false,
+        };
+
+        if step {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // let the last `%` swallow one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    tokens[p..].iter().all(|token| matches!(token, PatternToken::AnySequence))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parse;
+    use crate::sql::ast::{ResultColumn, Span, Statement};
+    use crate::sql_engine::query_fixture;
+
+    fn eval_sql(expr: &str) -> Value {
+        let Statement::Select(select) = parse(&format!("SELECT {}", expr)).unwrap() else { panic!() };
+        let ResultColumn::Expr { expr, .. } = &select.columns[0] else { panic!() };
+        let scope = Scope::for_table("t", &["a".to_string(), "b".to_string()]);
+        let row = [Value::Integer(5), Value::Null, Value::Integer(42)];
+        EvalContext::new(&scope, &row, TextEncoding::Utf8, Clock::System).eval(expr).unwrap()
+    }
+
+    #[test]
+    fn test_eval_arithmetic() {
+        assert_eq!(eval_sql("a + 2 * 3"), Value::Integer(11));
+        assert_eq!(eval_sql("(a + 2) * 3"), Value::Integer(21));
+        assert_eq!(eval_sql("7 / 2"), Value::Integer(3));
+        assert_eq!(eval_sql("7 / 2.0"), Value::Real(3.5));
+        assert_eq!(eval_sql("5.5 % 2"), Value::Real(1.0));
+        assert_eq!(eval_sql("1 / 0"), Value::Null);
+        assert_eq!(eval_sql("9223372036854775807 + 1"), Value::Real(9223372036854775808.0));
+        assert_eq!(eval_sql("'12abc' + 1"), Value::Integer(13));
+        assert_eq!(eval_sql("-8 >> 1"), Value::Integer(-4));
+        assert_eq!(eval_sql("'x' || a || 1.5"), Value::Text("x51.5".into()));
+        assert_eq!(eval_sql("b + 1"), Value::Null);
+    }
+
+    #[test]
+    fn test_eval_rowid() {
+        assert_eq!(eval_sql("rowid"), Value::Integer(42));
+        assert_eq!(eval_sql("t._ROWID_ + oid"), Value::Integer(84));
+
+        // a real column named rowid hides the pseudo-column
+        let scope = Scope::for_table("t", &["rowid".to_string()]);
+        let column = ColumnRef { table: None, name: "oid".into(), double_quoted: false, span: Span::default(), position: None };
+        assert_eq!(scope.resolve(&column).unwrap(), Some(1));
+        let column = ColumnRef { name: "ROWID".into(), ..column };
+        assert_eq!(scope.resolve(&column).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn test_eval_logic_with_nulls() {
+        assert_eq!(eval_sql("b AND 0"), Value::Integer(0));
+        assert_eq!(eval_sql("b OR 1"), Value::Integer(1));
+        assert_eq!(eval_sql("b AND 1"), Value::Null);
+        assert_eq!(eval_sql("NOT b"), Value::Null);
+        assert_eq!(eval_sql("b IS NULL"), Value::Integer(1));
+        assert_eq!(eval_sql("a IS NOT NULL AND b ISNULL"), Value::Integer(1));
+        assert_eq!(eval_sql("b = NULL"), Value::Null);
+    }
+
+    #[test]
+    fn test_eval_between_and_in() {
+        assert_eq!(eval_sql("a BETWEEN 1 AND 5"), Value::Integer(1));
+        assert_eq!(eval_sql("a NOT BETWEEN 1 AND 4"), Value::Integer(1));
+        assert_eq!(eval_sql("a IN (1, 5.0)"), Value::Integer(1));
+        assert_eq!(eval_sql("a IN (NULL, 2)"), Value::Null);
+        assert_eq!(eval_sql("a NOT IN (1, 2)"), Value::Integer(1));
+        assert_eq!(eval_sql("b IN ()"), Value::Integer(0));
+
+        // one draw per row keeps about a third of the 2000 items, two draws would keep 4/9
+        let kept = query_fixture("SELECT count(*) FROM items WHERE abs(random()) % 3 BETWEEN 1 AND 1").unwrap();
+        assert!(kept[0].parse::<i64>().unwrap() < 780, "{:?}", kept);
+    }
+
+    #[test]
+    fn test_eval_affinity() {
+        // columns declared without a type compare as stored
+        assert_eq!(eval_sql("a = '5'"), Value::Integer(0));
+        assert_eq!(eval_sql("rowid = '42'"), Value::Integer(1));
+
+        let mut scope = Scope::for_table("t", &["i".to_string(), "s".to_string()]);
+        scope.columns[0].affinity = Some(Affinity::Integer);
+        scope.columns[1].affinity = Some(Affinity::Text);
+        let row = [Value::Integer(10), Value::Text("10".into()), Value::Integer(1)];
+        let eval = |sql: &str| {
+            let Statement::Select(select) = parse(&format!("SELECT {}", sql)).unwrap() else { panic!() };
+            let ResultColumn::Expr { expr, .. } = &select.columns[0] else { panic!() };
+            EvalContext::new(&scope, &row, TextEncoding::Utf8, Clock::System).eval(expr).unwrap()
+        };
+        assert_eq!(eval("i = ' 10 '"), Value::Integer(1));
+        assert_eq!(eval("s = 10"), Value::Integer(1));
+        assert_eq!(eval("s = i"), Value::Integer(1));
+        assert_eq!(eval("s < 9"), Value::Integer(1));
+        assert_eq!(eval("i BETWEEN '9' AND '11'"), Value::Integer(1));
+        assert_eq!(eval("s IN (1, 10.0)"), Value::Integer(0));
+        assert_eq!(eval("i IN ('1', '10')"), Value::Integer(1));
+        // the unary + takes the affinity away
+        assert_eq!(eval("+i = '10'"), Value::Integer(0));
+        assert_eq!(eval("'abc' = 'ABC'"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_eval_collation() {
+        let mut scope = Scope::for_table("t", &["n".to_string(), "s".to_string()]);
+        scope.columns[0].collation = Collation::NoCase;
+        let row = [Value::Text("abc".into()), Value::Text("ABC".into()), Value::Integer(1)];
+        let eval = |sql: &str| {
+            let Statement::Select(select) = parse(&format!("SELECT {}", sql)).unwrap() else { panic!() };
+            let ResultColumn::Expr { expr, .. } = &select.columns[0] else { panic!() };
+            EvalContext::new(&scope, &row, TextEncoding::Utf8, Clock::System).eval(expr).unwrap()
+        };
+        assert_eq!(eval("n = 'ABC'"), Value::Integer(1));
+        // the column on the left decides, an explicit COLLATE beats both
+        assert_eq!(eval("n = s"), Value::Integer(1));
+        assert_eq!(eval("s = n"), Value::Integer(0));
+        assert_eq!(eval("s = n COLLATE binary"), Value::Integer(0));
+        assert_eq!(eval("n = s COLLATE binary"), Value::Integer(0));
+        assert_eq!(eval("s COLLATE nocase = 'abc'"), Value::Integer(1));
+        assert_eq!(eval("+n = 'ABC'"), Value::Integer(1));
+        assert_eq!(eval("n || '' = 'ABC'"), Value::Integer(0));
+        assert_eq!(eval("s BETWEEN 'abb' AND 'abd' COLLATE nocase"), Value::Integer(0));
+        assert_eq!(eval("s COLLATE nocase BETWEEN 'abb' AND 'abd'"), Value::Integer(1));
+        assert_eq!(eval("s IN ('x', 'abc' COLLATE nocase)"), Value::Integer(1));
+        assert_eq!(eval("'abc ' = 'abc' COLLATE rtrim"), Value::Integer(1));
+    }
+
+    #[test]
+    fn test_eval_like_and_glob() {
+        assert_eq!(eval_sql("'Golden Delicious' LIKE 'gold%'"), Value::Integer(1));
+        assert_eq!(eval_sql("'abc' LIKE 'a_c'"), Value::Integer(1));
+        assert_eq!(eval_sql("'a%c' LIKE 'a!%c' ESCAPE '!'"), Value::Integer(1));
+        assert_eq!(eval_sql("'abc' LIKE 'a!%c' ESCAPE '!'"), Value::Integer(0));
+        assert_eq!(eval_sql("'abc' GLOB 'A*'"), Value::Integer(0));
+        assert_eq!(eval_sql("'abc' GLOB 'a[a-c]?'"), Value::Integer(1));
+        assert_eq!(eval_sql("'abc' GLOB '*[^x]'"), Value::Integer(1));
+        assert_eq!(eval_sql("'mississippi' LIKE '%iss%ppi'"), Value::Integer(1));
+    }
+}
@@ -0,0 +1,865 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+
+use crate::aggregate::is_aggregate;
+use crate::collation::Collation;
+use crate::datetime::{self, Clock};
+use crate::encoding::TextEncoding;
+use crate::eval::EvalContext;
+use crate::json;
+use crate::sql::ParseError;
+use crate::sql::ast::{Expr, FunctionArgs, Span};
+use crate::value::{format_real, Value};
+
+
+/// Longest string or blob a function may build, SQLite's default length limit.
+const MAX_LENGTH: usize = 1_000_000_000;
+
+/// Number of arguments each scalar function takes, `None` for names that are not scalar functions.
+fn arity(name: &str) -> Option<RangeInclusive<usize>> {
+    Some(match name {
+        "random" => 0..=0,
+        "length" | "lower" | "upper" | "abs" | "typeof" | "hex" | "quote" | "unicode" | "zeroblob"
+        | "randomblob" | "likely" | "unlikely" => 1..=1,
+        "trim" | "ltrim" | "rtrim" | "round" => 1..=2,
+        "substr" | "substring" => 2..=3,
+        "instr" | "ifnull" | "nullif" | "likelihood" => 2..=2,
+        "replace" => 3..=3,
+        "coalesce" | "iif" | "if" => 2..=usize::MAX,
+        "min" | "max" => 1..=usize::MAX,
+        "printf" | "format" | "char" => 0..=usize::MAX,
+        "date" | "time" | "datetime" | "julianday" | "unixepoch" | "strftime" => 0..=usize::MAX,
+        "timediff" => 2..=2,
+        "json" | "json_quote" => 1..=1,
+        "json_valid" | "json_type" | "json_array_length" => 1..=2,
+        "json_array" | "json_object" => 0..=usize::MAX,
+        "json_extract" | "json_insert" | "json_replace" | "json_set" | "json_remove" => 1..=usize::MAX,
+        _ => return None,
+    })
+}
+
+/// Whether `name` is one of the built-in scalar functions.
+pub fn is_scalar_function(name: &str) -> bool {
+    arity(name).is_some()
+}
+
+/// Whether a call always gives the same result for the same arguments.
+pub fn is_deterministic(name: &str, args: &FunctionArgs) -> bool {
+    is_scalar_function(name) && !is_aggregate(name, args) && !matches!(name, "random" | "randomblob")
+}
+
+/// The arguments of a call to a scalar function, once the function and their number are checked.
+pub fn arguments<'e>(name: &str, args: &'e FunctionArgs, span: Span) -> Result<&'e [Expr], ParseError> {
+    let Some(arity) = arity(name) else {
+        return Err(ParseError::new(format!("no such function: {}", name), span));
+    };
+    // f(*) is f() for anything but count
+    let args = match args {
+        FunctionArgs::Star => &[][..],
+        FunctionArgs::List(args) => &args[..],
+    };
+    if !arity.contains(&args.len()) {
+        return Err(ParseError::new(format!("wrong number of arguments to function {}()", name), span));
+    }
+    Ok(args)
+}
+
+/// Evaluates a call to a scalar function.
+/// `coalesce`, `ifnull` and `iif` only evaluate the arguments they need.
+pub fn call(ctx: &EvalContext, name: &str, args: &FunctionArgs, span: Span) -> Result<Value, Box<dyn Error>> {
+    let args = arguments(name, args, span)?;
+    // the JSON functions tell JSON arguments from text by where they come from
+    if json::is_json_function(name) {
+        return json::call(ctx, name, args);
+    }
+
+    match name {
+        "coalesce" | "ifnull" => {
+            for arg in args {
+                let value = ctx.eval(arg)?;
+                if !value.is_null() {
+                    return Ok(value);
+                }
+            }
+            return Ok(Value::Null);
+        }
+        // iif(c1, v1, c2, v2, ..., else) works like CASE
+        "iif" | "if" => {
+            for pair in args.chunks(2) {
+                match pair {
+                    [condition, value] => {
+                        if ctx.is_true(condition)? {
+                            return ctx.eval(value);
+                        }
+                    }
+                    [otherwise] => return ctx.eval(otherwise),
+                    _ => unreachable!(),
+                }
+            }
+            return Ok(Value::Null);
+        }
+        "likelihood" => {
+            let probability = match &args[1] {
+                Expr::Literal(Value::Real(r)) => Some(*r),
+                _ => None,
+            };
+            if !probability.is_some_and(|p| (0.0..=1.0).contains(&p)) {
+                return Err(ParseError::new(
+                    "second argument to likelihood() must be a constant between 0.0 and 1.0",
+                    span,
+                )
+                .into());
+            }
+            return ctx.eval(&args[0]);
+        }
+        _ => {}
+    }
+
+    let values = args.iter().map(|arg| ctx.eval(arg)).collect::<Result<Vec<_>, _>>()?;
+    apply(name, &values, ctx.encoding, ctx.clock)
+}
+
+/// Runs a scalar function on evaluated arguments whose number has been checked, for a database
+/// that stores text in `encoding`, with 'now' read from `clock`.
+fn apply(name: &str, args: &[Value], encoding: TextEncoding, clock: Clock) -> Result<Value, Box<dyn Error>> {
+    let arg = |i: usize| args.get(i).unwrap_or(&Value::Null);
+    // replacing an empty string gives X as text, whatever the replacement is
+    if name == "replace" && !arg(0).is_null() && !arg(1).is_null() && arg(1).to_text(encoding).is_empty() {
+        return Ok(match arg(0) {
+            Value::Blob(_) => arg(0).clone(),
+            value => Value::Text(value.to_text(encoding)),
+        });
+    }
+    if matches!(name, "lower" | "upper" | "trim" | "ltrim" | "rtrim" | "replace" | "substr" | "substring"
+        | "instr" | "length" | "unicode" | "round" | "abs")
+        && args.iter().any(Value::is_null)
+    {
+        return Ok(Value::Null);
+    }
+
+    Ok(match name {
+        "length" => Value::Integer(match arg(0) {
+            Value::Blob(b) => b.len(),
+            // text ends at its first NUL
+            value => value.to_text(encoding).chars().take_while(|&c| c != '\0').count(),
+        } as i64),
+
+        "lower" => Value::Text(arg(0).to_text(encoding).to_ascii_lowercase()),
+        "upper" => Value::Text(arg(0).to_text(encoding).to_ascii_uppercase()),
+
+        "substr" | "substring" => substr(arg(0), arg(1).to_i64(encoding), args.get(2).map(|n| n.to_i64(encoding)), encoding),
+
+        "trim" | "ltrim" | "rtrim" => {
+            let set = match args.get(1) {
+                Some(set) => set.to_text(encoding),
+                None => " ".to_string(),
+            };
+            let text = arg(0).to_text(encoding);
+            let trimmed = match name {
+                "ltrim" => text.trim_start_matches(|c| set.contains(c)),
+                "rtrim" => text.trim_end_matches(|c| set.contains(c)),
+                _ => text.trim_matches(|c| set.contains(c)),
+            };
+            Value::Text(trimmed.to_string())
+        }
+
+        "replace" => Value::Text(arg(0).to_text(encoding).replace(&arg(1).to_text(encoding), &arg(2).to_text(encoding))),
+
+        "instr" => Value::Integer(match (arg(0), arg(1)) {
+            (Value::Blob(haystack), Value::Blob(needle)) => match needle.len() {
+                0 => 1,
+                n => haystack.windows(n).position(|w| w == &needle[..]).map_or(0, |i| i + 1),
+            },
+            (haystack, needle) => {
+                let haystack = haystack.to_text(encoding);
+                haystack.find(&needle.to_text(encoding)).map_or(0, |i| haystack[..i].chars().count() + 1)
+            }
+        } as i64),
+
+        "abs" => match arg(0) {
+            Value::Integer(n) => Value::Integer(n.checked_abs().ok_or("integer overflow")?),
+            value => Value::Real(value.to_f64(encoding).abs()),
+        },
+
+        "round" => round(arg(0).to_f64(encoding), args.get(1).map_or(0, |n| n.to_i64(encoding))),
+
+        "nullif" => match arg(0).compare(arg(1)) {
+            Ordering::Equal => Value::Null,
+            _ => arg(0).clone(),
+        },
+
+        "typeof" => Value::Text(
+            match arg(0) {
+                Value::Null => "null",
+                Value::Integer(_) => "integer",
+                Value::Real(_) => "real",
+                Value::Text(_) => "text",
+                Value::Blob(_) => "blob",
+            }
+            .to_string(),
+        ),
+
+        "hex" => Value::Text(match arg(0) {
+            Value::Null => String::new(),
+            Value::Blob(b) => hex(b),
+            Value::Text(t) => hex(&encoding.encode(t)),
+            number => hex(number.to_text(encoding).as_bytes()),
+        }),
+
+        "quote" => Value::Text(quote(arg(0))),
+
+        "printf" | "format" => match args.first() {
+            None | Some(Value::Null) => Value::Null,
+            Some(format) => Value::Text(printf(&format.to_text(encoding), &args[1..], encoding)?),
+        },
+
+        "unicode" => match arg(0).to_text(encoding).chars().next() {
+            Some(c) => Value::Integer(c as i64),
+            None => Value::Null,
+        },
+
+        "char" => Value::Text(
+            args.iter()
+                .map(|value| {
+                    u32::try_from(value.to_i64(encoding)).ok().and_then(char::from_u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+                })
+                .collect(),
+        ),
+
+        "min" | "max" => {
+            // ties go to the last argument for min and to the first one for max
+            let mut best = arg(0);
+            for value in args {
+                if value.is_null() {
+                    return Ok(Value::Null);
+                }
+                let order = Collation::Binary.compare(best, value, encoding);
+                let better = if name == "min" { order != Ordering::Less } else { order == Ordering::Less };
+                if better {
+                    best = value;
+                }
+            }
+            best.clone()
+        }
+
+        "random" => Value::Integer(random() as i64),
+
+        "randomblob" => {
+            let n = arg(0).to_i64(encoding).max(1) as usize;
+            check_length(n)?;
+            Value::Blob((0..n.div_ceil(8)).flat_map(|_| random().to_le_bytes()).take(n).collect())
+        }
+
+        "zeroblob" => {
+            let n = arg(0).to_i64(encoding).max(0) as usize;
+            check_length(n)?;
+            Value::Blob(vec![0; n])
+        }
+
+        "likely" | "unlikely" => arg(0).clone(),
+
+        "date" | "time" | "datetime" | "julianday" | "unixepoch" | "strftime" | "timediff" => {
+            datetime::call(name, args, encoding, clock)?
+        }
+
+        _ => unreachable!("no scalar function {}", name),
+    })
+}
+
+fn check_length(n: usize) -> Result<(), Box<dyn Error>> {
+    if n > MAX_LENGTH {
+        return Err("string or blob too big".into());
+    }
+    Ok(())
+}
+
+/// substr(X, start, length): positions count characters of text and bytes of blobs, start at 1,
+/// and a negative start counts from the end. A negative length takes the characters before start.
+fn substr(value: &Value, start: i64, length: Option<i64>, encoding: TextEncoding) -> Value {
+    let blob = matches!(value, Value::Blob(_));
+    let text = if blob { String::new() } else { value.to_text(encoding) };
+    let len = match value {
+        Value::Blob(b) => b.len() as i64,
+        _ if start < 0 => text.chars().count() as i64,
+        _ => 0,
+    };
+
+    let (mut p1, mut p2) = (start, length.map_or(i64::MAX, i64::saturating_abs));
+    if p1 < 0 {
+        p1 += len;
+        if p1 < 0 {
+            p2 = p2.saturating_add(p1).max(0);
+            p1 = 0;
+        }
+    } else if p1 > 0 {
+        p1 -= 1;
+    } else if p2 > 0 {
+        p2 -= 1;
+    }
+    if length.is_some_and(|l| l < 0) {
+        p1 -= p2;
+        if p1 < 0 {
+            p2 += p1;
+            p1 = 0;
+        }
+    }
+
+    let (p1, p2) = (p1 as usize, p2.max(0) as usize);
+    match value {
+        Value::Blob(b) => Value::Blob(b.iter().skip(p1).take(p2).copied().collect()),
+        _ => Value::Text(text.chars().skip(p1).take(p2).collect()),
+    }
+}
+
+/// round(X, N) always gives a REAL; N is kept within 0..=30.
+fn round(r: f64, digits: i64) -> Value {
+    let digits = digits.clamp(0, 30) as usize;
+    // beyond 2^52 a double has no fractional part
+    if !(-4503599627370496.0..=4503599627370496.0).contains(&r) {
+        return Value::Real(r);
+    }
+    if digits == 0 {
+        return Value::Real((r + if r < 0.0 { -0.5 } else { 0.5 }) as i64 as f64);
+    }
+    let spec = Spec { alternate2: true, precision: Some(digits), ..Spec::default() };
+    Value::Real(float(r, b'f', &spec).parse().unwrap_or(r))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// An SQL literal for the value.
+fn quote(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(n) => n.to_string(),
+        Value::Real(r) if r.is_infinite() => if *r > 0.0 { "9.0e+999" } else { "-9.0e+999" }.to_string(),
+        Value::Real(r) => {
+            // 15 digits unless that loses precision
+            let short = format_real(*r);
+            if short.parse::<f64>() == Ok(*r) {
+                short
+            } else {
+                float(*r, b'e', &Spec { alternate2: true, precision: Some(20), ..Spec::default() })
+            }
+        }
+        Value::Text(s) => format!("'{}'", s.replace('\'', "''")),
+        Value::Blob(b) => format!("X'{}'", hex(b)),
+    }
+}
+
+static RANDOM_CALLS: AtomicU64 = AtomicU64::new(0);
+
+/// A pseudo-random number from the standard library's randomly keyed hasher.
+fn random() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(RANDOM_CALLS.fetch_add(1, AtomicOrdering::Relaxed));
+    hasher.finish()
+}
+
+
+/// Flags, width and precision of one printf conversion.
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    /// '+' or ' ' in front of non-negative numbers
+    sign: Option<char>,
+    /// '#'
+    alternate: bool,
+    /// '!': characters instead of bytes, more digits for reals
+    alternate2: bool,
+    zero: bool,
+    /// ','
+    thousands: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+/// The arguments consumed by printf conversions; missing ones are NULL.
+struct Arguments<'a> {
+    values: &'a [Value],
+    next: usize,
+    encoding: TextEncoding,
+}
+
+impl<'a> Arguments<'a> {
+    fn next(&mut self) -> &'a Value {
+        let value = self.values.get(self.next).unwrap_or(&Value::Null);
+        self.next += 1;
+        value
+    }
+
+    fn int(&mut self) -> i64 {
+        self.next().to_i64(self.encoding)
+    }
+
+    /// NULL is `None`, which most conversions print as an empty string.
+    fn text(&mut self) -> Option<String> {
+        Some(self.next()).filter(|v| !v.is_null()).map(|v| v.to_text(self.encoding))
+    }
+}
+
+/// SQLite's printf: the C conversions plus %q, %Q, %w (SQL quoting) and %r (ordinals),
+/// with ',' for thousands separators and '!' for character widths.
+/// An unknown conversion ends the output. Blobs are read as `encoding` text.
+pub fn printf(format: &str, values: &[Value], encoding: TextEncoding) -> Result<String, Box<dyn Error>> {
+    let bytes = format.as_bytes();
+    let at = |i: usize| bytes.get(i).copied().unwrap_or(0);
+    let mut args = Arguments { values, next: 0, encoding };
+    let mut out = String::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            let end = bytes[i..].iter().position(|&b| b == b'%').map_or(bytes.len(), |n| i + n);
+            out.push_str(&format[i..end]);
+            i = end;
+            continue;
+        }
+        i += 1;
+        if i == bytes.len() {
+            out.push('%');
+            break;
+        }
+
+        let mut spec = Spec::default();
+        let mut c = at(i);
+        loop {
+            match c {
+                b'-' => spec.left = true,
+                b'+' => spec.sign = Some('+'),
+                b' ' => spec.sign = Some(' '),
+                b'#' => spec.alternate = true,
+                b'!' => spec.alternate2 = true,
+                b'0' => spec.zero = true,
+                b',' => spec.thousands = true,
+                // length modifiers change nothing here
+                b'l' => {
+                    i += 1;
+                    if at(i) == b'l' {
+                        i += 1;
+                    }
+                    c = at(i);
+                    break;
+                }
+                b'1'..=b'9' => {
+                    let mut width = 0usize;
+                    while at(i).is_ascii_digit() {
+                        width = width.saturating_mul(10).saturating_add((at(i) - b'0') as usize);
+                        i += 1;
+                    }
+                    spec.width = width;
+                    c = at(i);
+                    if c != b'.' && c != b'l' {
+                        break;
+                    }
+                    i -= 1;
+                }
+                b'*' => {
+                    let width = args.int();
+                    if width < 0 {
+                        spec.left = true;
+                    }
+                    spec.width = width.unsigned_abs() as usize;
+                    if at(i + 1) != b'.' && at(i + 1) != b'l' {
+                        i += 1;
+                        c = at(i);
+                        break;
+                    }
+                }
+                b'.' => {
+                    i += 1;
+                    c = at(i);
+                    if c == b'*' {
+                        // a negative precision counts as positive
+                        spec.precision = Some(args.int().unsigned_abs() as usize);
+                        i += 1;
+                        c = at(i);
+                    } else {
+                        let mut precision = 0usize;
+                        while c.is_ascii_digit() {
+                            precision = precision.saturating_mul(10).saturating_add((c - b'0') as usize);
+                            i += 1;
+                            c = at(i);
+                        }
+                        spec.precision = Some(precision);
+                    }
+                    if c != b'l' {
+                        break;
+                    }
+                    i -= 1;
+                }
+                _ => break,
+            }
+            i += 1;
+            c = at(i);
+            if c == 0 {
+                break;
+            }
+        }
+        i += 1;
+        if spec.width > MAX_LENGTH || spec.precision.is_some_and(|p| p > MAX_LENGTH) {
+            return Err("string or blob too big".into());
+        }
+
+        // the converted text, and whether the width counts characters rather than bytes
+        let (text, in_chars) = match c {
+            b'd' | b'i' | b'u' | b'x' | b'X' | b'o' | b'p' | b'r' => (integer(args.int(), c, &spec), false),
+            b'f' | b'e' | b'E' | b'g' | b'G' => (float(args.next().to_f64(encoding), c, &spec), false),
+            b's' | b'z' => {
+                let text = args.text().unwrap_or_default();
+                let text = match spec.precision {
+                    Some(p) if spec.alternate2 => text.chars().take(p).collect(),
+                    Some(p) => text[..floor_char_boundary(&text, p)].to_string(),
+                    None => text,
+                };
+                (text, spec.alternate2)
+            }
+            b'c' => {
+                let c = args.text().and_then(|s| s.chars().next());
+                let text = c.map(|c| c.to_string().repeat(spec.precision.unwrap_or(1).max(1)));
+                (text.unwrap_or_default(), true)
+            }
+            b'q' | b'Q' | b'w' => {
+                let quote = if c == b'w' { '"' } else { '\'' };
+                let text = match args.text() {
+                    Some(text) => {
+                        let text: String = text.chars().take(spec.precision.unwrap_or(usize::MAX)).collect();
+                        let escaped = text.replace(quote, &format!("{0}{0}", quote));
+                        if c == b'Q' { format!("'{}'", escaped) } else { escaped }
+                    }
+                    None if c == b'Q' => "NULL".to_string(),
+                    None => "(NULL)".to_string(),
+                };
+                (text, spec.alternate2)
+            }
+            b'%' => ("%".to_string(), false),
+            b'n' => continue,
+            _ => break,
+        };
+
+        let len = if in_chars { text.chars().count() } else { text.len() };
+        let padding = " ".repeat(spec.width.saturating_sub(len));
+        if spec.left {
+            out.push_str(&text);
+            out.push_str(&padding);
+        } else {
+            out.push_str(&padding);
+            out.push_str(&text);
+        }
+    }
+    Ok(out)
+}
+
+fn floor_char_boundary(s: &str, mut at: usize) -> usize {
+    if at >= s.len() {
+        return s.len();
+    }
+    while !s.is_char_boundary(at) {
+        at -= 1;
+    }
+    at
+}
+
+/// The integer conversions: %d %i %u %x %X %o %p and %r.
+fn integer(n: i64, conversion: u8, spec: &Spec) -> String {
+    let signed = matches!(conversion, b'd' | b'i' | b'r');
+    let (magnitude, sign) = match signed {
+        true if n < 0 => (n.unsigned_abs(), Some('-')),
+        true => (n as u64, spec.sign),
+        false => (n as u64, None),
+    };
+    let (base, prefix) = match conversion {
+        b'x' => (16, "0x"),
+        b'X' => (16, "0X"),
+        b'p' => (16, ""),
+        b'o' => (8, "0"),
+        _ => (10, ""),
+    };
+
+    let mut digits = match base {
+        16 if conversion == b'X' => format!("{:X}", magnitude),
+        16 => format!("{:x}", magnitude),
+        8 => format!("{:o}", magnitude),
+        _ => magnitude.to_string(),
+    };
+    if conversion == b'r' {
+        let suffix = match magnitude % 10 {
+            _ if (magnitude / 10) % 10 == 1 => "th",
+            1 => "st",
+            2 => "nd",
+            3 => "rd",
+            _ => "th",
+        };
+        digits.push_str(suffix);
+    }
+
+    // zero padding fills the width through the precision
+    let mut precision = spec.precision.unwrap_or(0);
+    if spec.zero {
+        precision = precision.max(spec.width.saturating_sub(sign.is_some() as usize));
+    }
+    if digits.len() < precision {
+        digits.insert_str(0, &"0".repeat(precision - digits.len()));
+    }
+    if spec.thousands && matches!(conversion, b'd' | b'i' | b'u') {
+        digits = group_thousands(&digits);
+    }
+
+    let mut out = String::new();
+    if spec.alternate && magnitude != 0 {
+        out.push_str(prefix);
+    }
+    out.extend(sign);
+    out.push_str(&digits);
+    out
+}
+
+fn group_thousands(digits: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// The decimal digits of a real the way SQLite's printf sees them.
+struct Decimal {
+    negative: bool,
+    /// significant digits, without trailing zeros
+    digits: Vec<u8>,
+    /// how many of the digits come before the decimal point, negative for leading zeros
+    point: i32,
+}
+
+impl Decimal {
+    /// At most 19 significant digits, rounded half up to `round` digits or, when `round` is 0 or
+    /// negative, to `-round` decimals. `max_digits` caps the rounding position.
+    fn new(r: f64, round: i32, max_digits: i32) -> Self {
+        if r == 0.0 {
+            return Decimal { negative: false, digits: vec![0], point: 1 };
+        }
+        let scientific = format!("{:.24e}", r.abs());
+        let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+        // SQLite scales the value into a 64-bit integer below 2^63, which holds 18 or 19 digits
+        let mut digits: Vec<u8> =
+            mantissa.bytes().filter(u8::is_ascii_digit).map(|b| b - b'0').take(19).collect();
+        if digits.iter().map(|d| (b'0' + d) as char).collect::<String>().as_str() > "9223372036854774784" {
+            digits.truncate(18);
+        }
+        let mut point = exponent.parse::<i32>().unwrap_or(0) + 1;
+
+        let mut round = round;
+        if round <= 0 {
+            round = point - round;
+            if round == 0 && digits[0] >= 5 {
+                round = 1;
+                digits.insert(0, 0);
+                point += 1;
+            }
+        }
+        if round > 0 && (round < digits.len() as i32 || digits.len() as i32 > max_digits) {
+            let round = round.min(max_digits) as usize;
+            let up = digits[round] >= 5;
+            digits.truncate(round);
+            if up {
+                let mut j = round;
+                loop {
+                    if j == 0 {
+                        digits.insert(0, 1);
+                        point += 1;
+                        break;
+                    }
+                    j -= 1;
+                    if digits[j] == 9 {
+                        digits[j] = 0;
+                    } else {
+                        digits[j] += 1;
+                        break;
+                    }
+                }
+            }
+        }
+        while digits.len() > 1 && digits.last() == Some(&0) {
+            digits.pop();
+        }
+        Decimal { negative: r < 0.0, digits, point }
+    }
+}
+
+/// The real conversions %f, %e, %E, %g and %G.
+/// Unlike C they round half away from zero on at most 16 significant digits (26 with '!').
+fn float(r: f64, conversion: u8, spec: &Spec) -> String {
+    if r.is_nan() {
+        return if spec.zero { "null" } else { "NaN" }.to_string();
+    }
+    if r.is_infinite() {
+        let sign = if r < 0.0 { Some('-') } else { spec.sign.filter(|&c| c == '+') };
+        return sign.into_iter().chain("Inf".chars()).collect();
+    }
+
+    let mut precision = spec.precision.unwrap_or(6) as i32;
+    let generic = matches!(conversion, b'g' | b'G');
+    let round = match conversion {
+        b'f' => -precision,
+        _ if generic => {
+            precision = precision.max(1);
+            precision
+        }
+        _ => precision + 1,
+    };
+    let decimal = Decimal::new(r, round, if spec.alternate2 { 26 } else { 16 });
+    let sign = if decimal.negative { Some('-') } else { spec.sign };
+    let exponent = decimal.point - 1;
+
+    // %g picks %e or %f and drops trailing zeros
+    let mut exponential = matches!(conversion, b'e' | b'E');
+    let trim_zeros = if generic {
+        precision -= 1;
+        if exponent < -4 || exponent > precision {
+            exponential = true;
+        } else {
+            precision -= exponent;
+        }
+        !spec.alternate
+    } else {
+        spec.alternate2
+    };
+
+    let mut digits = decimal.digits.iter().map(|d| (b'0' + d) as char);
+    let mut next_digit = || digits.next().unwrap_or('0');
+    let mut out = String::new();
+    out.extend(sign);
+    let mut e2 = if exponential { 0 } else { decimal.point - 1 };
+    if e2 < 0 {
+        out.push('0');
+    } else {
+        while e2 >= 0 {
+            out.push(next_digit());
+            if spec.thousands && e2 % 3 == 0 && e2 > 1 {
+                out.push(',');
+            }
+            e2 -= 1;
+        }
+    }
+    let point = precision > 0 || spec.alternate || spec.alternate2;
+    if point {
+        out.push('.');
+    }
+    e2 += 1;
+    while e2 < 0 && precision > 0 {
+        out.push('0');
+        precision -= 1;
+        e2 += 1;
+    }
+    while precision > 0 {
+        out.push(next_digit());
+        precision -= 1;
+    }
+    if trim_zeros && point {
+        while out.ends_with('0') {
+            out.pop();
+        }
+        if out.ends_with('.') {
+            if spec.alternate2 {
+                out.push('0');
+            } else {
+                out.pop();
+            }
+        }
+    }
+    if exponential {
+        out.push(if conversion.is_ascii_uppercase() { 'E' } else { 'e' });
+        out.push(if exponent < 0 { '-' } else { '+' });
+        out.push_str(&format!("{:02}", exponent.abs()));
+    }
+
+    if spec.zero && !spec.left && out.len() < spec.width {
+        let at = sign.is_some() as usize;
+        out.insert_str(at, &"0".repeat(spec.width - out.len()));
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(format: &str, values: &[Value]) -> String {
+        printf(format, values, TextEncoding::Utf8).unwrap()
+    }
+
+    fn integers(values: &[i64]) -> Vec<Value> {
+        values.iter().map(|&n| Value::Integer(n)).collect()
+    }
+
+    #[test]
+    fn test_printf_integers() {
+        assert_eq!(text("%5d|%-5d|%05d|%+d|%.3d", &integers(&[42, 42, 42, 42, 5])), "   42|42   |00042|+42|005");
+        assert_eq!(text("%,d %u", &integers(&[-1234567, -1])), "-1,234,567 18446744073709551615");
+        assert_eq!(text("%#x %#o %X", &integers(&[255, 8, 255])), "0xff 010 FF");
+        assert_eq!(text("%r %r %r", &integers(&[1, 12, 23])), "1st 12th 23rd");
+        assert_eq!(text("%d %d", &[Value::Text("12abc".into())]), "12 0");
+        // an unknown conversion ends the output
+        assert_eq!(text("%d%hd", &integers(&[5])), "5");
+    }
+
+    #[test]
+    fn test_printf_reals() {
+        let cases = [
+            ("%.0f", 2.5, "3"),
+            ("%.2f", 2.675, "2.67"),
+            ("%.20f", 0.1, "0.10000000000000000000"),
+            ("%e", 12345.678, "1.234568e+04"),
+            ("%g", 1000000.0, "1e+06"),
+            ("%g", 123456.5, "123457"),
+            ("%#g", 1.0, "1.00000"),
+            ("%!.3g", 1.0, "1.0"),
+            ("%08.3f", -1.23456, "-001.235"),
+            ("%,.1f", 1234567.25, "1,234,567.3"),
+        ];
+        for (format, r, expected) in cases {
+            assert_eq!(text(format, &[Value::Real(r)]), expected, "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_printf_strings() {
+        let values = [Value::Text("it's".into()), Value::Null, Value::Text("abc".into())];
+        assert_eq!(text("%q|%Q|%5.1s|%c", &values), "it''s|NULL|    a|");
+        assert_eq!(text("%s%%", &[Value::Real(1.0)]), "1.0%");
+    }
+
+    #[test]
+    fn test_substr() {
+        let hello = Value::Text("hello".into());
+        let cases = [
+            (0, Some(2), "h"),
+            (-3, None, "llo"),
+            (-3, Some(2), "ll"),
+            (2, Some(-1), "h"),
+            (3, Some(-5), "he"),
+            (-10, Some(8), "hel"),
+        ];
+        for (start, length, expected) in cases {
+            assert_eq!(substr(&hello, start, length, TextEncoding::Utf8), Value::Text(expected.into()));
+        }
+    }
+
+    #[test]
+    fn test_round() {
+        assert_eq!(round(-2.5, 0), Value::Real(-3.0));
+        assert_eq!(round(2.675, 2), Value::Real(2.67));
+        assert_eq!(round(0.125, 2), Value::Real(0.13));
+        assert_eq!(round(1234567.891, 1), Value::Real(1234567.9));
+    }
+}
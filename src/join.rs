@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::error::Error;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::aggregate::GroupKey;
+use crate::collation::Collation;
+use crate::datetime::Clock;
+use crate::eval::{self, comparison_affinity, comparison_collation, EvalContext, Scope, SubqueryRunner};
+use crate::json;
+use crate::planner::{plan_access, AccessPlan, ScanPlan, TableScan};
+use crate::read::Pager;
+use crate::schema::{Schema, SchemaEntry};
+use crate::sql::ast::{BinaryOp, Expr, JoinKind, TableDef};
+use crate::value::{Affinity, Value};
+
+
+type Rows<'a> = Box<dyn Iterator<Item = Result<Vec<Value>, Box<dyn Error>>> + 'a>;
+
+/// rowids by hash of their join key
+type RowidTable = HashMap<u64, Vec<i64>>;
+
+
+/// One entry of the FROM clause. Its columns follow those of the entries before it in the scope.
+pub struct JoinTable<'a> {
+    pub rows: TableRows<'a>,
+    pub column_count: usize,
+    pub join: JoinKind,
+    /// ON terms, including the equalities implied by USING and NATURAL
+    pub on: Vec<Expr>,
+}
+
+pub enum TableRows<'a> {
+    /// a table of the database, read through its b-tree; its rowid follows the columns unless it is WITHOUT ROWID
+    Stored { entry: &'a SchemaEntry, definition: Rc<TableDef> },
+    /// the result of a subquery in FROM, computed before the join
+    Derived(Rc<Vec<Vec<Value>>>),
+    /// a table-valued function, called again for every row of the tables before it;
+    /// the position of each row follows the columns as its rowid
+    Function { name: String, args: Vec<Expr> },
+}
+
+impl<'a> JoinTable<'a> {
+    /// values per row
+    fn width(&self) -> usize {
+        match &self.rows {
+            TableRows::Stored { definition, .. } if definition.without_rowid => self.column_count,
+            TableRows::Stored { .. } | TableRows::Function { .. } => self.column_count + 1,
+            TableRows::Derived(_) => self.column_count,
+        }
+    }
+
+    fn scan(&'a self, pager: &'a Pager, plan: &ScanPlan, clock: Clock) -> Result<Rows<'a>, Box<dyn Error>> {
+        Ok(match &self.rows {
+            TableRows::Stored { entry, definition } => Box::new(TableScan::new(pager, entry.rootpage, definition, plan, clock)?),
+            TableRows::Derived(rows) => Box::new(rows.iter().cloned().map(Ok)),
+            TableRows::Function { .. } => return Err("Table-valued function read without its arguments".into()),
+        })
+    }
+
+    /// Rows of a table-valued function for the current row of the tables before it.
+    fn call(&self, ctx: &EvalContext) -> Result<Rows<'a>, Box<dyn Error>> {
+        let TableRows::Function { name, args } = &self.rows else {
+            return Err("Not a table-valued function".into());
+        };
+        let values = args.iter().map(|arg| ctx.eval(arg)).collect::<Result<Vec<_>, _>>()?;
+        let rows = json::table_rows(name, &values, ctx.encoding)?;
+        Ok(Box::new(rows.into_iter().enumerate().map(|(position, mut row)| {
+            row.push(Value::Integer(position as i64));
+            Ok(row)
+        })))
+    }
+
+    /// The row a hash join remembered: a rowid, or a position among derived rows.
+    fn fetch(&'a self, pager: &'a Pager, id: i64, clock: Clock) -> Rows<'a> {
+        match &self.rows {
+            TableRows::Stored { .. } => {
+                let plan = ScanPlan::RowidRange { lower: Some(id), upper: Some(id) };
+                self.scan(pager, &plan, clock).unwrap_or_else(|e| Box::new(std::iter::once(Err(e))))
+            }
+            TableRows::Derived(rows) => Box::new(rows.get(id as usize).cloned().map(Ok).into_iter()),
+            TableRows::Function { .. } => Box::new(std::iter::once(Err("Table-valued function in a hash join".into()))),
+        }
+    }
+}
+
+
+enum Strategy<'a> {
+    /// seek or scan again for every row of the tables before
+    Lookup(AccessPlan),
+    Hash(HashJoin<'a>),
+}
+
+/// Rowids of the table read once, grouped by a hash of its side of the join equalities.
+/// Matching rows are fetched by rowid and checked against the terms like any other row,
+/// so only the hash and the rowid of each row are kept in memory.
+/// Derived tables are already in memory and are remembered by position instead.
+struct HashJoin<'a> {
+    plan: AccessPlan,
+    /// this table's side of each equality
+    build: Vec<&'a Expr>,
+    /// the other side, over the tables before
+    probe: Vec<&'a Expr>,
+    /// what both sides of each equality are converted to before they are compared
+    affinities: Vec<Affinity>,
+    /// terms on this table alone, checked while building
+    filters: Vec<&'a Expr>,
+    rowids: Option<RowidTable>,
+}
+
+impl HashJoin<'_> {
+    /// Reads the table into the hash table; `None` if it does not fit in the memory budget.
+    fn load(
+        &self,
+        pager: &Pager,
+        ctx: &EvalContext,
+        table: &JoinTable,
+        start: usize,
+        memory_budget: usize,
+    ) -> Result<Option<RowidTable>, Box<dyn Error>> {
+        let mut rowids = RowidTable::new();
+        let Some(plan) = self.plan.bind(ctx)? else {
+            return Ok(Some(rowids));
+        };
+
+        // the table's values sit after the enclosing row and NULLs standing in for the tables before
+        let mut combined = ctx.row[..ctx.scope.own_start()].to_vec();
+        combined.resize(start, Value::Null);
+        let mut size = 0;
+        for (position, values) in table.scan(pager, &plan, ctx.clock)?.enumerate() {
+            combined.truncate(start);
+            combined.extend(values?);
+            let ctx = EvalContext::new(ctx.scope, &combined, ctx.encoding, ctx.clock).with_subqueries(ctx.subqueries);
+            if !passes(&ctx, self.filters.iter().copied())? {
+                continue;
+            }
+            let key = join_key(&ctx, &self.build, &self.affinities)?;
+            // NULL equals nothing
+            if key.iter().any(Value::is_null) {
+                continue;
+            }
+
+            size += size_of::<u64>() + size_of::<i64>();
+            if size > memory_budget {
+                return Ok(None);
+            }
+            let id = match (&table.rows, combined.last()) {
+                (TableRows::Derived(_), _) => position as i64,
+                (TableRows::Stored { .. }, Some(Value::Integer(rowid))) => *rowid,
+                _ => return Err("Table row without rowid".into()),
+            };
+            rowids.entry(hash_key(key)).or_default().push(id);
+        }
+        Ok(Some(rowids))
+    }
+}
+
+
+struct Level<'a> {
+    rows: Rows<'a>,
+    /// a row got past the ON terms; otherwise a LEFT JOIN adds one of NULLs
+    matched: bool,
+}
+
+
+/// Rows of the FROM clause joined left to right with nested loops, filtered by ON and WHERE.
+/// Each table is read with the index or rowid seek its terms allow for the current outer row;
+/// without one, an equality join reads the table once into a hash table instead.
+/// Rows come out one at a time, so a LIMIT can stop the join early.
+/// In a subquery every row starts with the values of the enclosing row.
+pub struct JoinedRows<'a> {
+    pager: &'a Pager,
+    scope: &'a Scope,
+    subqueries: Option<&'a dyn SubqueryRunner>,
+    clock: Clock,
+    tables: &'a [JoinTable<'a>],
+    memory_budget: usize,
+    /// scope position of each table's first column
+    starts: Vec<usize>,
+    strategies: Vec<Strategy<'a>>,
+    /// WHERE terms of each level, checked once the last table they read is joined
+    filters: Vec<Vec<&'a Expr>>,
+    levels: Vec<Level<'a>>,
+    row: Vec<Value>,
+    started: bool,
+}
+
+impl<'a> JoinedRows<'a> {
+    /// `outer` has the scope of the joined rows and, in a correlated subquery, the enclosing row.
+    pub fn new(
+        pager: &'a Pager,
+        schema: &Schema,
+        outer: EvalContext<'a>,
+        tables: &'a [JoinTable<'a>],
+        where_terms: Vec<&'a Expr>,
+        memory_budget: usize,
+    ) -> Self {
+        let scope = outer.scope;
+        let mut starts = Vec::with_capacity(tables.len());
+        let mut start = scope.own_start();
+        for table in tables {
+            starts.push(start);
+            start += table.width();
+        }
+        let level_of = |position: usize| starts.iter().rposition(|&start| start <= position).unwrap_or(0);
+
+        let mut filters = vec![Vec::new(); tables.len().max(1)];
+        for term in where_terms {
+            let level = match column_positions(term, scope) {
+                Some(positions) => positions.into_iter().max().map_or(0, level_of),
+                // an unknown column is reported when the term is evaluated
+                None => filters.len() - 1,
+            };
+            filters[level].push(term);
+        }
+
+        let mut strategies = Vec::with_capacity(tables.len());
+        for (k, table) in tables.iter().enumerate() {
+            let columns = starts[k]..starts[k] + table.width();
+            // WHERE terms must not narrow a LEFT JOIN table, its unmatched rows still count
+            let mut terms: Vec<&Expr> = table.on.iter().collect();
+            if table.join != JoinKind::Left {
+                terms.extend(&filters[k]);
+            }
+
+            let plan = match &table.rows {
+                TableRows::Stored { entry, definition } => plan_access(schema, entry, definition, scope, columns.clone(), &terms),
+                TableRows::Derived(_) | TableRows::Function { .. } => AccessPlan::FullScan,
+            };
+            // a function's rows depend on its arguments, it is called for every outer row;
+            // a hash join remembers rowids, which a WITHOUT ROWID table does not have
+            let rescanned = match &table.rows {
+                TableRows::Stored { definition, .. } => definition.without_rowid,
+                TableRows::Function { .. } => true,
+                TableRows::Derived(_) => false,
+            };
+            let strategy = if k == 0 || plan.is_correlated() || rescanned {
+                Strategy::Lookup(plan)
+            } else {
+                hash_join(plan, scope, columns, &terms)
+            };
+            strategies.push(strategy);
+        }
+
+        let mut row = outer.row.to_vec();
+        row.resize(scope.own_start(), Value::Null);
+
+        JoinedRows {
+            pager,
+            scope,
+            subqueries: outer.subqueries,
+            clock: outer.clock,
+            tables,
+            memory_budget,
+            starts,
+            strategies,
+            filters,
+            levels: Vec::new(),
+            row,
+            started: false,
+        }
+    }
+
+    /// Starts reading table `depth` for the current row of the tables before it.
+    fn open(&mut self, depth: usize) -> Result<(), Box<dyn Error>> {
+        let table = &self.tables[depth];
+        let start = self.starts[depth];
+        self.row.truncate(start);
+        let ctx = EvalContext::new(self.scope, &self.row, self.pager.text_encoding(), self.clock).with_subqueries(self.subqueries);
+
+        if let Strategy::Hash(hash) = &mut self.strategies[depth]
+            && hash.rowids.is_none()
+        {
+            match hash.load(self.pager, &ctx, table, start, self.memory_budget)? {
+                Some(rowids) => hash.rowids = Some(rowids),
+                // too big to keep, fall back to reading the table for every outer row
+                None => self.strategies[depth] = Strategy::Lookup(hash.plan.clone()),
+            }
+        }
+
+        let rows: Rows<'a> = match &self.strategies[depth] {
+            Strategy::Lookup(_) if matches!(table.rows, TableRows::Function { .. }) => table.call(&ctx)?,
+            Strategy::Lookup(plan) => match plan.bind(&ctx)? {
+                Some(plan) => table.scan(self.pager, &plan, self.clock)?,
+                None => Box::new(std::iter::empty()),
+            },
+            Strategy::Hash(hash) => {
+                let key = join_key(&ctx, &hash.probe, &hash.affinities)?;
+                let matches = match key.iter().any(Value::is_null) {
+                    true => None,
+                    false => hash.rowids.as_ref().and_then(|rowids| rowids.get(&hash_key(key))),
+                };
+                let (pager, clock) = (self.pager, self.clock);
+                let ids = matches.cloned().unwrap_or_default();
+                Box::new(ids.into_iter().flat_map(move |id| table.fetch(pager, id, clock)))
+            }
+        };
+        self.levels.push(Level { rows, matched: false });
+        Ok(())
+    }
+
+    fn advance(&mut self) -> Result<Option<Vec<Value>>, Box<dyn Error>> {
+        if !self.started {
+            self.started = true;
+            // without FROM there is a single empty row
+            if self.tables.is_empty() {
+                let ctx = EvalContext::new(self.scope, &self.row, self.pager.text_encoding(), self.clock).with_subqueries(self.subqueries);
+                return Ok(passes(&ctx, self.filters[0].iter().copied())?.then(|| self.row.clone()));
+            }
+            self.open(0)?;
+        }
+
+        while let Some(depth) = self.levels.len().checked_sub(1) {
+            let table = &self.tables[depth];
+            let start = self.starts[depth];
+            let level = &mut self.levels[depth];
+
+            let values = match level.rows.next().transpose()? {
+                Some(values) => Some(values),
+                None if table.join == JoinKind::Left && !level.matched => {
+                    level.matched = true;
+                    None
+                }
+                None => {
+                    self.levels.pop();
+                    continue;
+                }
+            };
+
+            self.row.truncate(start);
+            match values {
+                Some(values) => {
+                    self.row.extend(values);
+                    let ctx = EvalContext::new(self.scope, &self.row, self.pager.text_encoding(), self.clock).with_subqueries(self.subqueries);
+                    if !passes(&ctx, table.on.iter())? {
+                        continue;
+                    }
+                    self.levels[depth].matched = true;
+                }
+                None => self.row.resize(start + table.width(), Value::Null),
+            }
+
+            let ctx = EvalContext::new(self.scope, &self.row, self.pager.text_encoding(), self.clock).with_subqueries(self.subqueries);
+            if !passes(&ctx, self.filters[depth].iter().copied())? {
+                continue;
+            }
+            if depth + 1 == self.tables.len() {
+                return Ok(Some(self.row.clone()));
+            }
+            self.open(depth + 1)?;
+        }
+
+        Ok(None)
+    }
+}
+
+impl Iterator for JoinedRows<'_> {
+    type Item = Result<Vec<Value>, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.advance().transpose()
+    }
+}
+
+
+/// Hashes the table on its equalities with the tables before, or rescans it if there are none.
+fn hash_join<'a>(plan: AccessPlan, scope: &Scope, columns: Range<usize>, terms: &[&'a Expr]) -> Strategy<'a> {
+    let within = |expr: &Expr, range: &Range<usize>| {
+        column_positions(expr, scope).is_some_and(|positions| positions.iter().all(|p| range.contains(p)))
+    };
+    let before = 0..columns.start;
+
+    let mut build = Vec::new();
+    let mut probe = Vec::new();
+    let mut affinities = Vec::new();
+    for term in terms {
+        // a collation other than BINARY changes what equal means, hashing would miss matches
+        let Expr::Binary { op: BinaryOp::Eq, left, right } = term else { continue };
+        let (Ok(l), Ok(r)) = (eval::collation(left, scope), eval::collation(right, scope)) else { continue };
+        if comparison_collation(l, r) != Collation::Binary {
+            continue;
+        }
+        if within(left, &columns) && within(right, &before) {
+            build.push(&**left);
+            probe.push(&**right);
+        } else if within(right, &columns) && within(left, &before) {
+            build.push(&**right);
+            probe.push(&**left);
+        } else {
+            continue;
+        }
+        affinities.push(comparison_affinity(eval::affinity(left, scope), eval::affinity(right, scope)));
+    }
+    if build.is_empty() {
+        return Strategy::Lookup(plan);
+    }
+
+    let filters = terms.iter().copied().filter(|term| within(term, &columns)).collect();
+    Strategy::Hash(HashJoin { plan, build, probe, affinities, filters, rowids: None })
+}
+
+/// One side of the join equalities, converted for the comparison.
+fn join_key(ctx: &EvalContext, side: &[&Expr], affinities: &[Affinity]) -> Result<Vec<Value>, Box<dyn Error>> {
+    side.iter().zip(affinities).map(|(expr, affinity)| Ok(affinity.apply(ctx.eval(expr)?))).collect()
+}
+
+/// Digest of a join key; 1 and 1.0 hash alike.
+fn hash_key(key: Vec<Value>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    GroupKey(key).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn passes<'e>(ctx: &EvalContext, terms: impl IntoIterator<Item = &'e Expr>) -> Result<bool, Box<dyn Error>> {
+    for term in terms {
+        if !ctx.is_true(term)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Scope positions of the columns an expression reads; `None` if one of them does not resolve,
+/// or the expression has a subquery, which may read any of them.
+fn column_positions(expr: &Expr, scope: &Scope) -> Option<Vec<usize>> {
+    let mut positions = Vec::new();
+    let mut pending = vec![expr];
+    while let Some(expr) = pending.pop() {
+        if expr.subquery().is_some() {
+            return None;
+        }
+        if let Expr::Column(column) = expr {
+            match scope.resolve(column) {
+                Ok(Some(position)) => positions.push(position),
+                // an unresolvable "name" is a string
+                Ok(None) if column.double_quoted => {}
+                _ => return None,
+            }
+        }
+        pending.extend(expr.children());
+    }
+    Some(positions)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parse;
+    use crate::sql::ast::{ResultColumn, Statement};
+    use crate::sql_engine::query_fixture;
+
+    #[test]
+    fn test_hash_join_terms() {
+        let Statement::Select(select) = parse("SELECT t.item = i.id, i.name = t.tag, t.item = t.tag").unwrap() else { panic!() };
+        let terms: Vec<&Expr> = select
+            .columns
+            .iter()
+            .map(|column| match column {
+                ResultColumn::Expr { expr, .. } => expr,
+                _ => panic!(),
+            })
+            .collect();
+        let mut scope = Scope::for_table("i", &["id".to_string(), "name".to_string(), "grp".to_string()]);
+        scope.columns[1].collation = Collation::NoCase;
+        scope.columns.extend(Scope::for_table("t", &["item".to_string(), "tag".to_string()]).columns);
+
+        let Strategy::Hash(hash) = hash_join(AccessPlan::FullScan, &scope, 4..7, &terms) else { panic!("expected a hash join") };
+        assert_eq!(hash.build, [terms[0].children()[0]]);
+        assert_eq!(hash.probe, [terms[0].children()[1]]);
+        assert_eq!(hash.filters, [terms[2]]);
+        // NOCASE equality cannot be hashed
+        assert!(matches!(hash_join(AccessPlan::FullScan, &scope, 4..7, &terms[1..2]), Strategy::Lookup(_)));
+    }
+
+    #[test]
+    fn test_joins() {
+        let joins = [
+            (
+                "SELECT g.id, g.label, count(i.id) FROM groups g LEFT JOIN items i ON i.grp = g.id GROUP BY g.id",
+                vec!["0|zero|200", "1|one|200", "2|two|200", "3|three|200", "10|ten|0", "11||0"],
+            ),
+            ("SELECT g.id FROM groups g LEFT JOIN items i ON i.grp = g.id WHERE i.id IS NULL", vec!["10", "11"]),
+            // tags has no index, it is hashed on item
+            (
+                "SELECT i.id, t.tag FROM items i LEFT JOIN tags t ON t.item = i.id WHERE i.id < 5 ORDER BY i.id, t.tag",
+                vec!["1|big", "1|red", "2|red", "3|", "4|blue"],
+            ),
+            // items is looked up by rowid
+            (
+                "SELECT t.tag, i.name FROM tags t LEFT JOIN items i ON i.id = t.item ORDER BY t.tag",
+                vec!["big|ITEM0001", "blue|item0004", "lost|", "red|ITEM0001", "red|item0002"],
+            ),
+            (
+                "SELECT t.tag, g.label FROM tags t JOIN items i ON i.id = t.item JOIN groups g ON g.id = i.grp ORDER BY 1, 2",
+                vec!["big|one", "red|one", "red|two"],
+            ),
+            // USING and NATURAL: unqualified, or by a table name both sides share, the left copy is meant
+            (
+                "SELECT id, t.tag, g.label FROM groups g JOIN (SELECT item AS id, tag FROM tags) t USING (id) ORDER BY 2, 1",
+                vec!["1|big|one", "1|red|one", "2|red|two"],
+            ),
+            ("SELECT id, label FROM groups NATURAL JOIN groups WHERE id < 3", vec!["0|zero", "1|one", "2|two"]),
+            (
+                "SELECT groups.id, count(*) FROM groups g NATURAL JOIN groups NATURAL JOIN groups WHERE g.id > 2 GROUP BY 1",
+                vec!["3|1", "10|1"],
+            ),
+        ];
+        for (sql, expected) in joins {
+            assert_eq!(query_fixture(sql).unwrap(), expected, "{}", sql);
+        }
+    }
+}
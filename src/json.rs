@@ -0,0 +1,1159 @@
+use std::error::Error;
+use std::fmt::{self, Write};
+
+use crate::encoding::TextEncoding;
+use crate::eval::{EvalContext, Scope};
+use crate::functions;
+use crate::sql::ast::{BinaryOp, Expr, FunctionArgs};
+use crate::value::{format_real, Value};
+
+
+/// Deepest nesting of arrays and objects accepted, as in SQLite.
+const MAX_DEPTH: usize = 1000;
+
+/// Columns of json_each() and json_tree(); the last two, `json` and `root`, are hidden.
+pub const TABLE_COLUMNS: [&str; 10] = ["key", "value", "type", "atom", "id", "parent", "fullkey", "path", "json", "root"];
+
+
+/// A parsed JSON value. Numbers and strings keep their JSON text, so they come out as written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    True,
+    False,
+    Integer(String),
+    Real(String),
+    /// the text between the quotes, escapes included
+    String(String),
+    Array(Vec<Json>),
+    /// members in order; duplicate keys are kept and lookups find the first
+    Object(Vec<(String, Json)>),
+}
+
+/// Minified JSON text.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::True => f.write_str("true"),
+            Json::False => f.write_str("false"),
+            Json::Integer(text) | Json::Real(text) => f.write_str(text),
+            Json::String(raw) => write!(f, "\"{}\"", raw),
+            Json::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_char(']')
+            }
+            Json::Object(members) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "\"{}\":{}", key, value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+impl Json {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::True => "true",
+            Json::False => "false",
+            Json::Integer(_) => "integer",
+            Json::Real(_) => "real",
+            Json::String(_) => "text",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    fn is_container(&self) -> bool {
+        matches!(self, Json::Array(_) | Json::Object(_))
+    }
+
+    /// The SQL value json_extract() gives: arrays and objects as JSON text, strings unescaped.
+    fn to_value(&self) -> Value {
+        match self {
+            Json::Null => Value::Null,
+            Json::True => Value::Integer(1),
+            Json::False => Value::Integer(0),
+            // integers too big for 64 bits become reals
+            Json::Integer(text) => text.parse().map(Value::Integer).unwrap_or_else(|_| Value::Real(text.parse().unwrap_or(0.0))),
+            Json::Real(text) => Value::Real(text.parse().unwrap_or(0.0)),
+            Json::String(raw) => Value::Text(unescape(raw).unwrap_or_else(|| raw.clone())),
+            Json::Array(_) | Json::Object(_) => Value::Text(self.to_string()),
+        }
+    }
+
+    /// Bytes of the value in SQLite's binary JSONB format, its header included.
+    /// json_each() and json_tree() number values by their offset in that format.
+    fn size(&self) -> usize {
+        let payload = self.payload();
+        header_size(payload) + payload
+    }
+
+    fn payload(&self) -> usize {
+        match self {
+            Json::Null | Json::True | Json::False => 0,
+            Json::Integer(text) | Json::Real(text) | Json::String(text) => text.len(),
+            Json::Array(items) => items.iter().map(Json::size).sum(),
+            Json::Object(members) => members.iter().map(|(key, value)| label_size(key) + value.size()).sum(),
+        }
+    }
+}
+
+fn header_size(payload: usize) -> usize {
+    match payload {
+        0..=11 => 1,
+        12..=0xff => 2,
+        0x100..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+fn label_size(key: &str) -> usize {
+    header_size(key.len()) + key.len()
+}
+
+
+/// Parses JSON text, JSON5 included.
+pub fn parse(text: &str) -> Result<Json, Box<dyn Error>> {
+    parse_checked(text).map(|(json, _)| json).ok_or_else(|| "malformed JSON".into())
+}
+
+/// The value and whether the text is plain RFC 8259 JSON; `None` if it is not even JSON5.
+fn parse_checked(text: &str) -> Option<(Json, bool)> {
+    let mut parser = Parser { text, bytes: text.as_bytes(), pos: 0, depth: 0, json5: false };
+    parser.skip_space();
+    let json = parser.value()?;
+    parser.skip_space();
+    (parser.pos == text.len()).then_some((json, !parser.json5))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+    /// a JSON5 extension was used
+    json5: bool,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        loop {
+            let rest = &self.text[self.pos..];
+            let skipped = match rest.chars().next() {
+                Some(' ' | '\t' | '\n' | '\r') => {
+                    self.pos += 1;
+                    continue;
+                }
+                Some(c) if is_json5_space(c) => c.len_utf8(),
+                _ if rest.starts_with("//") => rest.find('\n').map_or(rest.len(), |i| i + 1),
+                // an unterminated comment is left for the value to fail on
+                _ if rest.starts_with("/*") => match rest[2..].find("*/") {
+                    Some(i) => i + 4,
+                    None => return,
+                },
+                _ => return,
+            };
+            self.pos += skipped;
+            self.json5 = true;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.bytes.get(self.pos) == Some(&byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let found = self.text[self.pos..].starts_with(word);
+        if found {
+            self.pos += word.len();
+        }
+        found
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        match *self.bytes.get(self.pos)? {
+            b'{' => self.container(b'}'),
+            b'[' => self.container(b']'),
+            b'"' | b'\'' => self.string().map(Json::String),
+            _ if self.eat_word("null") => Some(Json::Null),
+            _ if self.eat_word("true") => Some(Json::True),
+            _ if self.eat_word("false") => Some(Json::False),
+            _ => self.number(),
+        }
+    }
+
+    /// An array or an object, `close` telling which; JSON5 allows a trailing comma.
+    fn container(&mut self, close: u8) -> Option<Json> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return None;
+        }
+        self.pos += 1;
+        let mut items = Vec::new();
+        let mut members = Vec::new();
+        self.skip_space();
+        if !self.eat(close) {
+            loop {
+                if close == b'}' {
+                    let key = match self.bytes.get(self.pos)? {
+                        b'"' | b'\'' => self.string()?,
+                        _ => self.identifier()?,
+                    };
+                    self.skip_space();
+                    if !self.eat(b':') {
+                        return None;
+                    }
+                    self.skip_space();
+                    members.push((key, self.value()?));
+                } else {
+                    items.push(self.value()?);
+                }
+                self.skip_space();
+                if self.eat(close) {
+                    break;
+                }
+                if !self.eat(b',') {
+                    return None;
+                }
+                self.skip_space();
+                if self.eat(close) {
+                    self.json5 = true;
+                    break;
+                }
+            }
+        }
+        self.depth -= 1;
+        Some(if close == b'}' { Json::Object(members) } else { Json::Array(items) })
+    }
+
+    /// An unquoted JSON5 object key.
+    fn identifier(&mut self) -> Option<String> {
+        let rest = &self.text[self.pos..];
+        let end = rest
+            .char_indices()
+            .find(|&(i, c)| !(c.is_ascii_alphabetic() || c == '_' || c == '$' || !c.is_ascii() || (i > 0 && c.is_ascii_digit())))
+            .map_or(rest.len(), |(i, _)| i);
+        if end == 0 {
+            return None;
+        }
+        self.pos += end;
+        self.json5 = true;
+        Some(rest[..end].to_string())
+    }
+
+    /// A quoted string, as the text to keep between double quotes.
+    fn string(&mut self) -> Option<String> {
+        let quote = self.bytes[self.pos];
+        let start = self.pos + 1;
+        let mut end = start;
+        while *self.bytes.get(end)? != quote {
+            end += if self.bytes[end] == b'\\' { 2 } else { 1 };
+        }
+        self.pos = end + 1;
+
+        let raw = self.text.get(start..end)?;
+        if quote == b'"' && is_canonical(raw) {
+            return Some(raw.to_string());
+        }
+        // JSON5 quoting and escapes are rewritten as plain JSON
+        self.json5 = true;
+        Some(escape(&unescape(raw)?))
+    }
+
+    fn number(&mut self) -> Option<Json> {
+        let mut pos = self.pos;
+        let sign = match self.bytes[pos] {
+            b'-' => "-",
+            b'+' => {
+                self.json5 = true;
+                ""
+            }
+            _ => "",
+        };
+        if self.bytes[pos] == b'-' || self.bytes[pos] == b'+' {
+            pos += 1;
+        }
+        let rest = &self.text[pos..];
+
+        if rest.starts_with("Infinity") {
+            self.json5 = true;
+            self.pos = pos + "Infinity".len();
+            return Some(Json::Real(format!("{}9e999", sign)));
+        }
+        if rest.starts_with("NaN") {
+            self.json5 = true;
+            self.pos = pos + "NaN".len();
+            return Some(Json::Null);
+        }
+        if rest.starts_with("0x") || rest.starts_with("0X") {
+            let digits = rest[2..].bytes().take_while(u8::is_ascii_hexdigit).count();
+            let n = u128::from_str_radix(&rest[2..2 + digits], 16).ok()?;
+            self.json5 = true;
+            self.pos = pos + 2 + digits;
+            return Some(Json::Integer(format!("{}{}", sign, n)));
+        }
+
+        let digits = |from: usize| self.bytes[from..].iter().take_while(|b| b.is_ascii_digit()).count();
+        let integer = &self.text[pos..pos + digits(pos)];
+        pos += integer.len();
+        if integer.len() > 1 && integer.starts_with('0') {
+            return None;
+        }
+        let fraction = match self.bytes.get(pos) {
+            Some(b'.') => {
+                let fraction = &self.text[pos + 1..pos + 1 + digits(pos + 1)];
+                pos += 1 + fraction.len();
+                Some(fraction)
+            }
+            _ => None,
+        };
+        if integer.is_empty() && fraction.is_none_or(str::is_empty) {
+            return None;
+        }
+        // .5 and 5. are JSON5
+        if integer.is_empty() || fraction == Some("") {
+            self.json5 = true;
+        }
+        let exponent_start = pos;
+        if let Some(b'e' | b'E') = self.bytes.get(pos) {
+            pos += 1;
+            if let Some(b'+' | b'-') = self.bytes.get(pos) {
+                pos += 1;
+            }
+            let exponent_digits = digits(pos);
+            if exponent_digits == 0 {
+                return None;
+            }
+            pos += exponent_digits;
+        }
+        let exponent = &self.text[exponent_start..pos];
+        self.pos = pos;
+
+        let integer = if integer.is_empty() { "0" } else { integer };
+        Some(match fraction {
+            Some(fraction) => {
+                let fraction = if fraction.is_empty() { "0" } else { fraction };
+                Json::Real(format!("{}{}.{}{}", sign, integer, fraction, exponent))
+            }
+            None if exponent.is_empty() => Json::Integer(format!("{}{}", sign, integer)),
+            None => Json::Real(format!("{}{}{}", sign, integer, exponent)),
+        })
+    }
+}
+
+/// White space JSON5 allows besides JSON's own.
+fn is_json5_space(c: char) -> bool {
+    matches!(
+        c,
+        '\u{b}' | '\u{c}' | '\u{a0}' | '\u{1680}' | '\u{2000}'..='\u{200a}' | '\u{2028}' | '\u{2029}' | '\u{202f}'
+            | '\u{205f}' | '\u{3000}' | '\u{feff}'
+    )
+}
+
+/// Whether string content only uses the escapes of plain JSON and has no control characters.
+fn is_canonical(raw: &str) -> bool {
+    let bytes = raw.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => match bytes.get(i + 1) {
+                Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => i += 2,
+                Some(b'u') if bytes.len() >= i + 6 && bytes[i + 2..i + 6].iter().all(u8::is_ascii_hexdigit) => i += 6,
+                _ => return false,
+            },
+            b if b < 0x20 => return false,
+            _ => i += 1,
+        }
+    }
+    true
+}
+
+/// String content with its escapes, JSON5 ones included, replaced by the characters they stand for.
+fn unescape(raw: &str) -> Option<String> {
+    if !raw.contains('\\') {
+        return Some(raw.to_string());
+    }
+    let hex = |digits: &str| u32::from_str_radix(digits, 16).ok().filter(|_| digits.bytes().all(|b| b.is_ascii_hexdigit()));
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        let mut chars = rest[i + 1..].chars();
+        let escaped = chars.next()?;
+        rest = chars.as_str();
+        match escaped {
+            '"' | '\\' | '/' | '\'' => out.push(escaped),
+            'b' => out.push('\u{8}'),
+            'f' => out.push('\u{c}'),
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            'v' => out.push('\u{b}'),
+            '0' => out.push('\0'),
+            'x' => {
+                out.push(char::from_u32(hex(rest.get(..2)?)?)?);
+                rest = &rest[2..];
+            }
+            'u' => {
+                let mut code = hex(rest.get(..4)?)?;
+                rest = &rest[4..];
+                // a surrogate pair is one character
+                if (0xd800..0xdc00).contains(&code)
+                    && let Some(low) = rest.strip_prefix("\\u").and_then(|r| r.get(..4)).and_then(hex)
+                    && (0xdc00..0xe000).contains(&low)
+                {
+                    code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                    rest = &rest[6..];
+                }
+                out.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            // a line continuation
+            '\n' | '\u{2028}' | '\u{2029}' => {}
+            '\r' => rest = rest.strip_prefix('\n').unwrap_or(rest),
+            _ => return None,
+        }
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+/// Text as the content of a JSON string.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+
+/// One step of a path: `.key`, `[N]`, or `[#-N]` counting from the end (`[#]` is just past it).
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+    FromEnd(usize),
+}
+
+impl Step {
+    /// Position in an array of `len` elements; `len` itself is where an element is appended.
+    fn position(&self, len: usize) -> Option<usize> {
+        match self {
+            Step::Key(_) => None,
+            Step::Index(i) => Some(*i),
+            Step::FromEnd(n) => len.checked_sub(*n),
+        }
+    }
+}
+
+/// The first step of the path after `$` and what follows it, `None` at the end.
+/// Steps are read as the value is walked, so a path is only found malformed when it is used.
+fn next_step(rest: &str) -> Result<Option<(Step, &str)>, ()> {
+    if rest.is_empty() {
+        return Ok(None);
+    }
+    if let Some(rest) = rest.strip_prefix('.') {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let bytes = quoted.as_bytes();
+            let mut end = 0;
+            while end < bytes.len() && bytes[end] != b'"' {
+                end += if bytes[end] == b'\\' { 2 } else { 1 };
+            }
+            let key = quoted.get(..end).and_then(unescape).ok_or(())?;
+            let rest = quoted.get(end + 1..).ok_or(())?;
+            return Ok(Some((Step::Key(key), rest)));
+        }
+        let end = rest.find(['.', '[']).unwrap_or(rest.len());
+        if end == 0 {
+            return Err(());
+        }
+        return Ok(Some((Step::Key(rest[..end].to_string()), &rest[end..])));
+    }
+
+    let rest = rest.strip_prefix('[').ok_or(())?;
+    let close = rest.find(']').ok_or(())?;
+    let number = |digits: &str| match !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        true => Ok(digits.parse().unwrap_or(usize::MAX)),
+        false => Err(()),
+    };
+    let step = match rest[..close].strip_prefix('#') {
+        Some("") => Step::FromEnd(0),
+        Some(back) => Step::FromEnd(number(back.strip_prefix('-').ok_or(())?)?),
+        None => Step::Index(number(&rest[..close])?),
+    };
+    Ok(Some((step, &rest[close + 1..])))
+}
+
+fn bad_path(path: &str) -> Box<dyn Error> {
+    format!("bad JSON path: '{}'", path).into()
+}
+
+/// Whether an object key, as stored, is `label`.
+fn key_matches(key: &str, label: &str) -> bool {
+    match key.contains('\\') {
+        true => unescape(key).as_deref() == Some(label),
+        false => key == label,
+    }
+}
+
+/// A value found by a path, with where it sits in the document.
+struct Located<'j> {
+    node: &'j Json,
+    /// offset of its JSONB form, where it starts
+    offset: usize,
+    /// the offset json_each() shows: that of the key for an object member
+    id: usize,
+    /// key in the object holding it, or index in the array holding it; NULL for the whole document
+    key: Value,
+    /// length of the path to what holds it
+    container_path: usize,
+}
+
+fn locate<'j>(root: &'j Json, path: &str) -> Result<Option<Located<'j>>, Box<dyn Error>> {
+    let mut rest = path.strip_prefix('$').ok_or_else(|| bad_path(path))?;
+    let mut found = Located { node: root, offset: 0, id: 0, key: Value::Null, container_path: path.len() };
+    while let Some((step, next)) = next_step(rest).map_err(|()| bad_path(path))? {
+        let mut at = found.offset + header_size(found.node.payload());
+        let child = match (found.node, &step) {
+            (Json::Object(members), Step::Key(label)) => members.iter().find_map(|(key, value)| {
+                let member = at;
+                at += label_size(key);
+                if key_matches(key, label) {
+                    return Some(Located { node: value, offset: at, id: member, key: Value::Text(label.clone()), container_path: 0 });
+                }
+                at += value.size();
+                None
+            }),
+            (Json::Array(items), step) => match step.position(items.len()) {
+                Some(i) if i < items.len() => {
+                    at += items[..i].iter().map(Json::size).sum::<usize>();
+                    Some(Located { node: &items[i], offset: at, id: at, key: Value::Integer(i as i64), container_path: 0 })
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some(child) = child else {
+            return Ok(None);
+        };
+        found = Located { container_path: path.len() - rest.len(), ..child };
+        rest = next;
+    }
+    Ok(Some(found))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Insert,
+    Replace,
+    Set,
+}
+
+/// Puts `value` where `path` leads: json_insert() only adds, json_replace() only overwrites
+/// and json_set() does both. Missing objects on the way are created, and arrays from `[0]` or `[#]`.
+fn edit(root: &mut Json, path: &str, value: Json, mode: Edit) -> Result<(), Box<dyn Error>> {
+    let mut rest = path.strip_prefix('$').ok_or_else(|| bad_path(path))?;
+    let mut node = root;
+    loop {
+        let Some((step, next)) = next_step(rest).map_err(|()| bad_path(path))? else {
+            if mode != Edit::Insert {
+                *node = value;
+            }
+            return Ok(());
+        };
+        rest = next;
+        match (node, step) {
+            (Json::Object(members), Step::Key(label)) => match members.iter().position(|(key, _)| key_matches(key, &label)) {
+                Some(i) => node = &mut members[i].1,
+                None => {
+                    if mode != Edit::Replace
+                        && let Some(value) = create(rest, value).map_err(|()| bad_path(path))?
+                    {
+                        members.push((escape(&label), value));
+                    }
+                    return Ok(());
+                }
+            },
+            (Json::Array(items), step) => match step.position(items.len()) {
+                Some(i) if i < items.len() => node = &mut items[i],
+                Some(i) if i == items.len() => {
+                    if mode != Edit::Replace
+                        && let Some(value) = create(rest, value).map_err(|()| bad_path(path))?
+                    {
+                        items.push(value);
+                    }
+                    return Ok(());
+                }
+                _ => return Ok(()),
+            },
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// The value to add for the rest of a path that does not exist yet, `None` if it cannot be made.
+fn create(rest: &str, value: Json) -> Result<Option<Json>, ()> {
+    Ok(match next_step(rest)? {
+        None => Some(value),
+        Some((Step::Key(label), rest)) => create(rest, value)?.map(|value| Json::Object(vec![(escape(&label), value)])),
+        Some((Step::Index(0) | Step::FromEnd(0), rest)) => create(rest, value)?.map(|value| Json::Array(vec![value])),
+        Some(_) => None,
+    })
+}
+
+/// Removes what `path` leads to, if anything; false when that is the whole document.
+fn remove(root: &mut Json, path: &str) -> Result<bool, Box<dyn Error>> {
+    let mut rest = path.strip_prefix('$').ok_or_else(|| bad_path(path))?;
+    if rest.is_empty() {
+        return Ok(false);
+    }
+    let mut node = root;
+    while let Some((step, next)) = next_step(rest).map_err(|()| bad_path(path))? {
+        let last = next.is_empty();
+        rest = next;
+        match (node, step) {
+            (Json::Object(members), Step::Key(label)) => {
+                let Some(i) = members.iter().position(|(key, _)| key_matches(key, &label)) else {
+                    break;
+                };
+                if last {
+                    members.remove(i);
+                    break;
+                }
+                node = &mut members[i].1;
+            }
+            (Json::Array(items), step) => match step.position(items.len()) {
+                Some(i) if i < items.len() => {
+                    if last {
+                        items.remove(i);
+                        break;
+                    }
+                    node = &mut items[i];
+                }
+                _ => break,
+            },
+            _ => break,
+        }
+    }
+    Ok(true)
+}
+
+
+/// Whether a value is JSON rather than text when passed to a JSON function, SQLite's JSON subtype.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subtype {
+    Text,
+    Json,
+    /// JSON when it is an array or an object: what json_extract() and the value column of json_each() hold
+    Container,
+}
+
+/// The subtype of the values of an expression.
+pub fn subtype(expr: &Expr, scope: &Scope) -> Subtype {
+    match expr {
+        Expr::Function { name, .. } => match name.as_str() {
+            "json" | "json_array" | "json_object" | "json_insert" | "json_replace" | "json_set" | "json_remove"
+            | "json_quote" | "json_group_array" | "json_group_object" => Subtype::Json,
+            "json_extract" => Subtype::Container,
+            _ => Subtype::Text,
+        },
+        Expr::Binary { op: BinaryOp::Extract, .. } => Subtype::Json,
+        Expr::Column(column) => match scope.resolve(column) {
+            Ok(Some(i)) if scope.columns[i].json => Subtype::Container,
+            _ => Subtype::Text,
+        },
+        _ => Subtype::Text,
+    }
+}
+
+/// An SQL value to put into JSON.
+fn to_json(value: &Value, subtype: Subtype) -> Result<Json, Box<dyn Error>> {
+    Ok(match value {
+        Value::Text(text) if subtype == Subtype::Json => parse(text)?,
+        Value::Text(text) if subtype == Subtype::Container && text.starts_with(['[', '{']) => {
+            parse(text).unwrap_or_else(|_| Json::String(escape(text)))
+        }
+        Value::Null => Json::Null,
+        Value::Integer(n) => Json::Integer(n.to_string()),
+        Value::Real(r) if r.is_nan() => Json::Null,
+        Value::Real(r) if r.is_infinite() => Json::Real(if *r > 0.0 { "9.0e+999" } else { "-9.0e+999" }.to_string()),
+        Value::Real(r) => Json::Real(format_real(*r)),
+        Value::Text(text) => Json::String(escape(text)),
+        Value::Blob(_) => return Err("JSON cannot hold BLOB values".into()),
+    })
+}
+
+/// The JSON document argument of a function, `None` for NULL.
+fn document(value: &Value, encoding: TextEncoding) -> Result<Option<Json>, Box<dyn Error>> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Blob(_) => Err("malformed JSON".into()),
+        value => parse(&value.to_text(encoding)).map(Some),
+    }
+}
+
+/// An argument of a function building JSON: text is a string unless it comes from a JSON function.
+fn json_argument(ctx: &EvalContext, expr: &Expr) -> Result<Json, Box<dyn Error>> {
+    // json_extract() knows whether it found an array or object
+    if let Expr::Function { name, args, over: None, span, .. } = expr
+        && name == "json_extract"
+    {
+        let args = functions::arguments(name, args, *span)?;
+        let (value, json) = evaluate(ctx, name, args)?;
+        return to_json(&value, if json { Subtype::Json } else { Subtype::Text });
+    }
+    to_json(&ctx.eval(expr)?, subtype(expr, ctx.scope))
+}
+
+fn json_result(json: &Json) -> (Value, bool) {
+    (Value::Text(json.to_string()), true)
+}
+
+
+/// Whether `name` is a JSON scalar function, taken care of by `call`.
+pub fn is_json_function(name: &str) -> bool {
+    matches!(
+        name,
+        "json" | "json_valid" | "json_quote" | "json_array" | "json_object" | "json_extract" | "json_insert"
+            | "json_replace" | "json_set" | "json_remove" | "json_type" | "json_array_length"
+    )
+}
+
+/// Evaluates a call to a JSON scalar function whose number of arguments has been checked.
+pub fn call(ctx: &EvalContext, name: &str, args: &[Expr]) -> Result<Value, Box<dyn Error>> {
+    Ok(evaluate(ctx, name, args)?.0)
+}
+
+/// A JSON function's result and whether it is JSON, which makes the JSON functions
+/// it is passed to take it as JSON rather than as a string.
+fn evaluate(ctx: &EvalContext, name: &str, args: &[Expr]) -> Result<(Value, bool), Box<dyn Error>> {
+    const NULL: (Value, bool) = (Value::Null, false);
+    let value = |i: usize| ctx.eval(&args[i]);
+    // the document and, when given, the path to the part of it to look at
+    let target = |document: &Json| -> Result<Option<Json>, Box<dyn Error>> {
+        let path = match args.get(1) {
+            Some(path) => ctx.eval(path)?,
+            None => return Ok(Some(document.clone())),
+        };
+        match path {
+            Value::Null => Ok(None),
+            path => Ok(locate(document, &path.to_text(ctx.encoding))?.map(|found| found.node.clone())),
+        }
+    };
+
+    Ok(match name {
+        "json" => match self::document(&value(0)?, ctx.encoding)? {
+            Some(json) => json_result(&json),
+            None => NULL,
+        },
+
+        "json_valid" => {
+            let flags = match args.get(1) {
+                Some(_) => value(1)?.to_i64(ctx.encoding),
+                None => 1,
+            };
+            if !(1..=15).contains(&flags) {
+                return Err("FLAGS parameter to json_valid() must be between 1 and 15".into());
+            }
+            let valid = match value(0)? {
+                Value::Null => return Ok(NULL),
+                Value::Blob(_) => false,
+                text => match parse_checked(&text.to_text(ctx.encoding)) {
+                    Some((_, strict)) => flags & 2 != 0 || (flags & 1 != 0 && strict),
+                    None => false,
+                },
+            };
+            (Value::from_bool(valid), false)
+        }
+
+        "json_quote" => json_result(&json_argument(ctx, &args[0])?),
+
+        "json_array" => {
+            let items = args.iter().map(|arg| json_argument(ctx, arg)).collect::<Result<_, _>>()?;
+            json_result(&Json::Array(items))
+        }
+
+        "json_object" => {
+            if !args.len().is_multiple_of(2) {
+                return Err("json_object() requires an even number of arguments".into());
+            }
+            let mut members = Vec::with_capacity(args.len() / 2);
+            for pair in args.chunks(2) {
+                let Value::Text(key) = ctx.eval(&pair[0])? else {
+                    return Err("json_object() labels must be TEXT".into());
+                };
+                members.push((escape(&key), json_argument(ctx, &pair[1])?));
+            }
+            json_result(&Json::Object(members))
+        }
+
+        "json_extract" => {
+            let Some(document) = self::document(&value(0)?, ctx.encoding)? else {
+                return Ok(NULL);
+            };
+            if let [_, path] = args {
+                let path = ctx.eval(path)?;
+                if path.is_null() {
+                    return Ok(NULL);
+                }
+                return Ok(match locate(&document, &path.to_text(ctx.encoding))? {
+                    Some(found) => (found.node.to_value(), found.node.is_container()),
+                    None => NULL,
+                });
+            }
+            // several paths give an array of what each finds
+            let mut items = Vec::with_capacity(args.len() - 1);
+            for path in &args[1..] {
+                let path = ctx.eval(path)?;
+                if path.is_null() {
+                    return Ok(NULL);
+                }
+                items.push(locate(&document, &path.to_text(ctx.encoding))?.map_or(Json::Null, |found| found.node.clone()));
+            }
+            json_result(&Json::Array(items))
+        }
+
+        "json_insert" | "json_replace" | "json_set" => {
+            if args.len().is_multiple_of(2) {
+                return Err(format!("{}() needs an odd number of arguments", name).into());
+            }
+            let Some(mut document) = self::document(&value(0)?, ctx.encoding)? else {
+                return Ok(NULL);
+            };
+            let mode = match name {
+                "json_insert" => Edit::Insert,
+                "json_replace" => Edit::Replace,
+                _ => Edit::Set,
+            };
+            for pair in args[1..].chunks(2) {
+                let path = ctx.eval(&pair[0])?;
+                if path.is_null() {
+                    continue;
+                }
+                let value = json_argument(ctx, &pair[1])?;
+                edit(&mut document, &path.to_text(ctx.encoding), value, mode)?;
+            }
+            json_result(&document)
+        }
+
+        "json_remove" => {
+            let Some(mut document) = self::document(&value(0)?, ctx.encoding)? else {
+                return Ok(NULL);
+            };
+            for path in &args[1..] {
+                let path = ctx.eval(path)?;
+                if path.is_null() || !remove(&mut document, &path.to_text(ctx.encoding))? {
+                    return Ok(NULL);
+                }
+            }
+            json_result(&document)
+        }
+
+        "json_type" => {
+            let Some(document) = self::document(&value(0)?, ctx.encoding)? else {
+                return Ok(NULL);
+            };
+            match target(&document)? {
+                Some(found) => (Value::Text(found.type_name().to_string()), false),
+                None => NULL,
+            }
+        }
+
+        "json_array_length" => {
+            let Some(document) = self::document(&value(0)?, ctx.encoding)? else {
+                return Ok(NULL);
+            };
+            match target(&document)? {
+                Some(Json::Array(items)) => (Value::Integer(items.len() as i64), false),
+                Some(_) => (Value::Integer(0), false),
+                None => NULL,
+            }
+        }
+
+        _ => return Err(format!("no such function: {}", name).into()),
+    })
+}
+
+/// `document -> selector` and `document ->> selector`: the JSON text of what the selector
+/// finds, or with `->>` its SQL value. The selector is a path, an array index (negative
+/// ones count from the end) or an object key.
+pub fn extract(document: &Value, selector: &Value, as_json: bool, encoding: TextEncoding) -> Result<Value, Box<dyn Error>> {
+    let Some(document) = self::document(document, encoding)? else {
+        return Ok(Value::Null);
+    };
+    let path = match selector {
+        Value::Null => return Ok(Value::Null),
+        Value::Integer(n) if *n >= 0 => format!("$[{}]", n),
+        Value::Integer(n) => format!("$[#{}]", n),
+        Value::Text(path) if path.starts_with('$') => path.clone(),
+        key => format!("$.\"{}\"", escape(&key.to_text(encoding))),
+    };
+    Ok(match locate(&document, &path)? {
+        Some(found) if as_json => Value::Text(found.node.to_string()),
+        Some(found) => found.node.to_value(),
+        None => Value::Null,
+    })
+}
+
+
+/// Whether `name` is a table-valued function, usable in FROM.
+pub fn is_table_function(name: &str) -> bool {
+    matches!(name, "json_each" | "json_tree")
+}
+
+/// Rows of json_each() or json_tree() over a document and an optional path, with the values
+/// of `TABLE_COLUMNS`. json_each() lists the elements of the array or object the path leads
+/// to; json_tree() walks it and everything below it, depth first.
+pub fn table_rows(name: &str, args: &[Value], encoding: TextEncoding) -> Result<Vec<Vec<Value>>, Box<dyn Error>> {
+    let Some(json) = args.first() else {
+        return Ok(Vec::new());
+    };
+    let Some(document) = self::document(json, encoding)? else {
+        return Ok(Vec::new());
+    };
+    let root = match args.get(1) {
+        None => "$".to_string(),
+        Some(Value::Null) => return Ok(Vec::new()),
+        Some(path) => path.to_text(encoding),
+    };
+    let Some(found) = locate(&document, &root)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut table = Table { rows: Vec::new(), json: json.clone(), root: root.clone() };
+    if name == "json_tree" {
+        let path = root[..found.container_path.min(root.len())].to_string();
+        table.push(found.key.clone(), found.node, found.id, Value::Null, root.clone(), path);
+        table.walk(found.node, found.offset, found.id, &root);
+    } else if found.node.is_container() {
+        for (key, node, id, _, fullkey) in children(found.node, found.offset, &root) {
+            table.push(key, node, id, Value::Null, fullkey, root.clone());
+        }
+    } else {
+        table.push(Value::Null, found.node, found.id, Value::Null, root.clone(), root.clone());
+    }
+    Ok(table.rows)
+}
+
+struct Table {
+    rows: Vec<Vec<Value>>,
+    json: Value,
+    root: String,
+}
+
+impl Table {
+    fn push(&mut self, key: Value, node: &Json, id: usize, parent: Value, fullkey: String, path: String) {
+        let atom = if node.is_container() { Value::Null } else { node.to_value() };
+        self.rows.push(vec![
+            key,
+            node.to_value(),
+            Value::Text(node.type_name().to_string()),
+            atom,
+            Value::Integer(id as i64),
+            parent,
+            Value::Text(fullkey),
+            Value::Text(path),
+            self.json.clone(),
+            Value::Text(self.root.clone()),
+        ]);
+    }
+
+    /// Rows for everything below `node`, whose row has `id` and `fullkey`.
+    fn walk(&mut self, node: &Json, offset: usize, id: usize, fullkey: &str) {
+        for (key, child, child_id, child_offset, child_fullkey) in children(node, offset, fullkey) {
+            self.push(key, child, child_id, Value::Integer(id as i64), child_fullkey.clone(), fullkey.to_string());
+            self.walk(child, child_offset, child_id, &child_fullkey);
+        }
+    }
+}
+
+/// Elements of an array or members of an object at `offset`: key, value, id, offset and full path.
+fn children<'j>(node: &'j Json, offset: usize, path: &str) -> Vec<(Value, &'j Json, usize, usize, String)> {
+    let mut at = offset + header_size(node.payload());
+    let mut children = Vec::new();
+    match node {
+        Json::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                children.push((Value::Integer(i as i64), item, at, at, format!("{}[{}]", path, i)));
+                at += item.size();
+            }
+        }
+        Json::Object(members) => {
+            for (key, value) in members {
+                let id = at;
+                at += label_size(key);
+                // keys that are not plain words are quoted
+                let plain = key.starts_with(|c: char| c.is_ascii_alphabetic()) && key.chars().all(|c| c.is_ascii_alphanumeric());
+                let fullkey = if plain { format!("{}.{}", path, key) } else { format!("{}.\"{}\"", path, key) };
+                let label = Value::Text(unescape(key).unwrap_or_else(|| key.clone()));
+                children.push((label, value, id, at, fullkey));
+                at += value.size();
+            }
+        }
+        _ => {}
+    }
+    children
+}
+
+
+/// State of json_group_array() or json_group_object() in one group.
+#[derive(Debug, Clone)]
+pub struct JsonGroup {
+    object: bool,
+    /// that of the value argument
+    subtype: Subtype,
+    /// the elements or members so far, without the brackets
+    text: String,
+    /// the first value that could not be put into JSON
+    error: Option<String>,
+}
+
+impl JsonGroup {
+    pub fn new(call: &Expr, scope: &Scope) -> Self {
+        let (object, value) = match call {
+            Expr::Function { name, args: FunctionArgs::List(args), .. } => {
+                let object = name == "json_group_object";
+                (object, args.get(usize::from(object)))
+            }
+            _ => (false, None),
+        };
+        let subtype = value.map_or(Subtype::Text, |value| subtype(value, scope));
+        JsonGroup { object, subtype, text: String::new(), error: None }
+    }
+
+    /// Adds one row's arguments; returns the bytes the state grew by.
+    /// json_group_object() leaves out members whose key is NULL.
+    pub fn step(&mut self, args: &[Value], encoding: TextEncoding) -> usize {
+        let (key, value) = match self.object {
+            true => (args.first(), args.get(1)),
+            false => (None, args.first()),
+        };
+        if self.object && key.is_none_or(Value::is_null) {
+            return 0;
+        }
+        let json = match to_json(value.unwrap_or(&Value::Null), self.subtype) {
+            Ok(json) => json,
+            Err(e) => {
+                self.error.get_or_insert(e.to_string());
+                return 0;
+            }
+        };
+        let before = self.text.len();
+        if before > 0 {
+            self.text.push(',');
+        }
+        if let Some(key) = key {
+            let _ = write!(self.text, "\"{}\":", escape(&key.to_text(encoding)));
+        }
+        let _ = write!(self.text, "{}", json);
+        self.text.len() - before
+    }
+
+    pub fn finish(&self) -> Result<Value, Box<dyn Error>> {
+        if let Some(error) = &self.error {
+            return Err(error.clone().into());
+        }
+        Ok(Value::Text(match self.object {
+            true => format!("{{{}}}", self.text),
+            false => format!("[{}]", self.text),
+        }))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minify(text: &str) -> String {
+        parse(text).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(minify(r#" { "a" : 1.50 , "b":[1e2, "A\n", true,null] } "#), r#"{"a":1.50,"b":[1e2,"A\n",true,null]}"#);
+        assert_eq!(
+            minify("{a:1, 'b':0x1F, c:.5, d:5., e:+3, f:-Infinity, g:NaN, h:[1,2,], /* note */ i:'x\"y'}"),
+            r#"{"a":1,"b":31,"c":0.5,"d":5.0,"e":3,"f":-9e999,"g":null,"h":[1,2],"i":"x\"y"}"#
+        );
+        assert_eq!(parse_checked("[1, 2]").map(|(_, strict)| strict), Some(true));
+        assert_eq!(parse_checked("[1, 2,]").map(|(_, strict)| strict), Some(false));
+        for bad in ["", "[1,", "{\"a\"}", "01", "1.", "[1] x", "\"a", "{\"a\":1,,}", "/* open"] {
+            assert!(parse_checked(bad).is_none_or(|(_, strict)| !strict), "{}", bad);
+        }
+        assert!(parse(&"[".repeat(MAX_DEPTH + 1)).is_err());
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(unescape(r"a\tb\u00e9\ud83d\ude00\/").unwrap(), "a\tb\u{e9}\u{1f600}/");
+        assert_eq!(escape("a\"b\\c\n\u{1}é"), r#"a\"b\\c\n\u0001é"#);
+        assert_eq!(parse(r#""AA""#).unwrap().to_value(), Value::Text("AA".into()));
+    }
+
+    #[test]
+    fn test_paths() {
+        let document = parse(r#"{"a":{"b":[1,2,{"c":"x"}]},"d e":3}"#).unwrap();
+        let find = |path: &str| locate(&document, path).unwrap().map(|found| found.node.to_string());
+        assert_eq!(find("$.a.b[2].c").as_deref(), Some("\"x\""));
+        assert_eq!(find("$.a.b[#-1].c").as_deref(), Some("\"x\""));
+        assert_eq!(find("$.\"d e\"").as_deref(), Some("3"));
+        assert_eq!(find("$.a.b[3]"), None);
+        // the rest of a path is only read once the part before it is found
+        assert_eq!(find("$.x["), None);
+        assert!(locate(&document, "a").is_err());
+        assert!(locate(&document, "$.").is_err());
+
+        let mut edited = document.clone();
+        edit(&mut edited, "$.a.b[#]", Json::Integer("4".into()), Edit::Set).unwrap();
+        edit(&mut edited, "$.n.m[0]", Json::True, Edit::Insert).unwrap();
+        edit(&mut edited, "$.z", Json::Null, Edit::Replace).unwrap();
+        assert!(remove(&mut edited, "$.\"d e\"").unwrap());
+        assert_eq!(edited.to_string(), r#"{"a":{"b":[1,2,{"c":"x"},4]},"n":{"m":[true]}}"#);
+        assert!(!remove(&mut edited, "$").unwrap());
+    }
+
+    #[test]
+    fn test_table_rows() {
+        let rows = table_rows("json_tree", &[Value::Text(r#"{"a":1,"b":[2,3]}"#.into())], TextEncoding::Utf8).unwrap();
+        let text = |value: &Value| value.to_text(TextEncoding::Utf8);
+        let summary: Vec<String> =
+            rows.iter().map(|row| format!("{}|{}|{}|{}", text(&row[0]), text(&row[4]), text(&row[5]), text(&row[6]))).collect();
+        assert_eq!(summary, ["|0||$", "a|1|0|$.a", "b|5|0|$.b", "0|8|5|$.b[0]", "1|10|5|$.b[1]"]);
+
+        let rows = table_rows("json_each", &[Value::Text("[1,2]".into()), Value::Text("$[1]".into())], TextEncoding::Utf8).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0][6], Value::Text("$[1]".into()));
+    }
+}
@@ -1,19 +1,31 @@
 use anyhow::{Result};
-use std::fs::File;
 use std::error::Error;
 
 
 
+mod aggregate;
+mod collation;
+mod datetime;
+mod encoding;
 mod page;
 mod util;
 mod read;
 mod sql_engine;
-mod command_parse;
-
-use page::Cell;
-
-use crate::read::SqliteRead;
-use sql_engine::sql_engine;
+mod sql;
+mod eval;
+mod functions;
+mod json;
+mod join;
+mod planner;
+mod schema;
+mod sorter;
+mod spill;
+mod value;
+mod window;
+
+use crate::read::Pager;
+use crate::schema::{Schema, SchemaKind};
+use sql_engine::{sql_engine, EngineConfig};
 
 fn main() -> Result<(), Box<dyn Error >>{
     // Parse arguments
@@ -24,57 +36,62 @@ fn main() -> Result<(), Box<dyn Error >>{
         _ => {}
     }
 
+    // collations beyond SQLite's own are registered once, like any application would
+    collation::register("unicode_nocase", collation::unicode_nocase)?;
+
     // Parse command and act accordingly
     let command = &args[2];
     match command.as_str() {
         ".dbinfo" => {
-            let mut file = File::open(&args[1])?;
-            // The page size is stored at the 16th byte offset, using 2 bytes in big-endian order
-            #[allow(unused_variables)]
-            if let Ok(page_size) = SqliteRead::page_size(&mut file) { println!("database page size: {}", page_size);}
-            if let Ok(table_count) = SqliteRead::table_count(&mut file) {println!("number of tables: {}", table_count);}; 
-            
+            let pager = Pager::open(&args[1])?;
+            let header = pager.header();
+            println!("database page size: {}", header.page_size);
+            println!("write format: {}", header.write_version);
+            println!("read format: {}", header.read_version);
+            println!("reserved bytes: {}", header.reserved_space);
+            println!("file change counter: {}", header.change_counter);
+            println!("database page count: {}", header.page_count);
+            println!("freelist page count: {}", header.freelist_count);
+            println!("schema cookie: {}", header.schema_cookie);
+            println!("schema format: {}", header.schema_format);
+            println!("default cache size: {}", header.default_cache_size);
+            println!("autovacuum top root: {}", header.autovacuum_root);
+            println!("incremental vacuum: {}", header.incremental_vacuum as u8);
+            let (encoding, name) = header.text_encoding.header_value();
+            println!("text encoding: {} ({})", encoding, name);
+            println!("user version: {}", header.user_version);
+            println!("application id: {}", header.application_id);
+            println!("software version: {}", header.sqlite_version);
+
+            let schema = Schema::load(&pager)?;
+            println!("number of tables: {}", schema.of_kind(SchemaKind::Table).count());
+            println!("number of indexes: {}", schema.of_kind(SchemaKind::Index).count());
+            println!("number of triggers: {}", schema.of_kind(SchemaKind::Trigger).count());
+            println!("number of views: {}", schema.of_kind(SchemaKind::View).count());
         }
         ".tables" => {
-            let mut file = File::open(&args[1])?;
-           
-            let page_size = SqliteRead::page_size(&mut file)?;
-            let table_count = SqliteRead::table_count(&mut file)?;
-            let page_0 = SqliteRead::read_first_page(&mut file, page_size)?;
-
+            let pager = Pager::open(&args[1])?;
+            let schema = Schema::load(&pager)?;
 
-            let ptrs = Cell::read_cell_pointer_array(&page_0, table_count);
             let mut result:String = String::from("");
-
-            for v in ptrs{
-                let tmp = Cell::parse_cell(&page_0, (v-100) as usize );
-                if let Ok(name) = tmp{
-                    if name[1] != String::from("sqlite_sequence") {
-                        result = result +  &format!("{} ", name[1]);
-                    }
+            for entry in &schema.entries {
+                if matches!(entry.kind, SchemaKind::Table | SchemaKind::View) && !entry.is_internal() {
+                    result = result +  &format!("{} ", entry.name);
                 }
             }
             println!("{}", result);
 
         }
         sql => {
-            let mut file = File::open(&args[1])?;
-           
-            let page_size = SqliteRead::page_size(&mut file)?;
-            let table_count = SqliteRead::table_count(&mut file)?;
-            let page_0 = SqliteRead::read_first_page(&mut file, page_size)?;
-            let ptrs = Cell::read_cell_pointer_array(&page_0, table_count);
+            let pager = Pager::open(&args[1])?;
+            let schema = Schema::load(&pager)?;
+            let config = EngineConfig::from_env()?;
 
-            let mut result =vec![];
-            for v in ptrs{
-                let metadata = Cell::parse_cell(&page_0, (v-100) as usize)?; //considering header...
-                if metadata[1] != String::from("sqlite_sequence") {
-                        result.push(vec![metadata[1].clone(),metadata[3].clone(), metadata[4].clone()]); // table name, table's page index 
-                }
+            if let Err(e) = sql_engine(&pager, sql, &schema, &config) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
             }
 
-            sql_engine(&mut file, sql, result, page_size);
-
         }
         
     }
@@ -1,134 +1,492 @@
-use std::{collections::HashMap, error::Error};
+use std::{cmp::Ordering, error::Error};
+use crate::collation::Collation;
+use crate::encoding::TextEncoding;
+use crate::read::Pager;
 use crate::util::read_varint;
-use regex::Regex;
+use crate::value::Value;
+
+/// Decodes a record (header + body) into its column values, text stored in `encoding`.
+pub fn parse_record(data: &[u8], encoding: TextEncoding) -> Result<Vec<Value>, Box<dyn Error>> {
+    let (header_size, len) = read_varint(data)?;
+    let mut pos = len;
+    let mut serial_types = Vec::new();
+    while pos < header_size as usize {
+        let (serial_type, width) = read_varint(&data[pos..])?;
+        serial_types.push(serial_type);
+        pos += width;
+    }
 
+    let mut values = Vec::new();
+    for &serial_type in &serial_types {
+        let value = SerialCode::read_value_by_serial_type(&data[pos..], serial_type, encoding)?;
+        pos += SerialCode::value_size(serial_type);
+        values.push(value);
+    }
+    Ok(values)
+}
 
+/// b-tree page kind, stored in the first byte of the page header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageType {
+    InteriorIndex,
+    InteriorTable,
+    LeafIndex,
+    LeafTable,
+}
 
+impl PageType {
+    pub fn from_byte(byte: u8) -> Result<Self, Box<dyn Error>> {
+        match byte {
+            0x02 => Ok(PageType::InteriorIndex),
+            0x05 => Ok(PageType::InteriorTable),
+            0x0a => Ok(PageType::LeafIndex),
+            0x0d => Ok(PageType::LeafTable),
+            n => Err(format!("Invalid b-tree page type: {:#04x}", n).into()),
+        }
+    }
 
+    pub fn is_leaf(&self) -> bool {
+        matches!(self, PageType::LeafIndex | PageType::LeafTable)
+    }
+}
 
-pub struct Cell{}
 
-impl Cell{
+/// A single b-tree page with its header decoded.
+/// Cell offsets are relative to the start of `data`, which is always the whole page.
+pub struct BTreePage {
+    pub page_type: PageType,
+    pub cell_count: u16,
+    /// right-most child pointer, only present on interior pages (header offset 8)
+    pub right_most: Option<u32>,
+    data: Vec<u8>,
+    header_offset: usize,
+}
 
-    pub fn read_cell_pointer_array(page_start: &[u8], cell_count: u16) -> Vec<u16> {
+impl BTreePage {
+    pub fn read(pager: &Pager, page_number: u32) -> Result<Self, Box<dyn Error>> {
+        let data = pager.read_page(page_number)?;
+        Self::parse(page_number, data)
+    }
 
-        let mut pointers = Vec::new();
-        let start_offset = 8; 
-        
-        for i in 0..cell_count {
-            let pos = start_offset + (i * 2) as usize;
-            let pointer = u16::from_be_bytes([page_start[pos], page_start[pos + 1]]);
-            pointers.push(pointer);
+    pub fn parse(page_number: u32, data: Vec<u8>) -> Result<Self, Box<dyn Error>> {
+        // page 1 starts with the 100 byte database header
+        let header_offset = if page_number == 1 { 100 } else { 0 };
+        if data.len() < header_offset + 12 {
+            return Err(format!("Page {} is too small", page_number).into());
         }
 
-        pointers
+        let header = &data[header_offset..];
+        let page_type = PageType::from_byte(header[0])?;
+        let cell_count = u16::from_be_bytes([header[3], header[4]]);
+        let right_most = if page_type.is_leaf() {
+            None
+        } else {
+            Some(u32::from_be_bytes([header[8], header[9], header[10], header[11]]))
+        };
+
+        Ok(BTreePage { page_type, cell_count, right_most, data, header_offset })
     }
 
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
 
-    pub fn parse_cell(data: &[u8], offset: usize) -> Result<Vec<String>, Box<dyn Error>> {
-        let mut pos = offset;
+    /// Offset of the i-th cell, read from the cell pointer array.
+    pub fn cell_offset(&self, i: u16) -> Result<usize, Box<dyn Error>> {
+        let header_size = if self.page_type.is_leaf() { 8 } else { 12 };
+        let pos = self.header_offset + header_size + i as usize * 2;
+        if pos + 2 > self.data.len() {
+            return Err("Cell pointer array out of bounds".into());
+        }
+        let offset = u16::from_be_bytes([self.data[pos], self.data[pos + 1]]) as usize;
+        if offset >= self.data.len() {
+            return Err(format!("Cell offset {} out of bounds", offset).into());
+        }
+        Ok(offset)
+    }
 
-        let (_, len1) = read_varint(&data[pos..])?;
-        pos += len1;
+    /// Reads a cell payload of `payload_size` bytes starting at `pos`,
+    /// following the overflow chain when it does not fit in the page.
+    pub fn read_payload(&self, pager: &Pager, pos: usize, payload_size: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let usable = pager.usable_size() as u64;
+        let local = Self::local_payload_size(self.page_type, usable, payload_size) as usize;
 
+        let mut payload = self.data.get(pos..pos + local).ok_or("Cell payload exceeds page")?.to_vec();
+        if local as u64 == payload_size {
+            return Ok(payload);
+        }
 
-        let (_, len2) = read_varint(&data[pos..])?;
-        pos += len2;
+        let ptr = self.data.get(pos + local..pos + local + 4).ok_or("Missing overflow page pointer")?;
+        let mut next = u32::from_be_bytes([ptr[0], ptr[1], ptr[2], ptr[3]]);
+        // every overflow page starts with the next page number, the rest is content
+        while (payload.len() as u64) < payload_size {
+            if next == 0 {
+                return Err(format!(
+                    "Overflow chain ended early: have {} of {} payload bytes",
+                    payload.len(),
+                    payload_size
+                ).into());
+            }
+            let page = pager.read_page(next)?;
+            let remaining = (payload_size - payload.len() as u64) as usize;
+            let take = remaining.min(usable as usize - 4);
+            payload.extend_from_slice(&page[4..4 + take]);
+            next = u32::from_be_bytes([page[0], page[1], page[2], page[3]]);
+        }
 
-        let (header_size, len3) = read_varint(&data[pos..])?;
-        let header_start = pos;
-        pos += len3;
+        Ok(payload)
+    }
 
-    
-        let mut serial_types = Vec::new();
-        while pos - header_start < header_size as usize {
-            let (serial_type, len) = read_varint(&data[pos..])?;
-            serial_types.push(serial_type);
-            pos += len;
+    /// Number of payload bytes stored on the b-tree page itself.
+    /// X is the largest payload kept entirely local, M the minimum spilled local size.
+    fn local_payload_size(page_type: PageType, usable: u64, payload_size: u64) -> u64 {
+        let max_local = match page_type {
+            PageType::LeafTable => usable - 35,
+            _ => (usable - 12) * 64 / 255 - 23,
+        };
+        if payload_size <= max_local {
+            return payload_size;
         }
 
-    
-        let mut values = Vec::new();
-        for &serial_type in &serial_types {
-            let value = SerialCode::read_value_by_serial_type(&data[pos..], serial_type)?;
-            pos += SerialCode::value_size(serial_type);
-            values.push(value);
-        }
+        let min_local = (usable - 12) * 32 / 255 - 23;
+        let k = min_local + (payload_size - min_local) % (usable - 4);
+        if k <= max_local { k } else { min_local }
+    }
 
-        Ok(values) // [type, name, tbl_name, rootpage, sql]
+    /// Left child pointer of the i-th cell of an interior page.
+    pub fn left_child(&self, i: u16) -> Result<u32, Box<dyn Error>> {
+        let offset = self.cell_offset(i)?;
+        let bytes = self.data.get(offset..offset + 4).ok_or("Truncated interior cell")?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
     }
+}
 
-    pub fn parse_cell_as_map(
-        data: &[u8], 
-        offset: usize, 
-        column_names: &[String]
-    ) -> Result<HashMap<String, String>, Box<dyn Error>> {
-        let values = Self::parse_cell(data, offset)?;
-        
-        if values.len() != column_names.len() {
-            return Err(format!(
-                "Column count mismatch: expected {}, got {}", 
-                column_names.len(), 
-                values.len()
-            ).into());
+
+/// SQLite gives up on b-trees deeper than this; only a corrupt file, e.g. one whose pages point
+/// back at each other, has them.
+const MAX_DEPTH: usize = 20;
+
+fn check_depth(stack: &[(BTreePage, impl Sized)], child: u32) -> Result<(), Box<dyn Error>> {
+    if stack.len() >= MAX_DEPTH {
+        return Err(format!("Database disk image is malformed: b-tree deeper than {} pages at page {}", MAX_DEPTH, child).into());
+    }
+    Ok(())
+}
+
+
+/// A row of a table b-tree leaf page.
+pub struct TableRow {
+    pub rowid: i64,
+    pub payload: Vec<u8>,
+}
+
+
+/// Walks a table b-tree (page types 0x05 / 0x0D) and yields every row in rowid order.
+pub struct TableCursor<'a> {
+    pager: &'a Pager,
+    root: u32,
+    // (page, index of the next cell or child to visit)
+    stack: Vec<(BTreePage, u16)>,
+}
+
+impl<'a> TableCursor<'a> {
+    pub fn new(pager: &'a Pager, root_page: u32) -> Result<Self, Box<dyn Error>> {
+        let root = BTreePage::read(pager, root_page)?;
+        match root.page_type {
+            PageType::InteriorTable | PageType::LeafTable => {}
+            other => return Err(format!("Page {} is not a table b-tree page: {:?}", root_page, other).into()),
         }
+        Ok(TableCursor { pager, root: root_page, stack: vec![(root, 0)] })
+    }
 
-        let mut row = HashMap::new();
-        for (col_name, value) in column_names.iter().zip(values.iter()) {
-            row.insert(col_name.clone(), value.clone());
+    /// Repositions the cursor on the first row with a rowid >= `rowid`,
+    /// binary searching the interior keys on the way down.
+    pub fn seek(&mut self, rowid: i64) -> Result<(), Box<dyn Error>> {
+        self.stack.clear();
+        let mut page = BTreePage::read(self.pager, self.root)?;
+
+        loop {
+            match page.page_type {
+                PageType::InteriorTable => {
+                    // left child i holds rowids <= key i
+                    let i = partition_point(page.cell_count, |i| Ok(Self::interior_key(&page, i)? < rowid))?;
+                    let child = if i < page.cell_count {
+                        page.left_child(i)?
+                    } else {
+                        page.right_most.ok_or("Interior page without right-most pointer")?
+                    };
+                    self.stack.push((page, i + 1));
+                    check_depth(&self.stack, child)?;
+                    page = BTreePage::read(self.pager, child)?;
+                }
+                PageType::LeafTable => {
+                    let i = partition_point(page.cell_count, |i| Ok(Self::leaf_rowid(&page, i)? < rowid))?;
+                    self.stack.push((page, i));
+                    return Ok(());
+                }
+                other => return Err(format!("Unexpected page type in table b-tree: {:?}", other).into()),
+            }
         }
+    }
 
-        Ok(row)
+    /// Point lookup by rowid.
+    pub fn find(&mut self, rowid: i64) -> Result<Option<TableRow>, Box<dyn Error>> {
+        self.seek(rowid)?;
+        match self.advance()? {
+            Some(row) if row.rowid == rowid => Ok(Some(row)),
+            _ => Ok(None),
+        }
     }
 
+    fn interior_key(page: &BTreePage, i: u16) -> Result<i64, Box<dyn Error>> {
+        let pos = page.cell_offset(i)? + 4;
+        let (key, _) = read_varint(page.data().get(pos..).ok_or("Truncated interior cell")?)?;
+        Ok(key as i64)
+    }
 
-    pub fn parse_create_table(sql: &str) -> Result<Vec<String>, Box<dyn Error>> {
-        // (?s)는 dotall 모드 - '.'가 newline도 매치하도록 함
-        // *? 는 lazy matching - 가능한 최소로 매치
-        let re = Regex::new(r"(?s)CREATE TABLE \w+\s*\((.*?)\)")?;
+    fn leaf_rowid(page: &BTreePage, i: u16) -> Result<i64, Box<dyn Error>> {
+        let mut pos = page.cell_offset(i)?;
+        let (_, len) = read_varint(&page.data()[pos..])?;
+        pos += len;
+        let (rowid, _) = read_varint(&page.data()[pos..])?;
+        Ok(rowid as i64)
+    }
 
-        if let Some(caps) = re.captures(sql) {
-            let columns_str = &caps[1];
+    fn read_leaf_row(pager: &Pager, page: &BTreePage, i: u16) -> Result<TableRow, Box<dyn Error>> {
+        let data = page.data();
+        let mut pos = page.cell_offset(i)?;
 
-            // 쉼표로 분리하되, 괄호 안의 쉼표는 무시 (예: CHECK 제약조건)
-            let columns: Vec<String> = columns_str
-                .split(',')
-                .filter_map(|col| {
-                    let trimmed = col.trim();
+        let (payload_size, len1) = read_varint(&data[pos..])?;
+        pos += len1;
+        let (rowid, len2) = read_varint(&data[pos..])?;
+        pos += len2;
 
-                    // 빈 문자열이나 제약조건은 스킵
-                    if trimmed.is_empty() {
-                        return None;
-                    }
+        let payload = page.read_payload(pager, pos, payload_size)?;
+        Ok(TableRow { rowid: rowid as i64, payload })
+    }
+
+    fn advance(&mut self) -> Result<Option<TableRow>, Box<dyn Error>> {
+        loop {
+            let Some((page, idx)) = self.stack.last_mut() else {
+                return Ok(None);
+            };
 
-                    // 테이블 제약조건 스킵 (PRIMARY KEY, FOREIGN KEY, CHECK 등)
-                    let upper = trimmed.to_uppercase();
-                    if upper.starts_with("PRIMARY KEY") 
-                       || upper.starts_with("FOREIGN KEY")
-                       || upper.starts_with("UNIQUE")
-                       || upper.starts_with("CHECK")
-                       || upper.starts_with("CONSTRAINT") {
-                        return None;
+            match page.page_type {
+                PageType::LeafTable => {
+                    if *idx < page.cell_count {
+                        let row = Self::read_leaf_row(self.pager, page, *idx)?;
+                        *idx += 1;
+                        return Ok(Some(row));
                     }
+                    self.stack.pop();
+                }
+                PageType::InteriorTable => {
+                    let child = if *idx < page.cell_count {
+                        page.left_child(*idx)?
+                    } else if *idx == page.cell_count {
+                        page.right_most.ok_or("Interior page without right-most pointer")?
+                    } else {
+                        self.stack.pop();
+                        continue;
+                    };
+                    *idx += 1;
+
+                    check_depth(&self.stack, child)?;
+                    let child_page = BTreePage::read(self.pager, child)?;
+                    if !matches!(child_page.page_type, PageType::InteriorTable | PageType::LeafTable) {
+                        return Err(format!("Page {} is not a table b-tree page", child).into());
+                    }
+                    self.stack.push((child_page, 0));
+                }
+                other => return Err(format!("Unexpected page type in table b-tree: {:?}", other).into()),
+            }
+        }
+    }
+}
 
-                    // 첫 번째 단어만 추출 (컬럼 이름)
-                    trimmed
-                        .split_whitespace()
-                        .next()
-                        .map(|s| s.to_string())
-                })
-                .collect();
-            
-            Ok(columns)
+impl Iterator for TableCursor<'_> {
+    type Item = Result<TableRow, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.advance() {
+            Ok(Some(row)) => Some(Ok(row)),
+            Ok(None) => None,
+            Err(e) => {
+                // stop after the first error instead of looping over a corrupt page
+                self.stack.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+
+/// First index in `0..count` for which `before` is false, assuming cells are sorted.
+fn partition_point(count: u16, mut before: impl FnMut(u16) -> Result<bool, Box<dyn Error>>) -> Result<u16, Box<dyn Error>> {
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if before(mid)? {
+            lo = mid + 1;
         } else {
-            Err("Failed to parse CREATE TABLE".into())
+            hi = mid;
         }
     }
+    Ok(lo)
+}
 
-    
 
+/// Walks an index b-tree (page types 0x02 / 0x0A) in key order.
+/// Each entry is the indexed column values followed by the rowid.
+/// Interior cells hold entries too, visited between their left child and the next cell.
+pub struct IndexCursor<'a> {
+    pager: &'a Pager,
+    root: u32,
+    /// per index column: stored in descending order
+    descending: Vec<bool>,
+    /// per index column: the collation its text is ordered by
+    collations: Vec<Collation>,
+    // (page, step) - on interior pages step 2i descends into child i and 2i+1 yields cell i,
+    // child `cell_count` being the right-most pointer; on leaf pages step is the next cell
+    stack: Vec<(BTreePage, u32)>,
 }
 
+impl<'a> IndexCursor<'a> {
+    pub fn new(pager: &'a Pager, root_page: u32, descending: Vec<bool>, collations: Vec<Collation>) -> Result<Self, Box<dyn Error>> {
+        let root = Self::read_index_page(pager, root_page)?;
+        Ok(IndexCursor { pager, root: root_page, descending, collations, stack: vec![(root, 0)] })
+    }
+
+    fn read_index_page(pager: &Pager, page_number: u32) -> Result<BTreePage, Box<dyn Error>> {
+        let page = BTreePage::read(pager, page_number)?;
+        match page.page_type {
+            PageType::InteriorIndex | PageType::LeafIndex => Ok(page),
+            other => Err(format!("Page {} is not an index b-tree page: {:?}", page_number, other).into()),
+        }
+    }
+
+    fn read_entry(pager: &Pager, page: &BTreePage, i: u16) -> Result<Vec<Value>, Box<dyn Error>> {
+        let mut pos = page.cell_offset(i)?;
+        if page.page_type == PageType::InteriorIndex {
+            pos += 4;
+        }
+        let (payload_size, len) = read_varint(page.data().get(pos..).ok_or("Truncated index cell")?)?;
+        let payload = page.read_payload(pager, pos + len, payload_size)?;
+        parse_record(&payload, pager.text_encoding())
+    }
+
+    /// Compares the leading columns of an entry with `key`, honoring collations and DESC columns.
+    pub fn compare_prefix(&self, entry: &[Value], key: &[Value]) -> Ordering {
+        for (i, k) in key.iter().enumerate() {
+            let Some(v) = entry.get(i) else {
+                return Ordering::Less;
+            };
+            let collation = self.collations.get(i).copied().unwrap_or_default();
+            let mut ord = collation.compare(v, k, self.pager.text_encoding());
+            if self.descending.get(i).copied().unwrap_or(false) {
+                ord = ord.reverse();
+            }
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Positions the cursor on the first entry whose prefix is >= `key`,
+    /// or > `key` when `strict`.
+    pub fn seek(&mut self, key: &[Value], strict: bool) -> Result<(), Box<dyn Error>> {
+        self.stack.clear();
+        let mut page = Self::read_index_page(self.pager, self.root)?;
+
+        loop {
+            let pager = self.pager;
+            let i = partition_point(page.cell_count, |i| {
+                let entry = Self::read_entry(pager, &page, i)?;
+                Ok(match self.compare_prefix(&entry, key) {
+                    Ordering::Less => true,
+                    Ordering::Equal => strict,
+                    Ordering::Greater => false,
+                })
+            })?;
+
+            match page.page_type {
+                PageType::InteriorIndex => {
+                    let child = if i < page.cell_count {
+                        page.left_child(i)?
+                    } else {
+                        page.right_most.ok_or("Interior page without right-most pointer")?
+                    };
+                    // after the child subtree, continue with cell i itself
+                    self.stack.push((page, 2 * i as u32 + 1));
+                    check_depth(&self.stack, child)?;
+                    page = Self::read_index_page(self.pager, child)?;
+                }
+                _ => {
+                    self.stack.push((page, i as u32));
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self) -> Result<Option<Vec<Value>>, Box<dyn Error>> {
+        loop {
+            let Some((page, step)) = self.stack.last_mut() else {
+                return Ok(None);
+            };
+            let count = page.cell_count as u32;
+
+            if page.page_type == PageType::LeafIndex {
+                if *step < count {
+                    let entry = Self::read_entry(self.pager, page, *step as u16)?;
+                    *step += 1;
+                    return Ok(Some(entry));
+                }
+                self.stack.pop();
+                continue;
+            }
+
+            if *step > 2 * count {
+                self.stack.pop();
+                continue;
+            }
+
+            let current = *step;
+            *step += 1;
+            if current % 2 == 1 {
+                return Self::read_entry(self.pager, page, (current / 2) as u16).map(Some);
+            }
+
+            let i = (current / 2) as u16;
+            let child = if i < page.cell_count {
+                page.left_child(i)?
+            } else {
+                page.right_most.ok_or("Interior page without right-most pointer")?
+            };
+            check_depth(&self.stack, child)?;
+            let child_page = Self::read_index_page(self.pager, child)?;
+            self.stack.push((child_page, 0));
+        }
+    }
+}
+
+impl Iterator for IndexCursor<'_> {
+    type Item = Result<Vec<Value>, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.advance() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => None,
+            Err(e) => {
+                self.stack.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+
 struct SerialCode {}
 
 impl SerialCode{
@@ -149,21 +507,21 @@ impl SerialCode{
         }
     }
 
-    fn read_value_by_serial_type(data: &[u8], serial_type: u64) -> Result<String, Box<dyn std::error::Error>> {
+    fn read_value_by_serial_type(data: &[u8], serial_type: u64, encoding: TextEncoding) -> Result<Value, Box<dyn std::error::Error>> {
     match serial_type {
-        0 => Ok("NULL".to_string()),
+        0 => Ok(Value::Null),
         
         1 => {
             // 8비트 signed integer
             if data.is_empty() { return Err("No data for integer".into()); }
-            Ok(format!("{}", data[0] as i8))
+            Ok(Value::Integer(data[0] as i8 as i64))
         },
         
         2 => {
             // 16비트 signed integer (big-endian)
             if data.len() < 2 { return Err("Not enough data for i16".into()); }
             let value = i16::from_be_bytes([data[0], data[1]]);
-            Ok(format!("{}", value))
+            Ok(Value::Integer(value as i64))
         },
         
         3 => {
@@ -178,18 +536,43 @@ impl SerialCode{
             } else {
                 value
             };
-            Ok(format!("{}", value))
+            Ok(Value::Integer(value as i64))
         },
         
         4 => {
 
             if data.len() < 4 { return Err("Not enough data for i32".into()); }
             let value = i32::from_be_bytes([data[0], data[1], data[2], data[3]]);
-            Ok(format!("{}", value))
+            Ok(Value::Integer(value as i64))
         },
         
-        8 => Ok("0".to_string()),    // 상수 0
-        9 => Ok("1".to_string()),    // 상수 1
+        5 => {
+            // 48비트 signed integer (big-endian)
+            if data.len() < 6 { return Err("Not enough data for i48".into()); }
+            let mut bytes = [0u8; 8];
+            bytes[2..8].copy_from_slice(&data[0..6]);
+            // 상위 16비트로 부호 확장
+            let value = (i64::from_be_bytes(bytes) << 16) >> 16;
+            Ok(Value::Integer(value))
+        },
+
+        6 => {
+            if data.len() < 8 { return Err("Not enough data for i64".into()); }
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&data[0..8]);
+            Ok(Value::Integer(i64::from_be_bytes(bytes)))
+        },
+
+        7 => {
+            // IEEE 754 64비트 float (big-endian)
+            if data.len() < 8 { return Err("Not enough data for f64".into()); }
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&data[0..8]);
+            Ok(Value::Real(f64::from_be_bytes(bytes)))
+        },
+
+        8 => Ok(Value::Integer(0)),    // 상수 0
+        9 => Ok(Value::Integer(1)),    // 상수 1
         
         // TEXT: N ≥ 13이고 홀수
         n if n >= 13 && n % 2 == 1 => {
@@ -197,11 +580,7 @@ impl SerialCode{
             if data.len() < length { 
                 return Err(format!("Not enough data for text: need {}, have {}", length, data.len()).into()); 
             }
-            let text_bytes = &data[0..length];
-            match String::from_utf8(text_bytes.to_vec()) {
-                Ok(s) => Ok(s),
-                Err(_) => Ok(format!("Invalid UTF-8: {:?}", text_bytes))
-            }
+            Ok(Value::Text(encoding.decode(&data[0..length])))
         },
         
         // BLOB: N ≥ 12이고 짝수
@@ -210,15 +589,95 @@ impl SerialCode{
             if data.len() < length { 
                 return Err("Not enough data for blob".into()); 
             }
-            Ok(format!("BLOB({} bytes): {:?}", length, &data[0..length]))
+            Ok(Value::Blob(data[0..length].to_vec()))
         },
         
-        _ => Ok(format!("Unknown serial type: {}", serial_type))
+        // 10, 11은 예약된 타입 - 정상적인 파일에는 나타나지 않음
+        _ => Err(format!("Database disk image is malformed: reserved serial type {}", serial_type).into())
     }
 }
     
 }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::Schema;
+
+    fn fixture() -> Pager {
+        Pager::open(concat!(env!("CARGO_MANIFEST_DIR"), "/fixture.db")).unwrap()
+    }
+
+    /// A database of 512 byte pages: an empty schema, then interior pages of the given types
+    /// whose right-most pointers point back at themselves.
+    fn looping_pages(name: &str, page_types: &[u8]) -> Pager {
+        let page_count = page_types.len() + 1;
+        let mut file = vec![0; 512 * page_count];
+        file[0..16].copy_from_slice(b"SQLite format 3\0");
+        file[16..24].copy_from_slice(&[2, 0, 1, 1, 0, 64, 32, 32]);
+        file[28..32].copy_from_slice(&(page_count as u32).to_be_bytes());
+        file[44..48].copy_from_slice(&4u32.to_be_bytes());
+        file[56..60].copy_from_slice(&1u32.to_be_bytes());
+        file[100] = 0x0d;
+        file[105..107].copy_from_slice(&512u16.to_be_bytes());
+        for (i, &page_type) in page_types.iter().enumerate() {
+            let page_number = i as u32 + 2;
+            let page = &mut file[512 * (i + 1)..512 * (i + 2)];
+            page[0] = page_type;
+            page[5..7].copy_from_slice(&512u16.to_be_bytes());
+            page[8..12].copy_from_slice(&page_number.to_be_bytes());
+        }
+        let path = std::env::temp_dir().join(format!("mydbms-{}-{}.db", name, std::process::id()));
+        std::fs::write(&path, file).unwrap();
+        let pager = Pager::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        pager
+    }
+
+    #[test]
+    fn test_table_cursor_walks_interior_pages() {
+        let pager = fixture();
+        let root = Schema::load(&pager).unwrap().table("items").unwrap().rootpage;
+        assert_eq!(BTreePage::read(&pager, root).unwrap().page_type, PageType::InteriorTable);
 
+        let rowids: Vec<i64> = TableCursor::new(&pager, root).unwrap().map(|row| row.unwrap().rowid).collect();
+        assert_eq!(rowids, (1..=2000).collect::<Vec<_>>());
+    }
 
+    #[test]
+    fn test_overflow_payloads() {
+        let pager = fixture();
+        let root = Schema::load(&pager).unwrap().table("items").unwrap().rootpage;
+        let body: String = (1..=1200).map(|i| format!("{:03}", i % 1000)).collect();
+
+        let spilled: Vec<TableRow> = TableCursor::new(&pager, root)
+            .unwrap()
+            .map(|row| row.unwrap())
+            .filter(|row| row.rowid == 7 || row.rowid == 1500)
+            .collect();
+        assert_eq!(spilled.len(), 2);
+        for row in spilled {
+            let record = parse_record(&row.payload, pager.text_encoding()).unwrap();
+            assert_eq!(record[3], Value::Text(body.clone()));
+            // row 7 holds a zeroblob, row 1500 the first 2500 characters of the text
+            let data = if row.rowid == 7 { vec![0; 2500] } else { body.as_bytes()[..2500].to_vec() };
+            assert_eq!(record[4], Value::Blob(data));
+        }
+    }
+
+    #[test]
+    fn test_page_loops_are_malformed() {
+        let pager = looping_pages("loops", &[0x05, 0x02]);
+        let malformed = |e: Box<dyn Error>| e.to_string().starts_with("Database disk image is malformed");
+
+        let mut table = TableCursor::new(&pager, 2).unwrap();
+        assert!(table.next().is_some_and(|row| row.is_err_and(malformed)));
+        assert!(table.next().is_none());
+        assert!(TableCursor::new(&pager, 2).unwrap().seek(1).is_err_and(malformed));
+
+        let mut index = IndexCursor::new(&pager, 3, Vec::new(), Vec::new()).unwrap();
+        assert!(index.next().is_some_and(|entry| entry.is_err_and(malformed)));
+        assert!(index.seek(&[Value::Integer(1)], false).is_err_and(malformed));
+    }
+}
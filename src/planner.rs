@@ -0,0 +1,881 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::ops::Range;
+
+use crate::collation::Collation;
+use crate::datetime::Clock;
+use crate::encoding::TextEncoding;
+use crate::eval::{self, comparison_affinity, comparison_collation, EvalContext, Scope};
+use crate::functions::is_deterministic;
+use crate::page::{parse_record, IndexCursor, TableCursor};
+use crate::read::Pager;
+use crate::schema::{IndexDef, Schema, SchemaEntry};
+use crate::sql::ast::{BinaryOp, ColumnRef, CreateIndexStatement, Expr, IndexedColumn, Span, TableConstraint, TableDef};
+use crate::value::{real_to_i64, Affinity, Value};
+
+
+#[derive(Debug, Clone)]
+pub struct Bound {
+    pub value: Value,
+    pub inclusive: bool,
+}
+
+
+/// How the rows of one table are read, with the key values known.
+#[derive(Debug, Clone)]
+pub enum ScanPlan {
+    FullScan,
+    /// Equality on the leading index columns, optionally a range on the next one.
+    /// Bounds are in value order; DESC columns are taken care of when scanning.
+    IndexScan {
+        rootpage: u32,
+        descending: Vec<bool>,
+        collations: Vec<Collation>,
+        eq: Vec<Value>,
+        lower: Option<Bound>,
+        upper: Option<Bound>,
+    },
+    /// Rowids between the inclusive bounds, read straight from the table b-tree.
+    RowidRange {
+        lower: Option<i64>,
+        upper: Option<i64>,
+    },
+}
+
+
+/// A key of an access plan: an expression over the tables before, converted the way
+/// its comparison with the column converts it.
+#[derive(Debug, Clone)]
+pub struct Key {
+    pub expr: Expr,
+    pub affinity: Affinity,
+}
+
+impl Key {
+    fn eval(&self, ctx: &EvalContext) -> Result<Value, Box<dyn Error>> {
+        Ok(self.affinity.apply(ctx.eval(&self.expr)?))
+    }
+}
+
+
+/// How the rows of one table are read, with the keys still expressions.
+/// In a join the keys may refer to the tables before this one and are evaluated for each of their rows.
+#[derive(Debug, Clone)]
+pub enum AccessPlan {
+    FullScan,
+    IndexScan {
+        rootpage: u32,
+        descending: Vec<bool>,
+        collations: Vec<Collation>,
+        eq: Vec<Key>,
+        /// key and whether it is inclusive
+        lower: Option<(Box<Key>, bool)>,
+        upper: Option<(Box<Key>, bool)>,
+    },
+    Rowid {
+        constraints: Vec<(BinaryOp, Key)>,
+    },
+}
+
+impl AccessPlan {
+    /// Whether the keys depend on the rows of other tables.
+    pub fn is_correlated(&self) -> bool {
+        let mut keys: Vec<&Expr> = Vec::new();
+        match self {
+            AccessPlan::FullScan => {}
+            AccessPlan::IndexScan { eq, lower, upper, .. } => {
+                keys.extend(eq.iter().map(|key| &key.expr));
+                keys.extend(lower.iter().chain(upper).map(|(key, _)| &key.expr));
+            }
+            AccessPlan::Rowid { constraints } => keys.extend(constraints.iter().map(|(_, key)| &key.expr)),
+        }
+        keys.into_iter().any(|key| !is_constant(key))
+    }
+
+    /// Evaluates the keys; `None` means no row can match, e.g. a key is NULL.
+    pub fn bind(&self, ctx: &EvalContext) -> Result<Option<ScanPlan>, Box<dyn Error>> {
+        Ok(match self {
+            AccessPlan::FullScan => Some(ScanPlan::FullScan),
+            AccessPlan::IndexScan { rootpage, descending, collations, eq, lower, upper } => {
+                let mut values = Vec::with_capacity(eq.len());
+                for key in eq {
+                    let value = key.eval(ctx)?;
+                    if value.is_null() {
+                        return Ok(None);
+                    }
+                    values.push(value);
+                }
+                let mut bounds = [None, None];
+                for (slot, bound) in bounds.iter_mut().zip([lower, upper]) {
+                    if let Some((key, inclusive)) = bound {
+                        let value = key.eval(ctx)?;
+                        if value.is_null() {
+                            return Ok(None);
+                        }
+                        *slot = Some(Bound { value, inclusive: *inclusive });
+                    }
+                }
+                let [lower, upper] = bounds;
+                Some(ScanPlan::IndexScan {
+                    rootpage: *rootpage,
+                    descending: descending.clone(),
+                    collations: collations.clone(),
+                    eq: values,
+                    lower,
+                    upper,
+                })
+            }
+            AccessPlan::Rowid { constraints } => {
+                let mut bound = Vec::with_capacity(constraints.len());
+                for (op, key) in constraints {
+                    let value = key.eval(ctx)?;
+                    if value.is_null() {
+                        return Ok(None);
+                    }
+                    bound.push((*op, value));
+                }
+                Some(rowid_range(&bound))
+            }
+        })
+    }
+}
+
+
+/// `column op value` term of a WHERE or ON clause, with the column counted within its table.
+#[derive(Debug, Clone)]
+struct Constraint {
+    column: usize,
+    op: BinaryOp,
+    value: Key,
+    /// what the comparison compares with; only an index in that collation can search it
+    collation: Collation,
+}
+
+
+/// Picks a rowid lookup, the index that constrains the most leading columns, or a full scan
+/// for the table whose columns sit at `columns` in the scope. A rowid equality beats any index;
+/// a rowid range is weighed like a range on an index column, so an index equality beats it.
+/// Usable terms compare a column of the table with an expression over the tables before it.
+/// The chosen plan only narrows the rows read; the terms are still applied to every row.
+/// A WITHOUT ROWID table is only searched through its primary key.
+pub fn plan_access(
+    schema: &Schema,
+    table: &SchemaEntry,
+    definition: &TableDef,
+    scope: &Scope,
+    columns: Range<usize>,
+    terms: &[&Expr],
+) -> AccessPlan {
+    let mut constraints = Vec::new();
+    for term in terms {
+        collect_constraints(term, scope, &columns, &mut constraints);
+    }
+    if constraints.is_empty() {
+        return AccessPlan::FullScan;
+    }
+
+    // index definitions name the table's columns without qualification
+    let table_scope = Scope { columns: scope.columns[columns.clone()].to_vec(), levels: Vec::new() };
+    if definition.without_rowid {
+        // other indexes hold the primary key rather than a rowid to find the row by
+        let index = primary_key_index(table, definition);
+        return plan_index(&index, &table_scope, &constraints).map_or(AccessPlan::FullScan, |(_, plan)| plan);
+    }
+    let rowid_alias = definition.rowid_alias();
+
+    // the rowid is the table's last, hidden column
+    let rowid = columns.len() - 1;
+    let on_rowid: Vec<(BinaryOp, Key)> = constraints
+        .iter()
+        .filter(|c| c.column == rowid || Some(c.column) == rowid_alias)
+        .map(|c| (c.op, c.value.clone()))
+        .collect();
+    if on_rowid.iter().any(|(op, _)| *op == BinaryOp::Eq) {
+        return AccessPlan::Rowid { constraints: on_rowid };
+    }
+
+    let mut best: Option<(usize, AccessPlan)> = None;
+    for index in schema.indexes(&table.name) {
+        if let Some((score, plan)) = plan_index(&index, &table_scope, &constraints)
+            && best.as_ref().is_none_or(|(best_score, _)| score > *best_score)
+        {
+            best = Some((score, plan));
+        }
+    }
+
+    let lower = on_rowid.iter().any(|(op, _)| matches!(op, BinaryOp::Gt | BinaryOp::GtEq));
+    let upper = on_rowid.iter().any(|(op, _)| matches!(op, BinaryOp::Lt | BinaryOp::LtEq));
+    let rowid_score = lower as usize + upper as usize;
+    match best {
+        Some((score, plan)) if score > rowid_score => plan,
+        _ if !on_rowid.is_empty() => AccessPlan::Rowid { constraints: on_rowid },
+        _ => AccessPlan::FullScan,
+    }
+}
+
+
+/// The primary key b-tree of a WITHOUT ROWID table, which holds the rows with the key columns first.
+fn primary_key_index(table: &SchemaEntry, definition: &TableDef) -> IndexDef {
+    let key: Vec<&IndexedColumn> = definition
+        .constraints
+        .iter()
+        .filter_map(|constraint| match constraint {
+            TableConstraint::PrimaryKey(key) => Some(key),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    let columns = definition
+        .primary_key()
+        .into_iter()
+        .map(|i| {
+            let column = &definition.columns[i];
+            // the COLLATE and DESC of a table PRIMARY KEY clause decide the order of its column
+            let indexed = key.iter().find(|k| matches!(&k.expr, Expr::Column(c) if c.name.eq_ignore_ascii_case(&column.name)));
+            let name = ColumnRef { table: None, name: column.name.clone(), double_quoted: false, span: Span::default(), position: None };
+            IndexedColumn {
+                expr: Expr::Column(name),
+                collation: indexed.and_then(|k| k.collation.clone()),
+                descending: column.primary_key.map_or(indexed.is_some_and(|k| k.descending), |pk| pk.descending),
+            }
+        })
+        .collect();
+    let definition = CreateIndexStatement {
+        name: table.name.clone(),
+        table: table.name.clone(),
+        unique: true,
+        columns,
+        where_clause: None,
+    };
+    IndexDef { rootpage: table.rootpage, definition }
+}
+
+fn plan_index(index: &IndexDef, scope: &Scope, constraints: &[Constraint]) -> Option<(usize, AccessPlan)> {
+    let definition = &index.definition;
+    // partial indexes do not contain every row
+    if definition.where_clause.is_some() {
+        return None;
+    }
+
+    // an index column is in the order of its own COLLATE, else of the table column's
+    let mut collations = Vec::with_capacity(definition.columns.len());
+    for indexed in &definition.columns {
+        let collation = match (&indexed.collation, &indexed.expr) {
+            (Some(name), _) => Collation::from_name(name)?,
+            (None, Expr::Column(column)) => scope.resolve(column).ok()?.map_or(Collation::Binary, |i| scope.columns[i].collation),
+            (None, _) => Collation::Binary,
+        };
+        collations.push(collation);
+    }
+
+    let mut eq = Vec::new();
+    let mut lower = None;
+    let mut upper = None;
+
+    for (indexed, &collation) in definition.columns.iter().zip(&collations) {
+        // expression indexes cannot be searched with plain values
+        let Expr::Column(column) = &indexed.expr else { break };
+        let Ok(Some(position)) = scope.resolve(column) else { break };
+        let on_column: Vec<&Constraint> =
+            constraints.iter().filter(|c| c.column == position && c.collation == collation).collect();
+
+        if let Some(c) = on_column.iter().find(|c| c.op == BinaryOp::Eq) {
+            eq.push(c.value.clone());
+            continue;
+        }
+
+        for c in on_column {
+            let bound = (Box::new(c.value.clone()), matches!(c.op, BinaryOp::GtEq | BinaryOp::LtEq));
+            match c.op {
+                BinaryOp::Gt | BinaryOp::GtEq => lower = Some(bound),
+                BinaryOp::Lt | BinaryOp::LtEq => upper = Some(bound),
+                _ => {}
+            }
+        }
+        break;
+    }
+
+    let score = eq.len() * 2 + lower.is_some() as usize + upper.is_some() as usize;
+    if score == 0 {
+        return None;
+    }
+
+    let plan = AccessPlan::IndexScan {
+        rootpage: index.rootpage,
+        descending: definition.columns.iter().map(|c| c.descending).collect(),
+        collations,
+        eq,
+        lower,
+        upper,
+    };
+    Some((score, plan))
+}
+
+
+/// Narrows the rowid to an inclusive integer range.
+fn rowid_range(constraints: &[(BinaryOp, Value)]) -> ScanPlan {
+    let mut lower: Option<i64> = None;
+    let mut upper: Option<i64> = None;
+
+    for (op, value) in constraints {
+        let (low, high) = match (value, *op) {
+            (Value::Integer(v), BinaryOp::Eq) => (Some(*v), Some(*v)),
+            (Value::Integer(v), BinaryOp::Gt) => (Some(v.saturating_add(1)), None),
+            (Value::Integer(v), BinaryOp::GtEq) => (Some(*v), None),
+            (Value::Integer(v), BinaryOp::Lt) => (None, Some(v.saturating_sub(1))),
+            (Value::Integer(v), BinaryOp::LtEq) => (None, Some(*v)),
+            // 2.5 < rowid is 3 <= rowid; an equality with a fraction matches nothing
+            (Value::Real(v), op) => {
+                let ceil = real_to_i64(v.ceil());
+                let floor = real_to_i64(v.floor());
+                let integral = v.fract() == 0.0;
+                match op {
+                    BinaryOp::Eq if integral => (Some(ceil), Some(ceil)),
+                    BinaryOp::Eq => (Some(1), Some(0)),
+                    BinaryOp::Gt if integral => (Some(ceil.saturating_add(1)), None),
+                    BinaryOp::Gt | BinaryOp::GtEq => (Some(ceil), None),
+                    BinaryOp::Lt if integral => (None, Some(floor.saturating_sub(1))),
+                    BinaryOp::Lt | BinaryOp::LtEq => (None, Some(floor)),
+                    _ => continue,
+                }
+            }
+            // text and blobs never equal an integer rowid; leave them to the WHERE clause
+            _ => continue,
+        };
+        if let Some(low) = low {
+            lower = Some(lower.map_or(low, |l| l.max(low)));
+        }
+        if let Some(high) = high {
+            upper = Some(upper.map_or(high, |u| u.min(high)));
+        }
+    }
+
+    ScanPlan::RowidRange { lower, upper }
+}
+
+
+/// The AND-ed terms of a condition.
+pub fn split_conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Binary { op: BinaryOp::And, left, right } => {
+            let mut terms = split_conjuncts(left);
+            terms.extend(split_conjuncts(right));
+            terms
+        }
+        _ => vec![expr],
+    }
+}
+
+/// Looks for `column op value` in one term, with the column in `columns`.
+fn collect_constraints(expr: &Expr, scope: &Scope, columns: &Range<usize>, out: &mut Vec<Constraint>) {
+    match expr {
+        Expr::Binary { op, left, right } => {
+            let op = *op;
+            if !matches!(op, BinaryOp::Eq | BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq) {
+                return;
+            }
+            if let Some(c) = constraint(left, op, right, scope, columns) {
+                out.push(c);
+            } else if let Some(c) = constraint(right, flip(op), left, scope, columns) {
+                out.push(c);
+            }
+        }
+        Expr::Between { expr, low, high, negated: false } => {
+            if let Some(c) = constraint(expr, BinaryOp::GtEq, low, scope, columns) {
+                out.push(c);
+            }
+            if let Some(c) = constraint(expr, BinaryOp::LtEq, high, scope, columns) {
+                out.push(c);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn constraint(column_expr: &Expr, op: BinaryOp, value: &Expr, scope: &Scope, columns: &Range<usize>) -> Option<Constraint> {
+    let Expr::Column(column) = column_expr else { return None };
+    let position = scope.resolve(column).ok()??;
+    if !columns.contains(&position) || !depends_only_on(value, scope, columns.start) {
+        return None;
+    }
+    // the stored values are only in the order of the comparison if it leaves them as they are
+    let stored = scope.columns[position].affinity;
+    let affinity = comparison_affinity(stored, eval::affinity(value, scope));
+    let in_order = match affinity {
+        Affinity::Blob => true,
+        Affinity::Text => stored == Some(Affinity::Text),
+        _ => stored.is_some_and(Affinity::is_numeric),
+    };
+    if !in_order {
+        return None;
+    }
+    let collation = comparison_collation(eval::collation(column_expr, scope).ok()?, eval::collation(value, scope).ok()?);
+    Some(Constraint { column: position - columns.start, op, value: Key { expr: value.clone(), affinity }, collation })
+}
+
+/// Whether an expression can be evaluated from the first `width` scope columns alone.
+pub fn depends_only_on(expr: &Expr, scope: &Scope, width: usize) -> bool {
+    match expr {
+        Expr::Column(column) => matches!(scope.resolve(column), Ok(Some(position)) if position < width),
+        Expr::Function { name, args, over: None, .. } if is_deterministic(name, args) => {
+            expr.children().into_iter().all(|child| depends_only_on(child, scope, width))
+        }
+        Expr::Function { .. } => false,
+        // what a subquery reads is not visible from here
+        _ if expr.subquery().is_some() => false,
+        _ => expr.children().into_iter().all(|child| depends_only_on(child, scope, width)),
+    }
+}
+
+fn is_constant(expr: &Expr) -> bool {
+    depends_only_on(expr, &Scope::default(), 0)
+}
+
+/// `5 < x` is `x > 5`.
+fn flip(op: BinaryOp) -> BinaryOp {
+    match op {
+        BinaryOp::Lt => BinaryOp::Gt,
+        BinaryOp::LtEq => BinaryOp::GtEq,
+        BinaryOp::Gt => BinaryOp::Lt,
+        BinaryOp::GtEq => BinaryOp::LtEq,
+        other => other,
+    }
+}
+
+
+enum ScanSource<'a> {
+    Full(TableCursor<'a>),
+    /// a WITHOUT ROWID table, whose rows are the entries of its primary key b-tree,
+    /// narrowed like `Index`
+    Entries {
+        index: IndexCursor<'a>,
+        eq: Vec<Value>,
+        stop: Option<Bound>,
+    },
+    Rowid {
+        table: TableCursor<'a>,
+        upper: Option<i64>,
+    },
+    Index {
+        index: IndexCursor<'a>,
+        table: TableCursor<'a>,
+        eq: Vec<Value>,
+        /// last key in index order, if any
+        stop: Option<Bound>,
+    },
+}
+
+/// A VIRTUAL column: its position, the expression it is computed from and its affinity.
+type GeneratedColumn = (usize, Expr, Affinity);
+
+/// Rows of one table, decoded and padded to the table's column count, with the rowid appended.
+/// The INTEGER PRIMARY KEY column is stored as NULL and filled in from the rowid.
+/// REAL columns store whole numbers as integers to save space; they are read back as reals.
+/// VIRTUAL generated columns are not in the record and are computed from the rest of the row.
+/// A WITHOUT ROWID table stores its primary key columns first and has no rowid to append.
+pub struct TableScan<'a> {
+    source: ScanSource<'a>,
+    column_count: usize,
+    rowid_alias: Option<usize>,
+    real_columns: Vec<usize>,
+    text_encoding: TextEncoding,
+    clock: Clock,
+    /// column of each record value, when the record is not simply the columns in order
+    stored_columns: Option<Vec<usize>>,
+    /// the VIRTUAL columns in an order where those a column reads come before it
+    generated: Vec<GeneratedColumn>,
+    /// what the generating expressions see; empty without VIRTUAL columns
+    scope: Scope,
+}
+
+impl<'a> TableScan<'a> {
+    pub fn new(pager: &'a Pager, rootpage: u32, definition: &TableDef, plan: &ScanPlan, clock: Clock) -> Result<Self, Box<dyn Error>> {
+        let source = match plan {
+            ScanPlan::FullScan if definition.without_rowid => {
+                let index = IndexCursor::new(pager, rootpage, Vec::new(), Vec::new())?;
+                ScanSource::Entries { index, eq: Vec::new(), stop: None }
+            }
+            // the primary key is the only index the rows of a WITHOUT ROWID table are found through
+            _ if definition.without_rowid && !matches!(plan, ScanPlan::IndexScan { rootpage: index_root, .. } if *index_root == rootpage) => {
+                return Err(format!("{} has no rowid to find its rows by", definition.name).into());
+            }
+            ScanPlan::FullScan => ScanSource::Full(TableCursor::new(pager, rootpage)?),
+            ScanPlan::RowidRange { lower, upper } => {
+                let mut table = TableCursor::new(pager, rootpage)?;
+                if let Some(lower) = lower {
+                    table.seek(*lower)?;
+                }
+                ScanSource::Rowid { table, upper: *upper }
+            }
+            ScanPlan::IndexScan { rootpage: index_root, descending, collations, eq, lower, upper } => {
+                let mut index = IndexCursor::new(pager, *index_root, descending.clone(), collations.clone())?;
+
+                // a DESC column is stored from the upper bound down
+                let range_descending = descending.get(eq.len()).copied().unwrap_or(false);
+                let (start, stop) = if range_descending {
+                    (upper.clone(), lower.clone())
+                } else {
+                    (lower.clone(), upper.clone())
+                };
+
+                let mut key = eq.clone();
+                match start {
+                    Some(bound) => {
+                        key.push(bound.value);
+                        index.seek(&key, !bound.inclusive)?;
+                    }
+                    // NULLs sort first and never match a range, skip them
+                    None if stop.is_some() && !range_descending => {
+                        key.push(Value::Null);
+                        index.seek(&key, true)?;
+                    }
+                    None => index.seek(&key, false)?,
+                }
+
+                match definition.without_rowid {
+                    true => ScanSource::Entries { index, eq: eq.clone(), stop },
+                    false => ScanSource::Index { index, table: TableCursor::new(pager, rootpage)?, eq: eq.clone(), stop },
+                }
+            }
+        };
+        let real_columns = (0..definition.columns.len()).filter(|&i| definition.columns[i].affinity == Affinity::Real).collect();
+        let virtual_columns: Vec<usize> = (0..definition.columns.len()).filter(|&i| is_virtual(definition, i)).collect();
+        let stored_columns = (definition.without_rowid || !virtual_columns.is_empty()).then(|| {
+            let mut stored = if definition.without_rowid { definition.primary_key() } else { Vec::new() };
+            for i in 0..definition.columns.len() {
+                if !stored.contains(&i) && !virtual_columns.contains(&i) {
+                    stored.push(i);
+                }
+            }
+            stored
+        });
+        let (scope, generated) = match virtual_columns.is_empty() {
+            true => (Scope::default(), Vec::new()),
+            false => {
+                let scope = Scope::for_table(&definition.name, &definition.column_names());
+                let generated = generation_order(definition, &virtual_columns, &scope)?;
+                (scope, generated)
+            }
+        };
+        Ok(TableScan {
+            source,
+            column_count: definition.columns.len(),
+            rowid_alias: definition.rowid_alias(),
+            real_columns,
+            text_encoding: pager.text_encoding(),
+            clock,
+            stored_columns,
+            generated,
+            scope,
+        })
+    }
+
+    fn advance(&mut self) -> Result<Option<Vec<Value>>, Box<dyn Error>> {
+        let row = match &mut self.source {
+            ScanSource::Entries { index, eq, stop } => {
+                let Some(entry) = index.next() else {
+                    return Ok(None);
+                };
+                let entry = entry?;
+                if !in_range(index, &entry, eq, stop) {
+                    return Ok(None);
+                }
+                return self.row(entry, None).map(Some);
+            }
+            ScanSource::Full(cursor) => match cursor.next() {
+                Some(row) => row?,
+                None => return Ok(None),
+            },
+            ScanSource::Rowid { table, upper } => match table.next() {
+                Some(row) => {
+                    let row = row?;
+                    if upper.is_some_and(|upper| row.rowid > upper) {
+                        return Ok(None);
+                    }
+                    row
+                }
+                None => return Ok(None),
+            },
+            ScanSource::Index { index, table, eq, stop } => {
+                let Some(entry) = index.next() else {
+                    return Ok(None);
+                };
+                let entry = entry?;
+                if !in_range(index, &entry, eq, stop) {
+                    return Ok(None);
+                }
+
+                // the rowid is the last column of every index entry
+                let Some(Value::Integer(rowid)) = entry.last() else {
+                    return Err("Index entry without rowid".into());
+                };
+                match table.find(*rowid)? {
+                    Some(row) => row,
+                    None => return Err(format!("Index points at missing rowid {}", rowid).into()),
+                }
+            }
+        };
+
+        let record = parse_record(&row.payload, self.text_encoding)?;
+        self.row(record, Some(row.rowid)).map(Some)
+    }
+
+    /// The table row of a record, with the rowid of a table that has one.
+    fn row(&self, mut values: Vec<Value>, rowid: Option<i64>) -> Result<Vec<Value>, Box<dyn Error>> {
+        // rows written before an ALTER TABLE ADD COLUMN are shorter
+        match &self.stored_columns {
+            None => values.resize(self.column_count, Value::Null),
+            Some(stored) => {
+                let mut row = vec![Value::Null; self.column_count];
+                for (value, &i) in values.into_iter().zip(stored) {
+                    row[i] = value;
+                }
+                values = row;
+            }
+        }
+        if let Some(alias) = self.rowid_alias
+            && let Some(rowid) = rowid
+        {
+            values[alias] = Value::Integer(rowid);
+        }
+        for &i in &self.real_columns {
+            if let Value::Integer(n) = values[i] {
+                values[i] = Value::Real(n as f64);
+            }
+        }
+        if let Some(rowid) = rowid {
+            values.push(Value::Integer(rowid));
+        }
+        for (i, expr, affinity) in &self.generated {
+            values[*i] = match affinity.apply(EvalContext::new(&self.scope, &values, self.text_encoding, self.clock).eval(expr)?) {
+                Value::Integer(n) if *affinity == Affinity::Real => Value::Real(n as f64),
+                value => value,
+            };
+        }
+        Ok(values)
+    }
+}
+
+impl Iterator for TableScan<'_> {
+    type Item = Result<Vec<Value>, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.advance().transpose()
+    }
+}
+
+
+/// Whether an index entry read after the seek still has the `eq` prefix and is not past `stop`.
+fn in_range(index: &IndexCursor, entry: &[Value], eq: &[Value], stop: &Option<Bound>) -> bool {
+    if index.compare_prefix(entry, eq) != Ordering::Equal {
+        return false;
+    }
+    let Some(bound) = stop else {
+        return true;
+    };
+    let mut key = eq.to_vec();
+    key.push(bound.value.clone());
+    match index.compare_prefix(entry, &key) {
+        Ordering::Greater => false,
+        Ordering::Equal => bound.inclusive,
+        Ordering::Less => true,
+    }
+}
+
+fn is_virtual(definition: &TableDef, i: usize) -> bool {
+    definition.columns[i].generated.as_ref().is_some_and(|generated| !generated.stored)
+}
+
+/// Orders the VIRTUAL columns so each is computed after the VIRTUAL columns it reads.
+fn generation_order(definition: &TableDef, virtual_columns: &[usize], scope: &Scope) -> Result<Vec<GeneratedColumn>, Box<dyn Error>> {
+    let mut pending = Vec::with_capacity(virtual_columns.len());
+    for &i in virtual_columns {
+        let Some(generated) = &definition.columns[i].generated else { continue };
+        let mut reads = Vec::new();
+        let mut exprs = vec![&generated.expr];
+        while let Some(expr) = exprs.pop() {
+            if let Expr::Column(column) = expr
+                && let Some(position) = scope.resolve(column)?
+                && virtual_columns.contains(&position)
+            {
+                reads.push(position);
+            }
+            exprs.extend(expr.children());
+        }
+        pending.push((i, &generated.expr, reads));
+    }
+
+    let mut order: Vec<GeneratedColumn> = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|(_, _, reads)| reads.iter().all(|read| order.iter().any(|(done, _, _)| done == read)));
+        let Some(ready) = ready else {
+            return Err(format!("generated column loop on \"{}\"", definition.columns[pending[0].0].name).into());
+        };
+        let (i, expr, _) = pending.remove(ready);
+        order.push((i, expr.clone(), definition.columns[i].affinity));
+    }
+    Ok(order)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::{self, ast::Statement};
+    use crate::sql_engine::query_fixture;
+
+    /// The plan for `SELECT * FROM items WHERE <where_clause>` and the rows it reads.
+    fn plan_items(pager: &Pager, schema: &Schema, where_clause: &str) -> (AccessPlan, Vec<Vec<Value>>) {
+        let table = schema.table("items").unwrap();
+        let definition = table.table_def().unwrap();
+        let scope = Scope::for_table("items", &definition.column_names());
+
+        let Ok(Statement::Select(select)) = sql::parse(&format!("SELECT * FROM items WHERE {}", where_clause)) else { panic!() };
+        let terms = split_conjuncts(select.where_clause.as_ref().unwrap());
+        let plan = plan_access(schema, table, &definition, &scope, 0..scope.columns.len(), &terms);
+
+        let rows = match plan.bind(&EvalContext::new(&scope, &[], pager.text_encoding(), Clock::System)).unwrap() {
+            Some(scan) => TableScan::new(pager, table.rootpage, &definition, &scan, Clock::System).unwrap().map(|row| row.unwrap()).collect(),
+            None => Vec::new(),
+        };
+        (plan, rows)
+    }
+
+    #[test]
+    fn test_index_seeks() {
+        let pager = Pager::open(concat!(env!("CARGO_MANIFEST_DIR"), "/fixture.db")).unwrap();
+        let schema = Schema::load(&pager).unwrap();
+        let index = schema.indexes("items").into_iter().find(|index| index.definition.name == "items_grp_name").unwrap();
+
+        // (WHERE clause, names read, grp values read); grp is stored descending
+        let seeks = [
+            ("grp = 3 AND name > 'ITEM1950'", vec!["ITEM1953", "ITEM1963", "ITEM1973", "ITEM1983", "ITEM1993"], vec![3]),
+            ("grp = 9 AND name <= 'ITEM0049'", vec!["ITEM0009", "ITEM0019", "ITEM0029", "ITEM0039", "ITEM0049"], vec![9]),
+            ("grp = 4 AND name BETWEEN 'item0100' AND 'item0134'", vec!["item0104", "item0114", "item0124", "item0134"], vec![4]),
+            ("grp >= 8", vec![], vec![9, 8]),
+            ("grp < 1", vec![], vec![0]),
+            ("5 < grp AND grp < 7", vec![], vec![6]),
+        ];
+        for (where_clause, names, groups) in seeks {
+            let (plan, rows) = plan_items(&pager, &schema, where_clause);
+            assert!(matches!(plan, AccessPlan::IndexScan { rootpage, .. } if rootpage == index.rootpage), "{}", where_clause);
+
+            if names.is_empty() {
+                // every group holds 200 rows
+                assert_eq!(rows.len(), 200 * groups.len(), "{}", where_clause);
+            } else {
+                assert_eq!(rows.iter().map(|row| row[1].to_text(pager.text_encoding())).collect::<Vec<_>>(), names, "{}", where_clause);
+            }
+            let mut read: Vec<Value> = rows.iter().map(|row| row[2].clone()).collect();
+            read.dedup();
+            assert_eq!(read, groups.into_iter().map(Value::Integer).collect::<Vec<_>>(), "{}", where_clause);
+        }
+
+        assert!(matches!(plan_items(&pager, &schema, "grp + 0 = 3").0, AccessPlan::FullScan));
+    }
+
+    #[test]
+    fn test_nocase_index_seeks() {
+        // name is NOCASE, and so are the index entries on it
+        let seeks = [
+            ("SELECT id, name FROM items WHERE name = 'item0043'", "43|ITEM0043"),
+            ("SELECT group_concat(id) FROM items WHERE name BETWEEN 'item0100' AND 'ITEM0103'", "100,101,102,103"),
+            ("SELECT group_concat(id) FROM items WHERE grp = 3 AND name > 'item1950'", "1953,1963,1973,1983,1993"),
+            ("SELECT group_concat(id) FROM items WHERE name = 'ITEM0042' COLLATE BINARY", ""),
+        ];
+        for (sql, expected) in seeks {
+            assert_eq!(query_fixture(sql).unwrap(), [expected], "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_rowid_lookups() {
+        let pager = Pager::open(concat!(env!("CARGO_MANIFEST_DIR"), "/fixture.db")).unwrap();
+        let schema = Schema::load(&pager).unwrap();
+
+        let lookups = [
+            ("id = 1234", vec![1234]),
+            ("rowid BETWEEN 10 AND 12", vec![10, 11, 12]),
+            ("1997 < id", vec![1998, 1999, 2000]),
+            ("id >= 4.5 AND _rowid_ < 7", vec![5, 6]),
+            ("id = 2.5", vec![]),
+            ("id = 0", vec![]),
+        ];
+        for (where_clause, ids) in lookups {
+            let (plan, rows) = plan_items(&pager, &schema, where_clause);
+            assert!(matches!(plan, AccessPlan::Rowid { .. }), "{}", where_clause);
+            let read: Vec<Value> = rows.iter().map(|row| row[0].clone()).collect();
+            assert_eq!(read, ids.into_iter().map(Value::Integer).collect::<Vec<_>>(), "{}", where_clause);
+        }
+
+        let mut cursor = TableCursor::new(&pager, schema.table("items").unwrap().rootpage).unwrap();
+        cursor.seek(1234).unwrap();
+        assert_eq!(cursor.next().unwrap().unwrap().rowid, 1234);
+        assert!(cursor.find(0).unwrap().is_none());
+        assert!(cursor.find(2001).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_generated_columns() {
+        // q(a INT, b TEXT GENERATED ALWAYS AS (upper(a)) VIRTUAL, c AS (a*2) STORED): b is not in the record
+        assert_eq!(query_fixture("SELECT a, b, c FROM q").unwrap(), ["1|1|2", "2|2|4", "x|X|0"]);
+        assert_eq!(query_fixture("SELECT typeof(b), c FROM q WHERE b = 'X'").unwrap(), ["text|0"]);
+        assert_eq!(query_fixture("SELECT * FROM q WHERE c > 2").unwrap(), ["2|2|4"]);
+    }
+
+    #[test]
+    fn test_without_rowid() {
+        // wr(v INTEGER, k TEXT PRIMARY KEY, w TEXT) WITHOUT ROWID stores k first and is read in k order
+        assert_eq!(query_fixture("SELECT * FROM wr").unwrap(), ["2|a|ay", "1|b|bee", "3|c|"]);
+        assert_eq!(query_fixture("SELECT g.label, w.k FROM groups g JOIN wr w ON w.v = g.id ORDER BY g.id").unwrap(), ["one|b", "two|a", "three|c"]);
+        assert_eq!(query_fixture("SELECT w1.w FROM wr w1 JOIN wr w2 ON w2.k = w1.k WHERE w2.v > 1").unwrap(), ["ay", ""]);
+        assert!(query_fixture("SELECT rowid FROM wr").unwrap_err().to_string().contains("no such column: rowid"));
+
+        // the primary key b-tree is searched like an index, also for every row of a join
+        assert_eq!(query_fixture("SELECT v, w FROM wr WHERE k = 'b'").unwrap(), ["1|bee"]);
+        assert_eq!(query_fixture("SELECT k FROM wr WHERE k > 'a' AND k <= 'c'").unwrap(), ["b", "c"]);
+        assert_eq!(query_fixture("SELECT k FROM wr WHERE k < 'b' OR k = 'c'").unwrap(), ["a", "c"]);
+        assert_eq!(
+            query_fixture("SELECT t.tag, w.w FROM tags t JOIN wr w ON w.k = substr(t.tag, 1, 1) ORDER BY t.tag").unwrap(),
+            ["big|bee", "blue|bee"]
+        );
+
+        let pager = Pager::open(concat!(env!("CARGO_MANIFEST_DIR"), "/fixture.db")).unwrap();
+        let schema = Schema::load(&pager).unwrap();
+        let table = schema.table("wr").unwrap();
+        let definition = table.table_def().unwrap();
+        let mut scope = Scope::for_table("wr", &definition.column_names());
+        scope.columns.pop();
+        let plan = |condition: &str| {
+            let Statement::Select(select) = sql::parse(&format!("SELECT * FROM wr WHERE {}", condition)).unwrap() else { panic!() };
+            let terms = split_conjuncts(select.where_clause.as_ref().unwrap());
+            plan_access(&schema, table, &definition, &scope, 0..scope.columns.len(), &terms)
+        };
+        assert!(matches!(plan("k = 'b' AND v > 0"), AccessPlan::IndexScan { rootpage, eq, .. } if rootpage == table.rootpage && eq.len() == 1));
+        assert!(matches!(plan("v = 1"), AccessPlan::FullScan));
+    }
+
+    #[test]
+    fn test_plan_access() {
+        let pager = Pager::open(concat!(env!("CARGO_MANIFEST_DIR"), "/fixture.db")).unwrap();
+        let schema = Schema::load(&pager).unwrap();
+        let table = schema.table("items").unwrap();
+        let definition = table.table_def().unwrap();
+        let mut scope = Scope::for_table("items", &definition.column_names());
+        scope.columns[1].collation = Collation::NoCase;
+
+        let plan = |condition: &str| {
+            let Statement::Select(select) = sql::parse(&format!("SELECT * FROM items WHERE {}", condition)).unwrap() else { panic!() };
+            let terms = split_conjuncts(select.where_clause.as_ref().unwrap());
+            plan_access(&schema, table, &definition, &scope, 0..scope.columns.len(), &terms)
+        };
+        assert!(matches!(plan("name = 'item0042' AND id = 7"), AccessPlan::Rowid { .. }));
+        // an index equality is narrower than an open rowid range
+        assert!(matches!(plan("name = 'item0042' AND id > 0"), AccessPlan::IndexScan { eq, .. } if eq.len() == 1));
+        assert!(matches!(plan("grp = 3 AND rowid <= 100"), AccessPlan::IndexScan { .. }));
+        assert!(matches!(plan("id > 5 AND id < 10 AND name > 'item'"), AccessPlan::Rowid { .. }));
+        assert!(matches!(plan("id > 5 AND grp > 3"), AccessPlan::Rowid { .. }));
+        assert!(matches!(plan("body = 'x'"), AccessPlan::FullScan));
+    }
+}
@@ -1,59 +1,225 @@
+
 use std::fs::File;
 use std::io::{prelude::*};
 use std::{error::Error, io::{Seek, SeekFrom}};
 
+use crate::encoding::TextEncoding;
+
+
+/// The 100 bytes at the start of the file, read before anything else.
+pub struct DatabaseHeader {
+    /// In bytes; the stored value 1 stands for 65536.
+    pub page_size: u32,
+    pub write_version: u8,
+    pub read_version: u8,
+    /// Bytes at the end of every page used by extensions.
+    pub reserved_space: u8,
+    pub change_counter: u32,
+    /// Taken from the file size when the stored count is stale.
+    pub page_count: u32,
+    /// First freelist trunk page, unchecked: a reader never follows the freelist.
+    #[allow(dead_code)]
+    pub freelist_trunk: u32,
+    pub freelist_count: u32,
+    pub schema_cookie: u32,
+    pub schema_format: u32,
+    pub default_cache_size: i32,
+    /// Largest root b-tree page in auto-vacuum databases, otherwise 0.
+    pub autovacuum_root: u32,
+    pub text_encoding: TextEncoding,
+    pub user_version: i32,
+    pub incremental_vacuum: bool,
+    pub application_id: i32,
+    /// Change counter the page count and SQLite version were written at.
+    pub version_valid_for: u32,
+    pub sqlite_version: u32,
+}
+
+impl DatabaseHeader {
+    pub const SIZE: usize = 100;
+
+    pub fn read(file: &mut File) -> Result<Self, Box<dyn Error>> {
+        let mut bytes = [0; Self::SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut bytes).map_err(|_| "file is not a database")?;
+        let mut header = Self::parse(&bytes)?;
+
+        // older writers left the count alone, it is only trusted when written with the last change
+        let file_pages = (file.metadata()?.len() / header.page_size as u64) as u32;
+        if header.page_count == 0 || header.version_valid_for != header.change_counter {
+            header.page_count = file_pages;
+        } else if header.page_count > file_pages {
+            return Err(format!("Database disk image is malformed: {} pages expected but the file holds {}", header.page_count, file_pages).into());
+        }
+        Ok(header)
+    }
+
+    pub fn parse(bytes: &[u8; Self::SIZE]) -> Result<Self, Box<dyn Error>> {
+        if &bytes[0..16] != b"SQLite format 3\0" {
+            return Err("file is not a database".into());
+        }
+        let u16_at = |offset: usize| u16::from_be_bytes([bytes[offset], bytes[offset + 1]]);
+        let u32_at = |offset: usize| u32::from_be_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+
+        let page_size = match u16_at(16) {
+            1 => 65536,
+            size if size.is_power_of_two() && size >= 512 => size as u32,
+            size => return Err(format!("Database disk image is malformed: invalid page size {}", size).into()),
+        };
+        let (write_version, read_version) = (bytes[18], bytes[19]);
+        if read_version > 2 {
+            return Err(format!("unsupported file format: read version {}", read_version).into());
+        }
+        let reserved_space = bytes[20];
+        if page_size - (reserved_space as u32) < 480 {
+            return Err(format!("Database disk image is malformed: {} reserved bytes leave too little of a {} byte page", reserved_space, page_size).into());
+        }
+        // the payload fractions were meant to be tunable but must be 64, 32 and 32
+        if bytes[21..24] != [64, 32, 32] {
+            return Err("Database disk image is malformed: invalid payload fractions".into());
+        }
+        let schema_format = u32_at(44);
+        if schema_format > 4 {
+            return Err(format!("unsupported file format: schema format {}", schema_format).into());
+        }
 
-pub struct SqliteRead{}
+        Ok(DatabaseHeader {
+            page_size,
+            write_version,
+            read_version,
+            reserved_space,
+            change_counter: u32_at(24),
+            page_count: u32_at(28),
+            // SQLite itself only checks the freelist when it allocates from it, so neither do we
+            freelist_trunk: u32_at(32),
+            freelist_count: u32_at(36),
+            schema_cookie: u32_at(40),
+            schema_format,
+            default_cache_size: u32_at(48) as i32,
+            autovacuum_root: u32_at(52),
+            text_encoding: TextEncoding::from_header(u32_at(56))?,
+            user_version: u32_at(60) as i32,
+            incremental_vacuum: u32_at(64) != 0,
+            application_id: u32_at(68) as i32,
+            version_valid_for: u32_at(92),
+            sqlite_version: u32_at(96),
+        })
+    }
 
-impl SqliteRead{
-    pub fn page_size(file_handler: &mut File) -> Result<u16, Box<dyn Error>>{
-        file_handler.seek(SeekFrom::Start(0))?;
-        let mut header = [0; 18];
-        file_handler.read(&mut header)?;
-        let page_size = u16::from_be_bytes([header[16], header[17]]) as u16;
-        Ok(page_size)
+    /// Page size minus the reserved space, the `U` of the file format docs.
+    pub fn usable_size(&self) -> u32 {
+        self.page_size - self.reserved_space as u32
     }
+}
 
 
+/// Page reader shared by every b-tree cursor.
+/// Reads go through `&File`, so several cursors can walk the file at the same time.
+pub struct Pager {
+    file: File,
+    header: DatabaseHeader,
+}
 
-    pub fn table_count(file_handler: &mut File) -> Result<u16, Box<dyn Error>>{
-        file_handler.seek(SeekFrom::Start(0))?;
-        let mut header = [0; 105];
-        file_handler.read(&mut header)?;
-        let table_count = u16::from_be_bytes([header[103], header[104]]) as u16;
-        Ok(table_count)
+impl Pager {
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut file = File::open(path)?;
+        let header = DatabaseHeader::read(&mut file)?;
+        Ok(Pager { file, header })
     }
-    
-    
-    /// doesn't works for first page. choose another target page.  
-    pub fn row_count(page: & Vec<u8>) -> Result<u16, Box<dyn Error>>{
-        let table_count = u16::from_be_bytes([page[3], page[4]]) as u16;
-        Ok(table_count)
+
+    pub fn header(&self) -> &DatabaseHeader {
+        &self.header
     }
 
+    pub fn text_encoding(&self) -> TextEncoding {
+        self.header.text_encoding
+    }
+
+    pub fn usable_size(&self) -> u32 {
+        self.header.usable_size()
+    }
 
     /// not zero bound. start from 1.
-    pub fn read_page_n(file_handler: &mut File, n: u16, page_size:u16) -> Result< Vec<u8> , Box<dyn Error>> {
-        
-        let mut single_page = vec![0;page_size as usize ];
-        
-        if n==1 {
-            file_handler.seek(SeekFrom::Start(100))?;
-        } else {
-            file_handler.seek(SeekFrom::Start((page_size* (n-1) ) as u64))?;
+    /// the whole page is returned, database header included.
+    pub fn read_page(&self, n: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+        if n == 0 {
+            return Err("Page number 0 is not a valid page".into());
         }
-        
-        file_handler.read(&mut single_page)?;
+        if n > self.header.page_count {
+            return Err(format!("Database disk image is malformed: page {} is past the end of the file", n).into());
+        }
+
+        let page_size = self.header.page_size;
+        let mut file = &self.file;
+        let mut page = vec![0; page_size as usize];
+        file.seek(SeekFrom::Start(page_size as u64 * (n as u64 - 1)))?;
+        file.read_exact(&mut page)?;
 
-        Ok(single_page)
+        Ok(page)
     }
+}
+
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn read_first_page(file_handler: &mut File, page_size:u16) -> Result< Vec<u8> , Box<dyn Error>> {
-        Self::read_page_n(file_handler, 1, page_size-100)
+    fn header_bytes() -> [u8; DatabaseHeader::SIZE] {
+        let mut bytes = [0; DatabaseHeader::SIZE];
+        bytes[0..16].copy_from_slice(b"SQLite format 3\0");
+        bytes[16..18].copy_from_slice(&4096u16.to_be_bytes());
+        bytes[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+        bytes[24..28].copy_from_slice(&7u32.to_be_bytes());
+        bytes[28..32].copy_from_slice(&6u32.to_be_bytes());
+        bytes[44..48].copy_from_slice(&4u32.to_be_bytes());
+        bytes[56..60].copy_from_slice(&2u32.to_be_bytes());
+        bytes[60..64].copy_from_slice(&(-1i32).to_be_bytes());
+        bytes[92..96].copy_from_slice(&7u32.to_be_bytes());
+        bytes[96..100].copy_from_slice(&3051002u32.to_be_bytes());
+        bytes
     }
 
+    #[test]
+    fn test_parse_header() {
+        let header = DatabaseHeader::parse(&header_bytes()).unwrap();
+        assert_eq!(header.page_size, 4096);
+        assert_eq!((header.change_counter, header.page_count, header.version_valid_for), (7, 6, 7));
+        assert_eq!(header.schema_format, 4);
+        assert_eq!(header.text_encoding, TextEncoding::Utf16Le);
+        assert_eq!(header.user_version, -1);
+        assert_eq!(header.sqlite_version, 3051002);
+        assert_eq!(header.usable_size(), 4096);
 
+        let mut bytes = header_bytes();
+        bytes[16..18].copy_from_slice(&1u16.to_be_bytes());
+        assert_eq!(DatabaseHeader::parse(&bytes).unwrap().page_size, 65536);
+        bytes[19] = 0;
+        bytes[32..36].copy_from_slice(&2u32.to_be_bytes());
+        let header = DatabaseHeader::parse(&bytes).unwrap();
+        assert_eq!((header.read_version, header.freelist_trunk, header.freelist_count), (0, 2, 0));
 
+        let malformed: [(usize, &[u8]); 5] = [
+            (0, b"SQLite format 2"),
+            (16, &[0x03, 0x00]),
+            (19, &[3]),
+            (16, &[0x02, 0x00, 1, 1, 40]),
+            (21, &[65]),
+        ];
+        for (offset, patch) in malformed {
+            let mut bytes = header_bytes();
+            bytes[offset..offset + patch.len()].copy_from_slice(patch);
+            assert!(DatabaseHeader::parse(&bytes).is_err(), "offset {}", offset);
+        }
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_freelist_trunk_past_the_end() {
+        let mut file = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/fixture.db")).unwrap();
+        file[32..36].copy_from_slice(&1000000u32.to_be_bytes());
+        let path = std::env::temp_dir().join(format!("mydbms-freelist-{}.db", std::process::id()));
+        std::fs::write(&path, file).unwrap();
+        let header = DatabaseHeader::read(&mut File::open(&path).unwrap());
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(header.unwrap().freelist_trunk, 1000000);
+    }
+}
@@ -0,0 +1,179 @@
+use std::error::Error;
+
+use crate::page::{parse_record, TableCursor};
+use crate::read::Pager;
+use crate::sql::{self, ast::{CreateIndexStatement, Statement, TableDef}};
+use crate::value::Value;
+
+
+/// Object kind stored in the `type` column of sqlite_schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaKind {
+    Table,
+    Index,
+    View,
+    Trigger,
+}
+
+impl SchemaKind {
+    fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        match s {
+            "table" => Ok(SchemaKind::Table),
+            "index" => Ok(SchemaKind::Index),
+            "view" => Ok(SchemaKind::View),
+            "trigger" => Ok(SchemaKind::Trigger),
+            other => Err(format!("Unknown schema object type: {}", other).into()),
+        }
+    }
+}
+
+
+/// One row of sqlite_schema.
+#[derive(Debug, Clone)]
+pub struct SchemaEntry {
+    pub kind: SchemaKind,
+    pub name: String,
+    pub tbl_name: String,
+    /// 0 for views and triggers
+    pub rootpage: u32,
+    /// NULL for automatically created indexes
+    pub sql: Option<String>,
+}
+
+impl SchemaEntry {
+    /// sqlite_sequence, sqlite_stat1, sqlite_autoindex_* ...
+    pub fn is_internal(&self) -> bool {
+        self.name.starts_with("sqlite_")
+    }
+
+    /// The parsed CREATE TABLE statement of a table.
+    pub fn table_def(&self) -> Result<TableDef, Box<dyn Error>> {
+        let sql = self.sql.as_deref().ok_or("Table without CREATE statement")?;
+        // reported like SQLite, without pointing into the user's query
+        match sql::parse(sql) {
+            Ok(Statement::CreateTable(definition)) => Ok(definition),
+            Ok(_) => Err(format!("malformed database schema ({}) - not a CREATE TABLE statement", self.name).into()),
+            Err(e) => Err(format!("malformed database schema ({}) - {}", self.name, e).into()),
+        }
+    }
+}
+
+
+/// An explicitly created index with its parsed definition.
+pub struct IndexDef {
+    pub rootpage: u32,
+    pub definition: CreateIndexStatement,
+}
+
+
+pub struct Schema {
+    pub entries: Vec<SchemaEntry>,
+}
+
+impl Schema {
+    /// The schema table is a regular table b-tree rooted at page 1.
+    pub fn load(pager: &Pager) -> Result<Self, Box<dyn Error>> {
+        let mut entries = Vec::new();
+
+        for row in TableCursor::new(pager, 1)? {
+            let values = parse_record(&row?.payload, pager.text_encoding())?;
+            if values.len() < 5 {
+                return Err(format!("Malformed sqlite_schema row: {} columns", values.len()).into());
+            }
+
+            let text = |v: &Value| match v {
+                Value::Text(s) => Ok(s.clone()),
+                other => Err(format!("Expected text in sqlite_schema, got {:?}", other)),
+            };
+
+            entries.push(SchemaEntry {
+                kind: SchemaKind::parse(&text(&values[0])?)?,
+                name: text(&values[1])?,
+                tbl_name: text(&values[2])?,
+                rootpage: match values[3] {
+                    Value::Integer(n) => n as u32,
+                    _ => 0,
+                },
+                sql: match &values[4] {
+                    Value::Text(s) => Some(s.clone()),
+                    _ => None,
+                },
+            });
+        }
+
+        Ok(Schema { entries })
+    }
+
+    pub fn of_kind(&self, kind: SchemaKind) -> impl Iterator<Item = &SchemaEntry> {
+        self.entries.iter().filter(move |e| e.kind == kind)
+    }
+
+    /// Table names are case-insensitive, as in SQLite.
+    pub fn table(&self, name: &str) -> Option<&SchemaEntry> {
+        self.of_kind(SchemaKind::Table).find(|e| e.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Indexes on `table` created with CREATE INDEX.
+    /// Automatic indexes have no SQL to parse and are left out, as are definitions we fail to parse.
+    pub fn indexes(&self, table: &str) -> Vec<IndexDef> {
+        self.of_kind(SchemaKind::Index)
+            .filter(|e| e.tbl_name.eq_ignore_ascii_case(table))
+            .filter_map(|e| match sql::parse(e.sql.as_deref()?) {
+                Ok(Statement::CreateIndex(definition)) => Some(IndexDef {
+                    rootpage: e.rootpage,
+                    definition,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::{BTreePage, PageType};
+
+    #[test]
+    fn test_load_schema_spanning_interior_pages() {
+        let pager = Pager::open(concat!(env!("CARGO_MANIFEST_DIR"), "/schema.db")).unwrap();
+        assert_eq!(BTreePage::read(&pager, 1).unwrap().page_type, PageType::InteriorTable);
+
+        let schema = Schema::load(&pager).unwrap();
+        assert_eq!(schema.of_kind(SchemaKind::Table).count(), 100);
+        assert_eq!(schema.of_kind(SchemaKind::Index).count(), 200);
+        assert_eq!(schema.of_kind(SchemaKind::View).count(), 50);
+        assert_eq!(schema.of_kind(SchemaKind::Trigger).count(), 50);
+
+        let entry = |name: &str| schema.entries.iter().find(|e| e.name == name).unwrap();
+        let page_type = |entry: &SchemaEntry| BTreePage::read(&pager, entry.rootpage).unwrap().page_type;
+
+        let table = schema.table("T042").unwrap();
+        assert_eq!((table.kind, table.name.as_str(), table.tbl_name.as_str()), (SchemaKind::Table, "t042", "t042"));
+        assert_eq!(table.sql.as_deref(), Some("CREATE TABLE t042(id INTEGER PRIMARY KEY, v TEXT UNIQUE)"));
+        assert_eq!(page_type(table), PageType::LeafTable);
+
+        let index = entry("i042");
+        assert_eq!((index.kind, index.tbl_name.as_str()), (SchemaKind::Index, "t042"));
+        assert_eq!(index.sql.as_deref(), Some("CREATE INDEX i042 ON t042(v, id)"));
+        assert_eq!(page_type(index), PageType::LeafIndex);
+
+        // UNIQUE constraints get an index without sql
+        let autoindex = entry("sqlite_autoindex_t042_1");
+        assert_eq!((autoindex.kind, autoindex.tbl_name.as_str(), autoindex.sql.as_deref()), (SchemaKind::Index, "t042", None));
+        assert!(autoindex.is_internal());
+        assert_eq!(page_type(autoindex), PageType::LeafIndex);
+
+        let view = entry("v042");
+        assert_eq!((view.kind, view.tbl_name.as_str(), view.rootpage), (SchemaKind::View, "v042", 0));
+        assert_eq!(view.sql.as_deref(), Some("CREATE VIEW v042 AS SELECT v FROM t042"));
+
+        let trigger = entry("r042");
+        assert_eq!((trigger.kind, trigger.tbl_name.as_str(), trigger.rootpage), (SchemaKind::Trigger, "t042", 0));
+        assert_eq!(trigger.sql.as_deref(), Some("CREATE TRIGGER r042 AFTER INSERT ON t042 BEGIN SELECT 1; END"));
+
+        // views are not tables
+        assert!(schema.table("v042").is_none());
+    }
+}
@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::value::Value;
+
+
+/// Temporary file that is removed when dropped.
+pub struct SpillFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl SpillFile {
+    pub fn create() -> Result<Self, Box<dyn Error>> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("mydbms-spill-{}-{}", std::process::id(), n));
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        Ok(SpillFile { path, file })
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Back to the start, to read what was written.
+    pub fn rewind(&mut self) -> Result<(), Box<dyn Error>> {
+        self.file.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+
+/// Rough heap footprint of a row, used against the memory budget.
+pub fn values_size(values: &[Value]) -> usize {
+    values
+        .iter()
+        .map(|v| {
+            std::mem::size_of::<Value>()
+                + match v {
+                    Value::Text(s) => s.len(),
+                    Value::Blob(b) => b.len(),
+                    _ => 0,
+                }
+        })
+        .sum::<usize>()
+        + std::mem::size_of::<Vec<Value>>()
+}
+
+
+// rows are stored as a value count followed by tagged values
+
+pub fn write_values(writer: &mut impl Write, values: &[Value]) -> Result<(), Box<dyn Error>> {
+    writer.write_all(&(values.len() as u32).to_le_bytes())?;
+    for value in values {
+        match value {
+            Value::Null => writer.write_all(&[0])?,
+            Value::Integer(n) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&n.to_le_bytes())?;
+            }
+            Value::Real(r) => {
+                writer.write_all(&[2])?;
+                writer.write_all(&r.to_le_bytes())?;
+            }
+            Value::Text(s) => {
+                writer.write_all(&[3])?;
+                writer.write_all(&(s.len() as u32).to_le_bytes())?;
+                writer.write_all(s.as_bytes())?;
+            }
+            Value::Blob(b) => {
+                writer.write_all(&[4])?;
+                writer.write_all(&(b.len() as u32).to_le_bytes())?;
+                writer.write_all(b)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn read_values(reader: &mut impl Read) -> Result<Option<Vec<Value>>, Box<dyn Error>> {
+    let mut count = [0u8; 4];
+    match reader.read_exact(&mut count) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let count = u32::from_le_bytes(count) as usize;
+    let mut values = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut tag = [0u8; 1];
+        reader.read_exact(&mut tag)?;
+        let value = match tag[0] {
+            0 => Value::Null,
+            1 => Value::Integer(i64::from_le_bytes(read_array(reader)?)),
+            2 => Value::Real(f64::from_le_bytes(read_array(reader)?)),
+            3 | 4 => {
+                let len = u32::from_le_bytes(read_array(reader)?) as usize;
+                let mut bytes = vec![0u8; len];
+                reader.read_exact(&mut bytes)?;
+                if tag[0] == 3 {
+                    Value::Text(String::from_utf8(bytes)?)
+                } else {
+                    Value::Blob(bytes)
+                }
+            }
+            other => return Err(format!("Corrupt spill file: value tag {}", other).into()),
+        };
+        values.push(value);
+    }
+    Ok(Some(values))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N], Box<dyn Error>> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,564 @@
+use crate::value::{Affinity, Value};
+
+
+/// Byte range in the original SQL text, used to point at errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(Box<SelectStatement>),
+    CreateIndex(CreateIndexStatement),
+    CreateTable(TableDef),
+}
+
+
+/// CREATE [TEMP] TABLE [IF NOT EXISTS] name (columns [, constraints]) [WITHOUT ROWID] [, STRICT]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableDef {
+    pub name: String,
+    pub columns: Vec<ColumnDef>,
+    pub constraints: Vec<TableConstraint>,
+    pub without_rowid: bool,
+    pub strict: bool,
+}
+
+impl TableDef {
+    pub fn column_names(&self) -> Vec<String> {
+        self.columns.iter().map(|c| c.name.clone()).collect()
+    }
+
+    /// Positions of the PRIMARY KEY columns in key order.
+    pub fn primary_key(&self) -> Vec<usize> {
+        if let Some(i) = self.columns.iter().position(|c| c.primary_key.is_some()) {
+            return vec![i];
+        }
+        let mut positions = Vec::new();
+        for constraint in &self.constraints {
+            if let TableConstraint::PrimaryKey(key) = constraint {
+                for indexed in key {
+                    if let Expr::Column(name) = &indexed.expr
+                        && let Some(i) = self.columns.iter().position(|c| c.name.eq_ignore_ascii_case(&name.name))
+                        && !positions.contains(&i)
+                    {
+                        positions.push(i);
+                    }
+                }
+            }
+        }
+        positions
+    }
+
+    /// Position of the column that is an alias for the rowid: the one INTEGER PRIMARY KEY column.
+    /// `INTEGER PRIMARY KEY DESC` on the column itself is not an alias, as in SQLite.
+    pub fn rowid_alias(&self) -> Option<usize> {
+        if self.without_rowid {
+            return None;
+        }
+        let is_integer = |c: &ColumnDef| c.declared_type.as_deref().is_some_and(|t| t.eq_ignore_ascii_case("INTEGER"));
+        if let Some(i) = self.columns.iter().position(|c| c.primary_key.is_some()) {
+            let column = &self.columns[i];
+            let descending = column.primary_key.is_some_and(|pk| pk.descending);
+            return (is_integer(column) && !descending).then_some(i);
+        }
+        self.constraints.iter().find_map(|constraint| match constraint {
+            TableConstraint::PrimaryKey(key) if let [IndexedColumn { expr: Expr::Column(name), .. }] = key.as_slice() => {
+                let i = self.columns.iter().position(|c| c.name.eq_ignore_ascii_case(&name.name))?;
+                is_integer(&self.columns[i]).then_some(i)
+            }
+            _ => None,
+        })
+    }
+}
+
+/// name [type] [constraints]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    pub name: String,
+    /// as written, e.g. "DECIMAL(10,2)"; None when the column has no type
+    pub declared_type: Option<String>,
+    /// derived from the declared type
+    pub affinity: Affinity,
+    pub collation: Option<String>,
+    pub default: Option<Expr>,
+    pub not_null: bool,
+    pub primary_key: Option<PrimaryKey>,
+    pub unique: bool,
+    pub checks: Vec<Expr>,
+    pub references: Option<ForeignKey>,
+    /// GENERATED ALWAYS AS (expr)
+    pub generated: Option<Generated>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrimaryKey {
+    pub descending: bool,
+    pub autoincrement: bool,
+}
+
+/// REFERENCES table [(columns)]; the actions and deferral clauses are not kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+    pub table: String,
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Generated {
+    pub expr: Expr,
+    /// STORED columns are in the record, VIRTUAL ones are computed on read
+    pub stored: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableConstraint {
+    PrimaryKey(Vec<IndexedColumn>),
+    Unique(Vec<IndexedColumn>),
+    Check(Expr),
+    ForeignKey { columns: Vec<String>, references: ForeignKey },
+}
+
+
+/// CREATE [UNIQUE] INDEX name ON table (columns) [WHERE expr]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndexStatement {
+    pub name: String,
+    pub table: String,
+    pub unique: bool,
+    pub columns: Vec<IndexedColumn>,
+    /// partial index condition
+    pub where_clause: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedColumn {
+    pub expr: Expr,
+    pub collation: Option<String>,
+    pub descending: bool,
+}
+
+
+/// A SELECT with its WITH clause. The first SELECT of a compound is the statement itself,
+/// the others follow in `compound`; ORDER BY and LIMIT then apply to the whole compound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectStatement {
+    pub with: Option<With>,
+    pub distinct: bool,
+    pub columns: Vec<ResultColumn>,
+    pub from: Vec<FromTable>,
+    pub where_clause: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    /// WINDOW name AS (...), in source order
+    pub windows: Vec<(String, WindowSpec)>,
+    pub compound: Vec<CompoundSelect>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Limit>,
+    pub span: Span,
+}
+
+
+/// WITH [RECURSIVE] name [(columns)] AS [[NOT] MATERIALIZED] (select), ...
+#[derive(Debug, Clone, PartialEq)]
+pub struct With {
+    pub recursive: bool,
+    pub ctes: Vec<CommonTableExpr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommonTableExpr {
+    pub name: String,
+    /// names for the result columns, empty to keep those of the select
+    pub columns: Vec<String>,
+    /// the MATERIALIZED / NOT MATERIALIZED hint
+    pub materialized: Option<bool>,
+    pub select: Box<SelectStatement>,
+}
+
+
+/// A further SELECT of a compound and the operator joining it to the SELECTs before it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompoundSelect {
+    pub op: CompoundOp,
+    pub select: SelectStatement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompoundOp {
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+impl CompoundOp {
+    pub fn keyword(self) -> &'static str {
+        match self {
+            CompoundOp::Union => "UNION",
+            CompoundOp::UnionAll => "UNION ALL",
+            CompoundOp::Intersect => "INTERSECT",
+            CompoundOp::Except => "EXCEPT",
+        }
+    }
+}
+
+
+/// LIMIT limit [OFFSET offset], also written LIMIT offset, limit
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub limit: Expr,
+    pub offset: Option<Expr>,
+}
+
+
+/// expr [COLLATE name] [ASC|DESC] [NULLS FIRST|LAST]
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub collation: Option<String>,
+    pub descending: bool,
+    /// `None` is SQLite's default: NULLs first ascending, last descending
+    pub nulls_first: Option<bool>,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+    /// `*`
+    Star,
+    /// `table.*`
+    TableStar(String),
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+        span: Span,
+    },
+}
+
+
+/// A FROM clause entry and how it joins the entries before it; the join of the first one is unused.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FromTable {
+    pub source: TableSource,
+    pub join: JoinKind,
+    pub natural: bool,
+    pub constraint: Option<JoinConstraint>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    /// `,`, JOIN and INNER JOIN
+    Inner,
+    /// LEFT [OUTER] JOIN
+    Left,
+    /// CROSS JOIN
+    Cross,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinConstraint {
+    On(Expr),
+    Using(Vec<String>),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableSource {
+    Table(TableRef),
+    /// (SELECT ...) [AS alias]
+    Subquery {
+        select: Box<SelectStatement>,
+        alias: Option<String>,
+    },
+    /// name(args) [AS alias]: a table-valued function such as json_each
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        alias: Option<String>,
+        span: Span,
+    },
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRef {
+    pub name: String,
+    pub alias: Option<String>,
+    pub span: Span,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnRef {
+    pub table: Option<String>,
+    pub name: String,
+    /// written as "name"; SQLite reads an unresolvable double-quoted identifier as a string
+    pub double_quoted: bool,
+    pub span: Span,
+    /// scope position of a reference the engine builds itself, which needs no name lookup
+    pub position: Option<usize>,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Column(ColumnRef),
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Function {
+        name: String,
+        args: FunctionArgs,
+        distinct: bool,
+        /// OVER clause of a window function call
+        over: Option<Box<Over>>,
+        span: Span,
+    },
+    /// expr COLLATE name
+    Collate {
+        expr: Box<Expr>,
+        collation: String,
+    },
+    /// expr [NOT] BETWEEN low AND high
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    /// expr [NOT] IN (list)
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    /// expr [NOT] LIKE|GLOB pattern [ESCAPE escape]
+    Like {
+        op: LikeOp,
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        escape: Option<Box<Expr>>,
+        negated: bool,
+    },
+    /// (SELECT ...): the first column of the first row, or NULL
+    Subquery(Box<SelectStatement>),
+    /// EXISTS (SELECT ...)
+    Exists(Box<SelectStatement>),
+    /// expr [NOT] IN (SELECT ...)
+    InSelect {
+        expr: Box<Expr>,
+        select: Box<SelectStatement>,
+        negated: bool,
+    },
+}
+
+
+impl Expr {
+    /// Direct subexpressions, in source order. Subqueries are not included, they have their own scope.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Column(_) | Expr::Subquery(_) | Expr::Exists(_) => vec![],
+            Expr::Unary { expr, .. } | Expr::Collate { expr, .. } | Expr::InSelect { expr, .. } => vec![expr],
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Function { args, over, .. } => {
+                let mut children: Vec<&Expr> = match args {
+                    FunctionArgs::Star => vec![],
+                    FunctionArgs::List(args) => args.iter().collect(),
+                };
+                if let Some(Over::Spec(spec)) = over.as_deref() {
+                    children.extend(spec.exprs());
+                }
+                children
+            }
+            Expr::Between { expr, low, high, .. } => vec![expr, low, high],
+            Expr::InList { expr, list, .. } => std::iter::once(&**expr).chain(list).collect(),
+            Expr::Like { expr, pattern, escape, .. } => {
+                let mut children = vec![&**expr, &**pattern];
+                children.extend(escape.as_deref());
+                children
+            }
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Literal(_) | Expr::Column(_) | Expr::Subquery(_) | Expr::Exists(_) => vec![],
+            Expr::Unary { expr, .. } | Expr::Collate { expr, .. } | Expr::InSelect { expr, .. } => vec![expr],
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Function { args, over, .. } => {
+                let mut children: Vec<&mut Expr> = match args {
+                    FunctionArgs::Star => vec![],
+                    FunctionArgs::List(args) => args.iter_mut().collect(),
+                };
+                if let Some(Over::Spec(spec)) = over.as_deref_mut() {
+                    children.extend(spec.partition_by.iter_mut());
+                    children.extend(spec.order_by.iter_mut().map(|term| &mut term.expr));
+                }
+                children
+            }
+            Expr::Between { expr, low, high, .. } => vec![expr, low, high],
+            Expr::InList { expr, list, .. } => std::iter::once(&mut **expr).chain(list).collect(),
+            Expr::Like { expr, pattern, escape, .. } => {
+                let mut children = vec![&mut **expr, &mut **pattern];
+                children.extend(escape.as_deref_mut());
+                children
+            }
+        }
+    }
+
+    /// The SELECT of a subquery expression.
+    pub fn subquery(&self) -> Option<&SelectStatement> {
+        match self {
+            Expr::Subquery(select) | Expr::Exists(select) | Expr::InSelect { select, .. } => Some(select),
+            _ => None,
+        }
+    }
+}
+
+
+/// OVER name | OVER (window-spec)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Over {
+    Named(String),
+    Spec(Box<WindowSpec>),
+}
+
+/// ([base-window] [PARTITION BY ...] [ORDER BY ...] [frame])
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WindowSpec {
+    /// named window this one extends
+    pub base: Option<String>,
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+    pub frame: Option<Frame>,
+}
+
+impl WindowSpec {
+    /// The PARTITION BY and ORDER BY expressions. Frame offsets are constants and not included.
+    pub fn exprs(&self) -> impl Iterator<Item = &Expr> {
+        self.partition_by.iter().chain(self.order_by.iter().map(|term| &term.expr))
+    }
+}
+
+/// {ROWS|RANGE|GROUPS} BETWEEN start AND end [EXCLUDE ...]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub unit: FrameUnit,
+    pub start: FrameBound,
+    pub end: FrameBound,
+    pub exclude: FrameExclude,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameUnit {
+    Rows,
+    /// offsets are distances between ORDER BY values
+    Range,
+    /// offsets count groups of peers
+    Groups,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(Expr),
+    CurrentRow,
+    Following(Expr),
+    UnboundedFollowing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameExclude {
+    NoOthers,
+    CurrentRow,
+    /// the current row and its peers
+    Group,
+    /// the peers of the current row, but not the row itself
+    Ties,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LikeOp {
+    /// case-insensitive for ASCII, `%` and `_` wildcards
+    Like,
+    /// case-sensitive, unix-style `*`, `?` and `[...]`
+    Glob,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FunctionArgs {
+    /// `count(*)`
+    Star,
+    List(Vec<Expr>),
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Plus,
+    Not,
+    BitNot,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Is,
+    IsNot,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Concat,
+    /// `->`: the JSON text of what a path finds in a JSON document
+    Extract,
+    /// `->>`: the SQL value of what a path finds in a JSON document
+    ExtractValue,
+}
+
+impl BinaryOp {
+    /// Operators that compare their operands, after converting them to a common affinity.
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Is | BinaryOp::IsNot | BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq
+        )
+    }
+}
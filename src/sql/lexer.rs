@@ -0,0 +1,325 @@
+use crate::sql::ast::Span;
+use crate::sql::ParseError;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// bare word: keyword or identifier, decided by the parser
+    Word(String),
+    /// "name", `name` or [name]
+    QuotedIdentifier(String),
+    /// 'text'
+    String(String),
+    /// x'0A0B'
+    Blob(Vec<u8>),
+    Integer(i64),
+    Real(f64),
+    /// 9223372036854775808: a real, unless negated into i64::MIN
+    MinIntegerMagnitude,
+    /// operators and punctuation, e.g. "<=", "||", "("
+    Symbol(&'static str),
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    /// Case-insensitive keyword check.
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.kind, TokenKind::Word(w) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    pub fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(&self.kind, TokenKind::Symbol(s) if *s == symbol)
+    }
+}
+
+
+// longest symbols first so "<=" wins over "<"
+const SYMBOLS: [&str; 27] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "<>", "||", "->>", "->",
+    "(", ")", ",", ";", ".", "*", "/", "%", "+", "-", "<", ">", "=", "&", "|", "~", "?",
+];
+
+
+pub fn tokenize(sql: &str) -> Result<Vec<Token>, ParseError> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let start = pos;
+        let c = bytes[pos];
+
+        // whitespace
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        // -- line comment
+        if sql[pos..].starts_with("--") {
+            pos = sql[pos..].find('\n').map(|i| pos + i + 1).unwrap_or(bytes.len());
+            continue;
+        }
+
+        // /* block comment */ (unterminated comments run to the end, as in SQLite)
+        if sql[pos..].starts_with("/*") {
+            pos = sql[pos + 2..].find("*/").map(|i| pos + 2 + i + 2).unwrap_or(bytes.len());
+            continue;
+        }
+
+        // x'...' blob literal
+        if (c == b'x' || c == b'X') && bytes.get(pos + 1) == Some(&b'\'') {
+            let (text, end) = read_quoted(sql, pos + 1, '\'')?;
+            let blob = decode_hex(&text).ok_or_else(|| {
+                ParseError::new(format!("malformed blob literal: x'{}'", text), Span::new(start, end))
+            })?;
+            tokens.push(Token { kind: TokenKind::Blob(blob), span: Span::new(start, end) });
+            pos = end;
+            continue;
+        }
+
+        match c {
+            b'\'' => {
+                let (text, end) = read_quoted(sql, pos, '\'')?;
+                tokens.push(Token { kind: TokenKind::String(text), span: Span::new(start, end) });
+                pos = end;
+            }
+            b'"' | b'`' => {
+                let (text, end) = read_quoted(sql, pos, c as char)?;
+                tokens.push(Token { kind: TokenKind::QuotedIdentifier(text), span: Span::new(start, end) });
+                pos = end;
+            }
+            b'[' => {
+                let end = sql[pos..].find(']').map(|i| pos + i + 1).ok_or_else(|| {
+                    ParseError::new("unrecognized token: \"[\"", Span::new(start, bytes.len()))
+                })?;
+                let text = sql[pos + 1..end - 1].to_string();
+                tokens.push(Token { kind: TokenKind::QuotedIdentifier(text), span: Span::new(start, end) });
+                pos = end;
+            }
+            b'0'..=b'9' => {
+                let (kind, end) = read_number(sql, pos)?;
+                tokens.push(Token { kind, span: Span::new(start, end) });
+                pos = end;
+            }
+            b'.' if bytes.get(pos + 1).is_some_and(|b| b.is_ascii_digit()) => {
+                let (kind, end) = read_number(sql, pos)?;
+                tokens.push(Token { kind, span: Span::new(start, end) });
+                pos = end;
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' || c >= 0x80 => {
+                let mut end = pos;
+                while end < bytes.len() {
+                    let b = bytes[end];
+                    if b.is_ascii_alphanumeric() || b == b'_' || b == b'$' || b >= 0x80 {
+                        end += 1;
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token { kind: TokenKind::Word(sql[pos..end].to_string()), span: Span::new(start, end) });
+                pos = end;
+            }
+            _ => {
+                let symbol = SYMBOLS.iter().find(|s| sql[pos..].starts_with(**s)).ok_or_else(|| {
+                    let ch = sql[pos..].chars().next().unwrap_or('?');
+                    ParseError::new(format!("unrecognized token: \"{}\"", ch), Span::new(start, start + ch.len_utf8()))
+                })?;
+                pos += symbol.len();
+                tokens.push(Token { kind: TokenKind::Symbol(symbol), span: Span::new(start, pos) });
+            }
+        }
+    }
+
+    tokens.push(Token { kind: TokenKind::Eof, span: Span::new(bytes.len(), bytes.len()) });
+    Ok(tokens)
+}
+
+
+/// Reads a quoted run starting at the opening quote; a doubled quote is an escaped quote.
+/// Returns the unescaped text and the position right after the closing quote.
+fn read_quoted(sql: &str, start: usize, quote: char) -> Result<(String, usize), ParseError> {
+    let mut text = String::new();
+    let mut chars = sql[start + 1..].char_indices().peekable();
+
+    while let Some((i, ch)) = chars.next() {
+        if ch == quote {
+            if chars.peek().map(|(_, c)| *c) == Some(quote) {
+                text.push(quote);
+                chars.next();
+            } else {
+                return Ok((text, start + 1 + i + 1));
+            }
+        } else {
+            text.push(ch);
+        }
+    }
+
+    Err(ParseError::new("unterminated quoted string", Span::new(start, sql.len())))
+}
+
+
+fn read_number(sql: &str, start: usize) -> Result<(TokenKind, usize), ParseError> {
+    let bytes = sql.as_bytes();
+    let mut pos = start;
+
+    // 0x hexadecimal integer
+    if sql[start..].starts_with("0x") || sql[start..].starts_with("0X") {
+        pos += 2;
+        while pos < bytes.len() && bytes[pos].is_ascii_hexdigit() {
+            pos += 1;
+        }
+        let digits = &sql[start + 2..pos];
+        // hex literals are 64-bit two's complement, 0xFFFFFFFFFFFFFFFF is -1
+        let value = u64::from_str_radix(digits, 16).map_err(|_| {
+            ParseError::new(format!("hex literal too big: {}", &sql[start..pos]), Span::new(start, pos))
+        })?;
+        return Ok((TokenKind::Integer(value as i64), pos));
+    }
+
+    let mut is_real = false;
+    while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+        pos += 1;
+    }
+    if pos < bytes.len() && bytes[pos] == b'.' {
+        is_real = true;
+        pos += 1;
+        while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+            pos += 1;
+        }
+    }
+    if pos < bytes.len() && (bytes[pos] == b'e' || bytes[pos] == b'E') {
+        let mut exp = pos + 1;
+        if exp < bytes.len() && (bytes[exp] == b'+' || bytes[exp] == b'-') {
+            exp += 1;
+        }
+        if exp < bytes.len() && bytes[exp].is_ascii_digit() {
+            is_real = true;
+            pos = exp;
+            while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                pos += 1;
+            }
+        }
+    }
+
+    // "12abc" is not a number followed by a word
+    if pos < bytes.len() && (bytes[pos].is_ascii_alphabetic() || bytes[pos] == b'_') {
+        return Err(ParseError::new(
+            format!("unrecognized token: \"{}\"", &sql[start..=pos]),
+            Span::new(start, pos + 1),
+        ));
+    }
+
+    let text = &sql[start..pos];
+    if !is_real && let Ok(n) = text.parse::<i64>() {
+        return Ok((TokenKind::Integer(n), pos));
+    }
+    if !is_real && text.parse::<u64>() == Ok(i64::MIN.unsigned_abs()) {
+        return Ok((TokenKind::MinIntegerMagnitude, pos));
+    }
+    // integers that overflow i64 become reals, as in SQLite
+    let value = text.parse::<f64>().map_err(|_| {
+        ParseError::new(format!("malformed number: {}", text), Span::new(start, pos))
+    })?;
+    Ok((TokenKind::Real(value), pos))
+}
+
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(sql: &str) -> Vec<TokenKind> {
+        tokenize(sql).unwrap().into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn test_tokenize_select() {
+        assert_eq!(
+            kinds("SELECT name,color FROM apples"),
+            vec![
+                TokenKind::Word("SELECT".into()),
+                TokenKind::Word("name".into()),
+                TokenKind::Symbol(","),
+                TokenKind::Word("color".into()),
+                TokenKind::Word("FROM".into()),
+                TokenKind::Word("apples".into()),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_literals() {
+        assert_eq!(
+            kinds("'it''s here' x'0aFF' 42 3.5 1e3 .5 0x10 9223372036854775808 9223372036854775809"),
+            vec![
+                TokenKind::String("it's here".into()),
+                TokenKind::Blob(vec![0x0a, 0xff]),
+                TokenKind::Integer(42),
+                TokenKind::Real(3.5),
+                TokenKind::Real(1000.0),
+                TokenKind::Real(0.5),
+                TokenKind::Integer(16),
+                TokenKind::MinIntegerMagnitude,
+                TokenKind::Real(9223372036854775809.0),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_quoted_identifiers_and_comments() {
+        assert_eq!(
+            kinds("\"my col\" -- trailing\n `b` /* block */ [c d]"),
+            vec![
+                TokenKind::QuotedIdentifier("my col".into()),
+                TokenKind::QuotedIdentifier("b".into()),
+                TokenKind::QuotedIdentifier("c d".into()),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_operators() {
+        assert_eq!(
+            kinds("a<=b||c<>d"),
+            vec![
+                TokenKind::Word("a".into()),
+                TokenKind::Symbol("<="),
+                TokenKind::Word("b".into()),
+                TokenKind::Symbol("||"),
+                TokenKind::Word("c".into()),
+                TokenKind::Symbol("<>"),
+                TokenKind::Word("d".into()),
+                TokenKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        assert!(tokenize("'open").is_err());
+        assert!(tokenize("x'abc'").is_err());
+        assert!(tokenize("12abc").is_err());
+        assert!(tokenize("a # b").is_err());
+    }
+}
@@ -0,0 +1,52 @@
+pub mod ast;
+pub mod lexer;
+pub mod parser;
+
+use std::{error::Error, fmt};
+
+use ast::{Span, Statement};
+use parser::Parser;
+
+
+/// Parse error pointing at the offending part of the SQL text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        ParseError { message: message.into(), span }
+    }
+
+    /// Error message with the statement and a marker under the span, like the sqlite3 shell.
+    pub fn render(&self, sql: &str) -> String {
+        let start = self.span.start.min(sql.len());
+        let line_start = sql[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = sql[start..].find('\n').map(|i| start + i).unwrap_or(sql.len());
+        let column = sql[line_start..start].chars().count();
+        let width = sql[start..self.span.end.clamp(start, line_end)].chars().count().max(1);
+
+        format!(
+            "{}\n  {}\n  {}{}",
+            self.message,
+            &sql[line_start..line_end],
+            " ".repeat(column),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+
+pub fn parse(sql: &str) -> Result<Statement, ParseError> {
+    Parser::new(sql)?.parse_statement()
+}
@@ -0,0 +1,1622 @@
+use crate::sql::ast::*;
+use crate::sql::lexer::{tokenize, Token, TokenKind};
+use crate::sql::ParseError;
+use crate::value::{Affinity, Value};
+
+
+/// Words that end a column's type name and start one of its constraints.
+const COLUMN_CONSTRAINTS: &[&str] = &[
+    "AS", "CHECK", "COLLATE", "CONSTRAINT", "DEFAULT", "GENERATED", "NOT", "NULL", "PRIMARY",
+    "REFERENCES", "UNIQUE",
+];
+
+
+/// Words that can never be used as a bare identifier or alias.
+const RESERVED: &[&str] = &[
+    "ALL", "AND", "AS", "ASC", "BETWEEN", "BY", "CASE", "CAST", "COLLATE", "CROSS", "DESC",
+    "DISTINCT", "ELSE", "END", "ESCAPE", "EXCEPT", "EXISTS", "FROM", "GLOB", "GROUP", "HAVING",
+    "IN", "INNER", "INTERSECT", "IS", "ISNULL", "JOIN", "LEFT", "LIKE", "LIMIT", "MATCH",
+    "NATURAL", "NOT", "NOTNULL", "NULL", "OFFSET", "ON", "OR", "ORDER", "OUTER", "REGEXP",
+    "SELECT", "THEN", "UNION", "USING", "VALUES", "WHEN", "WHERE", "WITH",
+];
+
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    pub fn new(sql: &str) -> Result<Self, ParseError> {
+        Ok(Parser { tokens: tokenize(sql)?, pos: 0 })
+    }
+
+    /// statement [;] EOF
+    pub fn parse_statement(&mut self) -> Result<Statement, ParseError> {
+        let statement = if self.at_select(0) {
+            Statement::Select(Box::new(self.parse_select()?))
+        } else if self.peek().is_keyword("CREATE")
+            && ["TABLE", "TEMP", "TEMPORARY"].iter().any(|k| self.peek_nth(1).is_keyword(k))
+        {
+            Statement::CreateTable(self.parse_create_table()?)
+        } else if self.peek().is_keyword("CREATE") {
+            Statement::CreateIndex(self.parse_create_index()?)
+        } else {
+            return Err(self.syntax_error());
+        };
+
+        while self.peek().is_symbol(";") {
+            self.advance();
+        }
+        if self.peek().kind != TokenKind::Eof {
+            return Err(self.syntax_error());
+        }
+        Ok(statement)
+    }
+
+
+    // ---- token helpers ----
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn peek_nth(&self, n: usize) -> &Token {
+        &self.tokens[(self.pos + n).min(self.tokens.len() - 1)]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn previous_span(&self) -> Span {
+        self.tokens[self.pos.saturating_sub(1)].span
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek().is_keyword(keyword) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        if self.peek().is_symbol(symbol) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<Token, ParseError> {
+        if self.peek().is_keyword(keyword) {
+            Ok(self.advance())
+        } else {
+            Err(self.syntax_error())
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<Token, ParseError> {
+        if self.peek().is_symbol(symbol) {
+            Ok(self.advance())
+        } else {
+            Err(self.syntax_error())
+        }
+    }
+
+    /// SQLite's wording: near "X": syntax error, or incomplete input at the end.
+    fn syntax_error(&self) -> ParseError {
+        let token = self.peek();
+        match &token.kind {
+            TokenKind::Eof => ParseError::new("incomplete input", token.span),
+            _ => ParseError::new(format!("near \"{}\": syntax error", token_text(token)), token.span),
+        }
+    }
+
+    fn is_identifier_token(token: &Token) -> bool {
+        match &token.kind {
+            TokenKind::Word(w) => !RESERVED.iter().any(|r| r.eq_ignore_ascii_case(w)),
+            TokenKind::QuotedIdentifier(_) => true,
+            _ => false,
+        }
+    }
+
+    fn parse_identifier(&mut self) -> Result<String, ParseError> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Word(w) if Self::is_identifier_token(&token) => {
+                self.advance();
+                Ok(w)
+            }
+            TokenKind::QuotedIdentifier(name) => {
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    /// [AS] alias; a string literal is accepted as an alias too.
+    fn parse_alias(&mut self) -> Result<Option<String>, ParseError> {
+        if self.at_window_clause() {
+            return Ok(None);
+        }
+        if self.eat_keyword("AS") {
+            if let TokenKind::String(s) = &self.peek().kind {
+                let s = s.clone();
+                self.advance();
+                return Ok(Some(s));
+            }
+            return self.parse_identifier().map(Some);
+        }
+        if Self::is_identifier_token(self.peek()) {
+            return self.parse_identifier().map(Some);
+        }
+        if let TokenKind::String(s) = &self.peek().kind {
+            let s = s.clone();
+            self.advance();
+            return Ok(Some(s));
+        }
+        Ok(None)
+    }
+
+
+    // ---- SELECT ----
+
+    /// Whether the token `n` ahead starts a select statement.
+    fn at_select(&self, n: usize) -> bool {
+        self.peek_nth(n).is_keyword("SELECT") || self.peek_nth(n).is_keyword("WITH")
+    }
+
+    /// WINDOW name AS, which starts the WINDOW clause rather than naming an alias "window"
+    fn at_window_clause(&self) -> bool {
+        self.peek().is_keyword("WINDOW") && self.peek_nth(2).is_keyword("AS")
+    }
+
+    /// [WITH ...] select-core [compound-op select-core]... [ORDER BY ...] [LIMIT ...]
+    fn parse_select(&mut self) -> Result<SelectStatement, ParseError> {
+        let start = self.peek().span;
+        let with = if self.eat_keyword("WITH") { Some(self.parse_with()?) } else { None };
+
+        let mut select = self.parse_select_core()?;
+        while let Some(op) = self.parse_compound_op() {
+            let member = self.parse_select_core()?;
+            select.compound.push(CompoundSelect { op, select: member });
+        }
+
+        if self.eat_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            select.order_by.push(self.parse_ordering_term()?);
+            while self.eat_symbol(",") {
+                select.order_by.push(self.parse_ordering_term()?);
+            }
+        }
+
+        if self.eat_keyword("LIMIT") {
+            let first = self.parse_expr()?;
+            select.limit = if self.eat_keyword("OFFSET") {
+                Some(Limit { limit: first, offset: Some(self.parse_expr()?) })
+            } else if self.eat_symbol(",") {
+                // LIMIT offset, limit
+                Some(Limit { limit: self.parse_expr()?, offset: Some(first) })
+            } else {
+                Some(Limit { limit: first, offset: None })
+            };
+        }
+
+        // ORDER BY and LIMIT can only close the whole compound
+        let misplaced = if select.limit.is_some() { "LIMIT" } else { "ORDER BY" };
+        let op_start = self.peek().span;
+        if let Some(op) = self.parse_compound_op() {
+            return Err(ParseError::new(
+                format!("{} clause should come after {} not before", misplaced, op.keyword()),
+                op_start.to(self.previous_span()),
+            ));
+        }
+
+        select.with = with;
+        select.span = start.to(self.previous_span());
+        Ok(select)
+    }
+
+    /// cte, ... after WITH
+    fn parse_with(&mut self) -> Result<With, ParseError> {
+        let recursive = self.eat_keyword("RECURSIVE");
+        let mut ctes = Vec::new();
+        loop {
+            let name = self.parse_identifier()?;
+            let mut columns = Vec::new();
+            if self.eat_symbol("(") {
+                columns.push(self.parse_identifier()?);
+                while self.eat_symbol(",") {
+                    columns.push(self.parse_identifier()?);
+                }
+                self.expect_symbol(")")?;
+            }
+            self.expect_keyword("AS")?;
+            let materialized = if self.eat_keyword("MATERIALIZED") {
+                Some(true)
+            } else if self.peek().is_keyword("NOT") && self.peek_nth(1).is_keyword("MATERIALIZED") {
+                self.advance();
+                self.advance();
+                Some(false)
+            } else {
+                None
+            };
+            self.expect_symbol("(")?;
+            let select = self.parse_select()?;
+            self.expect_symbol(")")?;
+            ctes.push(CommonTableExpr { name, columns, materialized, select: Box::new(select) });
+
+            if !self.eat_symbol(",") {
+                return Ok(With { recursive, ctes });
+            }
+        }
+    }
+
+    /// UNION [ALL] | INTERSECT | EXCEPT
+    fn parse_compound_op(&mut self) -> Option<CompoundOp> {
+        if self.eat_keyword("UNION") {
+            Some(if self.eat_keyword("ALL") { CompoundOp::UnionAll } else { CompoundOp::Union })
+        } else if self.eat_keyword("INTERSECT") {
+            Some(CompoundOp::Intersect)
+        } else if self.eat_keyword("EXCEPT") {
+            Some(CompoundOp::Except)
+        } else {
+            None
+        }
+    }
+
+    /// SELECT ... [HAVING ...], one SELECT of a compound
+    fn parse_select_core(&mut self) -> Result<SelectStatement, ParseError> {
+        let start = self.expect_keyword("SELECT")?.span;
+
+        let distinct = if self.eat_keyword("DISTINCT") {
+            true
+        } else {
+            self.eat_keyword("ALL");
+            false
+        };
+
+        let mut columns = vec![self.parse_result_column()?];
+        while self.eat_symbol(",") {
+            columns.push(self.parse_result_column()?);
+        }
+
+        let from = if self.eat_keyword("FROM") { self.parse_from()? } else { Vec::new() };
+
+        let where_clause = if self.eat_keyword("WHERE") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        let mut group_by = Vec::new();
+        if self.eat_keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            group_by.push(self.parse_expr()?);
+            while self.eat_symbol(",") {
+                group_by.push(self.parse_expr()?);
+            }
+        }
+
+        let having = if self.eat_keyword("HAVING") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        let mut windows = Vec::new();
+        if self.at_window_clause() {
+            self.advance();
+            loop {
+                let name = self.parse_identifier()?;
+                self.expect_keyword("AS")?;
+                self.expect_symbol("(")?;
+                windows.push((name, self.parse_window_spec()?));
+                self.expect_symbol(")")?;
+                if !self.eat_symbol(",") {
+                    break;
+                }
+            }
+        }
+
+        Ok(SelectStatement {
+            with: None,
+            distinct,
+            columns,
+            from,
+            where_clause,
+            group_by,
+            having,
+            windows,
+            compound: Vec::new(),
+            order_by: Vec::new(),
+            limit: None,
+            span: start.to(self.previous_span()),
+        })
+    }
+
+    fn parse_result_column(&mut self) -> Result<ResultColumn, ParseError> {
+        if self.eat_symbol("*") {
+            return Ok(ResultColumn::Star);
+        }
+
+        // table.*
+        if Self::is_identifier_token(self.peek())
+            && self.peek_nth(1).is_symbol(".")
+            && self.peek_nth(2).is_symbol("*")
+        {
+            let table = self.parse_identifier()?;
+            self.advance();
+            self.advance();
+            return Ok(ResultColumn::TableStar(table));
+        }
+
+        let start = self.peek().span;
+        let expr = self.parse_expr()?;
+        let span = start.to(self.previous_span());
+        let alias = self.parse_alias()?;
+        Ok(ResultColumn::Expr { expr, alias, span })
+    }
+
+    /// table (join-operator table [ON expr | USING (columns)])*
+    fn parse_from(&mut self) -> Result<Vec<FromTable>, ParseError> {
+        let first = self.parse_table_source()?;
+        let mut from = vec![FromTable { source: first, join: JoinKind::Inner, natural: false, constraint: None }];
+
+        loop {
+            let (join, natural) = if self.eat_symbol(",") {
+                (JoinKind::Inner, false)
+            } else {
+                let start = self.pos;
+                let natural = self.eat_keyword("NATURAL");
+                let join = if self.eat_keyword("LEFT") {
+                    self.eat_keyword("OUTER");
+                    JoinKind::Left
+                } else if self.eat_keyword("CROSS") {
+                    JoinKind::Cross
+                } else if self.peek().is_keyword("RIGHT") || self.peek().is_keyword("FULL") {
+                    return Err(ParseError::new("RIGHT and FULL OUTER JOINs are not supported", self.peek().span));
+                } else {
+                    self.eat_keyword("INNER");
+                    JoinKind::Inner
+                };
+                if !self.eat_keyword("JOIN") {
+                    // NATURAL, LEFT, CROSS or INNER have to be followed by JOIN
+                    if self.pos != start {
+                        return Err(self.syntax_error());
+                    }
+                    break;
+                }
+                (join, natural)
+            };
+
+            let start = self.peek().span;
+            let source = self.parse_table_source()?;
+            let span = start.to(self.previous_span());
+            let constraint = if self.eat_keyword("ON") {
+                Some(JoinConstraint::On(self.parse_expr()?))
+            } else if self.eat_keyword("USING") {
+                self.expect_symbol("(")?;
+                let mut columns = vec![self.parse_identifier()?];
+                while self.eat_symbol(",") {
+                    columns.push(self.parse_identifier()?);
+                }
+                self.expect_symbol(")")?;
+                Some(JoinConstraint::Using(columns))
+            } else {
+                None
+            };
+            if natural && constraint.is_some() {
+                return Err(ParseError::new("a NATURAL join may not have an ON or USING clause", span));
+            }
+            from.push(FromTable { source, join, natural, constraint });
+        }
+
+        Ok(from)
+    }
+
+    /// table [[AS] alias] | function(args) [[AS] alias] | (SELECT ...) [[AS] alias]
+    fn parse_table_source(&mut self) -> Result<TableSource, ParseError> {
+        if Self::is_identifier_token(self.peek()) && self.peek_nth(1).is_symbol("(") {
+            return self.parse_table_function();
+        }
+        if !(self.peek().is_symbol("(") && self.at_select(1)) {
+            return self.parse_table_ref().map(TableSource::Table);
+        }
+        self.advance();
+        let select = self.parse_select()?;
+        self.expect_symbol(")")?;
+        let alias = self.parse_table_alias()?;
+        Ok(TableSource::Subquery { select: Box::new(select), alias })
+    }
+
+    fn parse_table_function(&mut self) -> Result<TableSource, ParseError> {
+        let start = self.peek().span;
+        let name = self.parse_identifier()?.to_lowercase();
+        self.expect_symbol("(")?;
+        let mut args = Vec::new();
+        if !self.peek().is_symbol(")") {
+            args.push(self.parse_expr()?);
+            while self.eat_symbol(",") {
+                args.push(self.parse_expr()?);
+            }
+        }
+        self.expect_symbol(")")?;
+        let span = start.to(self.previous_span());
+        let alias = self.parse_table_alias()?;
+        Ok(TableSource::Function { name, args, alias, span })
+    }
+
+    fn parse_table_ref(&mut self) -> Result<TableRef, ParseError> {
+        let start = self.peek().span;
+        let mut name = self.parse_identifier()?;
+        // schema-qualified: main.apples
+        if self.eat_symbol(".") {
+            name = self.parse_identifier()?;
+        }
+        let alias = self.parse_table_alias()?;
+        Ok(TableRef { name, alias, span: start.to(self.previous_span()) })
+    }
+
+    fn parse_table_alias(&mut self) -> Result<Option<String>, ParseError> {
+        // `a RIGHT JOIN b` must not read RIGHT as an alias
+        if self.peek().is_keyword("RIGHT") || self.peek().is_keyword("FULL") {
+            return Ok(None);
+        }
+        self.parse_alias()
+    }
+
+
+    // ---- CREATE INDEX ----
+
+    fn parse_create_index(&mut self) -> Result<CreateIndexStatement, ParseError> {
+        self.expect_keyword("CREATE")?;
+        let unique = self.eat_keyword("UNIQUE");
+        self.expect_keyword("INDEX")?;
+        if self.eat_keyword("IF") {
+            self.expect_keyword("NOT")?;
+            self.expect_keyword("EXISTS")?;
+        }
+
+        let mut name = self.parse_identifier()?;
+        if self.eat_symbol(".") {
+            name = self.parse_identifier()?;
+        }
+        self.expect_keyword("ON")?;
+        let table = self.parse_identifier()?;
+        let columns = self.parse_indexed_columns()?;
+
+        let where_clause = if self.eat_keyword("WHERE") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        Ok(CreateIndexStatement { name, table, unique, columns, where_clause })
+    }
+
+    // ---- CREATE TABLE ----
+
+    fn parse_create_table(&mut self) -> Result<TableDef, ParseError> {
+        self.expect_keyword("CREATE")?;
+        if !self.eat_keyword("TEMP") {
+            self.eat_keyword("TEMPORARY");
+        }
+        self.expect_keyword("TABLE")?;
+        if self.eat_keyword("IF") {
+            self.expect_keyword("NOT")?;
+            self.expect_keyword("EXISTS")?;
+        }
+
+        let mut name = self.parse_identifier()?;
+        if self.eat_symbol(".") {
+            name = self.parse_identifier()?;
+        }
+
+        self.expect_symbol("(")?;
+        let mut columns = Vec::new();
+        let mut constraints = Vec::new();
+        loop {
+            if ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"].iter().any(|k| self.peek().is_keyword(k)) {
+                break;
+            }
+            columns.push(self.parse_column_def()?);
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+        // table constraints may also be separated by nothing but whitespace
+        while !self.peek().is_symbol(")") {
+            constraints.push(self.parse_table_constraint()?);
+            self.eat_symbol(",");
+        }
+        self.expect_symbol(")")?;
+
+        let mut without_rowid = false;
+        let mut strict = false;
+        loop {
+            if self.eat_keyword("WITHOUT") {
+                self.expect_keyword("ROWID")?;
+                without_rowid = true;
+            } else if self.eat_keyword("STRICT") {
+                strict = true;
+            } else {
+                break;
+            }
+            if !self.eat_symbol(",") {
+                break;
+            }
+        }
+
+        Ok(TableDef { name, columns, constraints, without_rowid, strict })
+    }
+
+    /// name [type] [column constraints]
+    fn parse_column_def(&mut self) -> Result<ColumnDef, ParseError> {
+        let name = match &self.peek().kind {
+            TokenKind::String(s) => {
+                let s = s.clone();
+                self.advance();
+                s
+            }
+            _ => self.parse_identifier()?,
+        };
+        let declared_type = self.parse_type_name()?;
+        let mut column = ColumnDef {
+            name,
+            affinity: Affinity::from_declared_type(declared_type.as_deref().unwrap_or("")),
+            declared_type,
+            collation: None,
+            default: None,
+            not_null: false,
+            primary_key: None,
+            unique: false,
+            checks: Vec::new(),
+            references: None,
+            generated: None,
+        };
+
+        loop {
+            let named = self.eat_keyword("CONSTRAINT");
+            if named {
+                self.parse_identifier()?;
+            }
+            if self.eat_keyword("PRIMARY") {
+                self.expect_keyword("KEY")?;
+                let descending = self.parse_direction();
+                self.parse_conflict_clause()?;
+                let autoincrement = self.eat_keyword("AUTOINCREMENT");
+                column.primary_key = Some(PrimaryKey { descending, autoincrement });
+            } else if self.eat_keyword("NOT") {
+                self.expect_keyword("NULL")?;
+                self.parse_conflict_clause()?;
+                column.not_null = true;
+            } else if self.eat_keyword("NULL") {
+                self.parse_conflict_clause()?;
+            } else if self.eat_keyword("UNIQUE") {
+                self.parse_conflict_clause()?;
+                column.unique = true;
+            } else if self.eat_keyword("CHECK") {
+                column.checks.push(self.parse_parenthesized()?);
+            } else if self.eat_keyword("DEFAULT") {
+                column.default = Some(self.parse_default()?);
+            } else if self.eat_keyword("COLLATE") {
+                column.collation = Some(self.parse_identifier()?);
+            } else if self.peek().is_keyword("REFERENCES") {
+                column.references = Some(self.parse_foreign_key_clause()?);
+            } else if self.peek().is_keyword("GENERATED") || self.peek().is_keyword("AS") {
+                if self.eat_keyword("GENERATED") {
+                    self.expect_keyword("ALWAYS")?;
+                }
+                self.expect_keyword("AS")?;
+                let expr = self.parse_parenthesized()?;
+                let stored = self.eat_keyword("STORED");
+                if !stored {
+                    self.eat_keyword("VIRTUAL");
+                }
+                column.generated = Some(Generated { expr, stored });
+            } else if named {
+                return Err(self.syntax_error());
+            } else {
+                return Ok(column);
+            }
+        }
+    }
+
+    /// The words of a type name and up to two signed numbers in parentheses: VARCHAR(255), DECIMAL(10, 2).
+    fn parse_type_name(&mut self) -> Result<Option<String>, ParseError> {
+        let mut words = Vec::new();
+        while let TokenKind::Word(w) = &self.peek().kind
+            && !COLUMN_CONSTRAINTS.iter().any(|k| w.eq_ignore_ascii_case(k))
+        {
+            words.push(w.clone());
+            self.advance();
+        }
+        if words.is_empty() {
+            return Ok(None);
+        }
+
+        let mut declared = words.join(" ");
+        if self.eat_symbol("(") {
+            let mut sizes = vec![self.parse_signed_number()?];
+            if self.eat_symbol(",") {
+                sizes.push(self.parse_signed_number()?);
+            }
+            self.expect_symbol(")")?;
+            declared.push_str(&format!("({})", sizes.join(",")));
+        }
+        Ok(Some(declared))
+    }
+
+    fn parse_signed_number(&mut self) -> Result<String, ParseError> {
+        let sign = if self.eat_symbol("-") {
+            "-"
+        } else {
+            self.eat_symbol("+");
+            ""
+        };
+        match self.peek().kind {
+            TokenKind::Integer(_) | TokenKind::Real(_) | TokenKind::MinIntegerMagnitude => Ok(format!("{}{}", sign, token_text(&self.advance()))),
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    /// DEFAULT takes a literal, a signed number, a bare word such as CURRENT_TIMESTAMP, or (expr).
+    fn parse_default(&mut self) -> Result<Expr, ParseError> {
+        if self.peek().is_symbol("(") {
+            return self.parse_parenthesized();
+        }
+        if self.eat_symbol("-") {
+            if self.eat_min_magnitude() {
+                return Ok(Expr::Literal(Value::Integer(i64::MIN)));
+            }
+            let expr = self.parse_primary()?;
+            return Ok(Expr::Unary { op: UnaryOp::Negate, expr: Box::new(expr) });
+        }
+        self.eat_symbol("+");
+        self.parse_primary()
+    }
+
+    /// [CONSTRAINT name] PRIMARY KEY (...) | UNIQUE (...) | CHECK (expr) | FOREIGN KEY (columns) REFERENCES ...
+    fn parse_table_constraint(&mut self) -> Result<TableConstraint, ParseError> {
+        if self.eat_keyword("CONSTRAINT") {
+            self.parse_identifier()?;
+        }
+        if self.eat_keyword("PRIMARY") {
+            self.expect_keyword("KEY")?;
+            let columns = self.parse_indexed_columns()?;
+            self.parse_conflict_clause()?;
+            Ok(TableConstraint::PrimaryKey(columns))
+        } else if self.eat_keyword("UNIQUE") {
+            let columns = self.parse_indexed_columns()?;
+            self.parse_conflict_clause()?;
+            Ok(TableConstraint::Unique(columns))
+        } else if self.eat_keyword("CHECK") {
+            Ok(TableConstraint::Check(self.parse_parenthesized()?))
+        } else if self.eat_keyword("FOREIGN") {
+            self.expect_keyword("KEY")?;
+            let columns = self.parse_column_list()?;
+            let references = self.parse_foreign_key_clause()?;
+            Ok(TableConstraint::ForeignKey { columns, references })
+        } else {
+            Err(self.syntax_error())
+        }
+    }
+
+    /// REFERENCES table [(columns)] [ON DELETE|UPDATE action | MATCH name]... [[NOT] DEFERRABLE [INITIALLY DEFERRED|IMMEDIATE]]
+    fn parse_foreign_key_clause(&mut self) -> Result<ForeignKey, ParseError> {
+        self.expect_keyword("REFERENCES")?;
+        let table = self.parse_identifier()?;
+        let columns = if self.peek().is_symbol("(") { self.parse_column_list()? } else { Vec::new() };
+
+        loop {
+            if self.eat_keyword("ON") {
+                if !self.eat_keyword("DELETE") {
+                    self.expect_keyword("UPDATE")?;
+                }
+                if self.eat_keyword("SET") {
+                    if !self.eat_keyword("NULL") {
+                        self.expect_keyword("DEFAULT")?;
+                    }
+                } else if self.eat_keyword("NO") {
+                    self.expect_keyword("ACTION")?;
+                } else if !self.eat_keyword("CASCADE") {
+                    self.expect_keyword("RESTRICT")?;
+                }
+            } else if self.eat_keyword("MATCH") {
+                self.parse_identifier()?;
+            } else {
+                break;
+            }
+        }
+
+        if self.peek().is_keyword("DEFERRABLE") || (self.peek().is_keyword("NOT") && self.peek_nth(1).is_keyword("DEFERRABLE")) {
+            self.eat_keyword("NOT");
+            self.advance();
+            if self.eat_keyword("INITIALLY") && !self.eat_keyword("DEFERRED") {
+                self.expect_keyword("IMMEDIATE")?;
+            }
+        }
+        Ok(ForeignKey { table, columns })
+    }
+
+    /// [ON CONFLICT ROLLBACK|ABORT|FAIL|IGNORE|REPLACE]; only a writer would need the choice.
+    fn parse_conflict_clause(&mut self) -> Result<(), ParseError> {
+        if self.eat_keyword("ON") {
+            self.expect_keyword("CONFLICT")?;
+            if !["ROLLBACK", "ABORT", "FAIL", "IGNORE", "REPLACE"].iter().any(|k| self.eat_keyword(k)) {
+                return Err(self.syntax_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// (expr)
+    fn parse_parenthesized(&mut self) -> Result<Expr, ParseError> {
+        self.expect_symbol("(")?;
+        let expr = self.parse_expr()?;
+        self.expect_symbol(")")?;
+        Ok(expr)
+    }
+
+    /// (name, ...)
+    fn parse_column_list(&mut self) -> Result<Vec<String>, ParseError> {
+        self.expect_symbol("(")?;
+        let mut columns = vec![self.parse_identifier()?];
+        while self.eat_symbol(",") {
+            columns.push(self.parse_identifier()?);
+        }
+        self.expect_symbol(")")?;
+        Ok(columns)
+    }
+
+    /// (indexed column, ...)
+    fn parse_indexed_columns(&mut self) -> Result<Vec<IndexedColumn>, ParseError> {
+        self.expect_symbol("(")?;
+        let mut columns = vec![self.parse_indexed_column()?];
+        while self.eat_symbol(",") {
+            columns.push(self.parse_indexed_column()?);
+        }
+        self.expect_symbol(")")?;
+        Ok(columns)
+    }
+
+    /// expr [COLLATE name] [ASC|DESC]
+    fn parse_indexed_column(&mut self) -> Result<IndexedColumn, ParseError> {
+        let (expr, collation) = split_collation(self.parse_expr()?);
+        let descending = self.parse_direction();
+        Ok(IndexedColumn { expr, collation, descending })
+    }
+
+    /// expr [COLLATE name] [ASC|DESC] [NULLS FIRST|LAST]
+    fn parse_ordering_term(&mut self) -> Result<OrderingTerm, ParseError> {
+        let (expr, collation) = split_collation(self.parse_expr()?);
+        let descending = self.parse_direction();
+        let nulls_first = if self.eat_keyword("NULLS") {
+            if self.eat_keyword("FIRST") {
+                Some(true)
+            } else {
+                self.expect_keyword("LAST")?;
+                Some(false)
+            }
+        } else {
+            None
+        };
+        Ok(OrderingTerm { expr, collation, descending, nulls_first })
+    }
+
+    /// [ASC|DESC], true for DESC
+    fn parse_direction(&mut self) -> bool {
+        if self.eat_keyword("DESC") {
+            true
+        } else {
+            self.eat_keyword("ASC");
+            false
+        }
+    }
+
+
+    // ---- expressions, lowest precedence first ----
+
+    pub fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("OR") {
+            let right = self.parse_and()?;
+            left = binary(BinaryOp::Or, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword("AND") {
+            let right = self.parse_not()?;
+            left = binary(BinaryOp::And, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, ParseError> {
+        if self.eat_keyword("NOT") {
+            let expr = self.parse_not()?;
+            return Ok(Expr::Unary { op: UnaryOp::Not, expr: Box::new(expr) });
+        }
+        self.parse_equality()
+    }
+
+    /// = == != <> IS [NOT], and the postfix forms on the same level:
+    /// [NOT] BETWEEN, [NOT] IN, [NOT] LIKE/GLOB, ISNULL, NOTNULL, NOT NULL
+    fn parse_equality(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_comparison()?;
+        loop {
+            let negated = self.peek().is_keyword("NOT")
+                && ["BETWEEN", "IN", "LIKE", "GLOB", "NULL"].iter().any(|k| self.peek_nth(1).is_keyword(k));
+            if negated {
+                self.advance();
+            }
+
+            let op = if self.eat_symbol("=") || self.eat_symbol("==") {
+                BinaryOp::Eq
+            } else if self.eat_symbol("!=") || self.eat_symbol("<>") {
+                BinaryOp::NotEq
+            } else if self.eat_keyword("IS") {
+                if self.eat_keyword("NOT") { BinaryOp::IsNot } else { BinaryOp::Is }
+            } else if self.eat_keyword("ISNULL") || (negated && self.eat_keyword("NULL")) {
+                let op = if negated { BinaryOp::IsNot } else { BinaryOp::Is };
+                left = binary(op, left, Expr::Literal(Value::Null));
+                continue;
+            } else if self.eat_keyword("NOTNULL") {
+                left = binary(BinaryOp::IsNot, left, Expr::Literal(Value::Null));
+                continue;
+            } else if self.eat_keyword("BETWEEN") {
+                let low = self.parse_comparison()?;
+                self.expect_keyword("AND")?;
+                let high = self.parse_comparison()?;
+                left = Expr::Between { expr: Box::new(left), low: Box::new(low), high: Box::new(high), negated };
+                continue;
+            } else if self.eat_keyword("IN") {
+                left = self.parse_in(left, negated)?;
+                continue;
+            } else if self.peek().is_keyword("LIKE") || self.peek().is_keyword("GLOB") {
+                let op = if self.advance().is_keyword("LIKE") { LikeOp::Like } else { LikeOp::Glob };
+                let pattern = self.parse_comparison()?;
+                let escape = if self.eat_keyword("ESCAPE") {
+                    Some(Box::new(self.parse_comparison()?))
+                } else {
+                    None
+                };
+                left = Expr::Like { op, expr: Box::new(left), pattern: Box::new(pattern), escape, negated };
+                continue;
+            } else {
+                return Ok(left);
+            };
+            let right = self.parse_comparison()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    /// IN (expr, ...) | IN (SELECT ...)
+    fn parse_in(&mut self, expr: Expr, negated: bool) -> Result<Expr, ParseError> {
+        self.expect_symbol("(")?;
+        if self.at_select(0) {
+            let select = self.parse_select()?;
+            self.expect_symbol(")")?;
+            return Ok(Expr::InSelect { expr: Box::new(expr), select: Box::new(select), negated });
+        }
+        let mut list = Vec::new();
+        if !self.peek().is_symbol(")") {
+            list.push(self.parse_expr()?);
+            while self.eat_symbol(",") {
+                list.push(self.parse_expr()?);
+            }
+        }
+        self.expect_symbol(")")?;
+        Ok(Expr::InList { expr: Box::new(expr), list, negated })
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_bitwise()?;
+        loop {
+            let op = if self.eat_symbol("<") {
+                BinaryOp::Lt
+            } else if self.eat_symbol("<=") {
+                BinaryOp::LtEq
+            } else if self.eat_symbol(">") {
+                BinaryOp::Gt
+            } else if self.eat_symbol(">=") {
+                BinaryOp::GtEq
+            } else {
+                return Ok(left);
+            };
+            let right = self.parse_bitwise()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    fn parse_bitwise(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_additive()?;
+        loop {
+            let op = if self.eat_symbol("&") {
+                BinaryOp::BitAnd
+            } else if self.eat_symbol("|") {
+                BinaryOp::BitOr
+            } else if self.eat_symbol("<<") {
+                BinaryOp::ShiftLeft
+            } else if self.eat_symbol(">>") {
+                BinaryOp::ShiftRight
+            } else {
+                return Ok(left);
+            };
+            let right = self.parse_additive()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = if self.eat_symbol("+") {
+                BinaryOp::Add
+            } else if self.eat_symbol("-") {
+                BinaryOp::Subtract
+            } else {
+                return Ok(left);
+            };
+            let right = self.parse_multiplicative()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_concat()?;
+        loop {
+            let op = if self.eat_symbol("*") {
+                BinaryOp::Multiply
+            } else if self.eat_symbol("/") {
+                BinaryOp::Divide
+            } else if self.eat_symbol("%") {
+                BinaryOp::Modulo
+            } else {
+                return Ok(left);
+            };
+            let right = self.parse_concat()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    /// ||, -> and ->>, which share a precedence
+    fn parse_concat(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_collate()?;
+        loop {
+            let op = if self.eat_symbol("||") {
+                BinaryOp::Concat
+            } else if self.eat_symbol("->") {
+                BinaryOp::Extract
+            } else if self.eat_symbol("->>") {
+                BinaryOp::ExtractValue
+            } else {
+                return Ok(left);
+            };
+            let right = self.parse_collate()?;
+            left = binary(op, left, right);
+        }
+    }
+
+    /// expr COLLATE name, binding tighter than any binary operator
+    fn parse_collate(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_unary()?;
+        while self.eat_keyword("COLLATE") {
+            let collation = self.parse_identifier()?;
+            expr = Expr::Collate { expr: Box::new(expr), collation };
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        let op = if self.eat_symbol("-") {
+            UnaryOp::Negate
+        } else if self.eat_symbol("+") {
+            UnaryOp::Plus
+        } else if self.eat_symbol("~") {
+            UnaryOp::BitNot
+        } else {
+            return self.parse_primary();
+        };
+        if op == UnaryOp::Negate && self.eat_min_magnitude() {
+            return Ok(Expr::Literal(Value::Integer(i64::MIN)));
+        }
+        let expr = self.parse_unary()?;
+        Ok(Expr::Unary { op, expr: Box::new(expr) })
+    }
+
+    /// The literal 9223372036854775808, which only fits in i64 once negated.
+    fn eat_min_magnitude(&mut self) -> bool {
+        let found = self.peek().kind == TokenKind::MinIntegerMagnitude;
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let token = self.peek().clone();
+        match token.kind {
+            TokenKind::Integer(n) => {
+                self.advance();
+                Ok(Expr::Literal(Value::Integer(n)))
+            }
+            TokenKind::Real(r) => {
+                self.advance();
+                Ok(Expr::Literal(Value::Real(r)))
+            }
+            TokenKind::MinIntegerMagnitude => {
+                self.advance();
+                Ok(Expr::Literal(Value::Real(-(i64::MIN as f64))))
+            }
+            TokenKind::String(s) => {
+                self.advance();
+                Ok(Expr::Literal(Value::Text(s)))
+            }
+            TokenKind::Blob(b) => {
+                self.advance();
+                Ok(Expr::Literal(Value::Blob(b)))
+            }
+            TokenKind::Word(_) if token.is_keyword("NULL") => {
+                self.advance();
+                Ok(Expr::Literal(Value::Null))
+            }
+            TokenKind::Symbol("(") if self.at_select(1) => {
+                self.advance();
+                let select = self.parse_select()?;
+                self.expect_symbol(")")?;
+                Ok(Expr::Subquery(Box::new(select)))
+            }
+            TokenKind::Symbol("(") => {
+                self.advance();
+                let expr = self.parse_expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            TokenKind::Word(_) if token.is_keyword("EXISTS") => {
+                self.advance();
+                self.expect_symbol("(")?;
+                let select = self.parse_select()?;
+                self.expect_symbol(")")?;
+                Ok(Expr::Exists(Box::new(select)))
+            }
+            // CURRENT_DATE, CURRENT_TIME and CURRENT_TIMESTAMP are date(), time() and datetime()
+            TokenKind::Word(_)
+                if ["CURRENT_DATE", "CURRENT_TIME", "CURRENT_TIMESTAMP"].iter().any(|k| token.is_keyword(k)) =>
+            {
+                self.advance();
+                let name = if token.is_keyword("CURRENT_DATE") {
+                    "date"
+                } else if token.is_keyword("CURRENT_TIME") {
+                    "time"
+                } else {
+                    "datetime"
+                };
+                Ok(Expr::Function {
+                    name: name.to_string(),
+                    args: FunctionArgs::List(Vec::new()),
+                    distinct: false,
+                    over: None,
+                    span: token.span,
+                })
+            }
+            TokenKind::Word(_) | TokenKind::QuotedIdentifier(_) if Self::is_identifier_token(&token) => {
+                if self.peek_nth(1).is_symbol("(") {
+                    self.parse_function()
+                } else {
+                    self.parse_column_ref()
+                }
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    /// name | table.name
+    fn parse_column_ref(&mut self) -> Result<Expr, ParseError> {
+        let start = self.peek().span;
+        let double_quoted = matches!(self.peek().kind, TokenKind::QuotedIdentifier(_));
+        let first = self.parse_identifier()?;
+
+        if self.eat_symbol(".") {
+            let name = self.parse_identifier()?;
+            return Ok(Expr::Column(ColumnRef {
+                table: Some(first),
+                name,
+                double_quoted: false,
+                span: start.to(self.previous_span()),
+                position: None,
+            }));
+        }
+
+        Ok(Expr::Column(ColumnRef { table: None, name: first, double_quoted, span: start, position: None }))
+    }
+
+    /// name([DISTINCT] args) [OVER ...] | name(*) [OVER ...]
+    fn parse_function(&mut self) -> Result<Expr, ParseError> {
+        let start = self.peek().span;
+        let name = self.parse_identifier()?.to_lowercase();
+        self.expect_symbol("(")?;
+
+        let mut distinct = false;
+        let args = if self.eat_symbol("*") {
+            FunctionArgs::Star
+        } else if self.peek().is_symbol(")") {
+            FunctionArgs::List(vec![])
+        } else {
+            distinct = self.eat_keyword("DISTINCT");
+            let mut args = vec![self.parse_expr()?];
+            while self.eat_symbol(",") {
+                args.push(self.parse_expr()?);
+            }
+            FunctionArgs::List(args)
+        };
+        self.expect_symbol(")")?;
+        let span = start.to(self.previous_span());
+
+        let over = if self.eat_keyword("OVER") {
+            if self.eat_symbol("(") {
+                let spec = self.parse_window_spec()?;
+                self.expect_symbol(")")?;
+                Some(Box::new(Over::Spec(Box::new(spec))))
+            } else {
+                Some(Box::new(Over::Named(self.parse_identifier()?)))
+            }
+        } else {
+            None
+        };
+
+        Ok(Expr::Function { name, args, distinct, over, span })
+    }
+
+    /// [base-window] [PARTITION BY exprs] [ORDER BY terms] [frame], inside the parentheses
+    fn parse_window_spec(&mut self) -> Result<WindowSpec, ParseError> {
+        let clause_start = ["PARTITION", "ORDER", "RANGE", "ROWS", "GROUPS"];
+        let base = if Self::is_identifier_token(self.peek()) && !clause_start.iter().any(|k| self.peek().is_keyword(k)) {
+            Some(self.parse_identifier()?)
+        } else {
+            None
+        };
+
+        let mut partition_by = Vec::new();
+        if self.eat_keyword("PARTITION") {
+            self.expect_keyword("BY")?;
+            partition_by.push(self.parse_expr()?);
+            while self.eat_symbol(",") {
+                partition_by.push(self.parse_expr()?);
+            }
+        }
+
+        let mut order_by = Vec::new();
+        if self.eat_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            order_by.push(self.parse_ordering_term()?);
+            while self.eat_symbol(",") {
+                order_by.push(self.parse_ordering_term()?);
+            }
+        }
+
+        let unit = if self.eat_keyword("ROWS") {
+            Some(FrameUnit::Rows)
+        } else if self.eat_keyword("RANGE") {
+            Some(FrameUnit::Range)
+        } else if self.eat_keyword("GROUPS") {
+            Some(FrameUnit::Groups)
+        } else {
+            None
+        };
+        let frame = match unit {
+            Some(unit) => Some(self.parse_frame(unit)?),
+            None => None,
+        };
+
+        Ok(WindowSpec { base, partition_by, order_by, frame })
+    }
+
+    /// BETWEEN start AND end | start, then [EXCLUDE ...]; a lone start bound ends at the current row.
+    fn parse_frame(&mut self, unit: FrameUnit) -> Result<Frame, ParseError> {
+        let (start, end) = if self.eat_keyword("BETWEEN") {
+            let start = self.parse_frame_bound(true)?;
+            self.expect_keyword("AND")?;
+            (start, self.parse_frame_bound(false)?)
+        } else {
+            (self.parse_frame_bound(true)?, FrameBound::CurrentRow)
+        };
+
+        let exclude = if self.eat_keyword("EXCLUDE") {
+            if self.eat_keyword("NO") {
+                self.expect_keyword("OTHERS")?;
+                FrameExclude::NoOthers
+            } else if self.eat_keyword("CURRENT") {
+                self.expect_keyword("ROW")?;
+                FrameExclude::CurrentRow
+            } else if self.eat_keyword("GROUP") {
+                FrameExclude::Group
+            } else {
+                self.expect_keyword("TIES")?;
+                FrameExclude::Ties
+            }
+        } else {
+            FrameExclude::NoOthers
+        };
+        Ok(Frame { unit, start, end, exclude })
+    }
+
+    /// UNBOUNDED PRECEDING (start only) | UNBOUNDED FOLLOWING (end only) | CURRENT ROW | expr {PRECEDING|FOLLOWING}
+    fn parse_frame_bound(&mut self, start: bool) -> Result<FrameBound, ParseError> {
+        if self.eat_keyword("UNBOUNDED") {
+            return if start {
+                self.expect_keyword("PRECEDING").map(|_| FrameBound::UnboundedPreceding)
+            } else {
+                self.expect_keyword("FOLLOWING").map(|_| FrameBound::UnboundedFollowing)
+            };
+        }
+        if self.eat_keyword("CURRENT") {
+            self.expect_keyword("ROW")?;
+            return Ok(FrameBound::CurrentRow);
+        }
+        let offset = self.parse_expr()?;
+        if self.eat_keyword("PRECEDING") {
+            Ok(FrameBound::Preceding(offset))
+        } else {
+            self.expect_keyword("FOLLOWING")?;
+            Ok(FrameBound::Following(offset))
+        }
+    }
+}
+
+
+/// Takes a trailing COLLATE off an indexed column or ORDER BY term.
+fn split_collation(expr: Expr) -> (Expr, Option<String>) {
+    match expr {
+        Expr::Collate { expr, collation } => (*expr, Some(collation)),
+        expr => (expr, None),
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary { op, left: Box::new(left), right: Box::new(right) }
+}
+
+fn token_text(token: &Token) -> String {
+    match &token.kind {
+        TokenKind::Word(w) => w.clone(),
+        TokenKind::QuotedIdentifier(s) => format!("\"{}\"", s),
+        TokenKind::String(s) => format!("'{}'", s),
+        TokenKind::Blob(_) => "blob literal".to_string(),
+        TokenKind::Integer(n) => n.to_string(),
+        TokenKind::Real(r) => r.to_string(),
+        TokenKind::MinIntegerMagnitude => i64::MIN.unsigned_abs().to_string(),
+        TokenKind::Symbol(s) => s.to_string(),
+        TokenKind::Eof => String::new(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parse;
+
+    fn select(sql: &str) -> SelectStatement {
+        match parse(sql).unwrap() {
+            Statement::Select(s) => *s,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    fn table(from: &FromTable) -> &TableRef {
+        match &from.source {
+            TableSource::Table(table) => table,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    fn column(name: &str) -> Expr {
+        match select(&format!("SELECT {}", name)).columns.remove(0) {
+            ResultColumn::Expr { expr, .. } => expr,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_columns_and_tables() {
+        let s = select("select  name,color FROM apples");
+        assert_eq!(s.columns.len(), 2);
+        assert_eq!(table(&s.from[0]).name, "apples");
+        assert!(s.where_clause.is_none());
+
+        let s = select("SELECT a.*, count(*) AS n FROM main.apples a, oranges;");
+        assert_eq!(s.columns[0], ResultColumn::TableStar("a".into()));
+        assert!(matches!(&s.columns[1], ResultColumn::Expr { alias: Some(n), .. } if n == "n"));
+        assert_eq!(table(&s.from[0]).alias.as_deref(), Some("a"));
+        assert_eq!(table(&s.from[1]).name, "oranges");
+    }
+
+    #[test]
+    fn test_parse_where_quoted_string_with_spaces() {
+        let s = select("SELECT name FROM apples WHERE color = 'Light Green'");
+        match s.where_clause.unwrap() {
+            Expr::Binary { op: BinaryOp::Eq, right, .. } => {
+                assert_eq!(*right, Expr::Literal(Value::Text("Light Green".into())));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        // a OR (b AND (c = (1 + (2 * 3))))
+        let e = column("a OR b AND c = 1 + 2 * 3");
+        let Expr::Binary { op: BinaryOp::Or, right, .. } = e else { panic!() };
+        let Expr::Binary { op: BinaryOp::And, right, .. } = *right else { panic!() };
+        let Expr::Binary { op: BinaryOp::Eq, right, .. } = *right else { panic!() };
+        let Expr::Binary { op: BinaryOp::Add, right, .. } = *right else { panic!() };
+        assert!(matches!(*right, Expr::Binary { op: BinaryOp::Multiply, .. }));
+    }
+
+    #[test]
+    fn test_parse_min_integer() {
+        assert_eq!(column("-9223372036854775808"), Expr::Literal(Value::Integer(i64::MIN)));
+        assert_eq!(column("9223372036854775808"), Expr::Literal(Value::Real(9223372036854775808.0)));
+        assert!(matches!(
+            column("-9223372036854775809"),
+            Expr::Unary { op: UnaryOp::Negate, expr } if *expr == Expr::Literal(Value::Real(9223372036854775809.0))
+        ));
+    }
+
+    #[test]
+    fn test_parse_not_binds_looser_than_equality() {
+        let e = column("NOT a = 1");
+        let Expr::Unary { op: UnaryOp::Not, expr } = e else { panic!() };
+        assert!(matches!(*expr, Expr::Binary { op: BinaryOp::Eq, .. }));
+    }
+
+    #[test]
+    fn test_parse_postfix_operators() {
+        assert!(matches!(column("a NOT BETWEEN 1 AND 2 AND b"), Expr::Binary { op: BinaryOp::And, .. }));
+        assert!(matches!(column("a NOT IN (1, 2)"), Expr::InList { negated: true, .. }));
+        assert!(matches!(column("a LIKE 'x%' ESCAPE '!'"), Expr::Like { op: LikeOp::Like, escape: Some(_), .. }));
+        assert!(matches!(column("a NOT GLOB 'x*'"), Expr::Like { op: LikeOp::Glob, negated: true, .. }));
+        assert!(matches!(column("a NOT NULL"), Expr::Binary { op: BinaryOp::IsNot, .. }));
+        assert!(matches!(column("a ISNULL"), Expr::Binary { op: BinaryOp::Is, .. }));
+    }
+
+    #[test]
+    fn test_parse_order_by() {
+        let s = select("SELECT a FROM t ORDER BY a COLLATE nocase DESC, -b COLLATE rtrim || c NULLS FIRST, 2 ASC NULLS LAST");
+        assert_eq!(s.order_by.len(), 3);
+        assert!(matches!(&s.order_by[0].expr, Expr::Column(c) if c.name == "a"));
+        assert_eq!(s.order_by[0].collation.as_deref(), Some("nocase"));
+        assert!(s.order_by[0].descending);
+        assert_eq!(s.order_by[0].nulls_first, None);
+
+        // COLLATE binds to the unary expression, not the whole concatenation
+        let Expr::Binary { op: BinaryOp::Concat, left, .. } = &s.order_by[1].expr else { panic!() };
+        assert!(matches!(**left, Expr::Collate { ref collation, .. } if collation == "rtrim"));
+        assert_eq!(s.order_by[1].collation, None);
+        assert_eq!(s.order_by[1].nulls_first, Some(true));
+
+        assert_eq!(s.order_by[2].expr, Expr::Literal(Value::Integer(2)));
+        assert!(!s.order_by[2].descending);
+        assert_eq!(s.order_by[2].nulls_first, Some(false));
+    }
+
+    #[test]
+    fn test_parse_group_by_having() {
+        let s = select("SELECT a, count(DISTINCT b) FROM t GROUP BY a, b % 2 HAVING count(*) > 1 ORDER BY 2");
+        assert_eq!(s.group_by.len(), 2);
+        assert!(matches!(&s.group_by[0], Expr::Column(c) if c.name == "a"));
+        assert!(matches!(&s.group_by[1], Expr::Binary { op: BinaryOp::Modulo, .. }));
+        assert!(matches!(s.having, Some(Expr::Binary { op: BinaryOp::Gt, .. })));
+        assert_eq!(s.order_by.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_limit() {
+        let limit = select("SELECT a FROM t LIMIT 10 OFFSET 5 * 2").limit.unwrap();
+        assert_eq!(limit.limit, Expr::Literal(Value::Integer(10)));
+        assert!(matches!(limit.offset, Some(Expr::Binary { op: BinaryOp::Multiply, .. })));
+
+        // the comma form lists the offset first
+        let limit = select("SELECT a FROM t LIMIT 5, 10").limit.unwrap();
+        assert_eq!(limit.limit, Expr::Literal(Value::Integer(10)));
+        assert_eq!(limit.offset, Some(Expr::Literal(Value::Integer(5))));
+    }
+
+    #[test]
+    fn test_parse_joins() {
+        let from = select("SELECT * FROM a, b x LEFT OUTER JOIN c ON c.id = x.id CROSS JOIN d NATURAL JOIN e JOIN f USING (k, l)").from;
+        let joins: Vec<(&str, JoinKind, bool)> = from.iter().map(|f| (table(f).name.as_str(), f.join, f.natural)).collect();
+        assert_eq!(
+            joins,
+            [
+                ("a", JoinKind::Inner, false),
+                ("b", JoinKind::Inner, false),
+                ("c", JoinKind::Left, false),
+                ("d", JoinKind::Cross, false),
+                ("e", JoinKind::Inner, true),
+                ("f", JoinKind::Inner, false),
+            ]
+        );
+        assert_eq!(table(&from[1]).alias.as_deref(), Some("x"));
+        assert!(matches!(from[2].constraint, Some(JoinConstraint::On(_))));
+        assert_eq!(from[5].constraint, Some(JoinConstraint::Using(vec!["k".to_string(), "l".to_string()])));
+
+        assert!(parse("SELECT * FROM a LEFT b").is_err());
+        assert!(parse("SELECT * FROM a RIGHT JOIN b").is_err());
+        assert!(parse("SELECT * FROM a NATURAL JOIN b USING (k)").is_err());
+    }
+
+    #[test]
+    fn test_parse_subqueries() {
+        let s = select("SELECT (SELECT max(a) FROM t) FROM (SELECT a FROM u) AS x WHERE NOT EXISTS (SELECT 1) AND b NOT IN (SELECT c FROM v)");
+        assert!(matches!(&s.columns[0], ResultColumn::Expr { expr: Expr::Subquery(_), .. }));
+        assert!(matches!(&s.from[0].source, TableSource::Subquery { alias: Some(a), .. } if a == "x"));
+        let Some(Expr::Binary { op: BinaryOp::And, left, right }) = s.where_clause else { panic!() };
+        assert!(matches!(*left, Expr::Unary { op: UnaryOp::Not, expr } if matches!(*expr, Expr::Exists(_))));
+        assert!(matches!(*right, Expr::InSelect { negated: true, .. }));
+
+        // a parenthesized expression is still just an expression
+        assert_eq!(column("(1)"), Expr::Literal(Value::Integer(1)));
+    }
+
+    #[test]
+    fn test_parse_compound() {
+        let s = select("SELECT a FROM t UNION SELECT b FROM u UNION ALL SELECT 1 INTERSECT SELECT 2 EXCEPT SELECT 3 ORDER BY 1 LIMIT 2");
+        let ops: Vec<CompoundOp> = s.compound.iter().map(|c| c.op).collect();
+        assert_eq!(ops, [CompoundOp::Union, CompoundOp::UnionAll, CompoundOp::Intersect, CompoundOp::Except]);
+        assert_eq!(s.order_by.len(), 1);
+        assert!(s.limit.is_some());
+        assert!(s.compound.iter().all(|c| c.select.order_by.is_empty() && c.select.limit.is_none()));
+
+        let err = parse("SELECT a FROM t ORDER BY a UNION ALL SELECT b FROM u").unwrap_err();
+        assert_eq!(err.message, "ORDER BY clause should come after UNION ALL not before");
+        let err = parse("SELECT a FROM t LIMIT 1 UNION SELECT b FROM u").unwrap_err();
+        assert_eq!(err.message, "LIMIT clause should come after UNION not before");
+    }
+
+    #[test]
+    fn test_parse_with() {
+        let s = select(
+            "WITH RECURSIVE c(n) AS NOT MATERIALIZED (SELECT 1 UNION ALL SELECT n + 1 FROM c ORDER BY 1 LIMIT 5), d AS (SELECT 2) \
+             SELECT * FROM c, d",
+        );
+        let with = s.with.unwrap();
+        assert!(with.recursive);
+        assert_eq!(with.ctes.len(), 2);
+        let c = &with.ctes[0];
+        assert_eq!((c.name.as_str(), c.columns.clone(), c.materialized), ("c", vec!["n".to_string()], Some(false)));
+        // ORDER BY and LIMIT belong to the compound, not to its last SELECT
+        assert_eq!(c.select.compound.len(), 1);
+        assert_eq!(c.select.compound[0].op, CompoundOp::UnionAll);
+        assert!(c.select.compound[0].select.order_by.is_empty());
+        assert_eq!(c.select.order_by.len(), 1);
+        assert!(c.select.limit.is_some());
+        assert!(with.ctes[1].columns.is_empty());
+
+        assert!(matches!(&select("SELECT (WITH x AS (SELECT 1) SELECT * FROM x)").columns[0],
+            ResultColumn::Expr { expr: Expr::Subquery(s), .. } if s.with.is_some()));
+        assert!(parse("WITH c AS SELECT 1 SELECT * FROM c").is_err());
+    }
+
+    #[test]
+    fn test_parse_windows() {
+        let s = select(
+            "SELECT rank() OVER (PARTITION BY a ORDER BY b DESC), sum(b) OVER w, count(*) OVER (w ROWS 2 PRECEDING) \
+             FROM t window WINDOW w AS (ORDER BY a GROUPS BETWEEN 1 PRECEDING AND UNBOUNDED FOLLOWING EXCLUDE TIES)",
+        );
+        let over = |i: usize| match &s.columns[i] {
+            ResultColumn::Expr { expr: Expr::Function { over: Some(over), .. }, .. } => over.as_ref().clone(),
+            other => panic!("unexpected {:?}", other),
+        };
+        let Over::Spec(spec) = over(0) else { panic!() };
+        assert!(matches!(&spec.partition_by[..], [Expr::Column(c)] if c.name == "a"));
+        assert_eq!((spec.order_by.len(), spec.order_by[0].descending), (1, true));
+        assert_eq!(over(1), Over::Named("w".to_string()));
+        let Over::Spec(spec) = over(2) else { panic!() };
+        assert_eq!(spec.base.as_deref(), Some("w"));
+        assert_eq!(spec.frame.map(|f| (f.unit, f.start, f.end)), Some((FrameUnit::Rows, FrameBound::Preceding(Expr::Literal(Value::Integer(2))), FrameBound::CurrentRow)));
+
+        // the table alias "window" does not swallow the WINDOW clause
+        assert_eq!(table(&s.from[0]).alias.as_deref(), Some("window"));
+        let (name, spec) = &s.windows[0];
+        assert_eq!(name, "w");
+        let frame = spec.frame.as_ref().unwrap();
+        assert_eq!((frame.unit, &frame.start, &frame.end, frame.exclude), (
+            FrameUnit::Groups,
+            &FrameBound::Preceding(Expr::Literal(Value::Integer(1))),
+            &FrameBound::UnboundedFollowing,
+            FrameExclude::Ties,
+        ));
+
+        assert!(parse("SELECT sum(a) OVER (ROWS BETWEEN UNBOUNDED FOLLOWING AND CURRENT ROW) FROM t").is_err());
+        assert!(parse("SELECT sum(a) OVER (ROWS BETWEEN CURRENT ROW AND UNBOUNDED PRECEDING) FROM t").is_err());
+    }
+
+    #[test]
+    fn test_parse_create_index() {
+        let sql = "create unique index if not exists idx_companies_country\n\ton companies (country COLLATE NOCASE, \"name\" desc) WHERE id > 0";
+        let Statement::CreateIndex(index) = parse(sql).unwrap() else { panic!() };
+        assert_eq!(index.name, "idx_companies_country");
+        assert_eq!(index.table, "companies");
+        assert!(index.unique);
+        assert_eq!(index.columns.len(), 2);
+        assert_eq!(index.columns[0].collation.as_deref(), Some("NOCASE"));
+        assert!(!index.columns[0].descending);
+        assert!(index.columns[1].descending);
+        assert!(index.where_clause.is_some());
+    }
+
+    #[test]
+    fn test_parse_create_table() {
+        let sql = "create table if not exists main.\"order items\" (\n\
+            id integer primary key autoincrement,\n\
+            price DECIMAL(10, 2) NOT NULL DEFAULT -1.5 CHECK (price > 0),\n\
+            name text collate nocase unique,\n\
+            owner INT REFERENCES users(id) ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED,\n\
+            total AS (price * 2) STORED,\n\
+            data,\n\
+            CONSTRAINT pk UNIQUE (name, owner DESC) CHECK (owner <> 0),\n\
+            FOREIGN KEY (owner) REFERENCES users\n\
+        ) STRICT";
+        let Statement::CreateTable(table) = parse(sql).unwrap() else { panic!() };
+        assert_eq!(table.name, "order items");
+        assert_eq!(table.column_names(), ["id", "price", "name", "owner", "total", "data"]);
+        assert!(table.strict && !table.without_rowid);
+        assert_eq!(table.rowid_alias(), Some(0));
+        assert_eq!(table.columns[0].primary_key, Some(PrimaryKey { descending: false, autoincrement: true }));
+
+        let price = &table.columns[1];
+        assert_eq!(price.declared_type.as_deref(), Some("DECIMAL(10,2)"));
+        assert_eq!(price.affinity, Affinity::Numeric);
+        assert!(price.not_null);
+        assert!(matches!(price.default, Some(Expr::Unary { op: UnaryOp::Negate, .. })));
+        assert_eq!(price.checks.len(), 1);
+
+        assert_eq!(table.columns[2].collation.as_deref(), Some("nocase"));
+        assert!(table.columns[2].unique);
+        assert_eq!(table.columns[3].references, Some(ForeignKey { table: "users".to_string(), columns: vec!["id".to_string()] }));
+        assert!(table.columns[4].generated.as_ref().is_some_and(|g| g.stored));
+        assert_eq!(table.columns[5].declared_type, None);
+        assert_eq!(table.columns[5].affinity, Affinity::Blob);
+
+        assert_eq!(table.constraints.len(), 3);
+        assert!(matches!(&table.constraints[0], TableConstraint::Unique(columns) if columns[1].descending));
+        assert!(matches!(table.constraints[1], TableConstraint::Check(_)));
+        assert!(matches!(&table.constraints[2], TableConstraint::ForeignKey { references, .. } if references.columns.is_empty()));
+
+        assert!(parse("CREATE TABLE t (a INTEGER PRIMARY KEY DESC)").is_ok_and(|s| matches!(s, Statement::CreateTable(t) if t.rowid_alias().is_none())));
+        assert!(parse("CREATE TABLE t (a, b INTEGER, PRIMARY KEY (b DESC))").is_ok_and(|s| matches!(s, Statement::CreateTable(t) if t.rowid_alias() == Some(1))));
+        assert!(parse("CREATE TABLE t (a INTEGER PRIMARY KEY) WITHOUT ROWID").is_ok_and(|s| matches!(s, Statement::CreateTable(t) if t.rowid_alias().is_none())));
+        assert!(parse("CREATE TABLE t (a INT PRIMARY KEY)").is_ok_and(|s| matches!(s, Statement::CreateTable(t) if t.rowid_alias().is_none())));
+        assert!(parse("CREATE TABLE t (a CONSTRAINT c)").is_err());
+        assert!(parse("CREATE TABLE t (a,)").is_err());
+    }
+
+    #[test]
+    fn test_parse_error_span() {
+        let err = parse("SELECT name FORM apples").unwrap_err();
+        assert_eq!(err.to_string(), "near \"apples\": syntax error");
+        assert_eq!(err.span, Span::new(17, 23));
+
+        let err = parse("SELECT name FROM").unwrap_err();
+        assert_eq!(err.to_string(), "incomplete input");
+    }
+}
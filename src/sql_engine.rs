@@ -1,126 +1,1792 @@
-use crate::{page::Cell, read::SqliteRead, command_parse::extract_columns, command_parse::extract_tables, command_parse::extract_where_conditions};
-use std::{collections::HashMap, fs::File};
 
+use crate::{read::Pager, schema::Schema, value::Value};
+use crate::collation::{Collation, KeyFolder};
+use crate::datetime::Clock;
+use crate::encoding::TextEncoding;
+use crate::json;
+use crate::aggregate::{collect_aggregates, Aggregator, GroupKey};
+use crate::eval::{self, comparison_affinity, comparison_collation, ColumnKind, EvalContext, ExprCollation, ExprType, Scope, ScopeColumn, SubqueryRunner};
+use crate::join::{JoinTable, JoinedRows, TableRows};
 
-pub fn sql_engine(file_handler: &mut File, v: &str, metadata: Vec<Vec<String>>, page_size: u16) {
-    let tmp_buffer: Vec<_> = v.split(" ").collect();
-    let command = tmp_buffer[0];
+use crate::planner::split_conjuncts;
+use crate::sorter::{compare_keys, SortKey, SortedRows, Sorter};
+use crate::window::{self, collect_windows, WindowCall, Windows};
+use crate::sql::{self, ParseError, ast::{
+    BinaryOp, ColumnRef, CommonTableExpr, CompoundOp, Expr, JoinConstraint, Limit, OrderingTerm, ResultColumn, SelectStatement,
+    Span, Statement, TableSource, With,
+}};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::rc::Rc;
 
-    let target_columns = extract_columns(&tmp_buffer);
-    let target_from_table = extract_tables(&tmp_buffer);
-    let where_conditions = extract_where_conditions(&tmp_buffer);
 
-    match command {
-        "select" => {
-            let _ = select(
-                file_handler,
-                target_columns,
-                target_from_table,
-                where_conditions,
-                page_size,
-                metadata,
-            );
+/// Resource limits for running a statement.
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// bytes of rows kept in memory by ORDER BY before sorted runs are spilled to temp files
+    pub memory_budget: usize,
+    /// what 'now' means to the date and time functions
+    pub clock: Clock,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig { memory_budget: 64 * 1024 * 1024, clock: Clock::System }
+    }
+}
+
+impl EngineConfig {
+    /// Defaults, overridden by `MYDBMS_MEMORY_BUDGET` (bytes) and `MYDBMS_NOW` (a fixed 'now',
+    /// as a unix timestamp or a UTC date and time) when set.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let mut config = EngineConfig::default();
+        if let Ok(budget) = std::env::var("MYDBMS_MEMORY_BUDGET") {
+            config.memory_budget = budget.parse().map_err(|_| format!("Invalid MYDBMS_MEMORY_BUDGET: {}", budget))?;
+        }
+        if let Ok(now) = std::env::var("MYDBMS_NOW") {
+            config.clock = Clock::parse(&now).ok_or_else(|| format!("Invalid MYDBMS_NOW: {}", now))?;
         }
-        _ => {}
+        Ok(config)
     }
 }
 
 
+pub fn sql_engine(pager: &Pager, v: &str, schema: &Schema, config: &EngineConfig) -> Result<(), Box<dyn Error>> {
+    run(pager, v, schema, config, &mut |row| {
+        println!("{}", row_text(&row, pager.text_encoding()));
+        Ok(true)
+    })
+}
+
+/// Runs `sql` against fixture.db, the database kept next to sample.db for tests and built by
+/// fixture.sh, with the rows as the CLI prints them. It holds:
+/// - items(id, name NOCASE, grp, body, data): 2000 rows on 512-byte pages, three levels deep, with
+///   indexes on (grp DESC, name) and name; rows 7 and 1500 spill 3600 characters of body and a
+///   2500 byte blob onto overflow pages
+/// - groups(id, label) and tags(item, tag) to join with, groups 10 and 11 having no items
+/// - q(a, b VIRTUAL, c STORED) with generated columns and wr(v, k PRIMARY KEY, w) WITHOUT ROWID
+#[cfg(test)]
+pub fn query_fixture(sql: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    query_database("fixture.db", sql)
+}
 
-fn select(
-    file_handler: &mut File,
-    target_columns: Vec<String>,
-    froms: Vec<String>,
-    where_conditions: Option<HashMap<String, String>>,
-    page_size: u16,
-    metadata: Vec<Vec<String>>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    for meta in &metadata {
-        let table_name = meta[0].clone();
-        let page_index = meta[1].clone();
-        let sql = meta[2].clone();
+/// Like [`query_fixture`], against another database built by fixture.sh, such as utf16le.db.
+#[cfg(test)]
+pub fn query_database(name: &str, sql: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let pager = Pager::open(&format!("{}/{}", env!("CARGO_MANIFEST_DIR"), name))?;
+    let schema = Schema::load(&pager)?;
+    let mut rows = Vec::new();
+    run(&pager, sql, &schema, &EngineConfig::default(), &mut |row| {
+        rows.push(row_text(&row, pager.text_encoding()));
+        Ok(true)
+    })?;
+    Ok(rows)
+}
 
-        if froms.contains(&table_name) {
-            let columns = Cell::parse_create_table(&sql)?;
+/// Parses and runs one statement, handing its rows to `sink`.
+fn run(pager: &Pager, v: &str, schema: &Schema, config: &EngineConfig, sink: &mut RowSink) -> Result<(), Box<dyn Error>> {
+    let statement = sql::parse(v).map_err(|e| e.render(v))?;
+    let engine = Engine {
+        sql: v,
+        pager,
+        schema,
+        config,
+        clock: config.clock.start_statement(),
+        ctes: RefCell::default(),
+        analyses: RefCell::default(),
+        results: RefCell::default(),
+    };
 
-            let page = SqliteRead::read_page_n(
-                file_handler,
-                page_index.parse::<u16>().unwrap(),
-                page_size,
-            )?;
-            let row_count = SqliteRead::row_count(&page)?;
-            let cell_ptrs = Cell::read_cell_pointer_array(&page, row_count);
+    let result = match statement {
+        Statement::Select(select_statement) => {
+            let scope = Scope::default();
+            engine.select(&select_statement, &EvalContext::new(&scope, &[], pager.text_encoding(), engine.clock), sink)
+        }
+        Statement::CreateIndex(_) => Err("CREATE INDEX is not supported: the database is opened read-only".into()),
+        Statement::CreateTable(_) => Err("CREATE TABLE is not supported: the database is opened read-only".into()),
+    };
 
-            if target_columns.len() == 1 && target_columns[0] == "count(*)" {
-                println!("{}", row_count);
-                continue;
+    // errors that carry a span are shown against the query text
+    result.map_err(|e| match e.downcast_ref::<ParseError>() {
+        Some(parse_error) => parse_error.render(v).into(),
+        None => e,
+    })
+}
+
+
+/// Rows kept in memory: a subquery in FROM or a WITH table.
+type RowSet = Rc<Vec<Vec<Value>>>;
+
+/// Receives result rows; returning false asks for no more.
+type RowSink<'s> = dyn FnMut(Vec<Value>) -> Result<bool, Box<dyn Error>> + 's;
+
+
+/// Runs the SELECTs of one statement, subqueries included.
+struct Engine<'a> {
+    sql: &'a str,
+    pager: &'a Pager,
+    schema: &'a Schema,
+    config: &'a EngineConfig,
+    /// 'now' for the whole statement
+    clock: Clock,
+    /// WITH tables in scope, innermost last
+    ctes: RefCell<Vec<Rc<CteBinding>>>,
+    /// what is known about each subquery, by its position in the SQL text
+    analyses: RefCell<HashMap<Span, Analysis>>,
+    /// results of the subqueries that do not depend on the enclosing row, computed once
+    results: RefCell<HashMap<Span, SubqueryResult>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Analysis {
+    /// refers to a column of an enclosing query, so it has to run again for every enclosing row
+    correlated: bool,
+    /// number of result columns
+    width: usize,
+    /// of the first result column, which a comparison with the subquery converts to and compares with
+    column: ExprType,
+}
+
+#[derive(Debug, Clone)]
+enum SubqueryResult {
+    Value(Value),
+    Exists(bool),
+    Set(Rc<ValueSet>),
+    Rows(RowSet),
+}
+
+/// A WITH clause entry visible to the query being run.
+struct CteBinding {
+    cte: CommonTableExpr,
+    columns: Vec<String>,
+    types: Vec<ExprType>,
+    /// a later SELECT of its compound reads the table itself
+    recursive: bool,
+    /// the enclosing row of the query the WITH belongs to, which the body runs against
+    scope: Scope,
+    row: Vec<Value>,
+    /// bindings the body sees: those before it and itself
+    visible: usize,
+    /// while a recursive SELECT runs: its span and the one row it reads as this table
+    step: RefCell<Option<(Span, RowSet)>>,
+    /// the body is being run
+    running: std::cell::Cell<bool>,
+}
+
+/// Values of a subquery on the right of IN.
+#[derive(Debug, Default)]
+struct ValueSet {
+    values: HashSet<GroupKey>,
+    /// folds the values for the collation of the IN
+    folder: RefCell<KeyFolder>,
+    has_null: bool,
+}
+
+impl<'a> Engine<'a> {
+    /// Runs a select statement, handing its rows to `sink`. `outer` is the enclosing row of a subquery.
+    fn select(&self, statement: &SelectStatement, outer: &EvalContext, sink: &mut RowSink) -> Result<(), Box<dyn Error>> {
+        self.with_ctes(statement, outer.scope, outer.row, || {
+            if statement.compound.is_empty() {
+                self.core(statement, outer, true, sink)
+            } else {
+                self.compound(statement, outer, sink)
+            }
+        })
+    }
+
+    /// Runs a compound select. The SELECTs up to the last one joined by UNION, INTERSECT or EXCEPT
+    /// are combined through a sorter, which leaves out the duplicates and returns the rest in order
+    /// as SQLite does; the SELECTs joined by UNION ALL after that pass their rows straight on.
+    fn compound(&self, statement: &SelectStatement, outer: &EvalContext, sink: &mut RowSink) -> Result<(), Box<dyn Error>> {
+        let members = compound_members(statement);
+        let names = members.iter().map(|(_, member)| self.result_names(member, outer.scope)).collect::<Result<Vec<_>, _>>()?;
+        check_widths(&members, &names)?;
+        let collations = self.column_collations(&members, outer.scope)?;
+        let (order, keys) = compound_order_by(&statement.order_by, &names, &collations, self.pager.text_encoding())?;
+
+        let mut limiter = Limiter::new(statement.limit.as_ref(), self, self.pager.text_encoding(), self.clock)?;
+        let mut output = Output::new((!keys.is_empty()).then_some(keys), self.config, &mut limiter, sink);
+        let push = |output: &mut Output, row: Vec<Value>| -> Result<bool, Box<dyn Error>> {
+            output.push(order.iter().map(|&i| row[i].clone()).collect(), row)?;
+            Ok(!output.is_done())
+        };
+
+        let last_distinct = members.iter().rposition(|(op, _)| *op != CompoundOp::UnionAll).unwrap_or(0);
+        let streamed = if last_distinct == 0 {
+            &members[..]
+        } else {
+            let encoding = self.pager.text_encoding();
+            let row_keys: Vec<SortKey> = collations
+                .iter()
+                .map(|&collation| SortKey { collation, encoding, descending: false, nulls_first: true })
+                .collect();
+            let budget = self.config.memory_budget;
+            let sorted = |member: &SelectStatement, sorter: &mut Sorter| {
+                self.core(member, outer, false, &mut |row| {
+                    sorter.push(row.clone(), row)?;
+                    Ok(true)
+                })
+            };
+
+            let mut sorter = Sorter::new(row_keys.clone(), budget);
+            sorted(members[0].1, &mut sorter)?;
+            for (op, member) in &members[1..=last_distinct] {
+                if let CompoundOp::Union | CompoundOp::UnionAll = op {
+                    sorted(member, &mut sorter)?;
+                    continue;
+                }
+                // INTERSECT and EXCEPT walk both sides in order, side by side
+                let left = Distinct::new(std::mem::replace(&mut sorter, Sorter::new(row_keys.clone(), budget)).finish()?, &row_keys);
+                let mut right_sorter = Sorter::new(row_keys.clone(), budget);
+                sorted(member, &mut right_sorter)?;
+                let mut right = Distinct::new(right_sorter.finish()?, &row_keys);
+                let mut next = right.next().transpose()?;
+                for row in left {
+                    let row = row?;
+                    while let Some(other) = &next
+                        && compare_keys(&row_keys, other, &row).is_lt()
+                    {
+                        next = right.next().transpose()?;
+                    }
+                    let found = next.as_ref().is_some_and(|other| compare_keys(&row_keys, other, &row).is_eq());
+                    if found == (*op == CompoundOp::Intersect) {
+                        sorter.push(row.clone(), row)?;
+                    }
+                }
+            }
+
+            for row in Distinct::new(sorter.finish()?, &row_keys) {
+                if !push(&mut output, row?)? {
+                    return output.finish();
+                }
+            }
+            &members[last_distinct + 1..]
+        };
+
+        for (_, member) in streamed {
+            self.core(member, outer, false, &mut |row| push(&mut output, row))?;
+            if output.is_done() {
+                break;
+            }
+        }
+        output.finish()
+    }
+
+    /// Runs one SELECT of a statement; its ORDER BY and LIMIT only when they are its own (`whole`).
+    fn core(&self, statement: &SelectStatement, outer: &EvalContext, whole: bool, sink: &mut RowSink) -> Result<(), Box<dyn Error>> {
+        let mut limiter = Limiter::new(statement.limit.as_ref().filter(|_| whole), self, self.pager.text_encoding(), self.clock)?;
+        let (scope, tables) = self.tables(statement, outer.scope, Some(outer))?;
+
+        let where_clause = statement.where_clause.as_ref().map(|e| resolve_aliases(e, &statement.columns, &scope)).transpose()?;
+
+        // aggregates cannot decide which rows there are
+        let mut misused = Vec::new();
+        for expr in where_clause.iter().chain(tables.iter().flat_map(|t| &t.on)) {
+            collect_aggregates(expr, &mut misused)?;
+        }
+        if let Some(Expr::Function { name, span, .. }) = misused.first() {
+            return Err(ParseError::new(format!("misuse of aggregate function {}()", name), *span).into());
+        }
+        // nor can window functions
+        for expr in where_clause.iter().chain(tables.iter().flat_map(|t| &t.on)) {
+            collect_windows(expr, &mut misused)?;
+        }
+        if let Some(Expr::Function { name, span, .. }) = misused.first() {
+            return Err(window::misuse(name, *span).into());
+        }
+
+        let where_terms = where_clause.as_ref().map(split_conjuncts).unwrap_or_default();
+        let ctx = EvalContext::new(&scope, outer.row, self.pager.text_encoding(), self.clock).with_subqueries(Some(self));
+        let rows = JoinedRows::new(self.pager, self.schema, ctx, &tables, where_terms, self.config.memory_budget);
+        let order_by = if whole { &statement.order_by[..] } else { &[] };
+        self.run(statement, &scope, rows, order_by, &mut limiter, sink)
+    }
+
+    /// Makes the WITH clause of `statement` visible while `f` runs.
+    /// `scope` and `row` are the enclosing row of the statement.
+    fn with_ctes<T>(
+        &self,
+        statement: &SelectStatement,
+        scope: &Scope,
+        row: &[Value],
+        f: impl FnOnce() -> Result<T, Box<dyn Error>>,
+    ) -> Result<T, Box<dyn Error>> {
+        let Some(with) = &statement.with else {
+            return f();
+        };
+        let base = self.ctes.borrow().len();
+        let result = self.bind_ctes(with, scope, row).and_then(|()| f());
+        self.ctes.borrow_mut().truncate(base);
+        result
+    }
+
+    fn bind_ctes(&self, with: &With, scope: &Scope, row: &[Value]) -> Result<(), Box<dyn Error>> {
+        for (i, cte) in with.ctes.iter().enumerate() {
+            let name = &cte.name;
+            if with.ctes[..i].iter().any(|c| c.name.eq_ignore_ascii_case(name)) {
+                return Err(format!("duplicate WITH table name: {}", name).into());
             }
 
-            let mut cell_list = vec![];
-            for cell_ptr in cell_ptrs {
-                let cell = Cell::parse_cell_as_map(&page, cell_ptr as usize, &columns)?;
-                cell_list.push(cell);
+            // only the SELECTs after the first may read the table, once each
+            let select = &cte.select;
+            if references(select, name) > 0 {
+                return Err(format!("circular reference: {}", name).into());
             }
+            let mut recursive = false;
+            for member in &select.compound {
+                match references(&member.select, name) {
+                    0 => {}
+                    1 => recursive = true,
+                    _ => return Err(format!("multiple references to recursive table: {}", name).into()),
+                }
+            }
+
+            let names = self.result_names(select, scope)?;
+            let columns = if cte.columns.is_empty() {
+                names
+            } else if cte.columns.len() != names.len() {
+                return Err(format!("table {} has {} values for {} columns", name, names.len(), cte.columns.len()).into());
+            } else {
+                cte.columns.clone()
+            };
+
+            let types = self.result_types(select, scope)?;
+            let visible = self.ctes.borrow().len() + 1;
+            self.ctes.borrow_mut().push(Rc::new(CteBinding {
+                cte: cte.clone(),
+                columns,
+                types,
+                recursive,
+                scope: scope.clone(),
+                row: row.to_vec(),
+                visible,
+                step: RefCell::default(),
+                running: Default::default(),
+            }));
+        }
+        Ok(())
+    }
+
+    /// Position of the innermost WITH table called `name`.
+    fn cte(&self, name: &str) -> Option<usize> {
+        self.ctes.borrow().iter().rposition(|binding| binding.cte.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Rows of a WITH table read in the FROM clause of `statement`.
+    fn cte_rows(&self, binding: &CteBinding, statement: &SelectStatement) -> Result<RowSet, Box<dyn Error>> {
+        let name = &binding.cte.name;
+        if let Some((span, rows)) = &*binding.step.borrow() {
+            if *span != statement.span {
+                return Err(format!("multiple recursive references: {}", name).into());
+            }
+            return Ok(rows.clone());
+        }
+        if binding.running.get() {
+            return Err(format!("circular reference: {}", name).into());
+        }
+
+        // the body sees the WITH tables of where it is written, not of where it is read
+        let visible = self.ctes.borrow()[..binding.visible].to_vec();
+        let outer = self.ctes.replace(visible);
+        binding.running.set(true);
+        let ctx = EvalContext::new(&binding.scope, &binding.row, self.pager.text_encoding(), self.clock).with_subqueries(Some(self));
+        let rows = if binding.recursive {
+            self.materialize(&binding.cte.select, &ctx, || Ok(SubqueryResult::Rows(self.recursive_rows(binding, &ctx)?)))
+                .and_then(|result| match result {
+                    SubqueryResult::Rows(rows) => Ok(rows),
+                    other => Err(format!("Unexpected subquery result {:?}", other).into()),
+                })
+        } else {
+            self.derived_rows(&binding.cte.select, &ctx)
+        };
+        binding.running.set(false);
+        self.ctes.replace(outer);
+        rows
+    }
+
+    /// Rows of a recursive WITH table. The rows of the SELECTs before the first one reading
+    /// the table are queued; every row taken from the queue is a result row, and the recursive
+    /// SELECTs run against it alone to queue more. ORDER BY picks the next row, LIMIT stops.
+    fn recursive_rows(&self, binding: &CteBinding, ctx: &EvalContext) -> Result<RowSet, Box<dyn Error>> {
+        let body = &*binding.cte.select;
+        let name = &binding.cte.name;
+        let members = compound_members(body);
+        let first_recursive = members.iter().position(|(_, member)| references(member, name) > 0).unwrap_or(members.len());
+
+        // only UNION and UNION ALL can build the table up
+        if members.iter().any(|(op, _)| !matches!(op, CompoundOp::Union | CompoundOp::UnionAll)) {
+            return Err(format!("circular reference: {}", name).into());
+        }
+        let names = members.iter().map(|(_, member)| self.result_names(member, ctx.scope)).collect::<Result<Vec<_>, _>>()?;
+        check_widths(&members, &names)?;
+        for (_, member) in &members[first_recursive..] {
+            let mut calls = Vec::new();
+            for column in &member.columns {
+                if let ResultColumn::Expr { expr, .. } = column {
+                    collect_aggregates(expr, &mut calls)?;
+                }
+            }
+            if !calls.is_empty() || !member.group_by.is_empty() {
+                return Err("recursive aggregate queries not supported".into());
+            }
+        }
+
+        let collations = self.column_collations(&members, ctx.scope)?;
+        let (order, keys) = compound_order_by(&body.order_by, &names, &collations, self.pager.text_encoding())?;
+        let union = members.get(first_recursive).map(|(op, _)| *op) == Some(CompoundOp::Union);
+        let mut queue = RecursionQueue {
+            heap: BinaryHeap::new(),
+            seen: union.then(|| (KeyFolder::new(collations), HashSet::new())),
+            order,
+            keys: keys.into(),
+            queued: 0,
+        };
+        let mut limiter = Limiter::new(body.limit.as_ref(), self, self.pager.text_encoding(), self.clock)?;
+
+        for (_, member) in &members[..first_recursive] {
+            self.core(member, ctx, false, &mut |row| {
+                queue.push(row);
+                Ok(true)
+            })?;
+        }
+
+        let mut rows = Vec::new();
+        while let Some(row) = queue.pop() {
+            let current = Rc::new(vec![row.clone()]);
+            if limiter.admit() {
+                rows.push(row);
+            }
+            if limiter.is_done() {
+                break;
+            }
+            for (_, member) in &members[first_recursive..] {
+                *binding.step.borrow_mut() = Some((member.span, current.clone()));
+                let result = self.core(member, ctx, false, &mut |row| {
+                    queue.push(row);
+                    Ok(true)
+                });
+                *binding.step.borrow_mut() = None;
+                result?;
+            }
+        }
+        Ok(Rc::new(rows))
+    }
+
+    /// The scope and tables of the FROM clause: the columns of every entry side by side,
+    /// after those of the enclosing queries. Subqueries in FROM are run against the `derived`
+    /// context; without one they are only named, as when checking what a subquery refers to.
+    fn tables(
+        &self,
+        statement: &SelectStatement,
+        outer: &Scope,
+        derived: Option<&EvalContext>,
+    ) -> Result<(Scope, Vec<JoinTable<'a>>), Box<dyn Error>> {
+        let mut scope = outer.nested();
+        let mut tables = Vec::with_capacity(statement.from.len());
+        for item in &statement.from {
+            // functions declare their columns without a type
+            let mut types = Vec::new();
+            let (name, columns, rows) = match &item.source {
+                TableSource::Table(table_ref) if let Some(i) = self.cte(&table_ref.name) => {
+                    let binding = self.ctes.borrow()[i].clone();
+                    let rows = match derived {
+                        Some(_) => self.cte_rows(&binding, statement)?,
+                        None => Rc::default(),
+                    };
+                    let name = table_ref.alias.clone().unwrap_or_else(|| binding.cte.name.clone());
+                    types = binding.types.clone();
+                    (name, binding.columns.clone(), TableRows::Derived(rows))
+                }
+                TableSource::Table(table_ref) => {
+                    let table = self.schema.table(&table_ref.name).ok_or_else(|| {
+                        ParseError::new(format!("no such table: {}", table_ref.name), table_ref.span)
+                    })?;
+                    let definition = table.table_def()?;
+                    let columns = definition.column_names();
+                    for column in &definition.columns {
+                        let collation = column.collation.as_deref().map(Collation::named).transpose()?.unwrap_or_default();
+                        let collation = Some(ExprCollation { collation, explicit: false });
+                        types.push(ExprType { affinity: Some(column.affinity), collation });
+                    }
+                    let name = table_ref.alias.clone().unwrap_or_else(|| table.name.clone());
+                    (name, columns, TableRows::Stored { entry: table, definition: Rc::new(definition) })
+                }
+                TableSource::Subquery { select, alias } => {
+                    let columns = self.result_names(select, outer)?;
+                    types = self.result_types(select, outer)?;
+                    let rows = match derived {
+                        Some(ctx) => self.derived_rows(select, ctx)?,
+                        None => Rc::default(),
+                    };
+                    let name = alias.clone().unwrap_or_else(|| "(subquery)".to_string());
+                    (name, columns, TableRows::Derived(rows))
+                }
+                TableSource::Function { name, args, alias, span } => {
+                    if !json::is_table_function(name) {
+                        return Err(ParseError::new(format!("no such table: {}", name), *span).into());
+                    }
+                    if args.len() > 2 {
+                        return Err(format!("too many arguments on {}() - max 2", name).into());
+                    }
+                    // the arguments may read the tables to the left
+                    for arg in args {
+                        check_columns(arg, &scope)?;
+                    }
+                    let columns = json::TABLE_COLUMNS.iter().map(|column| column.to_string()).collect();
+                    let rows = TableRows::Function { name: name.clone(), args: args.clone() };
+                    (alias.clone().unwrap_or_else(|| name.clone()), columns, rows)
+                }
+            };
+
+            let mut table_scope = Scope::for_table(&name, &columns);
+            for (column, column_type) in table_scope.columns.iter_mut().zip(types) {
+                column.affinity = column_type.affinity;
+                column.collation = column_type.sort_collation();
+            }
+            match &rows {
+                // a subquery has no rowid, nor has a WITHOUT ROWID table
+                TableRows::Derived(_) => {
+                    table_scope.columns.pop();
+                }
+                TableRows::Stored { definition, .. } if definition.without_rowid => {
+                    table_scope.columns.pop();
+                }
+                TableRows::Function { .. } => {
+                    table_scope.columns[1].json = true;
+                    for column in &mut table_scope.columns[json::TABLE_COLUMNS.len() - 2..json::TABLE_COLUMNS.len()] {
+                        column.kind = ColumnKind::Hidden;
+                    }
+                }
+                TableRows::Stored { .. } => {}
+            }
+
+            let earlier = &scope.columns[scope.own_start()..];
+            let using = match &item.constraint {
+                Some(JoinConstraint::Using(using)) => using.clone(),
+                // NATURAL joins on every column name the two sides share
+                _ if item.natural => columns
+                    .iter()
+                    .filter(|c| earlier.iter().any(|s| s.kind == ColumnKind::Regular && s.name.eq_ignore_ascii_case(c)))
+                    .cloned()
+                    .collect(),
+                _ => Vec::new(),
+            };
+            let mut on = Vec::new();
+            for column in &using {
+                let missing = || format!("cannot join using column {} - column not present in both tables", column);
+                let left = earlier
+                    .iter()
+                    .position(|c| c.kind == ColumnKind::Regular && c.name.eq_ignore_ascii_case(column))
+                    .ok_or_else(missing)?;
+                let right = table_scope
+                    .columns
+                    .iter()
+                    .position(|c| c.kind == ColumnKind::Regular && c.name.eq_ignore_ascii_case(column))
+                    .ok_or_else(missing)?;
+                // the right-hand copy is only reached through its table name
+                table_scope.columns[right].kind = ColumnKind::Merged;
+                // by position, both sides may have the same table name
+                on.push(Expr::Binary {
+                    op: BinaryOp::Eq,
+                    left: Box::new(column_at(&earlier[left], scope.own_start() + left)),
+                    right: Box::new(column_at(&table_scope.columns[right], scope.columns.len() + right)),
+                });
+            }
+            if let Some(JoinConstraint::On(expr)) = &item.constraint {
+                on.extend(split_conjuncts(expr).into_iter().cloned());
+            }
+
+            scope.columns.extend(table_scope.columns);
+            tables.push(JoinTable { rows, column_count: columns.len(), join: item.join, on });
+        }
+        Ok((scope, tables))
+    }
+
+    /// Names of the result columns, as a subquery in FROM exposes them:
+    /// the alias, the column name, or else the expression as written.
+    fn column_names(&self, result_columns: &[ResultColumn], scope: &Scope) -> Vec<String> {
+        let mut names = Vec::new();
+        for result_column in result_columns {
+            let table = match result_column {
+                ResultColumn::Expr { alias: Some(alias), .. } => {
+                    names.push(alias.clone());
+                    continue;
+                }
+                ResultColumn::Expr { expr: Expr::Column(column), .. } => {
+                    names.push(column.name.clone());
+                    continue;
+                }
+                ResultColumn::Expr { span, .. } => {
+                    names.push(self.sql[span.start..span.end].to_string());
+                    continue;
+                }
+                ResultColumn::Star => None,
+                ResultColumn::TableStar(table) => Some(table.as_str()),
+            };
+            names.extend(scope.star_columns(table).into_iter().map(|i| scope.columns[i].name.clone()));
+        }
+        names
+    }
+
+    /// Affinities and collations of the result columns of a select statement, those of its first SELECT.
+    fn result_types(&self, select: &SelectStatement, outer: &Scope) -> Result<Vec<ExprType>, Box<dyn Error>> {
+        self.with_ctes(select, outer, &[], || {
+            let (scope, _) = self.tables(select, outer, None)?;
+            column_types(&select.columns, &scope)
+        })
+    }
+
+    /// How each result column of a compound compares: with the collation of the column in
+    /// the leftmost SELECT that gives it one.
+    fn column_collations(&self, members: &[(CompoundOp, &SelectStatement)], outer: &Scope) -> Result<Vec<Collation>, Box<dyn Error>> {
+        let mut collations: Vec<Option<ExprCollation>> = Vec::new();
+        for (_, member) in members {
+            let types = self.result_types(member, outer)?;
+            collations.resize(types.len(), None);
+            for (collation, column_type) in collations.iter_mut().zip(types) {
+                *collation = collation.or(column_type.collation);
+            }
+        }
+        Ok(collations.into_iter().map(|c| c.map_or(Collation::Binary, |c| c.collation)).collect())
+    }
+
+    /// Result column names of a select statement, those of its first SELECT.
+    fn result_names(&self, select: &SelectStatement, outer: &Scope) -> Result<Vec<String>, Box<dyn Error>> {
+        self.with_ctes(select, outer, &[], || {
+            let (scope, _) = self.tables(select, outer, None)?;
+            Ok(self.column_names(&select.columns, &scope))
+        })
+    }
+
+    fn analyze(&self, select: &SelectStatement, outer: &Scope) -> Result<Analysis, Box<dyn Error>> {
+        if let Some(analysis) = self.analyses.borrow().get(&select.span) {
+            return Ok(*analysis);
+        }
+        let outside = self.ctes.borrow().len();
+        let analysis = Analysis {
+            correlated: self.refers_before(select, outer, outer.columns.len(), outside)?,
+            width: self.result_names(select, outer)?.len(),
+            column: self.result_types(select, outer)?.first().copied().unwrap_or_default(),
+        };
+        self.analyses.borrow_mut().insert(select.span, analysis);
+        Ok(analysis)
+    }
+
+    /// Whether the query reads a column at a position below `boundary` of the enclosing scope.
+    /// WITH tables bound before position `outside` are taken to depend on the row they were
+    /// bound for, unless that was the outermost query.
+    fn refers_before(&self, select: &SelectStatement, outer: &Scope, boundary: usize, outside: usize) -> Result<bool, Box<dyn Error>> {
+        self.with_ctes(select, outer, &[], || {
+            for cte in select.with.iter().flat_map(|with| &with.ctes) {
+                if self.refers_before(&cte.select, outer, boundary, outside)? {
+                    return Ok(true);
+                }
+            }
+
+            let members = std::iter::once(select).chain(select.compound.iter().map(|member| &member.select));
+            for (i, member) in members.enumerate() {
+                let (scope, _) = self.tables(member, outer, None)?;
+
+                let mut pending: Vec<&Expr> = Vec::new();
+                for item in &member.from {
+                    if let TableSource::Function { args, .. } = &item.source {
+                        pending.extend(args);
+                    }
+                    match (&item.source, &item.constraint) {
+                        // subqueries in FROM only see the enclosing queries
+                        (TableSource::Subquery { select, .. }, _) if self.refers_before(select, outer, boundary, outside)? => {
+                            return Ok(true);
+                        }
+                        (TableSource::Table(table_ref), _)
+                            if self.cte(&table_ref.name).is_some_and(|i| {
+                                i < outside && !self.ctes.borrow()[i].scope.columns.is_empty()
+                            }) =>
+                        {
+                            return Ok(true);
+                        }
+                        (_, Some(JoinConstraint::On(expr))) => pending.push(expr),
+                        _ => {}
+                    }
+                }
+                for column in &member.columns {
+                    if let ResultColumn::Expr { expr, .. } = column {
+                        pending.push(expr);
+                    }
+                }
+                pending.extend(member.where_clause.iter().chain(&member.group_by).chain(&member.having));
+                // ORDER BY and LIMIT belong to the whole statement
+                if i == 0 {
+                    pending.extend(select.order_by.iter().map(|term| &term.expr));
+                    pending.extend(select.limit.iter().flat_map(|limit| std::iter::once(&limit.limit).chain(&limit.offset)));
+                }
+
+                while let Some(expr) = pending.pop() {
+                    if let Expr::Column(column) = expr
+                        && let Ok(Some(position)) = scope.resolve(column)
+                        && position < boundary
+                    {
+                        return Ok(true);
+                    }
+                    if let Some(subquery) = expr.subquery()
+                        && self.refers_before(subquery, &scope, boundary, outside)?
+                    {
+                        return Ok(true);
+                    }
+                    pending.extend(expr.children());
+                }
+            }
+            Ok(false)
+        })
+    }
+
+    /// Filters, aggregates and sorts the rows produced by the FROM clause.
+    fn run(
+        &self,
+        statement: &SelectStatement,
+        scope: &Scope,
+        rows: impl Iterator<Item = Result<Vec<Value>, Box<dyn Error>>>,
+        order_by: &[OrderingTerm],
+        limiter: &mut Limiter,
+        sink: &mut RowSink,
+    ) -> Result<(), Box<dyn Error>> {
+        let order_by = OrderBy::resolve(order_by, &statement.columns, scope, self.pager.text_encoding())?;
+        let group_by = resolve_group_by(&statement.group_by, &statement.columns, scope)?;
+        let having = statement.having.as_ref().map(|e| resolve_aliases(e, &statement.columns, scope)).transpose()?;
+
+        // aggregates cannot decide which groups there are; window functions only see the groups that are kept
+        let mut misused = Vec::new();
+        for expr in &group_by {
+            collect_aggregates(expr, &mut misused)?;
+        }
+        if !misused.is_empty() {
+            return Err("aggregate functions are not allowed in the GROUP BY clause".into());
+        }
+        for expr in group_by.iter().chain(&having) {
+            collect_windows(expr, &mut misused)?;
+        }
+        if let Some(Expr::Function { name, span, .. }) = misused.first() {
+            return Err(window::misuse(name, *span).into());
+        }
+
+        let mut calls = Vec::new();
+        let mut window_calls = Vec::new();
+        for column in &statement.columns {
+            if let ResultColumn::Expr { expr, .. } = column {
+                collect_aggregates(expr, &mut calls)?;
+                collect_windows(expr, &mut window_calls)?;
+            }
+        }
+        let aggregate = !calls.is_empty() || !group_by.is_empty();
+        if having.is_some() && !aggregate {
+            return Err("HAVING clause on a non-aggregate query".into());
+        }
+        if let Some(expr) = &having {
+            collect_aggregates(expr, &mut calls)?;
+        }
+        for expr in order_by.exprs() {
+            collect_aggregates(expr, &mut calls)?;
+            collect_windows(expr, &mut window_calls)?;
+        }
+        let window_calls = window_calls
+            .into_iter()
+            .map(|call| WindowCall::resolve(call, &statement.windows, scope, self.pager.text_encoding()))
+            .collect::<Result<Vec<_>, _>>()?;
+        for expr in window_calls.iter().flat_map(WindowCall::exprs) {
+            collect_aggregates(expr, &mut calls)?;
+        }
+        // an aggregate in ORDER BY alone does not make an aggregate query
+        if let (false, Some(Expr::Function { name, span, .. })) = (aggregate, calls.first()) {
+            return Err(ParseError::new(format!("misuse of aggregate: {}()", name), *span).into());
+        }
+
+        // window functions run once all rows are in, the rows come out in the order of their first window
+        let mut windows = (!window_calls.is_empty()).then(|| {
+            Windows::new(scope, &calls, window_calls, self.config.memory_budget, self.pager.text_encoding(), self.clock)
+                .with_subqueries(Some(self))
+        });
+        // groups come out of the hash table in no particular order, SQLite returns them by key
+        let mut by_group = Vec::with_capacity(group_by.len());
+        for expr in &group_by {
+            let collation = eval::sort_collation(expr, scope)?;
+            by_group.push(SortKey { collation, encoding: self.pager.text_encoding(), descending: false, nulls_first: true });
+        }
+        let keys = if order_by.terms.is_empty() && aggregate && windows.is_none() { by_group.clone() } else { order_by.keys.clone() };
+        let distinct = match statement.distinct {
+            true => Some(column_types(&statement.columns, scope)?.iter().map(ExprType::sort_collation).collect()),
+            false => None,
+        };
+        let mut output = Output::new((!keys.is_empty()).then_some(keys), self.config, limiter, sink).with_distinct(distinct);
+
+        if !aggregate {
+            for row in rows {
+                // stop reading the table once the last wanted row is out
+                if output.is_done() {
+                    break;
+                }
+                let values = row?;
+                if let Some(windows) = &mut windows {
+                    windows.push(values, vec![])?;
+                    continue;
+                }
+                let ctx = EvalContext::new(scope, &values, self.pager.text_encoding(), self.clock).with_subqueries(Some(self));
+                let row = project(&statement.columns, &ctx)?;
+                output.push(order_by.key(&ctx, &row)?, row)?;
+            }
+        } else {
+            let mut aggregator = Aggregator::new(scope, &group_by, &calls, self.config.memory_budget, self.pager.text_encoding(), self.clock)?
+                .with_subqueries(Some(self));
+            for row in rows {
+                aggregator.push(&row?)?;
+            }
+
+            // window functions see the groups in key order
+            let mut groups = windows.is_some().then(|| Sorter::new(by_group, self.config.memory_budget));
+            aggregator.finish(&mut |group| {
+                let ctx = EvalContext {
+                    scope,
+                    row: &group.row,
+                    aggregates: &group.aggregates,
+                    subqueries: Some(self),
+                    encoding: self.pager.text_encoding(),
+                    clock: self.clock,
+                };
+                if let Some(expr) = &having && !ctx.is_true(expr)? {
+                    return Ok(());
+                }
+                if let Some(groups) = &mut groups {
+                    let mut row = group.row;
+                    row.extend(group.aggregates.into_iter().map(|(_, value)| value));
+                    return groups.push(group.key, row);
+                }
+                let row = project(&statement.columns, &ctx)?;
+                let key = if order_by.terms.is_empty() { group.key } else { order_by.key(&ctx, &row)? };
+                output.push(key, row)
+            })?;
+
+            if let (Some(groups), Some(windows)) = (groups, &mut windows) {
+                for row in groups.finish()? {
+                    let mut row = row?;
+                    let aggregates = row.split_off(scope.columns.len());
+                    windows.push(row, aggregates)?;
+                }
+            }
+        }
+
+        if let Some(windows) = windows {
+            windows.finish(&mut |result| {
+                let ctx = EvalContext {
+                    scope,
+                    row: &result.row,
+                    aggregates: &result.aggregates,
+                    subqueries: Some(self),
+                    encoding: self.pager.text_encoding(),
+                    clock: self.clock,
+                };
+                let row = project(&statement.columns, &ctx)?;
+                output.push(order_by.key(&ctx, &row)?, row)
+            })?;
+        }
+        output.finish()
+    }
+
+    /// Runs `compute`, or reuses its result when the subquery does not depend on the enclosing row.
+    fn materialize(
+        &self,
+        select: &SelectStatement,
+        ctx: &EvalContext,
+        compute: impl FnOnce() -> Result<SubqueryResult, Box<dyn Error>>,
+    ) -> Result<SubqueryResult, Box<dyn Error>> {
+        if self.analyze(select, ctx.scope)?.correlated {
+            return compute();
+        }
+        let kept = self.results.borrow().get(&select.span).cloned();
+        if let Some(result) = kept {
+            return Ok(result);
+        }
+        let result = compute()?;
+        self.results.borrow_mut().insert(select.span, result.clone());
+        Ok(result)
+    }
+
+    fn expect_one_column(&self, select: &SelectStatement, ctx: &EvalContext) -> Result<(), Box<dyn Error>> {
+        let width = self.analyze(select, ctx.scope)?.width;
+        if width != 1 {
+            return Err(format!("sub-select returns {} columns - expected 1", width).into());
+        }
+        Ok(())
+    }
+
+    fn derived_rows(&self, select: &SelectStatement, ctx: &EvalContext) -> Result<RowSet, Box<dyn Error>> {
+        let result = self.materialize(select, ctx, || {
+            let mut rows = Vec::new();
+            self.select(select, ctx, &mut |row| {
+                rows.push(row);
+                Ok(true)
+            })?;
+            Ok(SubqueryResult::Rows(Rc::new(rows)))
+        })?;
+        match result {
+            SubqueryResult::Rows(rows) => Ok(rows),
+            other => Err(format!("Unexpected subquery result {:?}", other).into()),
+        }
+    }
+}
+
+impl SubqueryRunner for Engine<'_> {
+    fn scalar(&self, select: &SelectStatement, ctx: &EvalContext) -> Result<Value, Box<dyn Error>> {
+        self.expect_one_column(select, ctx)?;
+        let result = self.materialize(select, ctx, || {
+            let mut value = Value::Null;
+            self.select(select, ctx, &mut |row| {
+                value = row.into_iter().next().unwrap_or(Value::Null);
+                Ok(false)
+            })?;
+            Ok(SubqueryResult::Value(value))
+        })?;
+        match result {
+            SubqueryResult::Value(value) => Ok(value),
+            other => Err(format!("Unexpected subquery result {:?}", other).into()),
+        }
+    }
+
+    fn exists(&self, select: &SelectStatement, ctx: &EvalContext) -> Result<bool, Box<dyn Error>> {
+        let result = self.materialize(select, ctx, || {
+            let mut found = false;
+            self.select(select, ctx, &mut |_| {
+                found = true;
+                Ok(false)
+            })?;
+            Ok(SubqueryResult::Exists(found))
+        })?;
+        match result {
+            SubqueryResult::Exists(found) => Ok(found),
+            other => Err(format!("Unexpected subquery result {:?}", other).into()),
+        }
+    }
+
+    fn contains(
+        &self,
+        select: &SelectStatement,
+        value: &Value,
+        left: ExprType,
+        ctx: &EvalContext,
+    ) -> Result<Option<bool>, Box<dyn Error>> {
+        self.expect_one_column(select, ctx)?;
+        // the subquery belongs to this one IN, so its values can be kept converted
+        let column = self.analyze(select, ctx.scope)?.column;
+        let conversion = comparison_affinity(left.affinity, column.affinity);
+        let collation = comparison_collation(left.collation, column.collation);
+        let value = &conversion.apply(value.clone());
 
-            cell_filtering(cell_list, &where_conditions, &target_columns);
+        // a correlated subquery runs anyway, so it stops at the first match
+        if self.analyze(select, ctx.scope)?.correlated {
+            let mut result = Some(false);
+            self.select(select, ctx, &mut |row| {
+                let item = &conversion.apply(row[0].clone());
+                if value.is_null() || item.is_null() {
+                    result = None;
+                    return Ok(!value.is_null());
+                }
+                if collation.compare(value, item, self.pager.text_encoding()).is_eq() {
+                    result = Some(true);
+                    return Ok(false);
+                }
+                Ok(true)
+            })?;
+            return Ok(result);
+        }
+
+        let result = self.materialize(select, ctx, || {
+            let mut set = ValueSet { folder: RefCell::new(KeyFolder::new(vec![collation])), ..Default::default() };
+            self.select(select, ctx, &mut |mut row| {
+                match conversion.apply(row.swap_remove(0)) {
+                    Value::Null => set.has_null = true,
+                    item => {
+                        set.values.insert(GroupKey(set.folder.get_mut().fold(vec![item])));
+                    }
+                }
+                Ok(true)
+            })?;
+            Ok(SubqueryResult::Set(Rc::new(set)))
+        })?;
+        let SubqueryResult::Set(set) = result else {
+            return Err(format!("Unexpected subquery result {:?}", result).into());
+        };
 
-            
+        // NULL IN (empty) is false, NULL IN (anything else) is NULL, like a miss next to a NULL
+        Ok(if set.values.is_empty() && !set.has_null {
+            Some(false)
+        } else if value.is_null() {
+            None
+        } else if set.values.contains(&GroupKey(set.folder.borrow_mut().fold(vec![value.clone()]))) {
+            Some(true)
+        } else if set.has_null {
+            None
         } else {
-            continue;
+            Some(false)
+        })
+    }
+
+    fn column_type(&self, select: &SelectStatement, ctx: &EvalContext) -> Result<ExprType, Box<dyn Error>> {
+        Ok(self.analyze(select, ctx.scope)?.column)
+    }
+}
+
+/// The SELECTs of a compound with the operator before each; the first has none and reads as UNION ALL.
+fn compound_members(statement: &SelectStatement) -> Vec<(CompoundOp, &SelectStatement)> {
+    std::iter::once((CompoundOp::UnionAll, statement))
+        .chain(statement.compound.iter().map(|member| (member.op, &member.select)))
+        .collect()
+}
+
+/// Every SELECT of a compound must have as many result columns as the first.
+fn check_widths(members: &[(CompoundOp, &SelectStatement)], names: &[Vec<String>]) -> Result<(), Box<dyn Error>> {
+    for ((op, _), member_names) in members.iter().zip(names).skip(1) {
+        if member_names.len() != names[0].len() {
+            return Err(format!(
+                "SELECTs to the left and right of {} do not have the same number of result columns",
+                op.keyword()
+            )
+            .into());
         }
     }
+    Ok(())
+}
 
+/// How many times the FROM clause of one SELECT names the table `name`.
+fn references(select: &SelectStatement, name: &str) -> usize {
+    select
+        .from
+        .iter()
+        .filter(|item| matches!(&item.source, TableSource::Table(table) if table.name.eq_ignore_ascii_case(name)))
+        .count()
+}
+
+/// Fails on the first column an expression reads that is not in `scope`; subqueries are left to run.
+fn check_columns(expr: &Expr, scope: &Scope) -> Result<(), Box<dyn Error>> {
+    if let Expr::Column(column) = expr
+        && scope.resolve(column)?.is_none()
+        && !(column.double_quoted && column.table.is_none())
+    {
+        let name = match &column.table {
+            Some(table) => format!("{}.{}", table, column.name),
+            None => column.name.clone(),
+        };
+        return Err(ParseError::new(format!("no such column: {}", name), column.span).into());
+    }
+    for child in expr.children() {
+        check_columns(child, scope)?;
+    }
     Ok(())
 }
 
+/// A reference to the scope column at `position`, named after `column`.
+fn column_at(column: &ScopeColumn, position: usize) -> Expr {
+    Expr::Column(ColumnRef {
+        table: Some(column.table.clone()),
+        name: column.name.clone(),
+        double_quoted: false,
+        span: Span::default(),
+        position: Some(position),
+    })
+}
+
+
+/// Where result rows go: to the sink straight away, or through the sorter first, within LIMIT/OFFSET.
+struct Output<'a, 's> {
+    /// rows output so far, for SELECT DISTINCT
+    seen: Option<(KeyFolder, HashSet<GroupKey>)>,
+    sorter: Option<Sorter>,
+    limiter: &'a mut Limiter,
+    sink: &'a mut RowSink<'s>,
+    /// the sink wants no more rows
+    stopped: bool,
+}
+
+impl<'a, 's> Output<'a, 's> {
+    fn new(sort_keys: Option<Vec<SortKey>>, config: &EngineConfig, limiter: &'a mut Limiter, sink: &'a mut RowSink<'s>) -> Self {
+        let sorter = sort_keys.map(|keys| Sorter::new(keys, config.memory_budget));
+        Output { seen: None, sorter, limiter, sink, stopped: false }
+    }
+
+    /// Leaves out rows equal to one output before, comparing the columns with `collations`.
+    fn with_distinct(self, collations: Option<Vec<Collation>>) -> Self {
+        Output { seen: collations.map(|collations| (KeyFolder::new(collations), HashSet::new())), ..self }
+    }
+
+    /// No further row can make it into the result.
+    fn is_done(&self) -> bool {
+        self.sorter.is_none() && (self.stopped || self.limiter.is_done())
+    }
+
+    fn push(&mut self, key: Vec<Value>, row: Vec<Value>) -> Result<(), Box<dyn Error>> {
+        if let Some((folder, seen)) = &mut self.seen
+            && !seen.insert(GroupKey(folder.fold(row.clone())))
+        {
+            return Ok(());
+        }
+        match &mut self.sorter {
+            Some(sorter) => sorter.push(key, row),
+            None => self.emit(row),
+        }
+    }
+
+    fn emit(&mut self, row: Vec<Value>) -> Result<(), Box<dyn Error>> {
+        if !self.stopped && self.limiter.admit() {
+            self.stopped = !(self.sink)(row)?;
+        }
+        Ok(())
+    }
 
-fn cell_filtering( cell_list:Vec<HashMap<String, String>> , condition_list: &Option<HashMap<String, String>>, target_columns: &Vec<String>) {
-    let is_none = condition_list.is_none();
+    fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        let Some(sorter) = self.sorter.take() else {
+            return Ok(());
+        };
+        for row in sorter.finish()? {
+            self.emit(row?)?;
+            if self.stopped || self.limiter.is_done() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
 
 
-    if is_none {
-        cell_list.iter().for_each( |cell| { 
-            let mut row_output = Vec::new();
-            for target in target_columns {
+/// GROUP BY terms; a number names a result column.
+fn resolve_group_by(terms: &[Expr], result_columns: &[ResultColumn], scope: &Scope) -> Result<Vec<Expr>, Box<dyn Error>> {
+    let mut group_by = Vec::new();
+    for (i, term) in terms.iter().enumerate() {
+        if let Expr::Literal(Value::Integer(n)) = term {
+            let outputs = output_exprs(result_columns, scope);
+            if *n < 1 || *n as usize > outputs.len() {
+                return Err(format!(
+                    "{} GROUP BY term out of range - should be between 1 and {}",
+                    ordinal(i + 1),
+                    outputs.len()
+                )
+                .into());
+            }
+            group_by.push(outputs[*n as usize - 1].clone());
+        } else {
+            group_by.push(resolve_aliases(term, result_columns, scope)?);
+        }
+    }
+    Ok(group_by)
+}
 
-                    if let Some(value) = cell.get(target) {
-                        row_output.push(value.clone());
+/// Copy of `expr` in which names that are not columns of the scope but result column aliases
+/// stand for the aliased expression, as SQLite allows in WHERE, GROUP BY, HAVING and ORDER BY.
+fn resolve_aliases(expr: &Expr, result_columns: &[ResultColumn], scope: &Scope) -> Result<Expr, Box<dyn Error>> {
+    fn substitute(expr: &mut Expr, result_columns: &[ResultColumn], scope: &Scope) -> Result<(), Box<dyn Error>> {
+        if let Expr::Column(column) = expr {
+            if column.table.is_none() && scope.resolve(column)?.is_none() {
+                let aliased = result_columns.iter().find_map(|c| match c {
+                    ResultColumn::Expr { expr, alias: Some(alias), .. } if alias.eq_ignore_ascii_case(&column.name) => {
+                        Some(expr)
                     }
-                    
+                    _ => None,
+                });
+                if let Some(aliased) = aliased {
+                    *expr = aliased.clone();
                 }
-                println!("{}", row_output.join("|"));
-        });
+            }
+            return Ok(());
+        }
+        for child in expr.children_mut() {
+            substitute(child, result_columns, scope)?;
+        }
+        Ok(())
+    }
 
-    } else {
-        
-        cell_list.iter().for_each( |cell| { 
-            let condition_map = condition_list.clone().unwrap();
-            let mut row_output = Vec::new();
-            let mut flag = true; 
-            for target in target_columns {
-
-                if let Some(value) = cell.get(target) {
-                    row_output.push(value.clone());
-                    let compe = condition_map.get(target);
-                   
-                    if compe.is_none() {
-                        continue;
-                    } else {
-                        let compe = compe.unwrap();
-                        if value.to_lowercase() != *compe {
-                            flag = false;
-                        }
+    let mut expr = expr.clone();
+    substitute(&mut expr, result_columns, scope)?;
+    Ok(expr)
+}
+
+/// The result columns with `*` and `table.*` expanded to column references.
+fn output_exprs(result_columns: &[ResultColumn], scope: &Scope) -> Vec<Expr> {
+    let mut exprs = Vec::new();
+    for result_column in result_columns {
+        let table = match result_column {
+            ResultColumn::Expr { expr, .. } => {
+                exprs.push(expr.clone());
+                continue;
+            }
+            ResultColumn::Star => None,
+            ResultColumn::TableStar(table) => Some(table),
+        };
+        for i in scope.star_columns(table.map(String::as_str)) {
+            let column = &scope.columns[i];
+            exprs.push(Expr::Column(ColumnRef {
+                table: Some(column.table.clone()),
+                name: column.name.clone(),
+                double_quoted: false,
+                span: Span::default(),
+                position: None,
+            }));
+        }
+    }
+    exprs
+}
+
+
+/// LIMIT/OFFSET bookkeeping: result rows still to skip and still to produce.
+struct Limiter {
+    skip: u64,
+    remaining: Option<u64>,
+}
+
+impl Limiter {
+    fn new(limit: Option<&Limit>, subqueries: &dyn SubqueryRunner, encoding: TextEncoding, clock: Clock) -> Result<Self, Box<dyn Error>> {
+        let Some(limit) = limit else {
+            return Ok(Limiter { skip: 0, remaining: None });
+        };
+        // a negative limit means no limit, a negative offset is no offset
+        let count = limit_value(&limit.limit, subqueries, encoding, clock)?;
+        let offset = match &limit.offset {
+            Some(expr) => limit_value(expr, subqueries, encoding, clock)?,
+            None => 0,
+        };
+        Ok(Limiter { skip: offset.max(0) as u64, remaining: (count >= 0).then_some(count as u64) })
+    }
+
+    fn is_done(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    /// Counts one result row, true if it is past the offset and within the limit.
+    fn admit(&mut self) -> bool {
+        if self.is_done() {
+            return false;
+        }
+        if self.skip > 0 {
+            self.skip -= 1;
+            return false;
+        }
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= 1;
+        }
+        true
+    }
+}
+
+/// LIMIT and OFFSET take constant expressions that must turn into an integer.
+fn limit_value(expr: &Expr, subqueries: &dyn SubqueryRunner, encoding: TextEncoding, clock: Clock) -> Result<i64, Box<dyn Error>> {
+    let scope = Scope::default();
+    let value = EvalContext::new(&scope, &[], encoding, clock).with_subqueries(Some(subqueries)).eval(expr)?;
+    // text has to be a number as a whole, '10 rows' is not accepted
+    let number = match value {
+        Value::Text(s) => match (s.trim().parse::<i64>(), s.trim().parse::<f64>()) {
+            (Ok(n), _) => Value::Integer(n),
+            (_, Ok(r)) => Value::Real(r),
+            _ => Value::Null,
+        },
+        other => other,
+    };
+    match number {
+        Value::Integer(n) => Ok(n),
+        Value::Real(r) if r.fract() == 0.0 && r.abs() < 9.2e18 => Ok(r as i64),
+        _ => Err("datatype mismatch".into()),
+    }
+}
+
+
+/// Where an ORDER BY term takes its value from.
+enum OrderSource {
+    /// a result column, named by position or alias
+    Output(usize),
+    Expr(Expr),
+}
+
+/// ORDER BY terms resolved against the result columns.
+struct OrderBy {
+    terms: Vec<OrderSource>,
+    keys: Vec<SortKey>,
+}
+
+impl OrderBy {
+    /// `encoding` is how the database stores the text the terms compare.
+    fn resolve(
+        terms: &[OrderingTerm],
+        result_columns: &[ResultColumn],
+        scope: &Scope,
+        encoding: TextEncoding,
+    ) -> Result<Self, Box<dyn Error>> {
+        // first output position of every result column, `*` expands to several
+        let mut starts = Vec::new();
+        let mut width = 0;
+        for result_column in result_columns {
+            starts.push(width);
+            width += match result_column {
+                ResultColumn::Star => scope.star_columns(None).len(),
+                ResultColumn::TableStar(table) => scope.star_columns(Some(table)).len(),
+                ResultColumn::Expr { .. } => 1,
+            };
+        }
+
+        let types = column_types(result_columns, scope)?;
+        let mut order_by = OrderBy { terms: Vec::new(), keys: Vec::new() };
+        for (i, term) in terms.iter().enumerate() {
+            let source = match &term.expr {
+                Expr::Literal(Value::Integer(n)) => {
+                    if *n < 1 || *n as usize > width {
+                        return Err(format!(
+                            "{} ORDER BY term out of range - should be between 1 and {}",
+                            ordinal(i + 1),
+                            width
+                        )
+                        .into());
                     }
+                    OrderSource::Output(*n as usize - 1)
+                }
+                Expr::Column(column) if column.table.is_none() => {
+                    let alias = result_columns.iter().position(|c| {
+                        matches!(c, ResultColumn::Expr { alias: Some(alias), .. } if alias.eq_ignore_ascii_case(&column.name))
+                    });
+                    match alias {
+                        Some(position) => OrderSource::Output(starts[position]),
+                        None => OrderSource::Expr(term.expr.clone()),
+                    }
+                }
+                expr => OrderSource::Expr(resolve_aliases(expr, result_columns, scope)?),
+            };
+
+            // without a COLLATE of its own a term sorts the way its value compares
+            let collation = match (&term.collation, &source) {
+                (Some(name), _) => Collation::named(name)?,
+                (None, OrderSource::Output(i)) => types[*i].sort_collation(),
+                (None, OrderSource::Expr(expr)) => eval::sort_collation(expr, scope)?,
+            };
+            order_by.terms.push(source);
+            order_by.keys.push(SortKey {
+                collation,
+                encoding,
+                descending: term.descending,
+                nulls_first: term.nulls_first.unwrap_or(!term.descending),
+            });
+        }
+        Ok(order_by)
+    }
+
+    /// Terms evaluated as expressions rather than taken from the output.
+    fn exprs(&self) -> impl Iterator<Item = &Expr> {
+        self.terms.iter().filter_map(|source| match source {
+            OrderSource::Expr(expr) => Some(expr),
+            OrderSource::Output(_) => None,
+        })
+    }
+
+    /// Sort key of one row.
+    fn key(&self, ctx: &EvalContext, output: &[Value]) -> Result<Vec<Value>, Box<dyn Error>> {
+        self.terms
+            .iter()
+            .map(|source| match source {
+                OrderSource::Output(i) => Ok(output[*i].clone()),
+                OrderSource::Expr(expr) => ctx.eval(expr),
+            })
+            .collect()
+    }
+}
+
+/// ORDER BY of a compound select: every term names a result column, by position or by a name
+/// it has in any of the SELECTs. Returns the positions and how to compare them.
+fn compound_order_by(
+    terms: &[OrderingTerm],
+    names: &[Vec<String>],
+    collations: &[Collation],
+    encoding: TextEncoding,
+) -> Result<(Vec<usize>, Vec<SortKey>), Box<dyn Error>> {
+    let width = names[0].len();
+    let mut positions = Vec::new();
+    let mut keys = Vec::new();
+    for (i, term) in terms.iter().enumerate() {
+        let position = match &term.expr {
+            Expr::Literal(Value::Integer(n)) if *n >= 1 && *n as usize <= width => Some(*n as usize - 1),
+            Expr::Literal(Value::Integer(_)) => {
+                return Err(format!(
+                    "{} ORDER BY term out of range - should be between 1 and {}",
+                    ordinal(i + 1),
+                    width
+                )
+                .into());
+            }
+            Expr::Column(column) if column.table.is_none() => {
+                names.iter().find_map(|names| names.iter().position(|name| name.eq_ignore_ascii_case(&column.name)))
+            }
+            _ => None,
+        };
+        let position = position
+            .ok_or_else(|| format!("{} ORDER BY term does not match any column in the result set", ordinal(i + 1)))?;
+
+        let collation = match &term.collation {
+            Some(name) => Collation::named(name)?,
+            None => collations[position],
+        };
+        positions.push(position);
+        let nulls_first = term.nulls_first.unwrap_or(!term.descending);
+        keys.push(SortKey { collation, encoding, descending: term.descending, nulls_first });
+    }
+    Ok((positions, keys))
+}
+
+/// Affinities and collations of the result columns, in the order of their names.
+fn column_types(result_columns: &[ResultColumn], scope: &Scope) -> Result<Vec<ExprType>, Box<dyn Error>> {
+    let mut types = Vec::new();
+    for result_column in result_columns {
+        let table = match result_column {
+            ResultColumn::Expr { expr, .. } => {
+                types.push(eval::expr_type(expr, scope)?);
+                continue;
+            }
+            ResultColumn::Star => None,
+            ResultColumn::TableStar(table) => Some(table.as_str()),
+        };
+        types.extend(scope.star_columns(table).into_iter().map(|i| ExprType {
+            affinity: scope.columns[i].affinity,
+            collation: Some(ExprCollation { collation: scope.columns[i].collation, explicit: false }),
+        }));
+    }
+    Ok(types)
+}
+
 
+/// Sorted rows with the duplicates left out. Of equal rows the last one stays, as in SQLite.
+struct Distinct<'k> {
+    rows: SortedRows,
+    keys: &'k [SortKey],
+    pending: Option<Vec<Value>>,
+}
+
+impl<'k> Distinct<'k> {
+    fn new(rows: SortedRows, keys: &'k [SortKey]) -> Self {
+        Distinct { rows, keys, pending: None }
+    }
+}
+
+impl Iterator for Distinct<'_> {
+    type Item = Result<Vec<Value>, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let row = match self.rows.next() {
+                Some(Ok(row)) => row,
+                Some(Err(e)) => return Some(Err(e)),
+                None => return self.pending.take().map(Ok),
+            };
+            if let Some(previous) = self.pending.replace(row)
+                && !compare_keys(self.keys, &previous, self.pending.as_ref()?).is_eq()
+            {
+                return Some(Ok(previous));
+            }
+        }
+    }
+}
+
+
+/// Rows waiting to be read by the recursive SELECTs of a WITH table.
+struct RecursionQueue {
+    heap: BinaryHeap<Queued>,
+    /// rows queued so far, when UNION leaves out duplicates
+    seen: Option<(KeyFolder, HashSet<GroupKey>)>,
+    /// result columns of the ORDER BY terms
+    order: Vec<usize>,
+    keys: Rc<[SortKey]>,
+    queued: usize,
+}
+
+impl RecursionQueue {
+    fn push(&mut self, row: Vec<Value>) {
+        if let Some((folder, seen)) = &mut self.seen
+            && !seen.insert(GroupKey(folder.fold(row.clone())))
+        {
+            return;
+        }
+        let key = self.order.iter().map(|&i| row[i].clone()).collect();
+        self.heap.push(Queued { key, queued: self.queued, row, keys: self.keys.clone() });
+        self.queued += 1;
+    }
+
+    fn pop(&mut self) -> Option<Vec<Value>> {
+        self.heap.pop().map(|entry| entry.row)
+    }
+}
+
+/// The heap pops the least sort key first, and of equal keys the row queued first.
+struct Queued {
+    key: Vec<Value>,
+    queued: usize,
+    row: Vec<Value>,
+    keys: Rc<[SortKey]>,
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_keys(&self.keys, &other.key, &self.key).then(other.queued.cmp(&self.queued))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Queued {}
+
+
+/// 1st, 2nd, 3rd, 4th, ... as SQLite words its ORDER BY errors.
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+
+/// Evaluates the result columns against one row, expanding `*` and `table.*`.
+fn project(result_columns: &[ResultColumn], ctx: &EvalContext) -> Result<Vec<Value>, Box<dyn Error>> {
+    let mut output = Vec::new();
+
+    for result_column in result_columns {
+        match result_column {
+            ResultColumn::Star => {
+                output.extend(ctx.scope.star_columns(None).into_iter().map(|i| ctx.row[i].clone()));
+            }
+            ResultColumn::TableStar(table) => {
+                let columns = ctx.scope.star_columns(Some(table));
+                if columns.is_empty() {
+                    return Err(format!("no such table: {}", table).into());
                 }
+                output.extend(columns.into_iter().map(|i| ctx.row[i].clone()));
             }
+            ResultColumn::Expr { expr, .. } => output.push(ctx.eval(expr)?),
+        }
+    }
+
+    Ok(output)
+}
+
+
+fn row_text(row: &[Value], encoding: TextEncoding) -> String {
+    let row_output: Vec<String> = row.iter().map(|v| v.to_text(encoding)).collect();
+    row_output.join("|")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            if flag {
-                println!("{}", row_output.join("|"));
+    fn assert_queries(cases: &[(&str, &[&str])]) {
+        for (sql, expected) in cases {
+            assert_eq!(query_fixture(sql).unwrap(), *expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_subqueries() {
+        assert_queries(&[
+            // correlated
+            (
+                "SELECT g.label, (SELECT count(*) FROM items i WHERE i.grp = g.id) FROM groups g ORDER BY g.id",
+                &["zero|200", "one|200", "two|200", "three|200", "ten|0", "|0"],
+            ),
+            (
+                "SELECT g.id FROM groups g WHERE EXISTS (SELECT 1 FROM tags t JOIN items i ON i.id = t.item WHERE i.grp = g.id) ORDER BY 1",
+                &["1", "2"],
+            ),
+            (
+                "SELECT t.tag, (SELECT name FROM items WHERE id = t.item) FROM tags t ORDER BY t.tag, t.item",
+                &["big|ITEM0001", "blue|item0004", "lost|", "red|ITEM0001", "red|item0002"],
+            ),
+            // uncorrelated, run once
+            ("SELECT id, name FROM items WHERE id IN (SELECT item FROM tags) ORDER BY id", &["1|ITEM0001", "2|item0002", "4|item0004"]),
+            ("SELECT count(*) FROM items WHERE grp NOT IN (SELECT id FROM groups WHERE label IS NOT NULL)", &["1200"]),
+            ("SELECT (SELECT max(id) FROM items), (SELECT label FROM groups WHERE id = 3)", &["2000|three"]),
+            (
+                "SELECT d.grp, d.n FROM (SELECT grp, count(*) AS n FROM items WHERE id <= 25 GROUP BY grp) d WHERE d.n > 2 ORDER BY d.grp",
+                &["1|3", "2|3", "3|3", "4|3", "5|3"],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn test_ctes() {
+        assert_queries(&[
+            // LIMIT stops a recursion that would never end
+            ("WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i * 2 FROM n LIMIT 6) SELECT group_concat(i) FROM n", &["1,2,4,8,16,32"]),
+            ("WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n LIMIT 4) SELECT group_concat(i) FROM n", &["1,2,3,4"]),
+            (
+                "WITH RECURSIVE chain(id, depth) AS (SELECT 1, 0 UNION SELECT id * 3, depth + 1 FROM chain WHERE id * 3 <= 2000) \
+                 SELECT c.depth, i.name FROM chain c JOIN items i ON i.id = c.id ORDER BY c.depth DESC LIMIT 3",
+                &["6|ITEM0729", "5|ITEM0243", "4|ITEM0081"],
+            ),
+            ("WITH big(g) AS (SELECT id FROM groups WHERE id > 2), small AS (SELECT g FROM big WHERE g < 11) SELECT * FROM small", &["3", "10"]),
+        ]);
+    }
+
+    #[test]
+    fn test_compound_selects() {
+        assert_queries(&[
+            ("SELECT item FROM tags INTERSECT SELECT id FROM items WHERE grp < 3", &["1", "2"]),
+            ("SELECT id FROM groups EXCEPT SELECT grp FROM items", &["10", "11"]),
+            ("SELECT tag FROM tags UNION SELECT label FROM groups WHERE id < 2 ORDER BY 1", &["big", "blue", "lost", "one", "red", "zero"]),
+            ("SELECT item FROM tags UNION ALL SELECT id FROM groups WHERE id > 9 ORDER BY 1 DESC LIMIT 4", &["2001", "11", "10", "4"]),
+            // the NOCASE column of the left member decides what is equal
+            ("SELECT name FROM items WHERE id < 3 INTERSECT SELECT upper(name) FROM items WHERE id < 5", &["ITEM0001", "item0002"]),
+        ]);
+    }
+
+    #[test]
+    fn test_utf16_databases() {
+        // (database, ids in BINARY order of w, ids of w > 'b', hex of the w of rows 3 and 4); sqlite3 compares the stored bytes
+        let databases = [
+            ("utf16le.db", "3,4,6,2,1,5", "1,5", ["00016200", "34D81EDD200063006C0065006600"]),
+            ("utf16be.db", "6,2,1,3,4,5", "1,3,4,5", ["01000062", "D834DD1E00200063006C00650066"]),
+        ];
+        for (database, order, above_b, hex_text) in databases {
+            let query = |sql: &str| query_database(database, sql).unwrap();
+            assert_eq!(query("SELECT group_concat(id) FROM (SELECT id FROM words ORDER BY w)"), [order], "{}", database);
+            // both go through the index on w
+            assert_eq!(query("SELECT group_concat(id) FROM words WHERE w > 'b'"), [above_b], "{}", database);
+            assert_eq!(query("SELECT id FROM words WHERE w = '𝄞 clef'"), ["4"], "{}", database);
+            assert_eq!(query("SELECT hex(w) FROM words WHERE id IN (3, 4)"), hex_text, "{}", database);
+            // numbers hex as their UTF-8 text whatever the encoding
+            let rows = [
+                "1|été|3|té|3132|integer",
+                "2|b|1||312E35|real",
+                "3|Āb|2|b||null",
+                "4|𝄞 clef|6| c|30|integer",
+                "5|�|1||00FF|blob",
+                "6|abc|3|bc|2D33|integer",
+            ];
+            assert_eq!(query("SELECT id, w, length(w), substr(w, 2, 2), hex(n), typeof(n) FROM words ORDER BY id"), rows, "{}", database);
+        }
+    }
+
+    #[test]
+    fn test_order_by() {
+        assert_queries(&[
+            // NULL is smallest unless NULLS FIRST/LAST says otherwise
+            ("SELECT id, label FROM groups ORDER BY label", &["11|", "1|one", "10|ten", "3|three", "2|two", "0|zero"]),
+            ("SELECT id, label FROM groups ORDER BY label DESC", &["0|zero", "2|two", "3|three", "10|ten", "1|one", "11|"]),
+            ("SELECT id, label FROM groups ORDER BY label NULLS LAST", &["1|one", "10|ten", "3|three", "2|two", "0|zero", "11|"]),
+            ("SELECT id, label FROM groups ORDER BY label DESC NULLS FIRST", &["11|", "0|zero", "2|two", "3|three", "10|ten", "1|one"]),
+            // expressions, positions and result aliases, several keys each with a direction
+            ("SELECT id FROM groups ORDER BY id % 3, id DESC", &["3", "0", "10", "1", "11", "2"]),
+            ("SELECT label, id FROM groups ORDER BY 2 DESC", &["|11", "ten|10", "three|3", "two|2", "one|1", "zero|0"]),
+            ("SELECT id * 2 AS twice FROM groups ORDER BY twice DESC LIMIT 3", &["22", "20", "6"]),
+            ("SELECT id, length(label) AS n FROM groups ORDER BY n DESC, id", &["3|5", "0|4", "1|3", "2|3", "10|3", "11|"]),
+            ("SELECT g.label, t.tag FROM groups g JOIN tags t ON t.item = g.id ORDER BY t.tag, g.label DESC", &["one|big", "two|red", "one|red"]),
+            // the column's NOCASE unless COLLATE says otherwise
+            ("SELECT name FROM items WHERE id IN (1, 2, 3, 4) ORDER BY name", &["ITEM0001", "item0002", "ITEM0003", "item0004"]),
+            ("SELECT name FROM items WHERE id IN (1, 2, 3, 4) ORDER BY name COLLATE BINARY", &["ITEM0001", "ITEM0003", "item0002", "item0004"]),
+            ("SELECT upper(tag) || 'x' FROM tags ORDER BY tag COLLATE NOCASE DESC, item", &["REDx", "REDx", "LOSTx", "BLUEx", "BIGx"]),
+            (
+                "SELECT x FROM (SELECT 'b' AS x UNION ALL SELECT 'a  ' UNION ALL SELECT 'B' UNION ALL SELECT 'a') ORDER BY x COLLATE NOCASE, x",
+                &["a", "a  ", "B", "b"],
+            ),
+            (
+                "SELECT x, length(x) FROM (SELECT 'a ' AS x UNION ALL SELECT 'a' UNION ALL SELECT 'a  ') ORDER BY x COLLATE RTRIM, length(x) DESC",
+                &["a  |3", "a |2", "a|1"],
+            ),
+        ]);
+        let out_of_range = [
+            ("SELECT id FROM groups ORDER BY 0", "1st ORDER BY term out of range - should be between 1 and 1"),
+            ("SELECT id, label FROM groups ORDER BY 1, 3", "2nd ORDER BY term out of range - should be between 1 and 2"),
+        ];
+        for (sql, message) in out_of_range {
+            assert_eq!(query_fixture(sql).unwrap_err().to_string(), message, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_limit() {
+        assert_queries(&[
+            ("SELECT id FROM items LIMIT 2, 3", &["3", "4", "5"]),
+            ("SELECT id FROM items LIMIT 3 OFFSET 2", &["3", "4", "5"]),
+            ("SELECT id FROM items LIMIT (SELECT count(*) FROM tags) - 2 OFFSET '1'", &["2", "3", "4"]),
+            ("SELECT id FROM items LIMIT 2.0", &["1", "2"]),
+            ("SELECT id FROM items LIMIT 0", &[]),
+            // a negative limit is no limit, a negative offset no offset
+            ("SELECT count(*) FROM (SELECT id FROM items LIMIT -1 OFFSET 1995)", &["5"]),
+            ("SELECT id FROM items LIMIT 2 OFFSET -5", &["1", "2"]),
+            ("SELECT id FROM items ORDER BY id DESC LIMIT 2", &["2000", "1999"]),
+            ("SELECT grp, count(*) FROM items GROUP BY grp ORDER BY 2 DESC, 1 LIMIT 2 OFFSET 3", &["3|200", "4|200"]),
+        ]);
+        for limit in ["1.5", "'2 rows'", "NULL"] {
+            let sql = format!("SELECT id FROM items LIMIT {}", limit);
+            assert_eq!(query_fixture(&sql).unwrap_err().to_string(), "datatype mismatch", "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_limit_stops_scan() {
+        // the first 64 items sit on pages 3 to 5; every later table leaf is made unreadable,
+        // those of sqlite_schema too, so the schema comes from the intact file
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/fixture.db");
+        let schema = Schema::load(&Pager::open(fixture).unwrap()).unwrap();
+        let mut file = std::fs::read(fixture).unwrap();
+        for page in file.chunks_mut(512).skip(5) {
+            if page[0] == 0x0d {
+                page[0] = 0xff;
             }
+        }
+        let path = std::env::temp_dir().join(format!("mydbms-limit-{}.db", std::process::id()));
+        std::fs::write(&path, file).unwrap();
+        let pager = Pager::open(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
 
-        });
+        let query = |sql: &str| {
+            let mut rows = Vec::new();
+            run(&pager, sql, &schema, &EngineConfig::default(), &mut |row| {
+                rows.push(row_text(&row, pager.text_encoding()));
+                Ok(true)
+            })
+            .map(|_| rows)
+        };
+        assert_eq!(query("SELECT id FROM items LIMIT 3").unwrap(), ["1", "2", "3"]);
+        assert_eq!(query("SELECT id, name FROM items WHERE grp = 3 LIMIT 2 OFFSET 4").unwrap(), ["43|ITEM0043", "53|ITEM0053"]);
+        assert!(query("SELECT count(*) FROM items").is_err());
+    }
 
+    #[test]
+    fn test_min_integer_literal() {
+        assert_queries(&[(
+            "SELECT -9223372036854775808, typeof(-9223372036854775808), typeof(9223372036854775808), typeof(-9223372036854775809)",
+            &["-9223372036854775808|integer|real|real"],
+        )]);
+        assert_eq!(query_fixture("SELECT abs(-9223372036854775808)").unwrap_err().to_string(), "integer overflow");
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_fixed_clock() {
+        let pager = Pager::open(concat!(env!("CARGO_MANIFEST_DIR"), "/fixture.db")).unwrap();
+        let schema = Schema::load(&pager).unwrap();
+        let config = EngineConfig { clock: Clock::parse("2024-06-01 08:00:00").unwrap(), ..EngineConfig::default() };
+        // 'now' reaches joins, aggregates, window functions, subqueries and LIMIT
+        let sql = "SELECT g.id, max(date('now', '+' || i.id || ' days')), min(time()) OVER (), \
+                   (SELECT unixepoch() FROM tags LIMIT 1) FROM groups g JOIN items i ON i.grp = g.id \
+                   WHERE i.id < 13 GROUP BY g.id ORDER BY g.id LIMIT strftime('%m') - 4";
+        let mut rows = Vec::new();
+        run(&pager, sql, &schema, &config, &mut |row| {
+            rows.push(row_text(&row, pager.text_encoding()));
+            Ok(true)
+        })
+        .unwrap();
+        assert_eq!(rows, ["0|2024-06-11|08:00:00|1717228800", "1|2024-06-12|08:00:00|1717228800"]);
+    }
+}
@@ -0,0 +1,371 @@
+use std::cmp::Ordering;
+
+use crate::encoding::TextEncoding;
+
+
+/// A single SQLite value, one variant per storage class.
+///
+/// A blob taken as text, by `to_text` or as a number by `to_numeric`, is read as text in the
+/// encoding passed in, which is that of the database the statement runs against.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// Type affinity of a column: the storage class its values are converted to when possible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    Blob,
+}
+
+impl Affinity {
+    /// SQLite's rules, tried in order on the declared type: INT, then CHAR/CLOB/TEXT,
+    /// then BLOB or no type at all, then REAL/FLOA/DOUB; anything else is NUMERIC.
+    pub fn from_declared_type(declared: &str) -> Self {
+        let declared = declared.to_ascii_uppercase();
+        let has = |words: &[&str]| words.iter().any(|w| declared.contains(w));
+        if has(&["INT"]) {
+            Affinity::Integer
+        } else if has(&["CHAR", "CLOB", "TEXT"]) {
+            Affinity::Text
+        } else if declared.is_empty() || has(&["BLOB"]) {
+            Affinity::Blob
+        } else if has(&["REAL", "FLOA", "DOUB"]) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    pub fn is_numeric(self) -> bool {
+        matches!(self, Affinity::Numeric | Affinity::Integer | Affinity::Real)
+    }
+
+    /// Converts an operand before a comparison: numeric affinities turn text that is a
+    /// well-formed number into that number, TEXT turns numbers into text, BLOB keeps the value.
+    pub fn apply(self, value: Value) -> Value {
+        match (self, value) {
+            (Affinity::Text, value @ (Value::Integer(_) | Value::Real(_))) => Value::Text(value.to_text(TextEncoding::Utf8)),
+            (affinity, Value::Text(text)) if affinity.is_numeric() => parse_number(&text).unwrap_or(Value::Text(text)),
+            (_, value) => value,
+        }
+    }
+}
+
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Numeric value used by arithmetic: text and blobs contribute their longest numeric prefix,
+    /// so 'abc' is 0 and '12abc' is 12.
+    pub fn to_numeric(&self, encoding: TextEncoding) -> Value {
+        match self {
+            Value::Null => Value::Null,
+            Value::Integer(_) | Value::Real(_) => self.clone(),
+            Value::Text(s) => parse_numeric_prefix(s),
+            Value::Blob(b) => parse_numeric_prefix(&encoding.decode(b)),
+        }
+    }
+
+    pub fn to_f64(&self, encoding: TextEncoding) -> f64 {
+        match self.to_numeric(encoding) {
+            Value::Integer(n) => n as f64,
+            Value::Real(r) => r,
+            _ => 0.0,
+        }
+    }
+
+    /// Integer value, truncating reals the way CAST(x AS INTEGER) does.
+    pub fn to_i64(&self, encoding: TextEncoding) -> i64 {
+        match self.to_numeric(encoding) {
+            Value::Integer(n) => n,
+            Value::Real(r) => real_to_i64(r),
+            _ => 0,
+        }
+    }
+
+    /// Truth value in a boolean context, NULL is unknown (`None`).
+    pub fn is_true(&self, encoding: TextEncoding) -> Option<bool> {
+        match self.to_numeric(encoding) {
+            Value::Null => None,
+            Value::Integer(n) => Some(n != 0),
+            Value::Real(r) => Some(r != 0.0),
+            _ => Some(false),
+        }
+    }
+
+    /// Output format of the sqlite3 shell: NULL prints as an empty string, a blob as its bytes
+    /// read as `encoding` text.
+    pub fn to_text(&self, encoding: TextEncoding) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Integer(n) => n.to_string(),
+            Value::Real(r) => format_real(*r),
+            Value::Text(s) => s.clone(),
+            Value::Blob(b) => encoding.decode(b),
+        }
+    }
+
+    pub fn from_bool(b: bool) -> Value {
+        Value::Integer(b as i64)
+    }
+
+    /// Sort order of the storage classes: NULL < INTEGER, REAL < TEXT < BLOB.
+    fn class_rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Integer(_) | Value::Real(_) => 1,
+            Value::Text(_) => 2,
+            Value::Blob(_) => 3,
+        }
+    }
+
+    /// Compares two values the way SQLite orders them.
+    /// Numbers compare numerically across INTEGER and REAL, text and blobs compare bytewise, text
+    /// as UTF-8: [`Collation`](crate::collation::Collation) compares it in the database's encoding.
+    pub fn compare(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Real(a), Value::Real(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Value::Integer(a), Value::Real(b)) => compare_int_real(*a, *b),
+            (Value::Real(a), Value::Integer(b)) => compare_int_real(*b, *a).reverse(),
+            (Value::Text(a), Value::Text(b)) => a.as_bytes().cmp(b.as_bytes()),
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+            _ => self.class_rank().cmp(&other.class_rank()),
+        }
+    }
+}
+
+/// Saturating real to integer conversion.
+pub fn real_to_i64(r: f64) -> i64 {
+    if r.is_nan() {
+        0
+    } else if r >= 9223372036854775807.0 {
+        i64::MAX
+    } else if r <= -9223372036854775808.0 {
+        i64::MIN
+    } else {
+        r as i64
+    }
+}
+
+/// Longest prefix of `s` that looks like a number, ignoring leading spaces.
+/// Returns INTEGER when there is no decimal point or exponent and the value fits, otherwise REAL.
+pub fn parse_numeric_prefix(s: &str) -> Value {
+    numeric_prefix(s.trim_start()).0
+}
+
+/// The number `s` spells out in full, surrounding spaces aside; `None` for anything else.
+pub fn parse_number(s: &str) -> Option<Value> {
+    let s = s.trim_matches(|c: char| c.is_ascii_whitespace());
+    match numeric_prefix(s) {
+        (value, length) if length > 0 && length == s.len() => Some(value),
+        _ => None,
+    }
+}
+
+/// Value and length of the number at the start of `s`; the length is 0 without one.
+fn numeric_prefix(s: &str) -> (Value, usize) {
+    let bytes = s.as_bytes();
+    let mut pos = 0;
+    let mut is_real = false;
+
+    if pos < bytes.len() && (bytes[pos] == b'+' || bytes[pos] == b'-') {
+        pos += 1;
+    }
+    let digits_start = pos;
+    while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+        pos += 1;
+    }
+    let mut has_digits = pos > digits_start;
+    if pos < bytes.len() && bytes[pos] == b'.' {
+        let frac_start = pos + 1;
+        let mut end = frac_start;
+        while end < bytes.len() && bytes[end].is_ascii_digit() {
+            end += 1;
+        }
+        if has_digits || end > frac_start {
+            is_real = true;
+            has_digits = true;
+            pos = end;
+        }
+    }
+    if !has_digits {
+        return (Value::Integer(0), 0);
+    }
+    if pos < bytes.len() && (bytes[pos] == b'e' || bytes[pos] == b'E') {
+        let mut end = pos + 1;
+        if end < bytes.len() && (bytes[end] == b'+' || bytes[end] == b'-') {
+            end += 1;
+        }
+        if end < bytes.len() && bytes[end].is_ascii_digit() {
+            while end < bytes.len() && bytes[end].is_ascii_digit() {
+                end += 1;
+            }
+            is_real = true;
+            pos = end;
+        }
+    }
+
+    let text = &s[..pos];
+    if !is_real && let Ok(n) = text.parse::<i64>() {
+        return (Value::Integer(n), pos);
+    }
+    (Value::Real(text.parse::<f64>().unwrap_or(0.0)), pos)
+}
+
+/// Exact integer/real comparison, without losing precision on large integers.
+fn compare_int_real(i: i64, r: f64) -> Ordering {
+    if r.is_nan() {
+        return Ordering::Greater;
+    }
+    if r >= 9223372036854775808.0 {
+        return Ordering::Less;
+    }
+    if r < -9223372036854775808.0 {
+        return Ordering::Greater;
+    }
+    let truncated = r.trunc();
+    match i.cmp(&(truncated as i64)) {
+        Ordering::Equal => 0.0f64.partial_cmp(&(r - truncated)).unwrap_or(Ordering::Equal),
+        other => other,
+    }
+}
+
+/// Formats a REAL the way the sqlite3 shell does (`%!.15g`):
+/// 15 significant digits, always with a decimal point, exponent form outside 1e-4..1e15.
+pub fn format_real(r: f64) -> String {
+    if r.is_nan() {
+        return String::new();
+    }
+    if r.is_infinite() {
+        return if r > 0.0 { "Inf".to_string() } else { "-Inf".to_string() };
+    }
+    if r == 0.0 {
+        return "0.0".to_string();
+    }
+
+    // d.dddddddddddddde[-]x
+    let sci = format!("{:.14e}", r);
+    let (mantissa, exp) = sci.split_once('e').unwrap_or((&sci, "0"));
+    let exp: i32 = exp.parse().unwrap_or(0);
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(m) => ("-", m),
+        None => ("", mantissa),
+    };
+    let digits: String = mantissa.chars().filter(|c| c.is_ascii_digit()).collect();
+    let digits = digits.trim_end_matches('0');
+    let digits = if digits.is_empty() { "0" } else { digits };
+
+    if !(-4..15).contains(&exp) {
+        let (first, rest) = digits.split_at(1);
+        let rest = if rest.is_empty() { "0" } else { rest };
+        let exp_sign = if exp < 0 { '-' } else { '+' };
+        return format!("{}{}.{}e{}{:02}", sign, first, rest, exp_sign, exp.abs());
+    }
+
+    if exp < 0 {
+        let zeros = "0".repeat((-exp - 1) as usize);
+        format!("{}0.{}{}", sign, zeros, digits)
+    } else {
+        let int_len = exp as usize + 1;
+        if digits.len() <= int_len {
+            format!("{}{}{}.0", sign, digits, "0".repeat(int_len - digits.len()))
+        } else {
+            format!("{}{}.{}", sign, &digits[..int_len], &digits[int_len..])
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_storage_classes() {
+        assert_eq!(Value::Null.compare(&Value::Integer(-5)), Ordering::Less);
+        assert_eq!(Value::Integer(10).compare(&Value::Text("9".into())), Ordering::Less);
+        assert_eq!(Value::Text("z".into()).compare(&Value::Blob(vec![0])), Ordering::Less);
+    }
+
+    #[test]
+    fn test_compare_numbers() {
+        assert_eq!(Value::Integer(10).compare(&Value::Integer(9)), Ordering::Greater);
+        assert_eq!(Value::Integer(10).compare(&Value::Real(10.0)), Ordering::Equal);
+        assert_eq!(Value::Real(2.5).compare(&Value::Integer(2)), Ordering::Greater);
+        assert_eq!(Value::Integer(i64::MAX).compare(&Value::Real(9.3e18)), Ordering::Less);
+    }
+
+    #[test]
+    fn test_affinity() {
+        assert_eq!(Affinity::from_declared_type("BIGINT"), Affinity::Integer);
+        assert_eq!(Affinity::from_declared_type("VARCHAR(255)"), Affinity::Text);
+        assert_eq!(Affinity::from_declared_type(""), Affinity::Blob);
+        assert_eq!(Affinity::from_declared_type("double precision"), Affinity::Real);
+        assert_eq!(Affinity::from_declared_type("DECIMAL(10,2)"), Affinity::Numeric);
+        // INT wins over the other rules, so POINT is INTEGER and FLOATING POINT too
+        assert_eq!(Affinity::from_declared_type("FLOATING POINT"), Affinity::Integer);
+        assert_eq!(Affinity::from_declared_type("STRING"), Affinity::Numeric);
+    }
+
+    #[test]
+    fn test_affinity_apply() {
+        let text = |s: &str| Value::Text(s.to_string());
+        assert_eq!(Affinity::Numeric.apply(text(" 10 ")), Value::Integer(10));
+        assert_eq!(Affinity::Integer.apply(text("2.50")), Value::Real(2.5));
+        assert_eq!(Affinity::Real.apply(text("1e2x")), text("1e2x"));
+        assert_eq!(Affinity::Numeric.apply(text("0x10")), text("0x10"));
+        assert_eq!(Affinity::Text.apply(Value::Real(10.0)), text("10.0"));
+        assert_eq!(Affinity::Text.apply(Value::Blob(vec![1])), Value::Blob(vec![1]));
+        assert_eq!(Affinity::Blob.apply(text("10")), text("10"));
+    }
+
+    #[test]
+    fn test_numeric_prefix() {
+        assert_eq!(parse_numeric_prefix("abc"), Value::Integer(0));
+        assert_eq!(parse_numeric_prefix("12abc"), Value::Integer(12));
+        assert_eq!(parse_numeric_prefix(" 3.5e1x"), Value::Real(35.0));
+        assert_eq!(parse_numeric_prefix("-.5"), Value::Real(-0.5));
+        assert_eq!(parse_numeric_prefix("1e"), Value::Integer(1));
+        assert_eq!(parse_numeric_prefix("99999999999999999999"), Value::Real(1e20));
+
+        // a blob reads as text in the encoding it is given
+        let blob = Value::Blob(TextEncoding::Utf16Le.encode("42x"));
+        assert_eq!(blob.to_numeric(TextEncoding::Utf16Le), Value::Integer(42));
+        assert_eq!(blob.to_text(TextEncoding::Utf16Le), "42x");
+        assert_eq!(blob.to_numeric(TextEncoding::Utf8), Value::Integer(4));
+    }
+
+    #[test]
+    fn test_format_real() {
+        let cases = [
+            (1e20, "1.0e+20"),
+            (1.5e-7, "1.5e-07"),
+            (100.0, "100.0"),
+            (1e15, "1.0e+15"),
+            (123456789012345.0, "123456789012345.0"),
+            (1.0 / 3.0, "0.333333333333333"),
+            (-2.5, "-2.5"),
+            (0.0001, "0.0001"),
+            (0.00001234, "1.234e-05"),
+            (12345678901234567.0, "1.23456789012346e+16"),
+            (f64::INFINITY, "Inf"),
+            (-0.0, "0.0"),
+        ];
+        for (r, expected) in cases {
+            assert_eq!(format_real(r), expected);
+        }
+    }
+}
@@ -0,0 +1,680 @@
+use std::error::Error;
+use std::ops::Range;
+
+use crate::aggregate::{call_name, check_arguments, is_aggregate, Accumulator};
+use crate::collation::Collation;
+use crate::datetime::Clock;
+use crate::encoding::TextEncoding;
+use crate::eval::{self, binary, EvalContext, Scope, SubqueryRunner};
+use crate::functions::is_scalar_function;
+use crate::sorter::{compare_keys, SortKey, Sorter};
+use crate::sql::ParseError;
+use crate::sql::ast::{
+    BinaryOp, Expr, Frame, FrameBound, FrameExclude, FrameUnit, FunctionArgs, OrderingTerm, Over, Span, WindowSpec,
+};
+use crate::value::Value;
+
+
+/// Functions that only exist as window functions; aggregates can be used as window functions too.
+pub fn is_window_function(name: &str) -> bool {
+    matches!(
+        name,
+        "row_number"
+            | "rank"
+            | "dense_rank"
+            | "percent_rank"
+            | "cume_dist"
+            | "ntile"
+            | "lag"
+            | "lead"
+            | "first_value"
+            | "last_value"
+            | "nth_value"
+    )
+}
+
+pub fn misuse(name: &str, span: Span) -> ParseError {
+    ParseError::new(format!("misuse of window function {}()", name), span)
+}
+
+/// Collects the window function calls of an expression, checking their arguments.
+pub fn collect_windows<'a>(expr: &'a Expr, out: &mut Vec<&'a Expr>) -> Result<(), ParseError> {
+    let Expr::Function { name, args, distinct, over, span } = expr else {
+        for child in expr.children() {
+            collect_windows(child, out)?;
+        }
+        return Ok(());
+    };
+
+    if over.is_none() {
+        if is_window_function(name) {
+            return Err(misuse(name, *span));
+        }
+        if is_aggregate(name, args) {
+            return forbid_windows(expr);
+        }
+        for child in expr.children() {
+            collect_windows(child, out)?;
+        }
+        return Ok(());
+    }
+
+    if is_window_function(name) {
+        let arg_count = match args {
+            FunctionArgs::Star => None,
+            FunctionArgs::List(args) => Some(args.len()),
+        };
+        let valid = match name.as_str() {
+            "ntile" | "first_value" | "last_value" => arg_count == Some(1),
+            "lag" | "lead" => matches!(arg_count, Some(1..=3)),
+            "nth_value" => arg_count == Some(2),
+            _ => arg_count == Some(0),
+        };
+        if !valid {
+            return Err(ParseError::new(format!("wrong number of arguments to function {}()", name), *span));
+        }
+    } else if is_aggregate(name, args) {
+        if *distinct {
+            return Err(ParseError::new("DISTINCT is not supported for window functions", *span));
+        }
+        check_arguments(name, args, false, *span)?;
+    } else if name == "min" || name == "max" || is_scalar_function(name) {
+        return Err(ParseError::new(format!("{}() may not be used as a window function", name), *span));
+    } else {
+        return Err(ParseError::new(format!("no such function: {}", name), *span));
+    }
+
+    forbid_windows(expr)?;
+    if !out.iter().any(|call| std::ptr::eq(*call, expr)) {
+        out.push(expr);
+    }
+    Ok(())
+}
+
+/// Window functions cannot be nested in the arguments of an aggregate or another window function.
+fn forbid_windows(call: &Expr) -> Result<(), ParseError> {
+    let mut nested = Vec::new();
+    for child in call.children() {
+        collect_windows(child, &mut nested)?;
+    }
+    match nested.first() {
+        Some(Expr::Function { name, span, .. }) => Err(misuse(name, *span)),
+        _ => Ok(()),
+    }
+}
+
+
+/// A window function call with its window resolved against the WINDOW clause.
+pub struct WindowCall<'a> {
+    call: &'a Expr,
+    name: &'a str,
+    args: &'a [Expr],
+    partition_by: &'a [Expr],
+    order_by: &'a [OrderingTerm],
+    frame: Frame,
+    /// sort keys of the PARTITION BY and then the ORDER BY terms
+    keys: Vec<SortKey>,
+    /// how the database stores text, for min() and max() over the frame
+    encoding: TextEncoding,
+}
+
+/// PARTITION BY, ORDER BY and frame of a window definition, following its base window.
+type Definition<'a> = (&'a [Expr], &'a [OrderingTerm], Option<&'a Frame>);
+
+impl<'a> WindowCall<'a> {
+    /// `scope` is what the PARTITION BY and ORDER BY terms are read in, for their collations, and
+    /// `encoding` how the database stores the text they compare.
+    pub fn resolve(
+        call: &'a Expr,
+        windows: &'a [(String, WindowSpec)],
+        scope: &Scope,
+        encoding: TextEncoding,
+    ) -> Result<Self, Box<dyn Error>> {
+        let Expr::Function { over: Some(over), .. } = call else {
+            return Err("Not a window function call".into());
+        };
+        let (partition_by, order_by, frame) = match &**over {
+            Over::Named(name) => definition(named_window(name, windows)?, windows, 0)?,
+            Over::Spec(spec) => definition(spec, windows, 0)?,
+        };
+
+        // without a frame: from the start of the partition to the last peer of the current row
+        let frame = frame.cloned().unwrap_or(Frame {
+            unit: FrameUnit::Range,
+            start: FrameBound::UnboundedPreceding,
+            end: FrameBound::CurrentRow,
+            exclude: FrameExclude::NoOthers,
+        });
+        let unsupported = matches!(
+            (&frame.start, &frame.end),
+            (FrameBound::CurrentRow, FrameBound::Preceding(_))
+                | (FrameBound::Following(_), FrameBound::Preceding(_) | FrameBound::CurrentRow)
+        );
+        if unsupported {
+            return Err("unsupported frame specification".into());
+        }
+        let has_offset = |bound: &FrameBound| matches!(bound, FrameBound::Preceding(_) | FrameBound::Following(_));
+        if frame.unit == FrameUnit::Range && order_by.len() != 1 && (has_offset(&frame.start) || has_offset(&frame.end)) {
+            return Err("RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression".into());
+        }
+
+        let mut keys = Vec::new();
+        for expr in partition_by {
+            keys.push(SortKey { collation: eval::sort_collation(expr, scope)?, encoding, descending: false, nulls_first: true });
+        }
+        for term in order_by {
+            let collation = match &term.collation {
+                Some(name) => Collation::named(name)?,
+                None => eval::sort_collation(&term.expr, scope)?,
+            };
+            let nulls_first = term.nulls_first.unwrap_or(!term.descending);
+            keys.push(SortKey { collation, encoding, descending: term.descending, nulls_first });
+        }
+
+        let args = match call {
+            Expr::Function { args: FunctionArgs::List(args), .. } => args.as_slice(),
+            _ => &[],
+        };
+        Ok(WindowCall { call, name: call_name(call), args, partition_by, order_by, frame, keys, encoding })
+    }
+
+    /// The PARTITION BY and ORDER BY expressions, which aggregate calls may appear in.
+    pub fn exprs(&self) -> impl Iterator<Item = &'a Expr> + use<'a> {
+        self.partition_by.iter().chain(self.order_by.iter().map(|term| &term.expr))
+    }
+
+    /// Frame offset, checked the way SQLite does when the statement starts stepping.
+    fn offset(
+        &self,
+        bound: &FrameBound,
+        which: &str,
+        subqueries: Option<&dyn SubqueryRunner>,
+        clock: Clock,
+    ) -> Result<Value, Box<dyn Error>> {
+        let (FrameBound::Preceding(expr) | FrameBound::Following(expr)) = bound else {
+            return Ok(Value::Null);
+        };
+        let scope = Scope::default();
+        let value = EvalContext::new(&scope, &[], self.encoding, clock).with_subqueries(subqueries).eval(expr)?;
+        let offset = match self.frame.unit {
+            FrameUnit::Range => number(&value).filter(|n| n.to_f64(self.encoding) >= 0.0),
+            FrameUnit::Rows | FrameUnit::Groups => integral(&value).filter(|n| *n >= 0).map(Value::Integer),
+        };
+        offset.ok_or_else(|| {
+            let kind = if self.frame.unit == FrameUnit::Range { "number" } else { "integer" };
+            format!("frame {} offset must be a non-negative {}", which, kind).into()
+        })
+    }
+
+    /// Values of the call for each row of one partition, in order. Each row holds its partition
+    /// and order values from position `at`, followed by the arguments of the call, which are read in `scope`.
+    fn evaluate(&self, scope: &Scope, rows: &[Vec<Value>], at: usize, offsets: &(Value, Value)) -> Result<Vec<Value>, Box<dyn Error>> {
+        let partition = Partition::new(self, rows, at);
+        let n = rows.len();
+        let mut values = Vec::with_capacity(n);
+
+        match self.name {
+            "row_number" => values.extend((0..n).map(|i| Value::Integer(i as i64 + 1))),
+            "rank" => values.extend((0..n).map(|i| Value::Integer(partition.peers(i).start as i64 + 1))),
+            "dense_rank" => values.extend((0..n).map(|i| Value::Integer(partition.group[i] as i64 + 1))),
+            "percent_rank" => values.extend((0..n).map(|i| {
+                let rank = partition.peers(i).start;
+                Value::Real(if n > 1 { rank as f64 / (n - 1) as f64 } else { 0.0 })
+            })),
+            "cume_dist" => values.extend((0..n).map(|i| Value::Real(partition.peers(i).end as f64 / n as f64))),
+            "ntile" => {
+                // the bucket count comes from the first row of the partition
+                let buckets = partition.arg(0, 0);
+                if buckets.is_null() || buckets.to_i64(self.encoding) <= 0 {
+                    return Err("argument of ntile must be a positive integer".into());
+                }
+                let buckets = buckets.to_i64(self.encoding) as usize;
+                let (size, extra) = (n / buckets, n % buckets);
+                let large = extra * (size + 1);
+                values.extend((0..n).map(|i| {
+                    // the first `extra` buckets hold one row more
+                    let bucket = if i < large || size == 0 { i / (size + 1) } else { extra + (i - large) / size };
+                    Value::Integer(bucket as i64 + 1)
+                }));
+            }
+            "lag" | "lead" => {
+                for i in 0..n {
+                    // an offset that is not an integer gives the default
+                    let offset = match self.args.len() {
+                        1 => Some(1),
+                        _ => integral(partition.arg(i, 1)),
+                    };
+                    let target = offset.and_then(|offset| match self.name {
+                        "lag" => (i as i64).checked_sub(offset),
+                        _ => (i as i64).checked_add(offset),
+                    });
+                    values.push(match target {
+                        Some(target) if target >= 0 && (target as usize) < n => partition.arg(target as usize, 0).clone(),
+                        _ if self.args.len() == 3 => partition.arg(i, 2).clone(),
+                        _ => Value::Null,
+                    });
+                }
+            }
+            "first_value" | "last_value" | "nth_value" => {
+                for i in 0..n {
+                    let frame = partition.frame(i, offsets);
+                    let mut included = frame.rows();
+                    let row = match self.name {
+                        "first_value" => included.next(),
+                        "last_value" => included.last(),
+                        _ => {
+                            let nth = integral(partition.arg(i, 1)).filter(|n| *n > 0);
+                            let nth = nth.ok_or("second argument to nth_value must be a positive integer")?;
+                            included.nth(nth as usize - 1)
+                        }
+                    };
+                    values.push(row.map_or(Value::Null, |row| partition.arg(row, 0).clone()));
+                }
+            }
+            name => {
+                // a frame that only grows is accumulated as it goes
+                if self.frame.start == FrameBound::UnboundedPreceding && self.frame.exclude == FrameExclude::NoOthers {
+                    let mut accumulator = Accumulator::new(self.call, scope)?;
+                    let mut added = 0;
+                    for i in 0..n {
+                        let end = partition.frame(i, offsets).range.end;
+                        for row in added..end {
+                            accumulator.step(partition.args(row), self.encoding);
+                        }
+                        added = added.max(end);
+                        values.push(accumulator.finish()?);
+                    }
+                    return Ok(values);
+                }
+                // one that only loses rows at the front is accumulated from the back, where the
+                // order of the values does not matter
+                if self.frame.end == FrameBound::UnboundedFollowing
+                    && self.frame.exclude == FrameExclude::NoOthers
+                    && !matches!(name, "group_concat" | "json_group_array" | "json_group_object")
+                {
+                    let mut accumulator = Accumulator::new(self.call, scope)?;
+                    let mut added = n;
+                    for i in (0..n).rev() {
+                        let start = partition.frame(i, offsets).range.start;
+                        while added > start {
+                            added -= 1;
+                            accumulator.step(partition.args(added), self.encoding);
+                        }
+                        values.push(accumulator.finish()?);
+                    }
+                    values.reverse();
+                    return Ok(values);
+                }
+
+                let mut previous: Option<(FrameRows, Value)> = None;
+                for i in 0..n {
+                    let frame = partition.frame(i, offsets);
+                    if let Some((rows, value)) = &previous
+                        && *rows == frame
+                    {
+                        values.push(value.clone());
+                        continue;
+                    }
+                    let mut accumulator = Accumulator::new(self.call, scope)?;
+                    for row in frame.rows() {
+                        accumulator.step(partition.args(row), self.encoding);
+                    }
+                    let value = accumulator.finish()?;
+                    values.push(value.clone());
+                    previous = Some((frame, value));
+                }
+            }
+        }
+        Ok(values)
+    }
+}
+
+fn named_window<'a>(name: &str, windows: &'a [(String, WindowSpec)]) -> Result<&'a WindowSpec, Box<dyn Error>> {
+    // the first of several windows with the same name is used
+    match windows.iter().find(|(window, _)| window.eq_ignore_ascii_case(name)) {
+        Some((_, spec)) => Ok(spec),
+        None => Err(format!("no such window: {}", name).into()),
+    }
+}
+
+/// A window may extend a named one with an ORDER BY and a frame, but not override them.
+fn definition<'a>(spec: &'a WindowSpec, windows: &'a [(String, WindowSpec)], depth: usize) -> Result<Definition<'a>, Box<dyn Error>> {
+    let Some(base) = &spec.base else {
+        return Ok((&spec.partition_by, &spec.order_by, spec.frame.as_ref()));
+    };
+    // a chain longer than the WINDOW clause goes around in circles
+    if depth > windows.len() {
+        return Err(format!("no such window: {}", base).into());
+    }
+    let (partition_by, order_by, frame) = definition(named_window(base, windows)?, windows, depth + 1)?;
+    if !spec.partition_by.is_empty() {
+        return Err(format!("cannot override PARTITION clause of window: {}", base).into());
+    }
+    if !spec.order_by.is_empty() && !order_by.is_empty() {
+        return Err(format!("cannot override ORDER BY clause of window: {}", base).into());
+    }
+    if frame.is_some() {
+        return Err(format!("cannot override frame specification of window: {}", base).into());
+    }
+    let order_by = if spec.order_by.is_empty() { order_by } else { &spec.order_by };
+    Ok((partition_by, order_by, spec.frame.as_ref()))
+}
+
+/// Integer value of an integer, an integral real, or text that spells one.
+fn integral(value: &Value) -> Option<i64> {
+    match value {
+        Value::Integer(n) => Some(*n),
+        Value::Real(r) if r.fract() == 0.0 && r.abs() < 9.2e18 => Some(*r as i64),
+        Value::Text(s) => match (s.trim().parse::<i64>(), s.trim().parse::<f64>()) {
+            (Ok(n), _) => Some(n),
+            (_, Ok(r)) => integral(&Value::Real(r)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// A number, or text that spells one.
+fn number(value: &Value) -> Option<Value> {
+    match value {
+        Value::Integer(_) | Value::Real(_) => Some(value.clone()),
+        Value::Text(s) => match (s.trim().parse::<i64>(), s.trim().parse::<f64>()) {
+            (Ok(n), _) => Some(Value::Integer(n)),
+            (_, Ok(r)) => Some(Value::Real(r)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+
+/// The rows of a frame: a range of the partition less the excluded rows.
+#[derive(Debug, Clone, PartialEq)]
+struct FrameRows {
+    range: Range<usize>,
+    excluded: Range<usize>,
+    /// the current row stays in for EXCLUDE TIES
+    kept: Option<usize>,
+}
+
+impl FrameRows {
+    fn rows(&self) -> impl Iterator<Item = usize> + '_ {
+        self.range.clone().filter(|row| !self.excluded.contains(row) || self.kept == Some(*row))
+    }
+}
+
+/// One partition, in window order, with its groups of peers.
+struct Partition<'p> {
+    call: &'p WindowCall<'p>,
+    rows: &'p [Vec<Value>],
+    /// position of the ORDER BY values and of the arguments in each row
+    order_at: usize,
+    args_at: usize,
+    /// peer group of every row
+    group: Vec<usize>,
+    /// rows of every peer group
+    groups: Vec<Range<usize>>,
+}
+
+impl<'p> Partition<'p> {
+    fn new(call: &'p WindowCall<'p>, rows: &'p [Vec<Value>], at: usize) -> Self {
+        let order_at = at + call.partition_by.len();
+        let args_at = order_at + call.order_by.len();
+        let order_keys = &call.keys[call.partition_by.len()..];
+
+        // rows with equal ORDER BY values are peers; without ORDER BY all rows are
+        let mut group = Vec::with_capacity(rows.len());
+        let mut groups: Vec<Range<usize>> = Vec::new();
+        for (i, row) in rows.iter().enumerate() {
+            let peer = i > 0 && compare_keys(order_keys, &row[order_at..args_at], &rows[i - 1][order_at..args_at]).is_eq();
+            match groups.last_mut() {
+                Some(last) if peer => last.end = i + 1,
+                _ => groups.push(i..i + 1),
+            }
+            group.push(groups.len() - 1);
+        }
+        Partition { call, rows, order_at, args_at, group, groups }
+    }
+
+    fn peers(&self, row: usize) -> Range<usize> {
+        self.groups[self.group[row]].clone()
+    }
+
+    fn args(&self, row: usize) -> &[Value] {
+        &self.rows[row][self.args_at..]
+    }
+
+    fn arg(&self, row: usize, n: usize) -> &Value {
+        &self.rows[row][self.args_at + n]
+    }
+
+    fn frame(&self, row: usize, offsets: &(Value, Value)) -> FrameRows {
+        let frame = &self.call.frame;
+        let start = self.bound(row, &frame.start, &offsets.0, true);
+        let end = self.bound(row, &frame.end, &offsets.1, false).max(start);
+        let (excluded, kept) = match frame.exclude {
+            FrameExclude::NoOthers => (0..0, None),
+            FrameExclude::CurrentRow => (row..row + 1, None),
+            FrameExclude::Group => (self.peers(row), None),
+            FrameExclude::Ties => (self.peers(row), Some(row)),
+        };
+        FrameRows { range: start..end, excluded, kept }
+    }
+
+    /// First row of the frame for the start bound, one past its last row for the end bound.
+    fn bound(&self, row: usize, bound: &FrameBound, offset: &Value, start: bool) -> usize {
+        let n = self.rows.len();
+        let edge = |range: Range<usize>| if start { range.start } else { range.end };
+        let preceding = match bound {
+            FrameBound::UnboundedPreceding => return 0,
+            FrameBound::UnboundedFollowing => return n,
+            FrameBound::CurrentRow if self.call.frame.unit == FrameUnit::Rows => return edge(row..row + 1),
+            FrameBound::CurrentRow => return edge(self.peers(row)),
+            FrameBound::Preceding(_) => true,
+            FrameBound::Following(_) => false,
+        };
+
+        if self.call.frame.unit == FrameUnit::Range {
+            // the ORDER BY value moved by the offset, against the direction of a descending sort;
+            // values that are not numbers only reach their peers
+            let key = &self.call.keys[self.call.partition_by.len()..][..1];
+            let value = &self.rows[row][self.order_at];
+            let op = if preceding != key[0].descending { BinaryOp::Subtract } else { BinaryOp::Add };
+            let target = match value {
+                Value::Integer(_) | Value::Real(_) => binary(op, value, offset, self.call.encoding),
+                _ => value.clone(),
+            };
+            let target = std::slice::from_ref(&target);
+            let order = |row: &Vec<Value>| compare_keys(key, &row[self.order_at..self.order_at + 1], target);
+            return if start {
+                self.rows.partition_point(|row| order(row).is_lt())
+            } else {
+                self.rows.partition_point(|row| !order(row).is_gt())
+            };
+        }
+
+        // ROWS counts rows, GROUPS counts groups of peers
+        let distance = if preceding { -offset.to_i64(self.call.encoding) } else { offset.to_i64(self.call.encoding) };
+        let (position, count) = match self.call.frame.unit {
+            FrameUnit::Rows => (row, n),
+            _ => (self.group[row], self.groups.len()),
+        };
+        match (position as i64).checked_add(distance) {
+            Some(target) if target < 0 => 0,
+            Some(target) if target < count as i64 => match self.call.frame.unit {
+                FrameUnit::Rows => edge(target as usize..target as usize + 1),
+                _ => edge(self.groups[target as usize].clone()),
+            },
+            _ => n,
+        }
+    }
+}
+
+
+/// A row with the values of the aggregate and window calls that apply to it.
+pub struct WindowRow<'a> {
+    pub row: Vec<Value>,
+    pub aggregates: Vec<(&'a Expr, Value)>,
+}
+
+/// Window function evaluation. Every call sorts the rows by its PARTITION BY and ORDER BY and
+/// then runs over one partition at a time. The calls are evaluated last to first, each sorting
+/// the output of the one before, so that like in SQLite the rows come out in the order of the
+/// first call's window.
+///
+/// While the calls run, a row is its columns, the values of the aggregate calls, and a slot
+/// for the value of every window call.
+pub struct Windows<'a> {
+    scope: &'a Scope,
+    subqueries: Option<&'a dyn SubqueryRunner>,
+    encoding: TextEncoding,
+    clock: Clock,
+    aggregates: &'a [&'a Expr],
+    calls: Vec<WindowCall<'a>>,
+    memory_budget: usize,
+    /// rows sorted for the last call, the first one evaluated
+    sorter: Sorter,
+}
+
+impl<'a> Windows<'a> {
+    pub fn new(
+        scope: &'a Scope,
+        aggregates: &'a [&'a Expr],
+        calls: Vec<WindowCall<'a>>,
+        memory_budget: usize,
+        encoding: TextEncoding,
+        clock: Clock,
+    ) -> Self {
+        let keys = calls.last().map_or_else(Vec::new, |call| call.keys.clone());
+        let sorter = Sorter::new(keys, memory_budget);
+        Windows { scope, subqueries: None, encoding, clock, aggregates, calls, memory_budget, sorter }
+    }
+
+    pub fn with_subqueries(self, subqueries: Option<&'a dyn SubqueryRunner>) -> Self {
+        Windows { subqueries, ..self }
+    }
+
+    /// Adds a row and the values of the aggregate calls for it.
+    pub fn push(&mut self, mut row: Vec<Value>, aggregates: Vec<Value>) -> Result<(), Box<dyn Error>> {
+        row.resize(self.scope.columns.len(), Value::Null);
+        row.extend(aggregates);
+        row.resize(self.width(), Value::Null);
+        let (key, row) = self.prepare(self.calls.len() - 1, row)?;
+        self.sorter.push(key, row)
+    }
+
+    /// Hands every row to `emit`, in the order of the first call's window.
+    pub fn finish(mut self, emit: &mut dyn FnMut(WindowRow<'a>) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+        let width = self.width();
+        let mut sorter = Some(std::mem::replace(&mut self.sorter, Sorter::new(vec![], self.memory_budget)));
+
+        for k in (0..self.calls.len()).rev() {
+            let call = &self.calls[k];
+            let offsets = (
+                call.offset(&call.frame.start, "starting", self.subqueries, self.clock)?,
+                call.offset(&call.frame.end, "ending", self.subqueries, self.clock)?,
+            );
+            let partition_keys = &call.keys[..call.partition_by.len()];
+            let mut next = (k > 0).then(|| Sorter::new(self.calls[k - 1].keys.clone(), self.memory_budget));
+
+            let mut rows = sorter.take().ok_or("Missing window sorter")?.finish()?;
+            let mut partition: Vec<Vec<Value>> = Vec::new();
+            loop {
+                let row = rows.next().transpose()?;
+                let complete = match (&row, partition.last()) {
+                    (Some(row), Some(last)) => compare_keys(partition_keys, &row[width..], &last[width..]).is_ne(),
+                    _ => row.is_none(),
+                };
+                if complete && !partition.is_empty() {
+                    let values = call.evaluate(self.scope, &partition, width, &offsets)?;
+                    for (mut row, value) in partition.drain(..).zip(values) {
+                        row.truncate(width);
+                        row[width - self.calls.len() + k] = value;
+                        match &mut next {
+                            Some(sorter) => {
+                                let (key, row) = self.prepare(k - 1, row)?;
+                                sorter.push(key, row)?;
+                            }
+                            None => emit(self.result(row))?,
+                        }
+                    }
+                }
+                match row {
+                    Some(row) => partition.push(row),
+                    None => break,
+                }
+            }
+
+            sorter = next;
+        }
+        Ok(())
+    }
+
+    /// Length of a row with the values of the aggregate and window calls.
+    fn width(&self) -> usize {
+        self.scope.columns.len() + self.aggregates.len() + self.calls.len()
+    }
+
+    /// Sort key of a row for call `k`, and the row followed by the key and the arguments of the call.
+    fn prepare(&self, k: usize, mut row: Vec<Value>) -> Result<(Vec<Value>, Vec<Value>), Box<dyn Error>> {
+        let call = &self.calls[k];
+        let columns = self.scope.columns.len();
+        let (key, args) = {
+            let aggregates: Vec<_> = self.aggregates.iter().copied().zip(row[columns..].iter().cloned()).collect();
+            let ctx = EvalContext {
+                scope: self.scope,
+                row: &row[..columns],
+                aggregates: &aggregates,
+                subqueries: self.subqueries,
+                encoding: self.encoding,
+                clock: self.clock,
+            };
+            let key = call.exprs().map(|expr| ctx.eval(expr)).collect::<Result<Vec<_>, _>>()?;
+            let args = call.args.iter().map(|arg| ctx.eval(arg)).collect::<Result<Vec<_>, _>>()?;
+            (key, args)
+        };
+        row.extend(key.iter().cloned());
+        row.extend(args);
+        Ok((key, row))
+    }
+
+    fn result(&self, mut row: Vec<Value>) -> WindowRow<'a> {
+        let values = row.split_off(self.scope.columns.len());
+        let calls = self.aggregates.iter().copied().chain(self.calls.iter().map(|call| call.call));
+        WindowRow { row, aggregates: calls.zip(values).collect() }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::sql_engine::query_fixture;
+
+    #[test]
+    fn test_window_frames() {
+        let cases: [(&str, &[&str]); 5] = [
+            (
+                "SELECT id, sum(id) OVER (ORDER BY id ROWS BETWEEN 1 PRECEDING AND 1 FOLLOWING) FROM items WHERE id <= 5",
+                &["1|3", "2|6", "3|9", "4|12", "5|9"],
+            ),
+            ("SELECT id, sum(id) OVER (ORDER BY id ROWS UNBOUNDED PRECEDING EXCLUDE CURRENT ROW) FROM items WHERE id <= 4", &["1|", "2|1", "3|3", "4|6"]),
+            // grp is 1 to 9, then 0, 1 and 2 again
+            (
+                "SELECT id, count(*) OVER (ORDER BY grp RANGE BETWEEN 1 PRECEDING AND CURRENT ROW) FROM items WHERE id <= 12 ORDER BY id",
+                &["1|3", "2|4", "3|3", "4|2", "5|2", "6|2", "7|2", "8|2", "9|2", "10|1", "11|3", "12|4"],
+            ),
+            (
+                "SELECT id, sum(id) OVER (ORDER BY grp GROUPS BETWEEN CURRENT ROW AND 1 FOLLOWING) FROM items WHERE id <= 12 ORDER BY id",
+                &["1|26", "2|17", "3|7", "4|9", "5|11", "6|13", "7|15", "8|17", "9|9", "10|22", "11|26", "12|17"],
+            ),
+            (
+                "SELECT grp, id, row_number() OVER w, rank() OVER (ORDER BY grp), lag(id) OVER w FROM items \
+                 WHERE id <= 22 AND grp < 2 WINDOW w AS (PARTITION BY grp ORDER BY id DESC) ORDER BY grp, id",
+                &["0|10|2|1|20", "0|20|1|1|", "1|1|3|3|11", "1|11|2|3|21", "1|21|1|3|"],
+            ),
+        ];
+        for (sql, expected) in cases {
+            assert_eq!(query_fixture(sql).unwrap(), expected, "{}", sql);
+        }
+    }
+}